@@ -2,6 +2,8 @@
 extern crate libazdice;
 use std::io;
 use libazdice::parse;
+use libazdice::chart::Chart;
+use libazdice::svg::SvgChart;
 
 fn main() {
     let reps = if let Some(n) = std::env::args().nth(1) {
@@ -9,6 +11,8 @@ fn main() {
     } else {
         10_000
     };
+    // An optional second argument is a file to draw the distribution to as SVG.
+    let svg_path = std::env::args().nth(2);
 
     println!("Hello, world! Lets make a dice distribution. Input the dice string:");
     let mut input = String::new();
@@ -30,6 +34,15 @@ fn main() {
     let probability_distribution = dice_bag.make_frequency_distribution(reps);
 
     println!("Result for {} rolls of ({})",reps, input.trim());
-    println!("Value\t| Percentage");
-    for (i,n) in probability_distribution { println!("{}\t|  {}",i,n); }
+    print!("{}", Chart::new().frequencies("", &probability_distribution));
+
+    if let Some(path) = svg_path {
+        let svg = SvgChart::new()
+            .title(&format!("{} rolls of {}", reps, input.trim()))
+            .frequencies(input.trim(), &probability_distribution);
+        match svg.save(&path) {
+            Ok(()) => println!("Chart written to {}", path),
+            Err(e) => println!("Could not write the chart to {}: {}", path, e),
+        }
+    }
 }
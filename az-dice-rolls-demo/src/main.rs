@@ -1,38 +1,36 @@
 // This is a mini-program which demonstrates the use of `libazdice`.
 extern crate libazdice;
-use libazdice::parse;
-use std::io;
+use libazdice::repl::Session;
+use std::io::{self, Write};
 
 fn main() {
+    println!("Hello, world! Lets roll some dice. Input a dice string, :help for more or :quit to stop.");
+    let mut session = Session::new();
     loop {
-        println!("Hello, world! Lets make a dice. Input the dice string:");
+        print!("> ");
+        let _ = io::stdout().flush();
         let mut input = String::new();
 
-        if let Err(e) = io::stdin().read_line(&mut input) {
-            println!("Could not read input: {}", e);
-            return;
+        match io::stdin().read_line(&mut input) {
+            Ok(0) => return,
+            Ok(_) => {}
+            Err(e) => {
+                println!("Could not read input: {}", e);
+                return;
+            }
         }
-        input = input.trim().to_owned();
+        let mut input = input.trim().to_owned();
 
+        if input == ":quit" || input == ":q" {
+            return;
+        }
         if input.is_empty() {
             input = "1d20".to_owned();
         }
 
-        // Parse input into dicebag.
-        let dice_bag = match parse::parse(input.clone()) {
-            Ok(db) => db,
-            Err(e) => {
-                println!("Could not parse input to dice:{:?}", e);
-                continue;
-            }
-        };
-        println!("Dicebag: {}", dice_bag);
-        let roll = dice_bag.roll();
-        println!(
-            "You rolled {}, and got {}.\n",
-            input,
-            roll.total()
-        );
-        println!("Result: {}", roll);
+        match session.eval(&input) {
+            Ok(shown) => println!("{}", shown),
+            Err(e) => println!("Could not do that: {}", e),
+        }
     }
 }
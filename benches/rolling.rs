@@ -0,0 +1,56 @@
+// A small timing comparison of the full roll report against the totals-only roll.
+// Run with `cargo bench`. There is no harness, so this is a plain program.
+extern crate libazdice;
+use libazdice::distribution::*;
+use std::time::{Duration, Instant};
+
+const ROLLS: usize = 1_000_000;
+
+fn time<F: FnMut() -> i64>(mut f: F) -> (Duration, i64) {
+    let start = Instant::now();
+    let mut checksum = 0_i64;
+    for _ in 0..ROLLS {
+        checksum = checksum.wrapping_add(f());
+    }
+    (start.elapsed(), checksum)
+}
+
+fn main() {
+    let inputs = ["1d20", "8d6", "4d6dl1", "10d6dl2dh2rr3be2+2d8!-4"];
+
+    println!("{} rolls each.", ROLLS);
+    println!("Dice\t| roll() (ms)\t| roll_total_with_rng() (ms)\t| Speed-up");
+    for input in inputs.iter() {
+        let bag = libazdice::parse(input.to_string()).expect("Bench dice must parse.");
+
+        let mut rng = seeded_rng(1);
+        let (full, sum_1) = time(|| bag.roll_with_rng(&mut rng).total());
+
+        let mut rng = seeded_rng(1);
+        let mut buffer = RollBuffer::new();
+        let (fast, sum_2) = time(|| bag.roll_total_with_rng(&mut rng, &mut buffer));
+
+        assert_eq!(sum_1, sum_2);
+        println!(
+            "{}\t| {}\t| {}\t| x{:.2}",
+            input,
+            full.as_millis(),
+            fast.as_millis(),
+            full.as_secs_f64() / fast.as_secs_f64()
+        );
+    }
+
+    let bag = libazdice::parse("4d6dl1".to_owned()).expect("Bench dice must parse.");
+    let start = Instant::now();
+    bag.make_count_distribution_with_rng(ROLLS, &mut seeded_rng(1));
+    println!(
+        "make_count_distribution (4d6dl1): {} ms",
+        start.elapsed().as_millis()
+    );
+    let start = Instant::now();
+    bag.make_count_distribution_parallel(ROLLS, 4, Some(1));
+    println!(
+        "make_count_distribution_parallel (4d6dl1, 4 threads): {} ms",
+        start.elapsed().as_millis()
+    );
+}
@@ -41,9 +41,22 @@ struct SingleRollResult {
     const char *err;
 };
 
+/* An opaque random number generator. Create with `rng_from_seed` or `rng_from_callback`,
+ * release with `free_rng`. */
+struct RngState;
+
 struct SingleRollResult parse_and_roll(char **);
 struct ResultListRolls parse_and_roll_n_times(char **, uint64_t, uint64_t);
 struct DistributionResult parse_and_generate_distribution(char **, uint64_t, uint64_t);
 long int parse_and_roll2(char **);
+
+struct RngState *rng_from_seed(uint64_t);
+/* Returns NULL if the callback is NULL. */
+struct RngState *rng_from_callback(uint64_t (*)(void *), void *);
+void free_rng(struct RngState *);
+struct SingleRollResult parse_and_roll_seeded(char **, uint64_t);
+struct SingleRollResult parse_and_roll_with_rng(char **, struct RngState *);
+struct ResultListRolls parse_and_roll_n_times_with_rng(char **, uint64_t, uint64_t, struct RngState *);
+struct DistributionResult parse_and_generate_distribution_with_rng(char **, uint64_t, uint64_t, struct RngState *);
 long int test2(char **);
 char *test(long unsigned int);
@@ -0,0 +1,192 @@
+//! The command line arguments of `azdice`.
+
+/// How the results are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Format {
+    Text,
+    Json,
+    Csv,
+    /// A chart, for "dist" and "compare" only.
+    Svg,
+}
+
+/// How a distribution is found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Method {
+    /// Exactly if possible, otherwise by sampling.
+    Auto,
+    /// Exactly, or not at all.
+    Exact,
+    /// By rolling this many times.
+    Samples(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Command {
+    Roll { expression: String, times: usize },
+    Dist { expression: String },
+    Stats { expression: String },
+    Compare { first: String, second: String },
+    Explain { expression: String },
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Args {
+    pub(crate) command: Command,
+    pub(crate) format: Format,
+    pub(crate) method: Method,
+    pub(crate) seed: Option<u64>,
+}
+
+const SUBCOMMANDS: [&str; 5] = ["roll", "dist", "stats", "compare", "explain"];
+
+/// Parse a number given to `option`.
+fn number<T: std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("{} needs a value.", option))?;
+    value
+        .parse::<T>()
+        .map_err(|_| format!("{} needs a whole number, not \"{}\".", option, value))
+}
+
+/// Parse the arguments after the program name. Options may come anywhere after the subcommand,
+/// as "--option value" or "--option=value". Anything else is an expression, so expressions
+/// starting with "-" (eg "-1d4+3") need no quoting beyond the shell's, and "--" ends the options.
+pub(crate) fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut args = args.iter().cloned();
+    let subcommand = match args.next() {
+        Some(s) => s,
+        None => return Err("No subcommand given.".to_owned()),
+    };
+    if let "help" | "-h" | "--help" = subcommand.as_str() {
+        return Ok(Args {
+            command: Command::Help,
+            format: Format::Text,
+            method: Method::Auto,
+            seed: None,
+        });
+    }
+    if !SUBCOMMANDS.contains(&subcommand.as_str()) {
+        return Err(format!("Unknown subcommand \"{}\".", subcommand));
+    }
+
+    let mut format = Format::Text;
+    let mut times = None;
+    let mut exact = false;
+    let mut samples = None;
+    let mut seed = None;
+    let mut expressions = Vec::new();
+    let mut options_ended = false;
+    while let Some(arg) = args.next() {
+        if options_ended || !arg.starts_with('-') {
+            expressions.push(arg);
+            continue;
+        }
+        let (option, inline) = match arg.split_once('=') {
+            Some((option, value)) => (option.to_owned(), Some(value.to_owned())),
+            None => (arg.clone(), None),
+        };
+        match option.as_str() {
+            "--" => options_ended = true,
+            "-h" | "--help" => {
+                return Ok(Args {
+                    command: Command::Help,
+                    format,
+                    method: Method::Auto,
+                    seed,
+                })
+            }
+            "-f" | "--format" => {
+                format = match inline.or_else(|| args.next()).as_deref() {
+                    Some("text") => Format::Text,
+                    Some("json") => Format::Json,
+                    Some("csv") => Format::Csv,
+                    Some("svg") => Format::Svg,
+                    Some(other) => {
+                        return Err(format!(
+                            "Unknown format \"{}\" (use text, json, csv or svg).",
+                            other
+                        ))
+                    }
+                    None => return Err(format!("{} needs a value.", option)),
+                }
+            }
+            "-n" | "--times" => times = Some(number(&option, inline.or_else(|| args.next()))?),
+            "--exact" if inline.is_none() => exact = true,
+            "--samples" => samples = Some(number(&option, inline.or_else(|| args.next()))?),
+            "--seed" => seed = Some(number(&option, inline.or_else(|| args.next()))?),
+            _ if option.starts_with("--") || inline.is_some() => {
+                return Err(format!("Unknown option \"{}\".", arg))
+            }
+            // Anything else starting with "-" is a negative expression.
+            _ => expressions.push(arg),
+        }
+    }
+
+    let method = match (exact, samples) {
+        (true, Some(_)) => return Err("--exact and --samples cannot be used together.".to_owned()),
+        (true, None) => Method::Exact,
+        (false, Some(0)) => return Err("--samples must be at least 1.".to_owned()),
+        (false, Some(n)) => Method::Samples(n),
+        (false, None) => Method::Auto,
+    };
+
+    let wanted = if subcommand == "compare" { 2 } else { 1 };
+    if expressions.len() != wanted {
+        return Err(format!(
+            "\"{}\" takes {} expression{} (got {}).",
+            subcommand,
+            wanted,
+            if wanted == 1 { "" } else { "s" },
+            expressions.len()
+        ));
+    }
+    let mut expressions = expressions.into_iter();
+    let mut expression = || expressions.next().expect("Checked.");
+
+    if times.is_some() && subcommand != "roll" {
+        return Err("-n only goes with \"roll\".".to_owned());
+    }
+    let distributed = matches!(subcommand.as_str(), "dist" | "stats" | "compare");
+    if method != Method::Auto && !distributed {
+        return Err(format!(
+            "--exact and --samples do not go with \"{}\".",
+            subcommand
+        ));
+    }
+    if format == Format::Svg && !matches!(subcommand.as_str(), "dist" | "compare") {
+        return Err(format!("--format svg does not go with \"{}\".", subcommand));
+    }
+    if seed.is_some() && !distributed && subcommand != "roll" {
+        return Err(format!("--seed does not go with \"{}\".", subcommand));
+    }
+
+    let command = match subcommand.as_str() {
+        "roll" => match times {
+            Some(0) => return Err("-n must be at least 1.".to_owned()),
+            times => Command::Roll {
+                expression: expression(),
+                times: times.unwrap_or(1),
+            },
+        },
+        "dist" => Command::Dist {
+            expression: expression(),
+        },
+        "stats" => Command::Stats {
+            expression: expression(),
+        },
+        "compare" => Command::Compare {
+            first: expression(),
+            second: expression(),
+        },
+        _ => Command::Explain {
+            expression: expression(),
+        },
+    };
+    Ok(Args {
+        command,
+        format,
+        method,
+        seed,
+    })
+}
@@ -0,0 +1,252 @@
+//! `azdice` rolls dice strings and works out their distributions from the command line, eg
+//! `azdice roll 4d6dl1 -n 6` or `azdice compare "1d20+5" "2d10+3" --format json`.
+//!
+//! It exits with 0 on success, 1 if an expression cannot be parsed, 2 if the arguments are
+//! wrong, 3 if a distribution asked for with `--exact` cannot be worked out exactly and 4 if the
+//! output cannot be written.
+extern crate libazdice;
+
+mod args;
+mod output;
+mod tests;
+
+use args::{parse_args, Command, Method};
+use libazdice::distribution::*;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+const USAGE: &str = "\
+Usage: azdice <subcommand> [options]
+
+Subcommands:
+    roll <expr> [-n N] [--seed S]       Roll the dice N times (default 1).
+    dist <expr>                         The probability of each total.
+    stats <expr>                        Mean, standard deviation, min, max, median and mode.
+    compare <expr> <expr>               The chances of the first beating, tying or losing.
+    explain <expr>                      What each group of dice does.
+    help                                Show this message.
+
+Options:
+    -f, --format <text|json|csv|svg>    How to write the results (default text); svg draws
+                                        a chart, for dist and compare only.
+    --exact                             Work distributions out exactly, or fail.
+    --samples <N>                       Find distributions by rolling N times.
+    --seed <S>                          Seed the rolls, to repeat them.
+
+Distributions are worked out exactly where possible, and otherwise sampled with 100000 rolls.
+";
+
+/// The number of rolls used for a distribution which cannot be worked out exactly.
+const DEFAULT_SAMPLES: usize = 100_000;
+
+const EXIT_INVALID: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_NOT_EXACT: i32 = 3;
+const EXIT_IO: i32 = 4;
+
+/// Why a command failed, and the exit code to fail with.
+#[derive(Debug)]
+pub(crate) struct Failure {
+    code: i32,
+    message: String,
+}
+
+impl Failure {
+    fn new(code: i32, message: String) -> Self {
+        Failure { code, message }
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Failure::new(EXIT_IO, format!("Could not write the output: {}", e))
+    }
+}
+
+/// A distribution on the base of 0-100%, and how many rolls it was found from (`None` if it was
+/// worked out exactly).
+#[derive(Debug, Clone)]
+pub(crate) struct Distribution {
+    pub(crate) percentages: BTreeMap<i64, f64>,
+    pub(crate) samples: Option<usize>,
+}
+
+/// The summary statistics of a distribution.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stats {
+    pub(crate) mean: f64,
+    pub(crate) std_dev: f64,
+    pub(crate) min: i64,
+    pub(crate) max: i64,
+    pub(crate) median: i64,
+    pub(crate) mode: i64,
+}
+
+/// The chances (in %) of the first of two bags rolling higher than, the same as and lower than
+/// the second.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Comparison {
+    pub(crate) greater: f64,
+    pub(crate) equal: f64,
+    pub(crate) less: f64,
+    pub(crate) mean_difference: f64,
+}
+
+impl Distribution {
+    /// Find the distribution of `bag` as asked.
+    fn find(bag: &DiceBag, method: Method, rng: &mut SeededRng) -> Result<Self, Failure> {
+        let sample = |n: usize, rng: &mut SeededRng| Distribution {
+            percentages: bag
+                .make_count_distribution_with_rng(n, rng)
+                .into_iter()
+                .map(|(total, count)| (total, count as f64 / n as f64 * 100.0))
+                .collect(),
+            samples: Some(n),
+        };
+        let exact = || {
+            bag.make_exact_distribution()
+                .map(|percentages| Distribution {
+                    percentages,
+                    samples: None,
+                })
+        };
+        match method {
+            Method::Samples(n) => Ok(sample(n, rng)),
+            Method::Exact => exact().map_err(|e| Failure::new(EXIT_NOT_EXACT, e)),
+            Method::Auto => Ok(exact().unwrap_or_else(|_| sample(DEFAULT_SAMPLES, rng))),
+        }
+    }
+
+    /// The totals which can be rolled, with their chances as fractions.
+    fn possible(&self) -> impl Iterator<Item = (i64, f64)> + '_ {
+        self.percentages
+            .iter()
+            .filter(|(_, p)| **p > 0.0)
+            .map(|(total, p)| (*total, p / 100.0))
+    }
+
+    fn mean(&self) -> f64 {
+        self.possible().map(|(total, p)| total as f64 * p).sum()
+    }
+
+    pub(crate) fn stats(&self) -> Stats {
+        let mean = self.mean();
+        let variance = self
+            .possible()
+            .map(|(total, p)| (total as f64 - mean).powi(2) * p)
+            .sum::<f64>();
+        let mut cumulative = 0.0;
+        let median = self
+            .possible()
+            .find(|(_, p)| {
+                cumulative += p;
+                cumulative >= 0.5
+            })
+            .or_else(|| self.possible().last())
+            .map_or(0, |(total, _)| total);
+        // The lowest of the most likely totals.
+        let mode = self
+            .possible()
+            .fold(None, |best: Option<(i64, f64)>, (total, p)| match best {
+                Some((_, q)) if q >= p => best,
+                _ => Some((total, p)),
+            })
+            .map_or(0, |(total, _)| total);
+        Stats {
+            mean,
+            std_dev: variance.sqrt(),
+            min: self.possible().next().map_or(0, |(total, _)| total),
+            max: self.possible().last().map_or(0, |(total, _)| total),
+            median,
+            mode,
+        }
+    }
+
+    pub(crate) fn compare(&self, other: &Distribution) -> Comparison {
+        // The chance of the other bag rolling each total or lower.
+        let mut cumulative = 0.0;
+        let at_or_below = other
+            .possible()
+            .map(|(total, p)| {
+                cumulative += p;
+                (total, cumulative)
+            })
+            .collect::<BTreeMap<_, _>>();
+        let below = |total: i64| at_or_below.range(..total).next_back().map_or(0.0, |c| *c.1);
+
+        let (mut greater, mut equal) = (0.0, 0.0);
+        for (total, p) in self.possible() {
+            let same = other.percentages.get(&total).map_or(0.0, |q| q / 100.0);
+            greater += p * below(total);
+            equal += p * same;
+        }
+        Comparison {
+            greater: greater * 100.0,
+            equal: equal * 100.0,
+            less: (1.0 - greater - equal).max(0.0) * 100.0,
+            mean_difference: self.mean() - other.mean(),
+        }
+    }
+}
+
+fn parse_bag(expression: &str) -> Result<DiceBag, Failure> {
+    expression.parse::<DiceBag>().map_err(|e| {
+        Failure::new(
+            EXIT_INVALID,
+            format!("Invalid expression \"{}\": {}", expression, e),
+        )
+    })
+}
+
+/// Run `azdice` with `args` (without the program name), writing the results to `out` and any
+/// error to `err`, and return the exit code.
+pub(crate) fn run<W: Write, E: Write>(args: &[String], out: &mut W, err: &mut E) -> i32 {
+    let args = match parse_args(args) {
+        Ok(args) => args,
+        Err(e) => {
+            let _ = writeln!(err, "azdice: {}\n\n{}", e, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    let (format, method) = (args.format, args.method);
+    let mut rng = seeded_rng(args.seed.unwrap_or_else(rand::random));
+    let result = match args.command {
+        Command::Help => write!(out, "{}", USAGE).map_err(Failure::from),
+        Command::Roll { expression, times } => parse_bag(&expression).and_then(|bag| {
+            let rolls = bag.rolls_with_rng(&mut rng).take(times).collect::<Vec<_>>();
+            output::write_rolls(out, format, &expression, &rolls)
+        }),
+        Command::Dist { expression } => parse_bag(&expression).and_then(|bag| {
+            let distribution = Distribution::find(&bag, method, &mut rng)?;
+            output::write_distribution(out, format, &expression, &distribution)
+        }),
+        Command::Stats { expression } => parse_bag(&expression).and_then(|bag| {
+            let distribution = Distribution::find(&bag, method, &mut rng)?;
+            output::write_stats(out, format, &expression, &distribution)
+        }),
+        Command::Compare { first, second } => parse_bag(&first).and_then(|first_bag| {
+            let second_bag = parse_bag(&second)?;
+            let a = Distribution::find(&first_bag, method, &mut rng)?;
+            let b = Distribution::find(&second_bag, method, &mut rng)?;
+            output::write_comparison(out, format, [&first, &second], [&a, &b])
+        }),
+        Command::Explain { expression } => parse_bag(&expression)
+            .and_then(|bag| output::write_explanation(out, format, &expression, &bag)),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(failure) => {
+            let _ = writeln!(err, "azdice: {}", failure.message);
+            failure.code
+        }
+    }
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let stdout = io::stdout();
+    let code = run(&args, &mut stdout.lock(), &mut io::stderr());
+    std::process::exit(code);
+}
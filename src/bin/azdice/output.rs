@@ -0,0 +1,328 @@
+//! Writing the results of `azdice` as text, JSON or CSV.
+use crate::args::Format;
+use crate::{Distribution, Failure};
+use libazdice::distribution::*;
+use libazdice::svg::SvgChart;
+use std::io::Write;
+
+/// A string as a JSON string, with quotes.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A string as a CSV field, quoted if it needs to be.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+/// How a distribution was found, from the number of rolls if sampled, eg "exact" or
+/// "100000 samples".
+fn method(samples: Option<usize>) -> String {
+    match samples {
+        None => "exact".to_owned(),
+        Some(n) => format!("{} samples", n),
+    }
+}
+
+/// The number of rolls as a JSON value.
+fn json_samples(samples: Option<usize>) -> String {
+    samples.map_or("null".to_owned(), |n| n.to_string())
+}
+
+/// A chart of `distributions`, named by their expressions and titled with how they were found.
+fn write_chart<W: Write>(
+    out: &mut W,
+    expressions: &[&str],
+    distributions: &[&Distribution],
+) -> Result<(), Failure> {
+    let samples = distributions.iter().find_map(|d| d.samples);
+    let title = format!("{} ({})", expressions.join(" vs "), method(samples));
+    let chart = expressions
+        .iter()
+        .zip(distributions)
+        .fold(SvgChart::new().title(&title), |chart, (e, d)| {
+            chart.frequencies(e, &d.percentages)
+        });
+    chart.write_to(out)?;
+    Ok(())
+}
+
+/// The dice of a rolled group with their sign, eg "-1d4".
+fn signed_dice(group: &DiceResult) -> String {
+    let sign = if group.dice().is_minus_dice() {
+        "-"
+    } else {
+        ""
+    };
+    format!("{}{}", sign, group.dice())
+}
+
+pub(crate) fn write_rolls<W: Write>(
+    out: &mut W,
+    format: Format,
+    expression: &str,
+    rolls: &[RollResults],
+) -> Result<(), Failure> {
+    match format {
+        Format::Text => {
+            for roll in rolls {
+                writeln!(out, "{}", roll)?;
+            }
+        }
+        Format::Json => {
+            let rolls = rolls
+                .iter()
+                .map(|roll| {
+                    let groups = roll
+                        .get_dice_groups()
+                        .iter()
+                        .map(|group| {
+                            let results = group
+                                .results()
+                                .iter()
+                                .map(|r| r.to_string())
+                                .collect::<Vec<_>>();
+                            format!(
+                                "{{\"dice\":{},\"results\":[{}],\"total\":{}}}",
+                                json_string(&signed_dice(group)),
+                                results.join(","),
+                                group.total()
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    format!(
+                        "{{\"groups\":[{}],\"bonus\":{},\"total\":{}}}",
+                        groups.join(","),
+                        roll.get_bonus().total(),
+                        roll.total()
+                    )
+                })
+                .collect::<Vec<_>>();
+            writeln!(
+                out,
+                "{{\"expression\":{},\"rolls\":[{}]}}",
+                json_string(expression),
+                rolls.join(",")
+            )?;
+        }
+        Format::Csv => {
+            writeln!(out, "roll,total,details")?;
+            for (i, roll) in rolls.iter().enumerate() {
+                writeln!(
+                    out,
+                    "{},{},{}",
+                    i + 1,
+                    roll.total(),
+                    csv_field(&roll.to_string())
+                )?;
+            }
+        }
+        Format::Svg => unreachable!("Only \"dist\" and \"compare\" are drawn."),
+    }
+    Ok(())
+}
+
+pub(crate) fn write_distribution<W: Write>(
+    out: &mut W,
+    format: Format,
+    expression: &str,
+    distribution: &Distribution,
+) -> Result<(), Failure> {
+    match format {
+        Format::Text => {
+            writeln!(out, "{} ({})", expression, method(distribution.samples))?;
+            let width = distribution
+                .percentages
+                .keys()
+                .map(|total| total.to_string().len())
+                .max()
+                .unwrap_or(1);
+            for (total, percent) in distribution.percentages.iter() {
+                writeln!(out, "{:>width$} {:>9.4}%", total, percent, width = width)?;
+            }
+        }
+        Format::Json => {
+            let entries = distribution
+                .percentages
+                .iter()
+                .map(|(total, percent)| format!("{{\"total\":{},\"percent\":{}}}", total, percent))
+                .collect::<Vec<_>>();
+            writeln!(
+                out,
+                "{{\"expression\":{},\"method\":{},\"samples\":{},\"distribution\":[{}]}}",
+                json_string(expression),
+                json_string(&method(distribution.samples)),
+                json_samples(distribution.samples),
+                entries.join(",")
+            )?;
+        }
+        Format::Csv => {
+            writeln!(out, "total,percent")?;
+            for (total, percent) in distribution.percentages.iter() {
+                writeln!(out, "{},{}", total, percent)?;
+            }
+        }
+        Format::Svg => write_chart(out, &[expression], &[distribution])?,
+    }
+    Ok(())
+}
+
+pub(crate) fn write_stats<W: Write>(
+    out: &mut W,
+    format: Format,
+    expression: &str,
+    distribution: &Distribution,
+) -> Result<(), Failure> {
+    let stats = distribution.stats();
+    match format {
+        Format::Text => {
+            writeln!(out, "{} ({})", expression, method(distribution.samples))?;
+            writeln!(out, "mean     {:.4}", stats.mean)?;
+            writeln!(out, "std dev  {:.4}", stats.std_dev)?;
+            writeln!(out, "min      {}", stats.min)?;
+            writeln!(out, "max      {}", stats.max)?;
+            writeln!(out, "median   {}", stats.median)?;
+            writeln!(out, "mode     {}", stats.mode)?;
+        }
+        Format::Json => writeln!(
+            out,
+            "{{\"expression\":{},\"method\":{},\"samples\":{},\"mean\":{},\"std_dev\":{},\
+             \"min\":{},\"max\":{},\"median\":{},\"mode\":{}}}",
+            json_string(expression),
+            json_string(&method(distribution.samples)),
+            json_samples(distribution.samples),
+            stats.mean,
+            stats.std_dev,
+            stats.min,
+            stats.max,
+            stats.median,
+            stats.mode
+        )?,
+        Format::Csv => {
+            writeln!(out, "expression,method,mean,std_dev,min,max,median,mode")?;
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{}",
+                csv_field(expression),
+                method(distribution.samples),
+                stats.mean,
+                stats.std_dev,
+                stats.min,
+                stats.max,
+                stats.median,
+                stats.mode
+            )?;
+        }
+        Format::Svg => unreachable!("Only \"dist\" and \"compare\" are drawn."),
+    }
+    Ok(())
+}
+
+pub(crate) fn write_comparison<W: Write>(
+    out: &mut W,
+    format: Format,
+    [first, second]: [&str; 2],
+    [a, b]: [&Distribution; 2],
+) -> Result<(), Failure> {
+    let comparison = a.compare(b);
+    // Sampled if either was sampled; both are sampled the same number of times.
+    let samples = a.samples.or(b.samples);
+    match format {
+        Format::Text => {
+            writeln!(out, "{} vs {} ({})", first, second, method(samples))?;
+            writeln!(out, "first higher     {:>9.4}%", comparison.greater)?;
+            writeln!(out, "same             {:>9.4}%", comparison.equal)?;
+            writeln!(out, "second higher    {:>9.4}%", comparison.less)?;
+            writeln!(out, "mean difference  {:.4}", comparison.mean_difference)?;
+        }
+        Format::Json => writeln!(
+            out,
+            "{{\"first\":{},\"second\":{},\"method\":{},\"samples\":{},\"greater\":{},\
+             \"equal\":{},\"less\":{},\"mean_difference\":{}}}",
+            json_string(first),
+            json_string(second),
+            json_string(&method(samples)),
+            json_samples(samples),
+            comparison.greater,
+            comparison.equal,
+            comparison.less,
+            comparison.mean_difference
+        )?,
+        Format::Csv => {
+            writeln!(
+                out,
+                "first,second,method,greater,equal,less,mean_difference"
+            )?;
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                csv_field(first),
+                csv_field(second),
+                method(samples),
+                comparison.greater,
+                comparison.equal,
+                comparison.less,
+                comparison.mean_difference
+            )?;
+        }
+        Format::Svg => write_chart(out, &[first, second], &[a, b])?,
+    }
+    Ok(())
+}
+
+pub(crate) fn write_explanation<W: Write>(
+    out: &mut W,
+    format: Format,
+    expression: &str,
+    bag: &DiceBag,
+) -> Result<(), Failure> {
+    let normalized = bag.normalize().to_string();
+    let lines = bag.explain();
+    match format {
+        Format::Text => {
+            writeln!(out, "{}", expression)?;
+            writeln!(out, "Canonical form: {}", normalized)?;
+            for line in lines {
+                writeln!(out, "{}", line)?;
+            }
+        }
+        Format::Json => {
+            let lines = lines.iter().map(|l| json_string(l)).collect::<Vec<_>>();
+            writeln!(
+                out,
+                "{{\"expression\":{},\"normalized\":{},\"explanation\":[{}]}}",
+                json_string(expression),
+                json_string(&normalized),
+                lines.join(",")
+            )?;
+        }
+        Format::Csv => {
+            writeln!(out, "kind,text")?;
+            writeln!(out, "expression,{}", csv_field(expression))?;
+            writeln!(out, "normalized,{}", csv_field(&normalized))?;
+            for line in lines {
+                writeln!(out, "explanation,{}", csv_field(&line))?;
+            }
+        }
+        Format::Svg => unreachable!("Only \"dist\" and \"compare\" are drawn."),
+    }
+    Ok(())
+}
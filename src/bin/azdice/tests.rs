@@ -0,0 +1,204 @@
+#![cfg(test)]
+use super::args::*;
+use super::*;
+
+/// Run `azdice` with the space separated `args`, returning the exit code, output and errors.
+fn azdice(args: &[&str]) -> (i32, String, String) {
+    let args = args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    let code = run(&args, &mut out, &mut err);
+    (
+        code,
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+    )
+}
+
+#[test]
+fn test_parse_args() {
+    let args = |a: &[&str]| parse_args(&a.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+
+    let parsed = args(&["roll", "-1d4+3", "-n", "5", "--format=json", "--seed", "7"]).unwrap();
+    assert_eq!(
+        parsed.command,
+        Command::Roll {
+            expression: "-1d4+3".to_owned(),
+            times: 5
+        }
+    );
+    assert_eq!(parsed.format, Format::Json);
+    assert_eq!(parsed.seed, Some(7));
+
+    let parsed = args(&["compare", "1d20", "--samples", "10", "--", "-2d6"]).unwrap();
+    assert_eq!(parsed.method, Method::Samples(10));
+    assert_eq!(
+        parsed.command,
+        Command::Compare {
+            first: "1d20".to_owned(),
+            second: "-2d6".to_owned()
+        }
+    );
+    assert_eq!(
+        args(&["dist", "2d6", "--exact"]).unwrap().method,
+        Method::Exact
+    );
+    assert_eq!(args(&["--help"]).unwrap().command, Command::Help);
+
+    assert_eq!(
+        args(&["compare", "1d20", "2d20dl1", "-f", "svg"])
+            .unwrap()
+            .format,
+        Format::Svg
+    );
+
+    let wrong: [&[&str]; 13] = [
+        &[],
+        &["frob", "1d6"],
+        &["roll"],
+        &["roll", "1d6", "1d8"],
+        &["roll", "1d6", "-n", "0"],
+        &["roll", "1d6", "-n", "x"],
+        &["dist", "1d6", "-n", "2"],
+        &["dist", "1d6", "--exact", "--samples", "10"],
+        &["explain", "1d6", "--exact"],
+        &["stats", "1d6", "--format", "xml"],
+        &["stats", "1d6", "--verbose"],
+        &["stats", "1d6", "-f", "svg"],
+        &["roll", "1d6", "--format=svg"],
+    ];
+    for a in wrong.iter() {
+        assert!(args(a).is_err(), "{:?}", a);
+    }
+}
+
+#[test]
+fn test_exit_codes() {
+    assert_eq!(azdice(&["roll", "2d6"]).0, 0);
+    assert_eq!(azdice(&["help"]).0, 0);
+
+    let (code, out, err) = azdice(&["roll", "2d0"]);
+    assert_eq!(code, EXIT_INVALID);
+    assert!(out.is_empty());
+    assert!(err.contains("2d0"));
+
+    assert_eq!(azdice(&["compare", "1d6", "4d6dl5"]).0, EXIT_INVALID);
+    assert_eq!(azdice(&["roll", "2d6", "--bogus"]).0, EXIT_USAGE);
+    assert_eq!(
+        azdice(&["dist", "1000d1000000", "--exact"]).0,
+        EXIT_NOT_EXACT
+    );
+    assert_eq!(azdice(&["dist", "4d6dl1!", "--exact"]).0, EXIT_NOT_EXACT);
+    // Without --exact, the distribution is sampled instead.
+    let (code, out, _) = azdice(&["stats", "4d6dl1!", "--samples", "1000"]);
+    assert_eq!(code, 0);
+    assert!(out.starts_with("4d6dl1! (1000 samples)"));
+}
+
+#[test]
+fn test_seeded_rolls() {
+    let first = azdice(&["roll", "4d6dl1 + 2", "-n", "20", "--seed", "42"]);
+    assert_eq!(
+        first,
+        azdice(&["roll", "4d6dl1 + 2", "-n", "20", "--seed", "42"])
+    );
+    assert_eq!(first.1.lines().count(), 20);
+
+    let (_, csv, _) = azdice(&["roll", "3d6", "-n", "4", "--seed", "1", "-f", "csv"]);
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "roll,total,details");
+    assert_eq!(lines.len(), 5);
+    for line in &lines[1..] {
+        let total = line.split(',').nth(1).unwrap().parse::<i64>().unwrap();
+        assert!((3..=18).contains(&total));
+    }
+
+    let (_, json, _) = azdice(&["roll", "2d6 - 1d4 + 3", "--seed", "3", "--format", "json"]);
+    assert!(json.starts_with(r#"{"expression":"2d6 - 1d4 + 3","rolls":[{"groups":[{"dice":"2d6","#));
+    assert!(json.contains(r#"{"dice":"-1d4","results":["#));
+    assert!(json.contains(r#""bonus":3,"#));
+}
+
+#[test]
+fn test_distributions() {
+    let (_, csv, _) = azdice(&["dist", "2d6", "-f", "csv"]);
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "total,percent");
+    assert_eq!(lines.len(), 12);
+    let seven = lines[6].split(',').collect::<Vec<_>>();
+    assert_eq!(seven[0], "7");
+    assert!((seven[1].parse::<f64>().unwrap() - 100.0 / 6.0).abs() < 1e-9);
+
+    let (_, json, _) = azdice(&["dist", "1d4", "--format", "json"]);
+    assert_eq!(
+        json.trim(),
+        r#"{"expression":"1d4","method":"exact","samples":null,"distribution":[{"total":1,"percent":25},{"total":2,"percent":25},{"total":3,"percent":25},{"total":4,"percent":25}]}"#
+    );
+
+    let (code, svg, _) = azdice(&["compare", "1d4", "1d6", "-f", "svg"]);
+    assert_eq!(code, 0);
+    assert!(svg.starts_with("<svg "));
+    assert!(svg.contains("<title>1d4 vs 1d6 (exact)</title>"));
+    assert_eq!(svg.matches("class=\"pmf\"").count(), 4 + 6);
+    let (_, svg, _) = azdice(&[
+        "dist",
+        "3d6",
+        "-f",
+        "svg",
+        "--samples",
+        "100",
+        "--seed",
+        "1",
+    ]);
+    assert!(svg.contains("<title>3d6 (100 samples)</title>"));
+
+    let (_, text, _) = azdice(&["stats", "2d6"]);
+    assert_eq!(
+        text,
+        "2d6 (exact)\nmean     7.0000\nstd dev  2.4152\nmin      2\nmax      12\nmedian   7\nmode     7\n"
+    );
+
+    // Each bag is as likely to beat the other as to lose.
+    let (_, text, _) = azdice(&["compare", "1d20", "1d20"]);
+    assert!(text.contains("first higher       47.5000%"));
+    assert!(text.contains("same                5.0000%"));
+    assert!(text.contains("second higher      47.5000%"));
+    let (_, csv, _) = azdice(&["compare", "1d20+5", "2d10+3", "-f", "csv"]);
+    let row = csv.lines().nth(1).unwrap().split(',').collect::<Vec<_>>();
+    assert_eq!(row[..3], ["1d20+5", "2d10+3", "exact"]);
+    for (value, expected) in row[3..].iter().zip([55.0, 4.95, 40.05, 1.5].iter()) {
+        assert!((value.parse::<f64>().unwrap() - expected).abs() < 1e-9);
+    }
+
+    // A range wider than the rolls keeps only the totals rolled.
+    let (code, csv, _) = azdice(&["dist", "1d1000000000", "-f", "csv", "--seed", "1"]);
+    assert_eq!(code, 0);
+    assert!(csv.lines().count() <= DEFAULT_SAMPLES + 1);
+    let (code, text, _) = azdice(&["stats", "1d1000000000", "--samples", "1000"]);
+    assert_eq!(code, 0);
+    assert!(
+        text.starts_with("1d1000000000 (1000 samples)\n"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn test_explain() {
+    let (_, text, _) = azdice(&["explain", "2d6+3d6-1"]);
+    assert_eq!(
+        text.lines().collect::<Vec<_>>(),
+        [
+            "2d6+3d6-1",
+            "Canonical form: 5d6 - 1",
+            "2d6: roll 2 dice with 6 sides, add the total (2 to 12)",
+            "3d6: roll 3 dice with 6 sides, add the total (3 to 18)",
+            "-1: subtract 1",
+            "Total: 4 to 29",
+        ]
+    );
+    let (_, json, _) = azdice(&["explain", "1d8", "-f", "json"]);
+    assert_eq!(
+        json.trim(),
+        r#"{"expression":"1d8","normalized":"1d8","explanation":["1d8: roll 1 die with 8 sides, add the total (1 to 8)","Total: 1 to 8"]}"#
+    );
+}
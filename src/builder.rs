@@ -0,0 +1,195 @@
+//! This module contains `DiceBagBuilder`, a chainable way of making a `DiceBag` in code which
+//! checks the dice in the same way as the parser does.
+use super::distribution::*;
+use super::parse::{fill_dice, ModifierGroup};
+
+/// A dice group waiting to be built, with the modifier clauses added to it so far.
+#[derive(Debug, Clone)]
+struct PendingGroup {
+    group: DiceGroup,
+    mods: Vec<ModifierGroup>,
+    explosive: bool,
+}
+
+/// A chainable builder for a `DiceBag`, made by `DiceBag::builder`.
+///
+/// Groups are added with `dice`, `minus_dice`, `plus` and `minus`. Modifiers (such as
+/// `drop_lowest` or `minimum`) apply to the dice group added last. Nothing is checked until
+/// `build`, which returns the same errors for impossible or conflicting clauses as the parser does
+/// for the equivalent dice string.
+/// ```
+/// use libazdice::distribution::*;
+///
+/// // The same as "4d6dl1+3".
+/// let bag: DiceBag = DiceBag::builder().dice(4, 6).drop_lowest(1).plus(3).build().unwrap();
+/// assert_eq!(bag, libazdice::parse("4d6dl1+3".to_owned()).unwrap());
+///
+/// // Clauses which would overwrite each other are an error, as they are for the parser.
+/// assert!(DiceBag::builder().dice(4, 6).drop_lowest(1).keep_highest(2).build().is_err());
+/// assert!(DiceBag::builder().dice(4, 6).drop_lowest(4).build().is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DiceBagBuilder {
+    groups: Vec<PendingGroup>,
+    error: Option<DiceError>,
+    limits: Limits,
+}
+
+impl DiceBag {
+    /// Start building a `DiceBag` in code. See `DiceBagBuilder`.
+    pub fn builder() -> DiceBagBuilder {
+        DiceBagBuilder::default()
+    }
+}
+
+impl DiceBagBuilder {
+    /// Add a group of `count` dice with `size` sides (eg `dice(8, 6)` for "+8d6").
+    pub fn dice(self, count: usize, size: i64) -> Self {
+        self.push(DiceGroup::dice(size, count))
+    }
+
+    /// Add a group of `count` dice with `size` sides to be subtracted (eg "-8d6").
+    pub fn minus_dice(self, count: usize, size: i64) -> Self {
+        let mut group = DiceGroup::dice(size, count);
+        group.add_op(DiceOp::Sub);
+        self.push(group)
+    }
+
+    /// Add a bonus of `n` (eg "+3").
+    pub fn plus(self, n: u32) -> Self {
+        self.push(Bonus::plus(n).into())
+    }
+
+    /// Add a penalty of `n` (eg "-3").
+    pub fn minus(self, n: u32) -> Self {
+        self.push(Bonus::minus(n).into())
+    }
+
+    /// Drop the `n` lowest dice of the last group ("dl").
+    pub fn drop_lowest(self, n: usize) -> Self {
+        self.modify("dl", ModifierGroup::DropLowest(n))
+    }
+
+    /// Keep the `n` highest dice of the last group ("kh").
+    pub fn keep_highest(self, n: usize) -> Self {
+        self.modify("kh", ModifierGroup::KeepHighest(n))
+    }
+
+    /// Drop the `n` highest dice of the last group ("dh").
+    pub fn drop_highest(self, n: usize) -> Self {
+        self.modify("dh", ModifierGroup::DropHighest(n))
+    }
+
+    /// Keep the `n` lowest dice of the last group ("kl").
+    pub fn keep_lowest(self, n: usize) -> Self {
+        self.modify("kl", ModifierGroup::KeepLowest(n))
+    }
+
+    /// Reroll up to `count` dice of the last group which roll above `threshold` ("rrNabM").
+    pub fn reroll_above(self, threshold: i64, count: usize) -> Self {
+        self.modify("rr", ModifierGroup::ReRollCount(count))
+            .modify("ab", ModifierGroup::ReRollAbove(threshold))
+    }
+
+    /// Reroll up to `count` dice of the last group which roll below `threshold` ("rrNbeM").
+    pub fn reroll_below(self, threshold: i64, count: usize) -> Self {
+        self.modify("rr", ModifierGroup::ReRollCount(count))
+            .modify("be", ModifierGroup::ReRollBelow(threshold))
+    }
+
+    /// Make every die of the last group roll at least `min` ("mn").
+    pub fn minimum(self, min: i64) -> Self {
+        self.modify("mn", ModifierGroup::CutOffMinimum(min))
+    }
+
+    /// Make every die of the last group roll at most `max` ("mx").
+    pub fn maximum(self, max: i64) -> Self {
+        self.modify("mx", ModifierGroup::CutOffMaximum(max))
+    }
+
+    /// Label the last group, dice or bonus (eg "[fire]"). The label is checked by `build`.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag = DiceBag::builder().dice(2, 6).label("fire").plus(3).build().unwrap();
+    /// assert_eq!(bag.to_string(), "2d6 [fire] + 3");
+    /// assert!(DiceBag::builder().dice(2, 6).label("[fire]").build().is_err());
+    /// ```
+    pub fn label(mut self, label: &str) -> Self {
+        match self.groups.last_mut() {
+            Some(pending) => pending.group.set_label(Some(label.to_owned())),
+            None => self.fail("label"),
+        }
+        self
+    }
+
+    /// Check the bag against `limits` rather than the default `Limits`.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Make the dice of the last group explode ("!").
+    pub fn explode(mut self) -> Self {
+        match self.groups.last_mut() {
+            Some(PendingGroup {
+                group: DiceGroup::Dice(_),
+                ref mut explosive,
+                ..
+            }) => *explosive = true,
+            _ => self.fail("!"),
+        }
+        self
+    }
+
+    /// Check every group and make the `DiceBag`. Returns the first error found, which is the same
+    /// `DiceError` that `DiceBag::validate` would give.
+    pub fn build(self) -> Result<DiceBag, DiceError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mut groups = Vec::with_capacity(self.groups.len());
+        for pending in self.groups {
+            let PendingGroup {
+                mut group,
+                mods,
+                explosive,
+            } = pending;
+            if let DiceGroup::Dice(ref mut dice) = group {
+                fill_dice(mods, dice)?;
+            }
+            group.is_explosive(explosive);
+            groups.push(group);
+        }
+        DiceBag::try_from_dice_with_limits(groups, self.limits)
+    }
+
+    fn push(mut self, group: DiceGroup) -> Self {
+        self.groups.push(PendingGroup {
+            group,
+            mods: Vec::new(),
+            explosive: false,
+        });
+        self
+    }
+
+    /// Add a modifier clause to the last group, which must be dice.
+    fn modify(mut self, name: &'static str, modifier: ModifierGroup) -> Self {
+        match self.groups.last_mut() {
+            Some(PendingGroup {
+                group: DiceGroup::Dice(_),
+                ref mut mods,
+                ..
+            }) => mods.push(modifier),
+            _ => self.fail(name),
+        }
+        self
+    }
+
+    /// Record that a modifier was added without dice to modify. Only the first error is kept.
+    fn fail(&mut self, name: &'static str) {
+        if self.error.is_none() {
+            self.error = Some(DiceError::ModifierWithoutDice(name));
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! This module contains `DistributionCache`, a bounded store of distributions keyed by the
+//! canonical form of a `DiceBag` (see `DiceBag::normalize`), so that the distributions of popular
+//! dice strings are only made once.
+use super::distribution::*;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// The first line of an exported cache.
+const HEADER: &str = "libazdice-distribution-cache 1";
+
+/// What a distribution is stored under: the canonical form of the bag, the explosion depth it was
+/// rolled with, and the number of rolls.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    bag: String,
+    explosion_depth: usize,
+    rolls: usize,
+}
+
+impl CacheKey {
+    fn new(bag: &DiceBag, rolls: usize) -> Self {
+        CacheKey {
+            bag: bag.normalize().to_string(),
+            explosion_depth: bag.limits().max_explosion_depth,
+            rolls,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    counts: BTreeMap<i64, usize>,
+    last_used: u64,
+}
+
+/// A cache of count distributions (as made by `DiceBag::make_count_distribution`), holding at
+/// most `capacity` distributions. When full, the distribution used least recently is dropped.
+///
+/// Bags which are equivalent (see `DiceBag::is_equivalent`) share an entry, so "2d6+3d6" gets
+/// exactly the same distribution back as "5d6" once either has been made. Distributions made with
+/// different numbers of rolls are kept apart.
+/// ```
+/// use libazdice::cache::DistributionCache;
+/// use libazdice::distribution::*;
+///
+/// let mut cache = DistributionCache::new(100);
+/// let bag: DiceBag = libazdice::parse("2d6+3d6".to_owned()).unwrap();
+/// let first = cache.frequency_distribution(&bag, 100_000);
+///
+/// let same: DiceBag = libazdice::parse("5d6".to_owned()).unwrap();
+/// assert_eq!(cache.frequency_distribution(&same, 100_000), first);
+/// assert_eq!(cache.len(), 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DistributionCache {
+    capacity: usize,
+    entries: HashMap<CacheKey, CacheEntry>,
+    clock: u64,
+}
+
+impl DistributionCache {
+    /// Create an empty cache which holds at most `capacity` distributions.
+    pub fn new(capacity: usize) -> Self {
+        DistributionCache {
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// The most distributions the cache holds.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of distributions in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Empty the cache.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Whether a distribution of `roll_count` rolls of `bag` (or an equivalent bag) is cached.
+    pub fn contains(&self, bag: &DiceBag, roll_count: usize) -> bool {
+        self.entries.contains_key(&CacheKey::new(bag, roll_count))
+    }
+
+    /// Get the distribution by count of `roll_count` rolls of `bag`, making it if it is not cached.
+    pub fn count_distribution(&mut self, bag: &DiceBag, roll_count: usize) -> BTreeMap<i64, usize> {
+        let key = CacheKey::new(bag, roll_count);
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.last_used = self.clock;
+            return entry.counts.clone();
+        }
+
+        let counts = bag.normalize().make_count_distribution(roll_count);
+        self.insert(key, counts.clone());
+        counts
+    }
+
+    /// Get the distribution of `roll_count` rolls of `bag` on the base of 0-100%, as
+    /// `DiceBag::make_frequency_distribution`, making it if it is not cached.
+    pub fn frequency_distribution(
+        &mut self,
+        bag: &DiceBag,
+        roll_count: usize,
+    ) -> BTreeMap<i64, f64> {
+        self.count_distribution(bag, roll_count)
+            .into_iter()
+            .map(|(i, c)| (i, c as f64 / roll_count as f64 * 100.0))
+            .collect()
+    }
+
+    /// Make and cache the distributions of `roll_count` rolls of each of `bags`, ahead of time.
+    /// ```
+    /// use libazdice::cache::DistributionCache;
+    /// use libazdice::distribution::*;
+    ///
+    /// let popular = ["1d20", "4d6dl1", "8d6"]
+    ///     .iter()
+    ///     .map(|s| s.parse::<DiceBag>())
+    ///     .collect::<Result<Vec<_>, _>>()
+    ///     .unwrap();
+    ///
+    /// let mut cache = DistributionCache::new(10);
+    /// cache.prewarm(&popular, 10_000);
+    /// assert_eq!(cache.len(), 3);
+    /// assert!(cache.contains(&popular[1], 10_000));
+    /// assert!(!cache.contains(&popular[1], 20_000));
+    /// ```
+    pub fn prewarm(&mut self, bags: &[DiceBag], roll_count: usize) {
+        for bag in bags {
+            self.count_distribution(bag, roll_count);
+        }
+    }
+
+    /// Add an entry, dropping the least recently used one if the cache is full.
+    fn insert(&mut self, key: CacheKey, counts: BTreeMap<i64, usize>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        let last_used = self.clock;
+        self.entries.insert(key, CacheEntry { counts, last_used });
+    }
+
+    /// Write the cache to a file, which `load` can read back. See `write_to`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Add the distributions in a file written by `save` to the cache, returning how many were
+    /// read. See `read_from`.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> io::Result<usize> {
+        self.read_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Write the cache as text: a header line, then one line per distribution of the number of
+    /// rolls, the explosion depth, the canonical dice string and the counts, separated by tabs
+    /// (eg "1000\t100\t1d4\t1:250,2:260,3:240,4:250"). The least recently used come first.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|(_, entry)| entry.last_used);
+        for (key, entry) in entries {
+            let counts = entry
+                .counts
+                .iter()
+                .map(|(value, count)| format!("{}:{}", value, count))
+                .collect::<Vec<_>>()
+                .join(",");
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                key.rolls, key.explosion_depth, key.bag, counts
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Read distributions written by `write_to` into the cache, returning how many were read.
+    /// The dice strings are parsed again, so a cache file cannot hold dice which could not be
+    /// parsed. If the file holds more distributions than fit, the last ones read are kept. If any
+    /// line cannot be read, the cache is left as it was.
+    pub fn read_from<R: BufRead>(&mut self, reader: R) -> io::Result<usize> {
+        let invalid = |line: usize, message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Cache line {}: {}", line + 1, message),
+            )
+        };
+
+        let mut lines = reader.lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(invalid(0, "not a distribution cache."));
+        }
+
+        let mut read = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let (rolls, depth, bag, counts) = match fields.as_slice() {
+                [rolls, depth, bag, counts] => (rolls, depth, bag, counts),
+                _ => return Err(invalid(i + 1, "wrong number of fields.")),
+            };
+
+            let rolls = rolls
+                .parse::<usize>()
+                .map_err(|e| invalid(i + 1, &e.to_string()))?;
+            let explosion_depth = depth
+                .parse::<usize>()
+                .map_err(|e| invalid(i + 1, &e.to_string()))?;
+            let bag = bag
+                .parse::<DiceBag>()
+                .map_err(|e| invalid(i + 1, &e))?
+                .normalize()
+                .to_string();
+
+            let mut parsed = BTreeMap::new();
+            for pair in counts.split(',').filter(|p| !p.is_empty()) {
+                let (value, count) = pair
+                    .split_once(':')
+                    .ok_or_else(|| invalid(i + 1, "count without a value."))?;
+                let value = value
+                    .parse::<i64>()
+                    .map_err(|e| invalid(i + 1, &e.to_string()))?;
+                let count = count
+                    .parse::<usize>()
+                    .map_err(|e| invalid(i + 1, &e.to_string()))?;
+                parsed.insert(value, count);
+            }
+            let total = parsed
+                .values()
+                .try_fold(0usize, |total, count| total.checked_add(*count))
+                .ok_or_else(|| invalid(i + 1, "counts are too large."))?;
+            if total != rolls {
+                return Err(invalid(i + 1, "counts do not add up to the rolls."));
+            }
+
+            let key = CacheKey {
+                bag,
+                explosion_depth,
+                rolls,
+            };
+            read.push((key, parsed));
+        }
+
+        let count = read.len();
+        for (key, counts) in read {
+            self.insert(key, counts);
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,464 @@
+//! This module contains `Chart`, which draws distributions (as made by eg
+//! `DiceBag::make_frequency_distribution`) as bar charts of text, for the terminal.
+//!
+//! Several distributions can be drawn side by side on the same scale, to compare `DiceBag`s.
+//! ```
+//! use libazdice::chart::*;
+//! use libazdice::distribution::*;
+//!
+//! let attack: DiceBag = "1d4+1".parse().unwrap();
+//! let damage: DiceBag = "2d2".parse().unwrap();
+//! let chart = Chart::new()
+//!     .frequencies("1d4+1", &attack.make_exact_distribution().unwrap())
+//!     .frequencies("2d2", &damage.make_exact_distribution().unwrap())
+//!     .width(10)
+//!     .charset(Charset::Ascii);
+//!
+//! let expected = [
+//!     "  | 1d4+1             | 2d2",
+//!     "2 | #####      25.00% | =====      25.00%",
+//!     "3 | #####      25.00% | ========== 50.00%",
+//!     "4 | #####      25.00% | =====      25.00%",
+//!     "5 | #####      25.00% |             0.00%",
+//! ];
+//! assert_eq!(chart.to_string(), expected.join("\n") + "\n");
+//! ```
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+
+/// Which way the bars go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    /// A row per total, with bars going right.
+    Horizontal,
+    /// A column per total, with bars going up.
+    Vertical,
+}
+
+/// What the bars (and their labels) show.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Labels {
+    /// The percentage of each total, labelled.
+    Percentage,
+    /// The values of the distribution as given (eg the counts of a count distribution), labelled.
+    Count,
+    /// The percentage of each total, unlabelled.
+    None,
+}
+
+/// The characters the chart is drawn with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Charset {
+    /// Plain ASCII, for any terminal.
+    Ascii,
+    /// Box drawing and block characters, with bars drawn to an eighth of a character.
+    Unicode,
+}
+
+impl Charset {
+    /// The character of full parts of the bars of the `i`th distribution.
+    fn glyph(self, i: usize) -> char {
+        match self {
+            Charset::Ascii => ['#', '=', '*', '+'][i % 4],
+            Charset::Unicode => ['█', '▓', '▒', '░'][i % 4],
+        }
+    }
+
+    /// The characters of the axes: the line along the bars, the line across them and where they
+    /// meet.
+    fn axes(self) -> (char, char, char) {
+        match self {
+            Charset::Ascii => ('|', '-', '+'),
+            Charset::Unicode => ('│', '─', '┼'),
+        }
+    }
+}
+
+/// A distribution to draw.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Series {
+    pub(crate) name: String,
+    values: BTreeMap<i64, f64>,
+    /// Whether the values are counts, so are labelled as whole numbers.
+    counts: bool,
+}
+
+impl Series {
+    pub(crate) fn frequencies(name: &str, distribution: &BTreeMap<i64, f64>) -> Self {
+        Series {
+            name: name.to_owned(),
+            values: distribution.clone(),
+            counts: false,
+        }
+    }
+
+    pub(crate) fn counts(name: &str, distribution: &BTreeMap<i64, usize>) -> Self {
+        Series {
+            name: name.to_owned(),
+            values: distribution.iter().map(|(k, v)| (*k, *v as f64)).collect(),
+            counts: true,
+        }
+    }
+
+    /// The values at each of `totals`, as percentages of all the values if `percentage`, and
+    /// added up from the lowest total if `cumulative`.
+    pub(crate) fn values_at(&self, totals: &[i64], percentage: bool, cumulative: bool) -> Vec<f64> {
+        let sum = self.values.values().sum::<f64>();
+        let mut running = 0.0;
+        totals
+            .iter()
+            .map(|t| {
+                let mut value = self.values.get(t).copied().unwrap_or(0.0);
+                if percentage {
+                    value = if sum > 0.0 { value / sum * 100.0 } else { 0.0 };
+                }
+                if cumulative {
+                    running += value;
+                    running
+                } else {
+                    value
+                }
+            })
+            .collect()
+    }
+}
+
+/// Every total of any of the distributions, in order.
+pub(crate) fn all_totals(series: &[Series]) -> Vec<i64> {
+    series
+        .iter()
+        .flat_map(|s| s.values.keys().cloned())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// A bar chart of one or more distributions. The settings are chained, as with
+/// `DiceBagBuilder`, and the chart is drawn with `render` (or `to_string`).
+///
+/// With more than one distribution, horizontal charts put the distributions side by side and
+/// vertical charts put their bars next to each other, in different characters. All the bars are
+/// drawn on the same scale.
+/// ```
+/// use libazdice::chart::*;
+/// use std::collections::BTreeMap;
+///
+/// let counts: BTreeMap<i64, usize> = vec![(1, 2), (2, 6), (3, 4)].into_iter().collect();
+/// let chart = Chart::new()
+///     .counts("", &counts)
+///     .orientation(Orientation::Vertical)
+///     .height(3)
+///     .labels(Labels::Count)
+///     .charset(Charset::Ascii);
+/// assert_eq!(
+///     chart.render(),
+///     "\
+/// 6 |  #
+///   |  ##
+///   | ###
+/// 0 +----
+///     123
+/// "
+/// );
+///
+/// // The chance of rolling each total or lower.
+/// let cumulative = chart.clone().cumulative(true).orientation(Orientation::Horizontal).width(6);
+/// assert_eq!(
+///     cumulative.render(),
+///     "\
+/// 1 | #       2
+/// 2 | ####    8
+/// 3 | ###### 12
+/// "
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chart {
+    series: Vec<Series>,
+    orientation: Orientation,
+    width: usize,
+    height: usize,
+    labels: Labels,
+    cumulative: bool,
+    charset: Charset,
+}
+
+impl Default for Chart {
+    fn default() -> Self {
+        Chart::new()
+    }
+}
+
+impl Chart {
+    /// An empty horizontal chart, with bars of up to 50 characters (or 15 rows if vertical),
+    /// percentage labels and Unicode characters.
+    pub fn new() -> Self {
+        Chart {
+            series: Vec::new(),
+            orientation: Orientation::Horizontal,
+            width: 50,
+            height: 15,
+            labels: Labels::Percentage,
+            cumulative: false,
+            charset: Charset::Unicode,
+        }
+    }
+
+    /// Add a distribution on the base of 0-100% (eg from `DiceBag::make_frequency_distribution`
+    /// or `DiceBag::make_exact_distribution`), called `name`.
+    pub fn frequencies(mut self, name: &str, distribution: &BTreeMap<i64, f64>) -> Self {
+        self.series.push(Series::frequencies(name, distribution));
+        self
+    }
+
+    /// Add a distribution by count (eg from `DiceBag::make_count_distribution` or
+    /// `Histogram::counts`), called `name`.
+    pub fn counts(mut self, name: &str, distribution: &BTreeMap<i64, usize>) -> Self {
+        self.series.push(Series::counts(name, distribution));
+        self
+    }
+
+    /// Draw the bars horizontally or vertically.
+    pub fn orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    /// The length in characters of the longest bar of a horizontal chart.
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// The height in rows of the tallest bar of a vertical chart.
+    pub fn height(mut self, height: usize) -> Self {
+        self.height = height;
+        self
+    }
+
+    /// What the bars show, and how they are labelled.
+    pub fn labels(mut self, labels: Labels) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Whether each bar shows the chance of a total or lower, rather than of a total.
+    pub fn cumulative(mut self, cumulative: bool) -> Self {
+        self.cumulative = cumulative;
+        self
+    }
+
+    /// The characters to draw with.
+    pub fn charset(mut self, charset: Charset) -> Self {
+        self.charset = charset;
+        self
+    }
+
+    /// What the bars of each distribution show at each of `totals`.
+    fn heights(&self, totals: &[i64]) -> Vec<Vec<f64>> {
+        let percentage = self.labels != Labels::Count;
+        self.series
+            .iter()
+            .map(|s| s.values_at(totals, percentage, self.cumulative))
+            .collect()
+    }
+
+    /// A label for what a bar shows.
+    fn label(&self, value: f64, counts: bool) -> String {
+        match self.labels {
+            Labels::Count if counts => format!("{:.0}", value),
+            Labels::Count => format!("{:.2}", value),
+            _ => format!("{:.2}%", value),
+        }
+    }
+
+    /// The number of eighths of a character a bar of `value` fills, out of `length`.
+    fn eighths(value: f64, scale: f64, length: usize) -> usize {
+        if scale > 0.0 {
+            (value / scale * length as f64 * 8.0).round() as usize
+        } else {
+            0
+        }
+    }
+
+    /// Draw the chart. An empty chart draws nothing.
+    pub fn render(&self) -> String {
+        if self.series.is_empty() {
+            return String::new();
+        }
+        let totals = all_totals(&self.series);
+        let heights = self.heights(&totals);
+        let scale = heights.iter().flatten().cloned().fold(0.0_f64, f64::max);
+        match self.orientation {
+            Orientation::Horizontal => self.render_horizontal(&totals, &heights, scale),
+            Orientation::Vertical => self.render_vertical(&totals, &heights, scale),
+        }
+    }
+
+    fn render_horizontal(&self, totals: &[i64], heights: &[Vec<f64>], scale: f64) -> String {
+        let (line, _, _) = self.charset.axes();
+        let total_width = totals
+            .iter()
+            .map(|t| t.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let labels = heights
+            .iter()
+            .zip(self.series.iter())
+            .map(|(h, s)| {
+                if self.labels == Labels::None {
+                    Vec::new()
+                } else {
+                    h.iter().map(|v| self.label(*v, s.counts)).collect()
+                }
+            })
+            .collect::<Vec<Vec<_>>>();
+        let label_widths = labels
+            .iter()
+            .map(|l| l.iter().map(|l| l.len()).max().map_or(0, |w| w + 1))
+            .collect::<Vec<_>>();
+
+        let mut chart = String::new();
+        if self.series.len() > 1 || !self.series[0].name.is_empty() {
+            chart.push_str(&" ".repeat(total_width));
+            for (s, label_width) in self.series.iter().zip(label_widths.iter()) {
+                let cell = format!(" {} {:<w$}", line, s.name, w = self.width + label_width);
+                chart.push_str(&cell);
+            }
+            chart = chart.trim_end().to_owned();
+            chart.push('\n');
+        }
+
+        for (i, total) in totals.iter().enumerate() {
+            let mut row = format!("{:>w$}", total, w = total_width);
+            for (j, h) in heights.iter().enumerate() {
+                let bar = self.horizontal_bar(h[i], scale, j);
+                row.push_str(&format!(" {} {:<w$}", line, bar, w = self.width));
+                if let Some(label) = labels[j].get(i) {
+                    row.push_str(&format!(" {:>w$}", label, w = label_widths[j] - 1));
+                }
+            }
+            chart.push_str(row.trim_end());
+            chart.push('\n');
+        }
+        chart
+    }
+
+    /// A bar going right for the `series`th distribution.
+    fn horizontal_bar(&self, value: f64, scale: f64, series: usize) -> String {
+        let glyph = self.charset.glyph(series);
+        let eighths = Chart::eighths(value, scale, self.width);
+        if glyph != '█' {
+            return glyph.to_string().repeat((eighths + 4) / 8);
+        }
+        let mut bar = glyph.to_string().repeat(eighths / 8);
+        let part = eighths % 8;
+        if part > 0 {
+            bar.push(['▏', '▎', '▍', '▌', '▋', '▊', '▉'][part - 1]);
+        }
+        bar
+    }
+
+    /// The character of a vertical bar of `eighths` in the `row`th row from the bottom.
+    fn vertical_cell(&self, eighths: usize, row: usize, series: usize) -> char {
+        let glyph = self.charset.glyph(series);
+        let filled = eighths.saturating_sub(row * 8).min(8);
+        if filled == 8 {
+            glyph
+        } else if filled == 0 {
+            ' '
+        } else if glyph == '█' {
+            ['▁', '▂', '▃', '▄', '▅', '▆', '▇'][filled - 1]
+        } else if filled >= 4 {
+            glyph
+        } else {
+            ' '
+        }
+    }
+
+    fn render_vertical(&self, totals: &[i64], heights: &[Vec<f64>], scale: f64) -> String {
+        let (line, across, corner) = self.charset.axes();
+        let n = self.series.len();
+        // Each total gets a column as wide as its label, with a bar for each distribution.
+        let total_labels = totals.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let group_width = total_labels
+            .iter()
+            .map(|l| l.len())
+            .max()
+            .unwrap_or(1)
+            .max(n);
+        let bar_width = group_width / n;
+        let gap = if group_width > 1 { " " } else { "" };
+
+        let counts = self.series.iter().all(|s| s.counts);
+        let (top, bottom) = match self.labels {
+            Labels::None => (String::new(), String::new()),
+            _ => (self.label(scale, counts), self.label(0.0, counts)),
+        };
+        let axis_width = top.len().max(bottom.len());
+
+        let mut chart = String::new();
+        if n > 1 || !self.series[0].name.is_empty() {
+            let legend = self
+                .series
+                .iter()
+                .enumerate()
+                .map(|(i, s)| format!("{} {}", self.charset.glyph(i), s.name))
+                .collect::<Vec<_>>();
+            chart.push_str(legend.join("   ").trim_end());
+            chart.push('\n');
+        }
+
+        let eighths = heights
+            .iter()
+            .map(|h| {
+                h.iter()
+                    .map(|v| Chart::eighths(*v, scale, self.height))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        for row in (0..self.height).rev() {
+            let axis = if row + 1 == self.height { &top } else { "" };
+            let mut text = format!("{:>w$} {} ", axis, line, w = axis_width);
+            for i in 0..totals.len() {
+                if i > 0 {
+                    text.push_str(gap);
+                }
+                text.push_str(&" ".repeat(group_width - bar_width * n));
+                for (j, e) in eighths.iter().enumerate() {
+                    let cell = self.vertical_cell(e[i], row, j);
+                    text.extend(std::iter::repeat_n(cell, bar_width));
+                }
+            }
+            chart.push_str(text.trim_end());
+            chart.push('\n');
+        }
+
+        let columns = totals.len() * group_width + totals.len().saturating_sub(1) * gap.len();
+        chart.push_str(&format!(
+            "{:>w$} {}{}\n",
+            bottom,
+            corner,
+            across.to_string().repeat(columns + 1),
+            w = axis_width
+        ));
+        let labels = total_labels
+            .iter()
+            .map(|l| format!("{:>w$}", l, w = group_width))
+            .collect::<Vec<_>>();
+        chart.push_str(&format!(
+            "{:w$}   {}\n",
+            "",
+            labels.join(gap),
+            w = axis_width
+        ));
+        chart
+    }
+}
+
+impl Display for Chart {
+    /// The same as `Chart::render`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render())
+    }
+}
@@ -5,15 +5,48 @@
 //! necessarily need to parse dice strings.
 #![allow(dead_code)]
 extern crate rand;
-use crate::distribution::rand::Rng;
+extern crate rand_chacha;
+use crate::distribution::rand::rngs::ThreadRng;
+use crate::distribution::rand::{Rng, SeedableRng};
 use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::nested::Nested;
+use crate::pick::Pick;
+pub use crate::validation::{DiceError, Limits};
+
+/// The random number generator used for seeded, reproducible rolls. A given seed produces the
+/// same sequence of rolls on every machine.
+pub type SeededRng = rand_chacha::ChaCha20Rng;
+
+/// Create a `SeededRng` from a 64-bit seed.
+/// ```
+/// use libazdice::distribution::*;
+///
+/// let bag: DiceBag = DiceBag::from_dice(vec![Dice::with_size_and_count(20, 3).into()]);
+///
+/// let mut rng_1 = seeded_rng(42);
+/// let mut rng_2 = seeded_rng(42);
+/// for _ in 0..1_000 {
+///     assert_eq!(bag.roll_with_rng(&mut rng_1), bag.roll_with_rng(&mut rng_2));
+/// }
+/// ```
+pub fn seeded_rng(seed: u64) -> SeededRng {
+    SeededRng::seed_from_u64(seed)
+}
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 /// A range.
 pub(crate) struct MinMax(pub [i64; 2]);
 
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 /// Represents the interactions of a Dicegroup with other groups.
 pub(crate) enum DiceOp {
     Add,
@@ -33,6 +66,13 @@ impl DiceOp {
             // DiceOp::Div => acc / x,
         }
     }
+    /// As `operate`, but saturating at the ends of `i64`.
+    pub(crate) fn saturating_operate(self, acc: i64, x: i64) -> i64 {
+        match self {
+            DiceOp::Add => acc.saturating_add(x),
+            DiceOp::Sub => acc.saturating_sub(x),
+        }
+    }
 }
 
 /// An enum representing the drop clause on a set of dice duch as the "dl4" on "6d6dl4".
@@ -56,20 +96,41 @@ pub(crate) enum CutOff {
     Both(MinMax),
 }
 
-impl CutOff {
-    /// Compare a rolled value to a cutoff and modify if appropriate.
-    fn use_to_cut_off(&self, val: &mut i64) {
+impl Drop {
+    /// Remove the dropped dice from `answer`, leaving the kept dice (in sorted order if any are
+    /// dropped).
+    pub(crate) fn apply(&self, answer: &mut Vec<i64>) {
         match self {
-            CutOff::Minimum(n) => {
-                if *val < *n {
-                    *val = *n;
-                }
+            // On drop lowest, drop the lowest N dice. Custom sorting is needed.
+            Drop::Lowest(n) => {
+                answer.sort_by(|n1, n2| n2.cmp(n1));
+                answer.truncate(answer.len().saturating_sub(*n));
             }
-            CutOff::Maximum(n) => {
-                if *val > *n {
-                    *val = *n;
+            // On highest, drop the highest N dice.
+            Drop::Highest(n) => {
+                answer.sort();
+                answer.truncate(answer.len().saturating_sub(*n));
+            }
+            // On custom, keep the selected dice. The indices are ascending, so this can be done
+            // in place.
+            Drop::Custom(v) => {
+                answer.sort();
+                for (k, i) in v.iter().enumerate() {
+                    answer[k] = answer[*i];
                 }
+                answer.truncate(v.len());
             }
+            _ => {}
+        }
+    }
+}
+
+impl CutOff {
+    /// Compare a rolled value to a cutoff and modify if appropriate.
+    pub(crate) fn use_to_cut_off(&self, val: &mut i64) {
+        match self {
+            CutOff::Minimum(n) if *val < *n => *val = *n,
+            CutOff::Maximum(n) if *val > *n => *val = *n,
             CutOff::Both(MinMax([mn, mx])) => {
                 if *val > *mx {
                     *val = *mx;
@@ -162,6 +223,12 @@ pub struct Dice {
     pub(crate) cutoff: CutOff,
     pub(crate) op: DiceOp,
     pub(crate) explosive: bool,
+    // Dice rolled for the count or size, eg "(1d4)d6". See the `nested` module.
+    pub(crate) nested: Option<Box<Nested>>,
+    // Bags of which the highest or lowest total is kept, eg "max(1d8!, 1d6!)". See the `pick`
+    // module.
+    pub(crate) pick: Option<Box<Pick>>,
+    pub(crate) label: Option<String>,
 }
 
 impl Dice {
@@ -180,6 +247,9 @@ impl Dice {
             cutoff: CutOff::Non,
             op: DiceOp::Add,
             explosive: false,
+            nested: None,
+            pick: None,
+            label: None,
         }
     }
 
@@ -211,22 +281,22 @@ impl Dice {
             cutoff: CutOff::Non,
             op: DiceOp::Add,
             explosive: false,
+            nested: None,
+            pick: None,
+            label: None,
         }
     }
 
-    pub(crate) fn add_drop(&mut self, k: Drop) {
-        self.drop = k;
-    }
-
     /// Rerolls up to `count` dice (non-recursively) if the result is above `threshold`.
-    /// NB, adding more die to reroll than the `DiceGroup` contains does not cause an error.
+    /// NB, rerolling more dice than the `DiceGroup` contains, a threshold which no die can roll
+    /// above, or adding a second reroll clause will result in an error.
     /// ```
     /// use libazdice::distribution::*;
     ///
     /// // Start with 1d6.
     /// let mut one_d_six: Dice = Dice::with_size_and_count(6, 1);
     /// // Convert to 1d6rr1ab5
-    /// one_d_six.add_reroll_if_above(5,1);
+    /// one_d_six.add_reroll_if_above(5,1).unwrap();
     ///
     /// let one_d_six: DiceGroup = one_d_six.into();
     ///
@@ -240,19 +310,20 @@ impl Dice {
     /// // The mean of a 1d6rr1ab5 should be about 3.08. (The mean of 1d6 is 3.5).
     /// assert!((mean > 3.0) && (mean < 3.15))
     /// ```
-    pub fn add_reroll_if_above(&mut self, threshold: i64, count: usize) {
-        self.reroll = ReRoll::if_above(threshold, count);
+    pub fn add_reroll_if_above(&mut self, threshold: i64, count: usize) -> Result<(), DiceError> {
+        self.set_reroll(ReRoll::if_above(threshold, count))
     }
 
     /// Rerolls up to `count` dice (non-recursively) if the result is below `threshold`.
-    /// NB, adding more die to reroll than the `DiceGroup` contains does not cause an error.
+    /// NB, rerolling more dice than the `DiceGroup` contains, a threshold which no die can roll
+    /// below, or adding a second reroll clause will result in an error.
     /// ```
     /// use libazdice::distribution::*;
     ///
     /// // Start with 1d6.
     /// let mut one_d_six: Dice = Dice::with_size_and_count(6, 1);
     /// // Convert to 1d6rr1be2
-    /// one_d_six.add_reroll_if_below(2,1);
+    /// one_d_six.add_reroll_if_below(2,1).unwrap();
     ///
     /// let one_d_six: DiceGroup = one_d_six.into();
     ///
@@ -266,17 +337,31 @@ impl Dice {
     /// // The mean of a 1d6rr1be2 should be about 3.92. (The mean of 1d6 is 3.5).
     /// assert!((mean > 3.85) && (mean < 4.0))
     /// ```
-    pub fn add_reroll_if_below(&mut self, threshold: i64, count: usize) {
-        self.reroll = ReRoll::if_below(threshold, count);
+    pub fn add_reroll_if_below(&mut self, threshold: i64, count: usize) -> Result<(), DiceError> {
+        self.set_reroll(ReRoll::if_below(threshold, count))
+    }
+
+    /// Check and set the reroll clause. There can only be one.
+    fn set_reroll(&mut self, reroll: ReRoll) -> Result<(), DiceError> {
+        if self.reroll != ReRoll::Never {
+            return Err(DiceError::MultipleClauses("rr"));
+        }
+        self.check_reroll(&reroll)?;
+        self.reroll = reroll;
+        Ok(())
     }
 
-    /// Add a prechecked cutoff.
-    pub(crate) fn add_checked_cutoff(&mut self, cutoff: CutOff) {
+    /// Check and set the cutoff clause.
+    fn set_cutoff(&mut self, cutoff: CutOff) -> Result<(), DiceError> {
+        self.check_cutoff(&cutoff)?;
         self.cutoff = cutoff;
+        Ok(())
     }
 
     /// Adds a minimum roll, thus if this is set to 3, any roll of a single die under 3 is set to 3.
-    /// NB, this function returns an error if the minimum is bigger than the maximumpossible roll.
+    /// If a maximum roll has already been set, both are kept.
+    /// NB, this function returns an error if the minimum is not between 2 and the size of the dice,
+    /// is above the maximum, or if a minimum has already been set.
     /// ```
     /// use libazdice::distribution::*;
     ///
@@ -300,16 +385,19 @@ impl Dice {
     ///     assert!(*distribution.get(&i).unwrap() > 0);
     /// }
     /// ```
-    pub fn with_minimum_roll(&mut self, min: i64) -> Result<(), String> {
-        if min > self.size {
-            return Err("Minimum cutoff is bigger than dice sidedness!".to_owned());
-        }
-        self.cutoff = CutOff::Minimum(min);
-        Ok(())
+    pub fn with_minimum_roll(&mut self, min: i64) -> Result<(), DiceError> {
+        let cutoff = match self.cutoff {
+            CutOff::Non => CutOff::Minimum(min),
+            CutOff::Maximum(max) => CutOff::Both(MinMax([min, max])),
+            _ => return Err(DiceError::MultipleClauses("mn")),
+        };
+        self.set_cutoff(cutoff)
     }
 
     /// Adds a maximum roll, thus if this is set to 4, any roll of a single die over 4 is set to 4.
-    /// Returns an error if the maximum is less than one.
+    /// If a minimum roll has already been set, both are kept.
+    /// Returns an error if the maximum is less than one, is not below the size of the dice, is
+    /// below the minimum, or if a maximum has already been set.
     /// ```
     /// use libazdice::distribution::*;
     ///
@@ -333,16 +421,19 @@ impl Dice {
     ///     assert!(*distribution.get(&i).unwrap() > 0);
     /// }
     /// ```
-    pub fn with_maximum_roll(&mut self, max: i64) -> Result<(), String> {
-        if max < 1 {
-            return Err("Maximum cutoff is less than one!".to_owned());
-        }
-        self.cutoff = CutOff::Maximum(max);
-        Ok(())
+    pub fn with_maximum_roll(&mut self, max: i64) -> Result<(), DiceError> {
+        let cutoff = match self.cutoff {
+            CutOff::Non => CutOff::Maximum(max),
+            CutOff::Minimum(min) => CutOff::Both(MinMax([min, max])),
+            _ => return Err(DiceError::MultipleClauses("mx")),
+        };
+        self.set_cutoff(cutoff)
     }
 
     /// Add a maximum and minimum cutoff value to the dice roll. For example if one sets a min of 2
     /// and a max of 5 for a d6 it essentially becomes a 1d4+1.
+    /// Returns an error if either would be an error for `with_minimum_roll` or `with_maximum_roll`,
+    /// or if any cutoff has already been set.
     /// ```
     /// use libazdice::distribution::*;
     ///
@@ -366,15 +457,11 @@ impl Dice {
     ///     assert!(*distribution.get(&i).unwrap() > 0);
     /// }
     /// ```
-    pub fn with_min_and_max_roll(&mut self, min: i64, max: i64) -> Result<(), String> {
-        if max < 1 {
-            return Err("Maximum cutoff is less than one!".to_owned());
-        } else if min > self.size {
-            return Err("Minimum cutoff is bigger than dice sidedness!".to_owned());
+    pub fn with_min_and_max_roll(&mut self, min: i64, max: i64) -> Result<(), DiceError> {
+        if self.cutoff != CutOff::Non {
+            return Err(DiceError::MultipleClauses("mn/mx"));
         }
-
-        self.cutoff = CutOff::Both(MinMax([min, max]));
-        Ok(())
+        self.set_cutoff(CutOff::Both(MinMax([min, max])))
     }
 
     pub(crate) fn add_op(&mut self, op: DiceOp) {
@@ -414,9 +501,15 @@ impl Dice {
         self.op = DiceOp::Sub;
     }
 
+    /// Whether the dice are subtracted from the total (eg `-8d6`).
+    pub fn is_minus_dice(&self) -> bool {
+        self.op == DiceOp::Sub
+    }
+
     /// A function to allow one to set how many of the lowest dice rolls in the group to
-    /// be dropped. (Eg "5d6dl2")
-    /// NB: Trying to add more dice to drop than the dicegroup contains will result in an error.
+    /// be dropped. (Eg "5d6dl2"). If the highest dice are already being dropped, both are kept.
+    /// NB: Trying to drop as many dice as the dicegroup contains (or more), or adding a second
+    /// drop-lowest clause will result in an error.
     /// ```
     /// use libazdice::distribution::*;
     ///
@@ -440,19 +533,44 @@ impl Dice {
     /// // The mean of a 4d6l1 will be ~12.245. This is above 3d6 (10.5) and below 4d6 (14).
     /// assert!((mean > 12.2) && (mean < 12.3));
     /// ```
-    pub fn with_drop_lowest(&mut self, n: usize) -> Result<(), String> {
-        if self.count < n {
-            return Err(
-                "Trying to make a dicegroup which drops more dice than it has.".to_string(),
-            );
-        }
-        self.drop = Drop::Lowest(n);
+    pub fn with_drop_lowest(&mut self, n: usize) -> Result<(), DiceError> {
+        let drop = match self.drop {
+            Drop::Non => Drop::Lowest(n),
+            Drop::Highest(h) => self.drop_lowest_and_highest(n, h)?,
+            _ => return Err(DiceError::MultipleClauses("dl/kh")),
+        };
+        self.check_drop(&drop)?;
+        self.drop = drop;
         Ok(())
     }
 
+    /// Keep the `n` highest dice rolls in the group (Eg "5d6kh3"). This is the same as dropping
+    /// the rest of the lowest, so the errors are those of `with_drop_lowest`, or an error if `n`
+    /// is not less than the number of dice.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let mut five_d_six: Dice = Dice::with_size_and_count(6, 5);
+    /// five_d_six.with_keep_highest(3).unwrap();
+    ///
+    /// let mut other_five_d_six: Dice = Dice::with_size_and_count(6, 5);
+    /// other_five_d_six.with_drop_lowest(2).unwrap();
+    /// assert_eq!(five_d_six, other_five_d_six);
+    /// ```
+    pub fn with_keep_highest(&mut self, n: usize) -> Result<(), DiceError> {
+        if n >= self.count {
+            return Err(DiceError::KeepingTooMany {
+                keeping: n,
+                count: self.count,
+            });
+        }
+        self.with_drop_lowest(self.count - n)
+    }
+
     /// A function to allow one to set how many of the highest dice rolls in the group to
-    /// be dropped. (Eg "5d6dh2")
-    /// NB: Trying to add more dice to drop than the dicegroup contains will result in an error.
+    /// be dropped. (Eg "5d6dh2"). If the lowest dice are already being dropped, both are kept.
+    /// NB: Trying to drop as many dice as the dicegroup contains (or more), or adding a second
+    /// drop-highest clause will result in an error.
     /// ```
     /// use libazdice::distribution::*;
     ///
@@ -476,31 +594,152 @@ impl Dice {
     /// // The mean of a 4d6l1 will be (~8.875).  Lower than 3d6 (10.5).
     /// assert!((mean > 8.7) && (mean < 8.8));
     /// ```
-    pub fn with_drop_highest(&mut self, n: usize) -> Result<(), String> {
-        if self.count < n {
-            return Err(
-                "Trying to make a dicegroup which drops more dice than it has.".to_string(),
-            );
-        }
-        self.drop = Drop::Highest(n);
+    pub fn with_drop_highest(&mut self, n: usize) -> Result<(), DiceError> {
+        let drop = match self.drop {
+            Drop::Non => Drop::Highest(n),
+            Drop::Lowest(l) => self.drop_lowest_and_highest(l, n)?,
+            _ => return Err(DiceError::MultipleClauses("dh/kl")),
+        };
+        self.check_drop(&drop)?;
+        self.drop = drop;
         Ok(())
     }
 
+    /// Keep the `n` lowest dice rolls in the group (Eg "5d6kl3"). This is the same as dropping
+    /// the rest of the highest, so the errors are those of `with_drop_highest`, or an error if `n`
+    /// is not less than the number of dice.
+    pub fn with_keep_lowest(&mut self, n: usize) -> Result<(), DiceError> {
+        if n >= self.count {
+            return Err(DiceError::KeepingTooMany {
+                keeping: n,
+                count: self.count,
+            });
+        }
+        self.with_drop_highest(self.count - n)
+    }
+
     /// A function to allow one to set how many of the lowest and highest rolls to discard.
     /// (Eg "5d6dl2dh1").
-    /// NB: If the count of highest and lowest to drop is greater than the total count of dice in
-    /// the dice group, an error will be returned.
-    pub fn with_drop_highest_and_lowest(&mut self, n_l: usize, n_h: usize) -> Result<(), String> {
-        if self.count < n_l + n_h {
-            return Err(
-                "Trying to make a dicegroup which drops more dice than it has.".to_string(),
-            );
+    /// NB: If the count of highest and lowest to drop is not less than the total count of dice in
+    /// the dice group, or if a drop clause has already been set, an error will be returned.
+    pub fn with_drop_highest_and_lowest(
+        &mut self,
+        n_l: usize,
+        n_h: usize,
+    ) -> Result<(), DiceError> {
+        if self.drop != Drop::Non {
+            return Err(DiceError::MultipleClauses("dl/dh"));
         }
-        let keep_vector = (n_l..(self.count - n_h)).collect::<Vec<_>>();
-        self.drop = Drop::Custom(keep_vector);
+        self.drop = self.drop_lowest_and_highest(n_l, n_h)?;
         Ok(())
     }
 
+    /// Roll the dice and put the results which are kept into `answer`, clearing it first.
+    /// This deals with explosions (at most `explosion_depth` per die), rerolls, cutoffs and drops,
+    /// in that order.
+    fn roll_into<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        answer: &mut Vec<i64>,
+        explosion_depth: usize,
+    ) {
+        let Dice {
+            size,
+            count,
+            ref drop,
+            ref reroll,
+            ref cutoff,
+            op: _,
+            explosive,
+            nested: _,
+            pick: _,
+            label: _,
+        } = *self;
+
+        // Roll all the dice.
+        answer.clear();
+        for _ in 0..count {
+            if !explosive {
+                answer.push(rng.gen_range(1, size + 1));
+            } else {
+                explode(answer, size, explosion_depth, rng);
+            }
+        }
+
+        // Deal with the reroll clause.
+        let mut reroll_count = 0;
+        let mut answer_cycler = answer.iter_mut();
+        match reroll {
+            ReRoll::IfAbove(ReRollType {
+                count,
+                ex_threshold,
+            }) => {
+                while let Some(ref mut roll) = answer_cycler.next() {
+                    // Checked first, so that "rr0" rerolls nothing.
+                    if reroll_count == *count {
+                        break;
+                    }
+                    if **roll > *ex_threshold {
+                        **roll = rng.gen_range(1, size + 1);
+                        reroll_count += 1;
+                    }
+                }
+            }
+            ReRoll::IfBelow(ReRollType {
+                count,
+                ex_threshold,
+            }) => {
+                while let Some(ref mut roll) = answer_cycler.next() {
+                    if reroll_count == *count {
+                        break;
+                    }
+                    if **roll < *ex_threshold {
+                        **roll = rng.gen_range(1, size + 1);
+                        reroll_count += 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        // Deal with the min-max clause:
+        for val in answer.iter_mut() {
+            cutoff.use_to_cut_off(val);
+        }
+
+        // Decide what to Drop.
+        drop.apply(answer);
+    }
+
+    /// The lowest and highest value a single die can show once the cutoff is applied.
+    fn face_range(&self) -> (i64, i64) {
+        let mut lowest = 1;
+        let mut highest = self.size;
+        self.cutoff.use_to_cut_off(&mut lowest);
+        self.cutoff.use_to_cut_off(&mut highest);
+        (lowest.min(highest), lowest.max(highest))
+    }
+
+    /// The fewest and most dice that can be kept after the drop clause. `None` means there is no
+    /// limit, as exploding dice can keep adding to the pool. If `explode` is false, explosions are
+    /// ignored.
+    fn kept_range(&self, explode: bool) -> (usize, Option<usize>) {
+        let pool_max = if explode && self.explosive {
+            None
+        } else {
+            Some(self.count)
+        };
+        match self.drop {
+            Drop::Non => (self.count, pool_max),
+            Drop::Lowest(n) | Drop::Highest(n) => (
+                self.count.saturating_sub(n),
+                pool_max.map(|p| p.saturating_sub(n)),
+            ),
+            // A fixed set of positions is kept, however many dice are rolled.
+            Drop::Custom(ref v) => (v.len(), Some(v.len())),
+        }
+    }
+
     fn get_true_count(&self) -> usize {
         let sub = match self.drop {
             Drop::Lowest(n) => n,
@@ -514,9 +753,19 @@ impl Dice {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
 pub struct Bonus {
     pub(crate) bonus: i64,
     pub(crate) op: DiceOp,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) label: Option<String>,
 }
 
 impl Bonus {
@@ -530,6 +779,7 @@ impl Bonus {
         Bonus {
             bonus: n,
             op: DiceOp::Add,
+            label: None,
         }
     }
 
@@ -546,6 +796,7 @@ impl Bonus {
         Bonus {
             bonus: n as i64,
             op: DiceOp::Add,
+            label: None,
         }
     }
 
@@ -562,6 +813,7 @@ impl Bonus {
         Bonus {
             bonus: n as i64,
             op: DiceOp::Sub,
+            label: None,
         }
     }
 
@@ -570,6 +822,7 @@ impl Bonus {
         Bonus {
             bonus: 0,
             op: DiceOp::Add,
+            label: None,
         }
     }
 
@@ -579,6 +832,11 @@ impl Bonus {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum DiceGroup {
     Bonus(Bonus),
     Dice(Dice),
@@ -626,75 +884,75 @@ impl DiceGroup {
         }
     }
 
-    /// Calculate the minmax for a single dice set.
-    pub(crate) fn calculate_minmax(&self) -> MinMax {
-        match *self {
-            DiceGroup::Bonus(ref n) => MinMax([n.bonus, n.bonus]),
-            DiceGroup::Dice(ref d) => {
-                let drop = match d.drop {
-                    Drop::Highest(n) | Drop::Lowest(n) => n,
-                    Drop::Custom(ref v) => v.len(),
-                    _ => 0,
-                };
-                if drop >= d.count {
-                    return MinMax([0, 0]);
-                }
-                let f = (d.count - drop) as i64;
-                let min_0 = f;
-                let max_0 = d.size * f;
+    /// The operation with which the group joins the rest of the bag.
+    pub(crate) fn op(&self) -> DiceOp {
+        match self {
+            DiceGroup::Dice(d) => d.op,
+            DiceGroup::Bonus(b) => b.op,
+        }
+    }
 
-                if min_0 > max_0 {
-                    MinMax([max_0, min_0])
-                } else {
-                    MinMax([min_0, max_0])
-                }
-            }
+    /// The label of the group, eg "fire" for the "2d6" of "1d20 + 2d6 [fire]".
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("1d8+3 [Slashing] + 2d6 [fire]".to_owned()).unwrap();
+    /// let labels: Vec<_> = bag.groups().iter().map(DiceGroup::label).collect();
+    /// assert_eq!(labels, [Some("Slashing"), Some("Slashing"), Some("fire")]);
+    /// ```
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            DiceGroup::Dice(d) => d.label.as_deref(),
+            DiceGroup::Bonus(b) => b.label.as_deref(),
         }
     }
 
-    /// Takes a minmax and performs an operation on a minmax.
-    pub(crate) fn add_to_range(&self, min_max: &mut [i64; 2]) {
+    /// Set (or remove) the label of the group.
+    pub(crate) fn set_label(&mut self, label: Option<String>) {
         match self {
-            DiceGroup::Bonus(n) => {
-                let min_0 = n.op.operate(min_max[0], n.bonus);
-                let max_0 = n.op.operate(min_max[1], n.bonus);
-
-                if min_0 > max_0 {
-                    // min is max.
-                    min_max[0] = max_0;
-                    min_max[1] = min_0;
-                } else {
-                    // max is max.
-                    min_max[0] = min_0;
-                    min_max[1] = max_0;
-                }
-            }
-            DiceGroup::Dice(ref d) => {
-                let true_count = d.get_true_count();
+            DiceGroup::Dice(ref mut d) => d.label = label,
+            DiceGroup::Bonus(ref mut b) => b.label = label,
+        }
+    }
 
-                let (s_min, s_max) = match d.cutoff {
-                    CutOff::Non => (1, d.size),
-                    CutOff::Maximum(n) => (1, n),
-                    CutOff::Minimum(n) => (n, d.size),
-                    CutOff::Both(MinMax([mi, ma])) => (mi, ma),
+    /// Calculate the lowest and highest amount that a single group can add to the total of a bag,
+    /// taking account of the cutoffs, drops and operation of the group.
+    /// `None` means that the group is unbounded in that direction, as a group of exploding dice
+    /// can always roll more dice. If `explode` is false, explosions are ignored.
+    pub(crate) fn calculate_bounds(&self, explode: bool) -> [Option<i64>; 2] {
+        let (low, high) = match *self {
+            DiceGroup::Bonus(ref b) => (Some(b.bonus), Some(b.bonus)),
+            DiceGroup::Dice(ref d) if d.pick.is_some() => d.pick_bounds(explode),
+            DiceGroup::Dice(ref d) if d.nested.is_some() => d.nested_bounds(explode),
+            DiceGroup::Dice(ref d) => {
+                let (face_min, face_max) = d.face_range();
+                let (kept_min, kept_max) = d.kept_range(explode);
+                let low = if face_min >= 0 {
+                    Some(face_min.saturating_mul(kept_min as i64))
+                } else {
+                    kept_max.map(|k| face_min.saturating_mul(k as i64))
                 };
-
-                let min_0 = d.op.operate(min_max[0], s_min * true_count as i64);
-                let max_0 = d.op.operate(min_max[1], s_max * true_count as i64);
-
-                if min_0 > max_0 {
-                    // min is max.
-                    min_max[0] = max_0;
-                    min_max[1] = min_0;
+                let high = if face_max >= 0 {
+                    kept_max.map(|k| face_max.saturating_mul(k as i64))
                 } else {
-                    // max is max.
-                    min_max[0] = min_0;
-                    min_max[1] = max_0;
-                }
+                    Some(face_max.saturating_mul(kept_min as i64))
+                };
+                (low, high)
             }
+        };
+
+        match self.op() {
+            DiceOp::Add => [low, high],
+            DiceOp::Sub => [high.map(i64::saturating_neg), low.map(i64::saturating_neg)],
         }
     }
 
+    /// Calculate the minmax for a single dice set. Unbounded ends are `i64::MIN` or `i64::MAX`.
+    pub(crate) fn calculate_minmax(&self) -> MinMax {
+        let [low, high] = self.calculate_bounds(true);
+        MinMax([low.unwrap_or(i64::MIN), high.unwrap_or(i64::MAX)])
+    }
+
     /// Add whether explosive or not.
     pub(crate) fn is_explosive(&mut self, x: bool) {
         if let DiceGroup::Dice(ref mut d) = self {
@@ -704,19 +962,50 @@ impl DiceGroup {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
 /// A `DiceResult` is a collection of individual dice results from a `DiceGroup`, of the dice type,
 ///as well as the total and the accompanying dice.
 pub struct DiceResult {
-    dice: Dice,
-    results: Vec<i64>,
-    total: i64,
+    pub(crate) dice: Dice,
+    pub(crate) results: Vec<i64>,
+    pub(crate) total: i64,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) count_roll: Option<Box<RollResults>>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub(crate) size_roll: Option<Box<RollResults>>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub(crate) pick_rolls: Vec<RollResults>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
 /// A `BonusResult` is a collection of all the static modifiers (boni) in a dice bag and their total.
 pub struct BonusResult {
-    boni: Vec<i64>,
-    total: i64,
+    pub(crate) boni: Vec<i64>,
+    pub(crate) total: i64,
+    /// The boni with labels, with their labels and operations applied.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub(crate) labelled: Vec<(String, i64)>,
 }
 
 impl BonusResult {
@@ -725,6 +1014,7 @@ impl BonusResult {
         BonusResult {
             boni: Vec::new(),
             total: 0,
+            labelled: Vec::new(),
         }
     }
 
@@ -743,6 +1033,9 @@ impl DiceResult {
             dice,
             results,
             total,
+            count_roll: None,
+            size_roll: None,
+            pick_rolls: Vec::new(),
         }
     }
 
@@ -750,13 +1043,80 @@ impl DiceResult {
     pub fn total(&self) -> i64 {
         self.total
     }
+
+    /// Gets the dice which were rolled.
+    pub fn dice(&self) -> &Dice {
+        &self.dice
+    }
+
+    /// Gets the label of the dice, eg "fire" for "2d6 [fire]".
+    pub fn label(&self) -> Option<&str> {
+        self.dice.label.as_deref()
+    }
+
+    /// Gets the values of the dice which were kept (after rerolls, cutoffs and drops), before the
+    /// operation of the dice is applied.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("4d6dl1 - 2d4".to_owned()).unwrap();
+    /// let roll = bag.roll();
+    /// let groups = roll.get_dice_groups();
+    /// assert_eq!(groups[0].results().len(), 3);
+    /// assert_eq!(groups[1].dice().to_string(), "2d4");
+    /// assert_eq!(groups[1].total(), -groups[1].results().iter().sum::<i64>());
+    /// ```
+    pub fn results(&self) -> &[i64] {
+        &self.results
+    }
+
+    /// Gets the roll which gave the number of dice, if it was rolled (eg the "(1d4)" of
+    /// "(1d4)d6"). The number of dice rolled is its total.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("(1d4)d6 + 2d8".to_owned()).unwrap();
+    /// let roll = bag.roll();
+    /// let groups = roll.get_dice_groups();
+    /// let count = groups[0].count_roll().unwrap().total();
+    /// assert!((1..=4).contains(&count));
+    /// assert_eq!(groups[0].results().len() as i64, count);
+    /// assert!(groups[1].count_roll().is_none());
+    /// ```
+    pub fn count_roll(&self) -> Option<&RollResults> {
+        self.count_roll.as_deref()
+    }
+
+    /// Gets the roll which gave the number of sides of the dice, if it was rolled (eg the
+    /// "(1d4+4)" of "2d(1d4+4)").
+    pub fn size_roll(&self) -> Option<&RollResults> {
+        self.size_roll.as_deref()
+    }
+
+    /// Gets the rolls of the bags of a group which keeps the highest or lowest of them (eg the
+    /// "1d8!" and "1d6!" of "max(1d8!, 1d6!)"), which is empty for other dice. The kept total is
+    /// the only result.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("max(1d8!, 1d6!) + 1d4".to_owned()).unwrap();
+    /// let roll = bag.roll();
+    /// let groups = roll.get_dice_groups();
+    /// let totals: Vec<i64> = groups[0].pick_rolls().iter().map(|r| r.total()).collect();
+    /// assert_eq!(totals.len(), 2);
+    /// assert_eq!(groups[0].results(), [*totals.iter().max().unwrap()]);
+    /// assert!(groups[1].pick_rolls().is_empty());
+    /// ```
+    pub fn pick_rolls(&self) -> &[RollResults] {
+        &self.pick_rolls
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct RollResults {
-    dice_groups: Vec<DiceResult>,
-    bonus: BonusResult,
-    total: i64,
+    pub(crate) dice_groups: Vec<DiceResult>,
+    pub(crate) bonus: BonusResult,
+    pub(crate) total: i64,
 }
 
 impl RollResults {
@@ -777,11 +1137,14 @@ impl RollResults {
 
     /// Add to bonus. NB: The +/- from `DiceOp` is calculated in the function.
     fn add_to_bonus(&mut self, b: &Bonus) {
-        let Bonus { bonus, op } = b;
-        let sub_total = op.operate(self.bonus.total, *bonus);
+        let Bonus { bonus, op, label } = b;
+        let sub_total = op.operate(0, *bonus);
         self.bonus.total += sub_total;
         self.total += sub_total;
         self.bonus.boni.push(*bonus);
+        if let Some(label) = label {
+            self.bonus.labelled.push((label.clone(), sub_total));
+        }
     }
 
     /// An instance of `RollResults` is a fairly comprehensive report, internally. This function
@@ -848,14 +1211,31 @@ impl RollResults {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+/// Reusable space for the individual dice of a roll, used by `DiceBag::roll_total_with_rng` to
+/// avoid allocating on every roll.
+pub struct RollBuffer {
+    rolls: Vec<i64>,
+}
+
+impl RollBuffer {
+    /// Create an empty buffer. It grows to fit the largest dice group rolled with it.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct DiceBag {
     pub(crate) dice: Vec<DiceGroup>,
     pub(crate) range: MinMax,
+    pub(crate) limits: Limits,
+    pub(crate) comment: Option<String>,
 }
 
 impl DiceBag {
     /// Create a distribution for a dice set from a vector of 'DiceGroup's.
+    /// NB: The dice are not checked, so for dice from untrusted input use `try_from_dice`.
     /// ```
     /// use libazdice::distribution::*;
     ///
@@ -875,6 +1255,8 @@ impl DiceBag {
         let mut dist = DiceBag {
             dice,
             range: MinMax([0, 0]),
+            limits: Limits::default(),
+            comment: None,
         };
         dist.calculate_range();
         dist
@@ -882,11 +1264,20 @@ impl DiceBag {
 
     /// Calculates a range for a distribution.
     pub(crate) fn calculate_range(&mut self) {
-        let range = self.dice.iter().fold([0; 2], |mut acc, x| {
-            x.add_to_range(&mut acc);
-            acc
-        });
-        self.range = MinMax(range);
+        let [low, high] = self.calculate_bounds(true);
+        self.range = MinMax([low.unwrap_or(i64::MIN), high.unwrap_or(i64::MAX)]);
+    }
+
+    /// The lowest and highest possible totals, with `None` for an unbounded end.
+    /// If `explode` is false, explosions are ignored.
+    pub(crate) fn calculate_bounds(&self, explode: bool) -> [Option<i64>; 2] {
+        self.dice.iter().fold([Some(0); 2], |acc, x| {
+            let [low, high] = x.calculate_bounds(explode);
+            [
+                acc[0].and_then(|a| low.map(|l| a.saturating_add(l))),
+                acc[1].and_then(|a| high.map(|h| a.saturating_add(h))),
+            ]
+        })
     }
 
     /// Roll the dicebag and obtains a value for each dice rolled and a resulting total.
@@ -909,114 +1300,148 @@ impl DiceBag {
     /// assert!(total_1 != total_2);
     /// ```
     pub fn roll(&self) -> RollResults {
+        self.roll_with_rng(&mut rand::thread_rng())
+    }
+
+    /// Roll the dicebag using the supplied random number generator. With a seeded generator
+    /// (see `seeded_rng`) the sequence of results is reproducible.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("4d6dl1+3".to_owned()).unwrap();
+    ///
+    /// let first: Vec<i64> = {
+    ///     let mut rng = seeded_rng(1234);
+    ///     (0..100).map(|_| bag.roll_with_rng(&mut rng).total()).collect()
+    /// };
+    /// let second: Vec<i64> = {
+    ///     let mut rng = seeded_rng(1234);
+    ///     (0..100).map(|_| bag.roll_with_rng(&mut rng).total()).collect()
+    /// };
+    /// assert_eq!(first, second);
+    /// ```
+    pub fn roll_with_rng<R: Rng + ?Sized>(&self, rng: &mut R) -> RollResults {
         // NB: Will need serious reworking for multiplication and division.
         let mut final_result = RollResults::new_empty();
         for x in self.dice.iter() {
             match *x {
                 DiceGroup::Bonus(ref b) => final_result.add_to_bonus(b),
                 DiceGroup::Dice(ref d) => {
-                    let Dice {
-                        size,
-                        count,
-                        ref drop,
-                        ref reroll,
-                        ref cutoff,
-                        op: _,
-                        explosive,
-                    } = d;
-                    // Roll all the dice.
-                    let mut answer = std::iter::repeat(0)
-                        .take(*count)
-                        .map(|_| {
-                            let mut result = Vec::new();
-                            if !explosive {
-                                let roll = rand::thread_rng().gen_range(1, size + 1);
-                                result.push(roll);
-                            } else {
-                                explode(&mut result, *size);
-                            }
+                    let depth = self.limits.max_explosion_depth;
+                    let result = match (&d.nested, &d.pick) {
+                        (_, Some(pick)) => {
+                            let (kept, rolls) = pick.roll(rng);
+                            let mut result = DiceResult::new(d, vec![kept]);
+                            result.pick_rolls = rolls;
                             result
-                        })
-                        .flatten()
-                        .collect::<Vec<_>>();
-
-                    // Deal with the reroll clause.
-                    let mut reroll_count = 0;
-                    let mut answer_cycler = answer.iter_mut();
-                    match reroll {
-                        ReRoll::IfAbove(ReRollType {
-                            count,
-                            ex_threshold,
-                        }) => {
-                            while let Some(ref mut roll) = answer_cycler.next() {
-                                if **roll > *ex_threshold {
-                                    **roll = rand::thread_rng().gen_range(1, size + 1);
-                                    reroll_count += 1;
-                                }
-                                if reroll_count == *count {
-                                    break;
-                                }
-                            }
-                        }
-                        ReRoll::IfBelow(ReRollType {
-                            count,
-                            ex_threshold,
-                        }) => {
-                            while let Some(ref mut roll) = answer_cycler.next() {
-                                if **roll < *ex_threshold {
-                                    **roll = rand::thread_rng().gen_range(1, size + 1);
-                                    reroll_count += 1;
-                                }
-                                if reroll_count == *count {
-                                    break;
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-
-                    // Deal with the min-max clause:
-                    for val in answer.iter_mut() {
-                        cutoff.use_to_cut_off(val);
-                    }
-
-                    // Decide what to Drop.
-                    let answer = match drop {
-                        // On drop lowest, drop the lowest N dice. Custom sorting is needed.
-                        Drop::Lowest(n) => {
-                            answer.sort_by(|n1, n2| n2.cmp(&n1));
-                            for _ in 0..*n {
-                                answer.pop();
-                            }
-                            answer
                         }
-                        // On highest, drop the highest N dice.
-                        Drop::Highest(n) => {
-                            answer.sort();
-                            for _ in 0..*n {
-                                answer.pop();
-                            }
-                            answer
+                        (None, None) => {
+                            let mut answer = Vec::with_capacity(d.count);
+                            d.roll_into(rng, &mut answer, depth);
+                            DiceResult::new(d, answer)
                         }
-                        // On custom, take selected dice and put in new vector.
-                        Drop::Custom(v) => {
-                            answer.sort();
-                            let mut output = Vec::with_capacity(v.len());
-                            for i in v.iter() {
-                                output.push(answer[*i]);
-                            }
-                            output
+                        (Some(nested), None) => {
+                            let (resolved, count_roll, size_roll) = nested.roll(d, rng);
+                            let mut answer = Vec::with_capacity(resolved.count);
+                            resolved.roll_into(rng, &mut answer, depth);
+                            let mut result = DiceResult::new(d, answer);
+                            result.count_roll = count_roll.map(Box::new);
+                            result.size_roll = size_roll.map(Box::new);
+                            result
                         }
-                        _ => answer,
                     };
-
-                    final_result.add_dice_result(DiceResult::new(&d, answer));
+                    final_result.add_dice_result(result);
                 }
             }
         }
         final_result
     }
 
+    /// Roll the dicebag and get only the total. This skips building the `RollResults` report.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("4d6dl1+3".to_owned()).unwrap();
+    /// for _ in 0..100_000 {
+    ///     let total = bag.roll_total();
+    ///     assert!((total >= 6) && (total <= 21));
+    /// }
+    /// ```
+    pub fn roll_total(&self) -> i64 {
+        self.roll_total_with_rng(&mut rand::thread_rng(), &mut RollBuffer::new())
+    }
+
+    /// Roll the dicebag with the supplied random number generator and get only the total.
+    /// The individual dice are rolled into `buffer`, so reusing one buffer for many rolls avoids
+    /// allocating on every roll. For a given random number generator this gives the same total
+    /// as `roll_with_rng`.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("10d6dl2dh2rr3be2+2d8!-4".to_owned()).unwrap();
+    ///
+    /// let mut rng_1 = seeded_rng(3);
+    /// let mut rng_2 = seeded_rng(3);
+    /// let mut buffer = RollBuffer::new();
+    /// for _ in 0..10_000 {
+    ///     let total = bag.roll_total_with_rng(&mut rng_1, &mut buffer);
+    ///     assert_eq!(total, bag.roll_with_rng(&mut rng_2).total());
+    /// }
+    /// ```
+    pub fn roll_total_with_rng<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        buffer: &mut RollBuffer,
+    ) -> i64 {
+        let mut total = 0;
+        for x in self.dice.iter() {
+            match *x {
+                DiceGroup::Bonus(ref b) => total = b.op.operate(total, b.bonus),
+                DiceGroup::Dice(ref d) => {
+                    let depth = self.limits.max_explosion_depth;
+                    match (&d.nested, &d.pick) {
+                        (_, Some(pick)) => {
+                            let kept = pick.roll_total(rng, buffer);
+                            buffer.rolls.clear();
+                            buffer.rolls.push(kept);
+                        }
+                        (None, None) => d.roll_into(rng, &mut buffer.rolls, depth),
+                        (Some(nested), None) => nested.roll_total(d, rng, buffer).roll_into(
+                            rng,
+                            &mut buffer.rolls,
+                            depth,
+                        ),
+                    }
+                    total = d.op.operate(total, buffer.rolls.iter().sum());
+                }
+            }
+        }
+        total
+    }
+
+    /// The `Limits` the bag was checked against when it was parsed or built. A bag made with
+    /// `from_dice` has the default limits, but is not checked against them.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// The groups of the bag, in order.
+    pub fn groups(&self) -> &[DiceGroup] {
+        &self.dice
+    }
+
+    /// The comment after a "#" at the end of the dice string, if there was one.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("1d20+5 [attack] # vs goblin".to_owned()).unwrap();
+    /// assert_eq!(bag.comment(), Some("vs goblin"));
+    /// assert_eq!(bag.to_string(), "1d20 + 5 [attack] # vs goblin");
+    /// ```
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
     /// A function to get a range as `[i64; 2]` (basically a minimum and maximum value).
     /// ```
     /// use libazdice::distribution::*;
@@ -1034,7 +1459,47 @@ impl DiceBag {
         range
     }
 
-    /// Get the range in a format which is useful. In this case as a vector.
+    /// The inclusive range of totals that the dicebag can roll. Every total that can be rolled is
+    /// inside it, and both ends can be rolled. Exploding dice can make a bag unbounded (see
+    /// `is_bounded`), in which case the open end is `i64::MAX` (or `i64::MIN` for subtracted dice).
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("4d6dl1mn2 - 1d4 + 3".to_owned()).unwrap();
+    /// assert_eq!(bag.range(), 5..=20);
+    /// assert!(bag.is_bounded());
+    ///
+    /// let bag: DiceBag = libazdice::parse("10 - 2d6!".to_owned()).unwrap();
+    /// assert_eq!(bag.range(), i64::MIN..=8);
+    /// assert!(!bag.is_bounded());
+    /// ```
+    pub fn range(&self) -> RangeInclusive<i64> {
+        let MinMax([min, max]) = self.range;
+        min..=max
+    }
+
+    /// Whether the dicebag has a finite range. Only exploding dice, which can keep adding dice to
+    /// the pool, make a bag unbounded. Exploding dice with a drop clause that keeps a fixed number
+    /// of dice (eg "5d6dl1dh1!") are still bounded.
+    pub fn is_bounded(&self) -> bool {
+        let [low, high] = self.calculate_bounds(true);
+        low.is_some() && high.is_some()
+    }
+
+    /// The range used to fill the keys of the distribution maps. For a bounded bag this is
+    /// `range`. For an unbounded bag this is the range of totals without any explosions.
+    fn distribution_range(&self) -> RangeInclusive<i64> {
+        if self.is_bounded() {
+            return self.range();
+        }
+        let [low, high] = self.calculate_bounds(false);
+        // Without explosions the bounds always exist.
+        low.unwrap_or(0)..=high.unwrap_or(0)
+    }
+
+    /// Get the range in a format which is useful. In this case as a vector of every total from the
+    /// minimum to the maximum, inclusive. For an unbounded bag (see `is_bounded`) it only goes as
+    /// far as the totals which can be rolled without explosions.
     /// ```
     /// use libazdice::distribution::*;
     ///
@@ -1044,21 +1509,34 @@ impl DiceBag {
     /// let bag: DiceBag = DiceBag::from_dice(vec![minus_five, two_d_six, one_d_twenty]);
     ///
     /// let range: Vec<i64> = bag.get_range_as_list();
-    /// let theoretical_range: Vec<i64> = (-2..27).collect();
+    /// let theoretical_range: Vec<i64> = (-2..=27).collect();
     /// assert!(range == theoretical_range);
     /// ```
     pub fn get_range_as_list(&self) -> Vec<i64> {
-        let MinMax([min, max]) = self.range;
-        (min..max).collect::<Vec<_>>()
+        self.distribution_range().collect::<Vec<_>>()
     }
 
     /// Used for building frequency distributions from multiple rolls.
     pub(crate) fn get_range_as_btreemap(&self) -> BTreeMap<i64, usize> {
-        let MinMax([min, max]) = self.range;
-        (min..max).map(|i| (i, 0)).collect::<BTreeMap<i64, usize>>()
+        self.distribution_range()
+            .map(|i| (i, 0))
+            .collect::<BTreeMap<i64, usize>>()
+    }
+
+    /// The counts to start a distribution of `roll_count` rolls from, with a zero for every total
+    /// in the range if there are no more totals than rolls.
+    fn empty_counts(&self, roll_count: usize) -> BTreeMap<i64, usize> {
+        let range = self.distribution_range();
+        let totals = *range.end() as i128 - *range.start() as i128 + 1;
+        if totals <= roll_count as i128 {
+            self.get_range_as_btreemap()
+        } else {
+            BTreeMap::new()
+        }
     }
 
-    /// Make a probability distribution by count.
+    /// Make a probability distribution by count. Every total in the range has an entry, unless
+    /// there are more totals than rolls (eg "1d1000000000"), when only the totals rolled do.
     /// ```
     /// use libazdice::distribution::*;
     /// use std::collections::BTreeMap;
@@ -1074,17 +1552,31 @@ impl DiceBag {
     /// assert!(distribution.get(&42).is_none());
     /// ```
     pub fn make_count_distribution(&self, roll_count: usize) -> BTreeMap<i64, usize> {
-        let mut range = self.get_range_as_btreemap();
+        self.make_count_distribution_with_rng(roll_count, &mut rand::thread_rng())
+    }
+
+    /// Make a probability distribution by count, using the supplied random number generator.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("3d6".to_owned()).unwrap();
+    ///
+    /// let dist_1 = bag.make_count_distribution_with_rng(10_000, &mut seeded_rng(7));
+    /// let dist_2 = bag.make_count_distribution_with_rng(10_000, &mut seeded_rng(7));
+    /// assert_eq!(dist_1, dist_2);
+    /// ```
+    pub fn make_count_distribution_with_rng<R: Rng + ?Sized>(
+        &self,
+        roll_count: usize,
+        rng: &mut R,
+    ) -> BTreeMap<i64, usize> {
+        let mut counts = self.empty_counts(roll_count);
+        let mut buffer = RollBuffer::new();
         for _ in 0..roll_count {
-            let roll = self.roll();
-            if let Some(c) = range.get_mut(&roll.total) {
-                *c += 1;
-            } else {
-                // This is excessive in this codebase, but just in case.
-                range.insert(roll.total, 1);
-            }
+            let total = self.roll_total_with_rng(rng, &mut buffer);
+            *counts.entry(total).or_insert(0) += 1;
         }
-        range
+        counts
     }
 
     /// Makes a probability distribution on the base of 0-100% percent.
@@ -1109,148 +1601,668 @@ impl DiceBag {
             .map(|(i, c)| (i, c as f64 / roll_count as f64 * 100.0))
             .collect::<BTreeMap<i64, f64>>()
     }
+
+    /// Make a probability distribution by count, splitting the rolls across `threads` threads.
+    /// Each thread rolls with its own random number generator and the per-thread counts are
+    /// merged at the end. If `seed` is given, thread `i` uses a `SeededRng` made from `seed`
+    /// on stream `i`, so the result is deterministic for a given seed and thread count.
+    /// A `threads` of zero is treated as one.
+    /// ```
+    /// use libazdice::distribution::*;
+    /// use std::collections::BTreeMap;
+    ///
+    /// let bag: DiceBag = libazdice::parse("4d6dl1".to_owned()).unwrap();
+    ///
+    /// let distribution: BTreeMap<i64, usize> =
+    ///     bag.make_count_distribution_parallel(200_000, 4, Some(2020));
+    /// assert_eq!(distribution.values().sum::<usize>(), 200_000);
+    /// for i in 3..19 {
+    ///     assert!(*distribution.get(&i).unwrap() > 0);
+    /// }
+    ///
+    /// // The same seed and thread count gives the same distribution.
+    /// assert_eq!(distribution, bag.make_count_distribution_parallel(200_000, 4, Some(2020)));
+    /// ```
+    pub fn make_count_distribution_parallel(
+        &self,
+        roll_count: usize,
+        threads: usize,
+        seed: Option<u64>,
+    ) -> BTreeMap<i64, usize> {
+        let threads = threads.max(1);
+        let per_thread = roll_count / threads;
+        let remainder = roll_count % threads;
+
+        let partials = std::thread::scope(|scope| {
+            let handles = (0..threads)
+                .map(|i| {
+                    // The first `remainder` threads take one extra roll each.
+                    let count = per_thread + if i < remainder { 1 } else { 0 };
+                    scope.spawn(move || match seed {
+                        Some(seed) => {
+                            let mut rng = seeded_rng(seed);
+                            rng.set_stream(i as u64);
+                            self.make_count_distribution_with_rng(count, &mut rng)
+                        }
+                        None => self.make_count_distribution(count),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("A distribution thread panicked."))
+                .collect::<Vec<_>>()
+        });
+
+        let mut counts = self.empty_counts(roll_count);
+        for partial in partials {
+            for (value, count) in partial {
+                *counts.entry(value).or_insert(0) += count;
+            }
+        }
+        counts
+    }
+
+    /// Makes a probability distribution on the base of 0-100% percent, splitting the rolls across
+    /// `threads` threads. See `make_count_distribution_parallel`.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("1d10".to_owned()).unwrap();
+    ///
+    /// let distribution = bag.make_frequency_distribution_parallel(1_000_000, 4, None);
+    /// for (_, f) in distribution {
+    ///     assert!((f > 9.5) && (f < 10.5));
+    /// }
+    /// ```
+    pub fn make_frequency_distribution_parallel(
+        &self,
+        roll_count: usize,
+        threads: usize,
+        seed: Option<u64>,
+    ) -> BTreeMap<i64, f64> {
+        self.make_count_distribution_parallel(roll_count, threads, seed)
+            .into_iter()
+            .map(|(i, c)| (i, c as f64 / roll_count as f64 * 100.0))
+            .collect::<BTreeMap<i64, f64>>()
+    }
+
+    /// Make a probability distribution by rolling until the requested precision is reached, or
+    /// until `max_rolls` rolls have been made. This avoids having to guess a roll count.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("3d6".to_owned()).unwrap();
+    ///
+    /// // Know the mean to within ±0.05.
+    /// let result = bag.make_adaptive_distribution(PrecisionTarget::Mean(0.1), 10_000_000);
+    /// assert!(result.converged());
+    /// assert!(result.precision() <= 0.1);
+    /// assert!(result.rolls() < 10_000_000);
+    /// let mean = result.histogram().mean().unwrap();
+    /// assert!((mean > 10.3) && (mean < 10.7));
+    ///
+    /// // A budget which is too small stops early, and says so.
+    /// let result = bag.make_adaptive_distribution(PrecisionTarget::EveryBucket(0.001), 5_000);
+    /// assert!(!result.converged());
+    /// assert_eq!(result.rolls(), 5_000);
+    /// ```
+    pub fn make_adaptive_distribution(
+        &self,
+        target: PrecisionTarget,
+        max_rolls: usize,
+    ) -> AdaptiveDistribution {
+        self.make_adaptive_distribution_with_rng(target, max_rolls, &mut rand::thread_rng())
+    }
+
+    /// Make a probability distribution by rolling until the requested precision is reached, or
+    /// until `max_rolls` rolls have been made, using the supplied random number generator.
+    /// The precision is checked after the first 1000 rolls, and then after every quarter again.
+    pub fn make_adaptive_distribution_with_rng<R: Rng + ?Sized>(
+        &self,
+        target: PrecisionTarget,
+        max_rolls: usize,
+        rng: &mut R,
+    ) -> AdaptiveDistribution {
+        // As `Histogram::for_bag`, but without filling in a range wider than the rolls allowed.
+        let mut histogram = Histogram {
+            counts: self.empty_counts(max_rolls),
+            ..Histogram::default()
+        };
+        let mut buffer = RollBuffer::new();
+        let mut next_check = ADAPTIVE_FIRST_BATCH.min(max_rolls);
+
+        loop {
+            while histogram.rolls() < next_check {
+                histogram.add(self.roll_total_with_rng(rng, &mut buffer));
+            }
+
+            let precision = target.achieved(&histogram).unwrap_or(f64::INFINITY);
+            let converged = precision <= target.width();
+            if converged || histogram.rolls() >= max_rolls {
+                return AdaptiveDistribution {
+                    histogram,
+                    precision,
+                    converged,
+                };
+            }
+            let batch = (histogram.rolls() / 4).max(ADAPTIVE_FIRST_BATCH);
+            next_check = (histogram.rolls() + batch).min(max_rolls);
+        }
+    }
+
+    /// An infinite iterator of rolls of the dicebag, using the thread's random number generator.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("2d6+1".to_owned()).unwrap();
+    ///
+    /// for roll in bag.rolls().take(1_000) {
+    ///     assert!((roll.total() >= 3) && (roll.total() <= 13));
+    /// }
+    /// ```
+    pub fn rolls(&self) -> RollIter<'_, ThreadRng> {
+        self.rolls_with_rng(rand::thread_rng())
+    }
+
+    /// An infinite iterator of rolls of the dicebag, using the supplied random number generator.
+    pub fn rolls_with_rng<R: Rng>(&self, rng: R) -> RollIter<'_, R> {
+        RollIter { bag: self, rng }
+    }
+
+    /// An infinite iterator of roll totals of the dicebag, using the thread's random number
+    /// generator. This uses the totals-only roll, so no `RollResults` are built.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("1d20".to_owned()).unwrap();
+    ///
+    /// let nat_20s = bag.totals().take(100_000).filter(|t| *t == 20).count();
+    /// assert!((nat_20s > 4_000) && (nat_20s < 6_000));
+    /// ```
+    pub fn totals(&self) -> TotalIter<'_, ThreadRng> {
+        self.totals_with_rng(rand::thread_rng())
+    }
+
+    /// An infinite iterator of roll totals of the dicebag, using the supplied random number
+    /// generator.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("3d6".to_owned()).unwrap();
+    ///
+    /// let totals: Vec<i64> = bag.totals_with_rng(seeded_rng(8)).take(100).collect();
+    /// let mut rng = seeded_rng(8);
+    /// let rolls: Vec<i64> = (0..100).map(|_| bag.roll_with_rng(&mut rng).total()).collect();
+    /// assert_eq!(totals, rolls);
+    /// ```
+    pub fn totals_with_rng<R: Rng>(&self, rng: R) -> TotalIter<'_, R> {
+        TotalIter {
+            bag: self,
+            rng,
+            buffer: RollBuffer::new(),
+        }
+    }
 }
 
-/// A function to make explosive dice explode
-fn explode(vec: &mut Vec<i64>, max: i64) {
-    let roll = rand::thread_rng().gen_range(1, max + 1);
-    vec.push(roll);
-    if vec.last() == Some(&max) {
-        explode(vec, max);
+/// An infinite iterator of `RollResults`, made by `DiceBag::rolls` or `DiceBag::rolls_with_rng`.
+#[derive(Debug)]
+pub struct RollIter<'a, R: Rng> {
+    bag: &'a DiceBag,
+    rng: R,
+}
+
+impl<'a, R: Rng> Iterator for RollIter<'a, R> {
+    type Item = RollResults;
+
+    fn next(&mut self) -> Option<RollResults> {
+        Some(self.bag.roll_with_rng(&mut self.rng))
     }
 }
 
-impl Display for DiceBag {
-    /// Reverse parsing. Yay!
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let mut bonus: i64 = 0;
+/// An infinite iterator of roll totals, made by `DiceBag::totals` or `DiceBag::totals_with_rng`.
+#[derive(Debug)]
+pub struct TotalIter<'a, R: Rng> {
+    bag: &'a DiceBag,
+    rng: R,
+    buffer: RollBuffer,
+}
 
-        for i in 0..self.dice.len() {
-            match self.dice[i] {
-                DiceGroup::Dice(ref d) => {
-                    if (i != 0) && (d.op == DiceOp::Add) {
-                        write!(f, " + ")?;
-                    } else if (i != 0) && (d.op == DiceOp::Sub) {
-                        write!(f, " - ")?;
-                    }
+impl<'a, R: Rng> Iterator for TotalIter<'a, R> {
+    type Item = i64;
+
+    fn next(&mut self) -> Option<i64> {
+        Some(
+            self.bag
+                .roll_total_with_rng(&mut self.rng, &mut self.buffer),
+        )
+    }
+}
 
-                    write!(f, "{}d{}", d.count, d.size)?;
+/// The z-score of a two-sided 95% confidence interval.
+const Z_95: f64 = 1.959_964;
 
-                    match d.drop {
-                        Drop::Highest(n) => {
-                            write!(f, "dh{}", n)?;
-                        }
-                        Drop::Lowest(n) => {
-                            write!(f, "dl{}", n)?;
-                        }
-                        Drop::Custom(ref v) => {
-                            if !v.is_empty() {
-                                let dh = v.last().expect("Checked.");
-                                let dl = v.first().expect("Checked.");
-                                write!(f, "dh{}dl{}", dh, dl)?;
-                            }
-                        }
-                        _ => {}
-                    }
+/// The number of rolls made before the precision of an adaptive distribution is first checked.
+const ADAPTIVE_FIRST_BATCH: usize = 1_000;
 
-                    match d.reroll {
-                        ReRoll::IfAbove(ref x) => {
-                            write!(f, "rr{}ab{}", x.count, x.ex_threshold)?;
-                        }
-                        ReRoll::IfBelow(ref x) => {
-                            write!(f, "rr{}be{}", x.count, x.ex_threshold)?;
-                        }
-                        _ => {}
-                    }
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+/// The precision wanted from `DiceBag::make_adaptive_distribution`. Each is the full width of a
+/// 95% confidence interval.
+pub enum PrecisionTarget {
+    /// The interval of the mean roll, in the units of the roll (eg `0.01`).
+    Mean(f64),
+    /// The interval of the frequency of every roll total, in percentage points (eg `0.05` for
+    /// every value being known to within ±0.025%).
+    EveryBucket(f64),
+}
 
-                    match d.cutoff {
-                        CutOff::Minimum(m) => {
-                            write!(f, "mn{}", m)?;
-                        }
-                        CutOff::Maximum(m) => {
-                            write!(f, "mx{}", m)?;
-                        }
-                        CutOff::Both(MinMax(mm)) => {
-                            write!(f, "mn{}mx{}", mm[0], mm[1])?;
-                        }
-                        _ => {}
-                    }
+impl PrecisionTarget {
+    /// The precision currently achieved by `histogram` for this kind of target.
+    fn achieved(&self, histogram: &Histogram) -> Option<f64> {
+        match self {
+            PrecisionTarget::Mean(_) => histogram.mean_interval_width(),
+            PrecisionTarget::EveryBucket(_) => histogram.bucket_interval_width(),
+        }
+    }
 
-                    if d.explosive {
-                        write!(f, "!")?;
-                    }
-                }
-                DiceGroup::Bonus(ref b) => {
-                    let op = if b.op == DiceOp::Add { 1 } else { -1 };
-                    bonus += op * b.bonus;
+    /// The requested width.
+    fn width(&self) -> f64 {
+        match *self {
+            PrecisionTarget::Mean(w) | PrecisionTarget::EveryBucket(w) => w,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The result of `DiceBag::make_adaptive_distribution`: the distribution, the number of rolls it
+/// took and the precision achieved.
+pub struct AdaptiveDistribution {
+    pub(crate) histogram: Histogram,
+    pub(crate) precision: f64,
+    pub(crate) converged: bool,
+}
+
+impl AdaptiveDistribution {
+    /// The rolls made, as a histogram.
+    pub fn histogram(&self) -> &Histogram {
+        &self.histogram
+    }
+
+    /// The count of each roll total, as `DiceBag::make_count_distribution`.
+    pub fn counts(&self) -> &BTreeMap<i64, usize> {
+        self.histogram.counts()
+    }
+
+    /// The frequency of each roll total, as `DiceBag::make_frequency_distribution`.
+    pub fn frequencies(&self) -> BTreeMap<i64, f64> {
+        self.histogram.frequencies()
+    }
+
+    /// The number of rolls used.
+    pub fn rolls(&self) -> usize {
+        self.histogram.rolls()
+    }
+
+    /// The width of the confidence interval achieved, in the units of the `PrecisionTarget`.
+    pub fn precision(&self) -> f64 {
+        self.precision
+    }
+
+    /// Whether the requested precision was reached before the roll budget ran out.
+    pub fn converged(&self) -> bool {
+        self.converged
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+/// A histogram of roll totals which can be fed one roll at a time and queried at any point,
+/// for building a distribution as rolls come in.
+pub struct Histogram {
+    pub(crate) counts: BTreeMap<i64, usize>,
+    pub(crate) rolls: usize,
+    pub(crate) sum: i128,
+}
+
+impl Histogram {
+    /// Create an empty histogram.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty histogram with a zero count for every value in the range of `bag`.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("3d6".to_owned()).unwrap();
+    /// let mut histogram = Histogram::for_bag(&bag);
+    /// assert_eq!(histogram.rolls(), 0);
+    ///
+    /// for (i, total) in bag.totals().take(10_000).enumerate() {
+    ///     histogram.add(total);
+    ///     assert_eq!(histogram.rolls(), i + 1);
+    /// }
+    /// assert_eq!(histogram.counts().values().sum::<usize>(), 10_000);
+    /// let mean = histogram.mean().unwrap();
+    /// assert!((mean > 10.0) && (mean < 11.0));
+    /// ```
+    pub fn for_bag(bag: &DiceBag) -> Self {
+        Histogram {
+            counts: bag.get_range_as_btreemap(),
+            rolls: 0,
+            sum: 0,
+        }
+    }
+
+    /// Add a single roll total.
+    pub fn add(&mut self, total: i64) {
+        *self.counts.entry(total).or_insert(0) += 1;
+        self.rolls += 1;
+        self.sum += total as i128;
+    }
+
+    /// Add the total of a `RollResults`.
+    pub fn add_roll(&mut self, roll: &RollResults) {
+        self.add(roll.total());
+    }
+
+    /// Add all the counts of another histogram to this one.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (value, count) in other.counts.iter() {
+            *self.counts.entry(*value).or_insert(0) += count;
+        }
+        self.rolls += other.rolls;
+        self.sum += other.sum;
+    }
+
+    /// The number of rolls added so far.
+    pub fn rolls(&self) -> usize {
+        self.rolls
+    }
+
+    /// The count of each roll total so far, in the same format as
+    /// `DiceBag::make_count_distribution`.
+    pub fn counts(&self) -> &BTreeMap<i64, usize> {
+        &self.counts
+    }
+
+    /// The frequency of each roll total so far on the base of 0-100%, in the same format as
+    /// `DiceBag::make_frequency_distribution`. Empty if nothing has been added.
+    pub fn frequencies(&self) -> BTreeMap<i64, f64> {
+        if self.rolls == 0 {
+            return BTreeMap::new();
+        }
+        self.counts
+            .iter()
+            .map(|(i, c)| (*i, *c as f64 / self.rolls as f64 * 100.0))
+            .collect::<BTreeMap<i64, f64>>()
+    }
+
+    /// The mean of the rolls so far, or `None` if nothing has been added.
+    pub fn mean(&self) -> Option<f64> {
+        if self.rolls == 0 {
+            None
+        } else {
+            Some(self.sum as f64 / self.rolls as f64)
+        }
+    }
+
+    /// The (sample) variance of the rolls so far, or `None` if fewer than two have been added.
+    pub fn variance(&self) -> Option<f64> {
+        if self.rolls < 2 {
+            return None;
+        }
+        let mean = self.mean()?;
+        let sq_dev = self
+            .counts
+            .iter()
+            .map(|(v, c)| (*v as f64 - mean).powi(2) * *c as f64)
+            .sum::<f64>();
+        Some(sq_dev / (self.rolls - 1) as f64)
+    }
+
+    /// The width of the 95% confidence interval of the mean, in the units of the roll.
+    /// `None` if fewer than two rolls have been added.
+    pub fn mean_interval_width(&self) -> Option<f64> {
+        let variance = self.variance()?;
+        Some(2.0 * Z_95 * (variance / self.rolls as f64).sqrt())
+    }
+
+    /// The widest 95% confidence interval of the frequency of any roll total, in percentage
+    /// points (as in `frequencies`). `None` if nothing has been added.
+    ///
+    /// These are Wilson intervals, which (unlike the plain normal approximation) are never 0
+    /// wide, so a total which has turned up every time (or a rare one, not yet seen) is not
+    /// known exactly after a few rolls.
+    /// ```
+    /// use libazdice::distribution::Histogram;
+    ///
+    /// let always_six: Histogram = (0..100).map(|_| 6).collect();
+    /// assert!(always_six.bucket_interval_width().unwrap() > 3.0);
+    /// ```
+    pub fn bucket_interval_width(&self) -> Option<f64> {
+        if self.rolls == 0 {
+            return None;
+        }
+        let n = self.rolls as f64;
+        let z2 = Z_95 * Z_95;
+        // The narrowest Wilson interval is that of a total never seen (or always seen).
+        let unseen = z2 / (n + z2) * 100.0;
+        let widest = self
+            .counts
+            .values()
+            .map(|c| {
+                let p = *c as f64 / n;
+                let spread = (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+                2.0 * Z_95 * spread / (1.0 + z2 / n) * 100.0
+            })
+            .fold(unseen, f64::max);
+        Some(widest)
+    }
+}
+
+impl Extend<i64> for Histogram {
+    fn extend<I: IntoIterator<Item = i64>>(&mut self, iter: I) {
+        for total in iter {
+            self.add(total);
+        }
+    }
+}
+
+impl std::iter::FromIterator<i64> for Histogram {
+    fn from_iter<I: IntoIterator<Item = i64>>(iter: I) -> Self {
+        let mut histogram = Histogram::new();
+        histogram.extend(iter);
+        histogram
+    }
+}
+
+/// A function to make explosive dice explode, rolling again on the maximum up to `depth` times.
+fn explode<R: Rng + ?Sized>(vec: &mut Vec<i64>, max: i64, depth: usize, rng: &mut R) {
+    for _ in 0..=depth {
+        let roll = rng.gen_range(1, max + 1);
+        vec.push(roll);
+        if roll != max {
+            break;
+        }
+    }
+}
+
+impl DiceOp {
+    /// Write the operation in front of the group at position `i` of a bag. The first group only
+    /// gets a sign if it is subtracted.
+    pub(crate) fn write_prefix(self, f: &mut Formatter<'_>, i: usize) -> std::fmt::Result {
+        match (i, self) {
+            (0, DiceOp::Add) => Ok(()),
+            (0, DiceOp::Sub) => write!(f, "-"),
+            (_, DiceOp::Add) => write!(f, " + "),
+            (_, DiceOp::Sub) => write!(f, " - "),
+        }
+    }
+}
+
+impl Dice {
+    /// Write the count and sides, with any dice rolled for them in brackets, or the bags of a
+    /// "max(..)" or "min(..)" group.
+    fn write_base(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(ref pick) = self.pick {
+            write!(f, "{}(", pick.name())?;
+            for (i, bag) in pick.bags.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
                 }
+                write!(f, "{}", bag)?;
+            }
+            return write!(f, ")");
+        }
+        let nested = self.nested.as_deref();
+        match nested.and_then(|n| n.count.as_ref()) {
+            Some(bag) => write!(f, "({})", bag)?,
+            None => write!(f, "{}", self.count)?,
+        }
+        match nested.and_then(|n| n.size.as_ref()) {
+            Some(bag) => write!(f, "d({})", bag),
+            None => write!(f, "d{}", self.size),
+        }
+    }
+
+    /// Write the clauses which follow the count and sides (eg "dl1rr1be2!").
+    fn write_clauses(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.drop {
+            Drop::Highest(n) => {
+                write!(f, "dh{}", n)?;
+            }
+            Drop::Lowest(n) => {
+                write!(f, "dl{}", n)?;
+            }
+            Drop::Custom(ref v) if !v.is_empty() => {
+                // The vector holds the (sorted) positions kept, so the first position is the
+                // number of lowest dice dropped.
+                let dl = v.first().expect("Checked.");
+                let dh = self.count - 1 - v.last().expect("Checked.");
+                write!(f, "dl{}dh{}", dl, dh)?;
+            }
+            _ => {}
+        }
+
+        match self.reroll {
+            ReRoll::IfAbove(ref x) => {
+                write!(f, "rr{}ab{}", x.count, x.ex_threshold)?;
+            }
+            ReRoll::IfBelow(ref x) => {
+                write!(f, "rr{}be{}", x.count, x.ex_threshold)?;
+            }
+            _ => {}
+        }
+
+        match self.cutoff {
+            CutOff::Minimum(m) => {
+                write!(f, "mn{}", m)?;
+            }
+            CutOff::Maximum(m) => {
+                write!(f, "mx{}", m)?;
             }
+            CutOff::Both(MinMax(mm)) => {
+                write!(f, "mn{}mx{}", mm[0], mm[1])?;
+            }
+            _ => {}
         }
 
-        if bonus > 0 {
-            write!(f, " + {}", bonus.abs())?;
-        } else if bonus < 0 {
-            write!(f, " - {}", bonus.abs())?;
+        if self.explosive {
+            write!(f, "!")?;
         }
         Ok(())
     }
 }
 
-impl Display for RollResults {
-    /// Reverse parsing. Yay!
+impl Display for Dice {
+    /// Reverse parsing of a single group of dice, without its operation (eg "4d6dl1rr1be2!" or
+    /// "(1d4 + 1)d6").
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        for i in 0..self.dice_groups.len() {
-            let d = &self.dice_groups[i].dice;
-            if (i != 0) && (d.op == DiceOp::Add) {
-                write!(f, " + ")?;
-            } else if (i != 0) && (d.op == DiceOp::Sub) {
-                write!(f, " - ")?;
-            }
-
-            write!(f, "{}d{}", d.count, d.size)?;
+        self.write_base(f)?;
+        self.write_clauses(f)
+    }
+}
 
-            match d.drop {
-                Drop::Highest(n) => {
-                    write!(f, "dh{}", n)?;
-                }
-                Drop::Lowest(n) => {
-                    write!(f, "dl{}", n)?;
+impl Display for DiceBag {
+    /// Reverse parsing. Yay! The result parses back into the same `DiceBag`.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = "-2 + 5d6dl1dh1rr2be3mn2! - 1d4 + 3".parse().unwrap();
+    /// assert_eq!(bag.to_string(), "-2 + 5d6dl1dh1rr2be3mn2! - 1d4 + 3");
+    /// assert_eq!(bag.to_string().parse::<DiceBag>().unwrap(), bag);
+    /// ```
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, group) in self.dice.iter().enumerate() {
+            match group {
+                DiceGroup::Dice(ref d) => {
+                    d.op.write_prefix(f, i)?;
+                    write!(f, "{}", d)?;
                 }
-                Drop::Custom(ref v) => {
-                    if !v.is_empty() {
-                        let dh = v.last().expect("Checked.");
-                        let dl = v.first().expect("Checked.");
-                        write!(f, "dh{}dl{}", dh, dl)?;
-                    }
+                DiceGroup::Bonus(ref b) => {
+                    b.op.write_prefix(f, i)?;
+                    write!(f, "{}", b.bonus)?;
                 }
-                _ => {}
             }
-
-            match d.reroll {
-                ReRoll::IfAbove(ref x) => {
-                    write!(f, "rr{}ab{}", x.count, x.ex_threshold)?;
-                }
-                ReRoll::IfBelow(ref x) => {
-                    write!(f, "rr{}be{}", x.count, x.ex_threshold)?;
+            // A label is written once, after the last of a run of groups with that label.
+            if let Some(label) = group.label() {
+                if self.dice.get(i + 1).and_then(DiceGroup::label) != Some(label) {
+                    write!(f, " [{}]", label)?;
                 }
-                _ => {}
             }
+        }
+        if let Some(ref comment) = self.comment {
+            write!(f, " # {}", comment)?;
+        }
+        Ok(())
+    }
+}
 
-            match d.cutoff {
-                CutOff::Minimum(m) => {
-                    write!(f, "mn{}", m)?;
+impl FromStr for DiceBag {
+    type Err = String;
+
+    /// The same as `parse::parse`.
+    fn from_str(s: &str) -> Result<DiceBag, String> {
+        crate::parse::parse(s.to_owned())
+    }
+}
+
+impl RollResults {
+    /// Write the groups and bonus, without the total. Dice whose count or sides were rolled show
+    /// those rolls in brackets, eg "(1d4( 3 = 3 ) = 3)d6( 2 + 6 + 1 = 9 )", as do the bags of a
+    /// "max(..)" or "min(..)" group, eg "max(1d8!( 5 = 5 ) = 5, 1d6!( 6 + 2 = 8 ) = 8)( 8 = 8 )".
+    fn write_groups(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for i in 0..self.dice_groups.len() {
+            let group = &self.dice_groups[i];
+            let d = &group.dice;
+            d.op.write_prefix(f, i)?;
+            if let Some(ref pick) = d.pick {
+                write!(f, "{}(", pick.name())?;
+                for (i, roll) in group.pick_rolls.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", NestedRoll(roll))?;
                 }
-                CutOff::Maximum(m) => {
-                    write!(f, "mx{}", m)?;
+                write!(f, ")")?;
+            } else if group.count_roll.is_none() && group.size_roll.is_none() {
+                write!(f, "{}", d)?;
+            } else {
+                match group.count_roll {
+                    Some(ref roll) => write!(f, "({})", NestedRoll(roll))?,
+                    None => write!(f, "{}", d.count)?,
                 }
-                CutOff::Both(MinMax(mm)) => {
-                    write!(f, "mn{}mx{}", mm[0], mm[1])?;
+                match group.size_roll {
+                    Some(ref roll) => write!(f, "d({})", NestedRoll(roll))?,
+                    None => write!(f, "d{}", d.size)?,
                 }
-                _ => {}
-            }
-
-            if d.explosive {
-                write!(f, "!")?;
+                d.write_clauses(f)?;
             }
 
             write!(f, "( ")?;
@@ -1266,15 +2278,53 @@ impl Display for RollResults {
                 }
             }
             write!(f, " = {} )", self.dice_groups[i].total)?;
+            if let Some(label) = group.label() {
+                write!(f, " [{}]", label)?;
+            }
         }
 
-        if self.bonus.total() > 0 {
-            write!(f, " + {}", self.bonus.total().abs())?;
-        } else if self.bonus.total() < 0 {
-            write!(f, " - {}", self.bonus.total().abs())?;
+        // The boni without labels are shown as one, and those with labels one by one.
+        let labelled = &self.bonus.labelled;
+        let unlabelled = self.bonus.total() - labelled.iter().map(|(_, b)| b).sum::<i64>();
+        if unlabelled > 0 {
+            write!(f, " + {}", unlabelled.abs())?;
+        } else if unlabelled < 0 {
+            write!(f, " - {}", unlabelled.abs())?;
+        }
+        for (label, bonus) in labelled.iter() {
+            let sign = if *bonus < 0 { '-' } else { '+' };
+            write!(f, " {} {} [{}]", sign, bonus.abs(), label)?;
         }
-
-        write!(f, " (Total = {} )", self.total())?;
         Ok(())
     }
 }
+
+/// A roll for the count or sides of dice, or of a bag of a "max(..)" group, shown with its total.
+struct NestedRoll<'a>(&'a RollResults);
+
+impl Display for NestedRoll<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.0.dice_groups.is_empty() {
+            return write!(f, "{}", self.0.total());
+        }
+        self.0.write_groups(f)?;
+        write!(f, " = {}", self.0.total())
+    }
+}
+
+impl Display for RollResults {
+    /// Reverse parsing. Yay!
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("(1d1+1)d1 + 2".to_owned()).unwrap();
+    /// assert_eq!(
+    ///     bag.roll().to_string(),
+    ///     "(1d1( 1 = 1 ) + 1 = 2)d1( 1 + 1 = 2 ) + 2 (Total = 4 )"
+    /// );
+    /// ```
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write_groups(f)?;
+        write!(f, " (Total = {} )", self.total())
+    }
+}
@@ -0,0 +1,536 @@
+//! This module contains the exact distributions of `DiceBag`s, worked out from the probability of
+//! each face rather than by rolling.
+use super::distribution::*;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// The most arithmetic (roughly, multiplications) an exact distribution may take, so that huge
+/// bags fail quickly rather than hanging or running out of memory.
+const WORK_LIMIT: u64 = 200_000_000;
+
+const TOO_BIG: &str = "Too much work to find the distribution exactly. Try sampling it instead.";
+
+/// What is left of the `WORK_LIMIT` for one distribution.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    left: u64,
+}
+
+impl Budget {
+    pub(crate) fn new() -> Self {
+        Budget { left: WORK_LIMIT }
+    }
+
+    /// Use up `work`, or fail if there is not enough left.
+    pub(crate) fn spend(&mut self, work: u64) -> Result<(), String> {
+        self.left = self.left.checked_sub(work).ok_or(TOO_BIG)?;
+        Ok(())
+    }
+}
+
+/// A probability mass function over a run of whole numbers: `probs[i]` is the probability of
+/// `offset + i`. An empty `probs` is no outcome at all (used to build up sums).
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Pmf {
+    offset: i64,
+    probs: Vec<f64>,
+}
+
+impl Pmf {
+    /// No outcomes, to be added to.
+    pub(crate) fn empty() -> Pmf {
+        Pmf {
+            offset: 0,
+            probs: Vec::new(),
+        }
+    }
+
+    /// Always `value`.
+    pub(crate) fn constant(value: i64) -> Pmf {
+        Pmf {
+            offset: value,
+            probs: vec![1.0],
+        }
+    }
+
+    /// Each of `low..=high` with equal probability.
+    pub(crate) fn uniform(low: i64, high: i64, budget: &mut Budget) -> Result<Pmf, String> {
+        let len = Pmf::length(low, high)?;
+        budget.spend(len as u64)?;
+        Ok(Pmf {
+            offset: low,
+            probs: vec![1.0 / len as f64; len],
+        })
+    }
+
+    /// Collect `(value, probability)` pairs, adding up repeated values.
+    pub(crate) fn from_pairs<I>(pairs: I, budget: &mut Budget) -> Result<Pmf, String>
+    where
+        I: IntoIterator<Item = (i64, f64)>,
+    {
+        let pairs = pairs.into_iter().collect::<Vec<_>>();
+        let (low, high) = match (
+            pairs.iter().map(|p| p.0).min(),
+            pairs.iter().map(|p| p.0).max(),
+        ) {
+            (Some(low), Some(high)) => (low, high),
+            _ => return Ok(Pmf::empty()),
+        };
+        let len = Pmf::length(low, high)?;
+        budget.spend(len as u64)?;
+        let mut probs = vec![0.0; len];
+        for (value, p) in pairs {
+            probs[(value - low) as usize] += p;
+        }
+        Ok(Pmf { offset: low, probs }.trimmed())
+    }
+
+    /// The number of values in `low..=high`, if it can be allocated at all.
+    fn length(low: i64, high: i64) -> Result<usize, String> {
+        high.checked_sub(low)
+            .and_then(|d| d.checked_add(1))
+            .and_then(|l| usize::try_from(l).ok())
+            .filter(|l| *l > 0)
+            .ok_or_else(|| TOO_BIG.to_owned())
+    }
+
+    /// Remove values with no chance (or too little to represent) from the ends.
+    fn trimmed(mut self) -> Pmf {
+        let start = self.probs.iter().position(|p| *p > 0.0);
+        let end = self.probs.iter().rposition(|p| *p > 0.0);
+        match (start, end) {
+            (Some(start), Some(end)) => {
+                self.probs.truncate(end + 1);
+                self.probs.drain(..start);
+                self.offset += start as i64;
+                self
+            }
+            _ => Pmf::empty(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.probs.is_empty()
+    }
+
+    /// The lowest possible value (or lower, if the ends have not been trimmed).
+    pub(crate) fn min(&self) -> i64 {
+        self.offset
+    }
+
+    /// The highest possible value (or higher, if the ends have not been trimmed).
+    pub(crate) fn max(&self) -> i64 {
+        self.offset + self.probs.len() as i64 - 1
+    }
+
+    /// Every value from `min` to `max` and its probability, in order.
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = (i64, f64)> + '_ {
+        self.probs
+            .iter()
+            .enumerate()
+            .map(move |(i, p)| (self.offset + i as i64, *p))
+    }
+
+    /// The distribution of `f(x)`.
+    pub(crate) fn map<F: Fn(i64) -> i64>(&self, f: F, budget: &mut Budget) -> Result<Pmf, String> {
+        Pmf::from_pairs(self.iter().map(|(v, p)| (f(v), p)), budget)
+    }
+
+    /// The distribution of `-x`.
+    pub(crate) fn negate(&self) -> Pmf {
+        let mut probs = self.probs.clone();
+        probs.reverse();
+        Pmf {
+            offset: -self.max(),
+            probs,
+        }
+    }
+
+    /// The distribution of the sum of `x` and `y`, where `y` has the distribution `other`.
+    pub(crate) fn convolve(&self, other: &Pmf, budget: &mut Budget) -> Result<Pmf, String> {
+        if self.is_empty() || other.is_empty() {
+            return Ok(Pmf::empty());
+        }
+        budget.spend(self.probs.len() as u64 * other.probs.len() as u64)?;
+        let mut probs = vec![0.0; self.probs.len() + other.probs.len() - 1];
+        for (i, a) in self.probs.iter().enumerate() {
+            if *a == 0.0 {
+                continue;
+            }
+            for (j, b) in other.probs.iter().enumerate() {
+                probs[i + j] += a * b;
+            }
+        }
+        Ok(Pmf {
+            offset: self.offset + other.offset,
+            probs,
+        })
+    }
+
+    /// The distribution of the sum of `n` independent copies of `x`.
+    pub(crate) fn power(&self, mut n: usize, budget: &mut Budget) -> Result<Pmf, String> {
+        let mut result = Pmf::constant(0);
+        let mut base = self.clone();
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result.convolve(&base, budget)?;
+            }
+            n >>= 1;
+            if n > 0 {
+                base = base.convolve(&base, budget)?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Add the outcomes of `other`, shifted by `shift` and with their probabilities multiplied by
+    /// `weight`.
+    pub(crate) fn add_scaled(
+        &mut self,
+        other: &Pmf,
+        weight: f64,
+        shift: i64,
+        budget: &mut Budget,
+    ) -> Result<(), String> {
+        if other.is_empty() || weight == 0.0 {
+            return Ok(());
+        }
+        let low = other.min() + shift;
+        let high = other.max() + shift;
+        if self.is_empty() {
+            self.offset = low;
+        }
+        let new_low = low.min(self.min());
+        let new_high = high.max(self.max());
+        let len = Pmf::length(new_low, new_high)?;
+        budget.spend(other.probs.len() as u64)?;
+        if new_low < self.offset {
+            let grow = (self.offset - new_low) as usize;
+            budget.spend(len as u64)?;
+            self.probs.splice(0..0, std::iter::repeat_n(0.0, grow));
+            self.offset = new_low;
+        }
+        self.probs.resize(len, 0.0);
+
+        let start = (low - self.offset) as usize;
+        for (i, p) in other.probs.iter().enumerate() {
+            self.probs[start + i] += p * weight;
+        }
+        Ok(())
+    }
+
+    /// The distribution on the base of 0-100%, as `DiceBag::make_frequency_distribution`.
+    pub(crate) fn to_percentages(&self) -> BTreeMap<i64, f64> {
+        self.iter().map(|(v, p)| (v, p * 100.0)).collect()
+    }
+}
+
+/// The probabilities of 0 to `n` successes out of `n` tries, each succeeding with probability `q`.
+pub(crate) fn binomial(n: usize, q: f64) -> Vec<f64> {
+    let mut weights = vec![0.0; n + 1];
+    if q <= 0.0 {
+        weights[0] = 1.0;
+    } else if q >= 1.0 {
+        weights[n] = 1.0;
+    } else {
+        // Worked out as logarithms, as the binomial coefficients of big pools do not fit in an f64.
+        let (ln_q, ln_not_q) = (q.ln(), (1.0 - q).ln());
+        let mut ln_choose = 0.0;
+        for (k, w) in weights.iter_mut().enumerate() {
+            if k > 0 {
+                ln_choose += ((n - k + 1) as f64).ln() - (k as f64).ln();
+            }
+            *w = (ln_choose + k as f64 * ln_q + (n - k) as f64 * ln_not_q).exp();
+        }
+    }
+    weights
+}
+
+impl Dice {
+    /// The value of a die once the cutoff is applied.
+    fn cut(&self, mut value: i64) -> i64 {
+        self.cutoff.use_to_cut_off(&mut value);
+        value
+    }
+
+    /// Whether a die showing `value` is rerolled, if there are rerolls left.
+    fn rerolls(&self, value: i64) -> bool {
+        match self.reroll {
+            ReRoll::IfAbove(ReRollType { ex_threshold, .. }) => value > ex_threshold,
+            ReRoll::IfBelow(ReRollType { ex_threshold, .. }) => value < ex_threshold,
+            ReRoll::Never => false,
+        }
+    }
+
+    /// The positions kept by the drop clause, counting down from the highest die, as `lo..hi`.
+    fn kept_positions(&self) -> Result<(usize, usize), String> {
+        let n = self.count;
+        match self.drop {
+            Drop::Non => Ok((0, n)),
+            Drop::Lowest(l) => Ok((0, n.saturating_sub(l))),
+            Drop::Highest(h) => Ok((h.min(n), n)),
+            Drop::Custom(ref keep) => match (keep.first(), keep.last()) {
+                (Some(&a), Some(&b)) if b < n && b + 1 - a == keep.len() => Ok((n - 1 - b, n - a)),
+                _ => Err("The drop clause does not keep a run of dice.".to_owned()),
+            },
+        }
+    }
+
+    /// The exact distribution of the total of the dice, before their operation is applied.
+    pub(crate) fn exact_pmf(
+        &self,
+        explosion_depth: usize,
+        budget: &mut Budget,
+    ) -> Result<Pmf, String> {
+        if let Some(ref pick) = self.pick {
+            return self.pick_pmf(pick, budget);
+        }
+        if let Some(ref nested) = self.nested {
+            return self.compound_pmf(nested, explosion_depth, budget);
+        }
+        if self.count == 0 {
+            return Ok(Pmf::constant(0));
+        }
+        if self.explosive {
+            if self.drop != Drop::Non || self.reroll != ReRoll::Never {
+                return Err(
+                    "Exploding dice which drop or reroll can be rolled, but not worked out exactly."
+                        .to_owned(),
+                );
+            }
+            return self
+                .chain_pmf(explosion_depth, budget)?
+                .power(self.count, budget);
+        }
+
+        let faces = Pmf::uniform(1, self.size, budget)?;
+        match (self.reroll, &self.drop) {
+            (ReRoll::Never, Drop::Non) => faces
+                .map(|v| self.cut(v), budget)?
+                .power(self.count, budget),
+            (ReRoll::Never, _) => {
+                let faces = faces.map(|v| self.cut(v), budget)?;
+                kept_sum_pmf(&faces, self.count, self.kept_positions()?, budget)
+            }
+            (ReRoll::IfAbove(rrt), Drop::Non) | (ReRoll::IfBelow(rrt), Drop::Non) => {
+                self.reroll_sum_pmf(&faces, rrt.count, budget)
+            }
+            _ => self.enumerated_pmf(budget),
+        }
+    }
+
+    /// The distribution of the total of one exploding die and everything it sets off, with each
+    /// die cut off on its own.
+    fn chain_pmf(&self, explosion_depth: usize, budget: &mut Budget) -> Result<Pmf, String> {
+        let p = 1.0 / self.size as f64;
+        let no_explosion = Pmf::from_pairs((1..self.size).map(|v| (self.cut(v), p)), budget)?;
+        let mut chain = Pmf::uniform(1, self.size, budget)?.map(|v| self.cut(v), budget)?;
+        for _ in 0..explosion_depth {
+            let mut next = no_explosion.clone();
+            next.add_scaled(&chain, p, self.cut(self.size), budget)?;
+            chain = next.trimmed();
+        }
+        Ok(chain)
+    }
+
+    /// The distribution of the total of rerolled dice with no drop clause. With `J` of the dice
+    /// first rolling something to be rerolled, the first `min(J, count)` of those are rerolled,
+    /// so the dice are a mixture over `J` of three kinds of independent dice.
+    fn reroll_sum_pmf(
+        &self,
+        faces: &Pmf,
+        count: usize,
+        budget: &mut Budget,
+    ) -> Result<Pmf, String> {
+        let q = faces
+            .iter()
+            .filter(|(v, _)| self.rerolls(*v))
+            .map(|(_, p)| p)
+            .sum::<f64>();
+        let conditioned = |reroll: bool, budget: &mut Budget| {
+            let total = if reroll { q } else { 1.0 - q };
+            Pmf::from_pairs(
+                faces
+                    .iter()
+                    .filter(|(v, _)| self.rerolls(*v) == reroll)
+                    .map(|(v, p)| (self.cut(v), p / total)),
+                budget,
+            )
+        };
+        let kept = conditioned(false, budget)?;
+        let not_rerolled = conditioned(true, budget)?;
+        let rerolled = faces.map(|v| self.cut(v), budget)?;
+
+        let mut result = Pmf::empty();
+        for (j, w) in binomial(self.count, q).into_iter().enumerate() {
+            if w == 0.0 {
+                continue;
+            }
+            let r = j.min(count);
+            let part = kept
+                .power(self.count - j, budget)?
+                .convolve(&not_rerolled.power(j - r, budget)?, budget)?
+                .convolve(&rerolled.power(r, budget)?, budget)?;
+            result.add_scaled(&part, w, 0, budget)?;
+        }
+        Ok(result.trimmed())
+    }
+
+    /// The distribution of the total found by trying every roll and reroll, for small groups
+    /// which both reroll and drop.
+    pub(crate) fn enumerated_pmf(&self, budget: &mut Budget) -> Result<Pmf, String> {
+        let rerolls = match self.reroll {
+            ReRoll::IfAbove(rrt) | ReRoll::IfBelow(rrt) => rrt.count,
+            ReRoll::Never => 0,
+        };
+        // There are at least as many leaves as rolls without rerolls, so hopeless groups fail at
+        // once rather than once the budget runs out.
+        let rolls = u32::try_from(self.count)
+            .ok()
+            .and_then(|count| (self.size as u64).checked_pow(count))
+            .ok_or(TOO_BIG)?;
+        budget.spend(rolls)?;
+        let mut totals = BTreeMap::new();
+        let mut dice = Vec::with_capacity(self.count);
+        self.enumerate(rerolls, 1.0, &mut dice, &mut totals, budget)?;
+        Pmf::from_pairs(totals, budget)
+    }
+
+    fn enumerate(
+        &self,
+        rerolls: usize,
+        p: f64,
+        dice: &mut Vec<i64>,
+        totals: &mut BTreeMap<i64, f64>,
+        budget: &mut Budget,
+    ) -> Result<(), String> {
+        if dice.len() == self.count {
+            budget.spend(self.count as u64)?;
+            let mut kept = dice.clone();
+            self.drop.apply(&mut kept);
+            *totals.entry(kept.iter().sum()).or_insert(0.0) += p;
+            return Ok(());
+        }
+
+        let p = p / self.size as f64;
+        for face in 1..=self.size {
+            if rerolls > 0 && self.rerolls(face) {
+                for new_face in 1..=self.size {
+                    dice.push(self.cut(new_face));
+                    self.enumerate(rerolls - 1, p / self.size as f64, dice, totals, budget)?;
+                    dice.pop();
+                }
+            } else {
+                dice.push(self.cut(face));
+                self.enumerate(rerolls, p, dice, totals, budget)?;
+                dice.pop();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The distribution of the sum of the dice at positions `lo..hi` (counting down from the highest)
+/// of `n` independent dice with the distribution `faces`.
+///
+/// The values are gone through from the highest down. With `t` dice already higher, the number of
+/// the other `n - t` dice showing the current value is binomial, and those dice take the next
+/// positions.
+fn kept_sum_pmf(
+    faces: &Pmf,
+    n: usize,
+    (lo, hi): (usize, usize),
+    budget: &mut Budget,
+) -> Result<Pmf, String> {
+    let values = faces.iter().filter(|(_, p)| *p > 0.0).collect::<Vec<_>>();
+    // The probability of rolling each value or lower.
+    let mut at_or_below = values
+        .iter()
+        .scan(0.0, |acc, (_, p)| {
+            *acc += p;
+            Some(*acc)
+        })
+        .collect::<Vec<_>>();
+    if let Some(last) = at_or_below.last_mut() {
+        *last = 1.0;
+    }
+
+    let mut placed = vec![Pmf::empty(); n + 1];
+    placed[0] = Pmf::constant(0);
+    for (i, (value, p)) in values.iter().enumerate().rev() {
+        let q = if i == 0 {
+            1.0
+        } else {
+            (p / at_or_below[i]).min(1.0)
+        };
+        let mut next = vec![Pmf::empty(); n + 1];
+        for (t, sums) in placed.iter().enumerate() {
+            if sums.is_empty() {
+                continue;
+            }
+            for (x, w) in binomial(n - t, q).into_iter().enumerate() {
+                let kept = (t + x).min(hi).saturating_sub(t.max(lo));
+                next[t + x].add_scaled(sums, w, kept as i64 * value, budget)?;
+            }
+        }
+        placed = next;
+    }
+    Ok(placed.swap_remove(n).trimmed())
+}
+
+impl DiceBag {
+    /// The exact distribution of the total of the bag.
+    pub(crate) fn exact_pmf(&self, budget: &mut Budget) -> Result<Pmf, String> {
+        self.validate()?;
+        let explosion_depth = self.limits().max_explosion_depth;
+        let mut total = Pmf::constant(0);
+        for group in self.normalize().dice.iter() {
+            let pmf = match group {
+                DiceGroup::Bonus(b) => Pmf::constant(b.bonus),
+                DiceGroup::Dice(d) => d.exact_pmf(explosion_depth, budget)?,
+            };
+            let pmf = match group.op() {
+                DiceOp::Add => pmf,
+                DiceOp::Sub => pmf.negate(),
+            };
+            total = total.convolve(&pmf, budget)?;
+        }
+        Ok(total.trimmed())
+    }
+
+    /// Work out the probability distribution of the bag exactly, on the base of 0-100% as
+    /// `make_frequency_distribution`, rather than by rolling. Every total from the lowest
+    /// possible to the highest possible is included, except at the ends of very wide bags, where
+    /// totals too unlikely for their chance to be represented (ie which would be 0%) are left
+    /// out.
+    ///
+    /// Exploding dice explode at most `Limits::max_explosion_depth` times each, as when rolled.
+    /// An error is returned for exploding dice which also drop or reroll, and for bags which would
+    /// take too long to work out (eg "1000d1000"), which can still be sampled.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("2d6".to_owned()).unwrap();
+    /// let distribution = bag.make_exact_distribution().unwrap();
+    /// assert!((distribution[&7] - 100.0 / 6.0).abs() < 1e-9);
+    /// assert!((distribution[&2] - 100.0 / 36.0).abs() < 1e-9);
+    /// assert_eq!(distribution.keys().cloned().collect::<Vec<_>>(), bag.get_range_as_list());
+    ///
+    /// // Rolling 4d6 and dropping the lowest gives 18 with probability 21/1296.
+    /// let bag: DiceBag = libazdice::parse("4d6dl1".to_owned()).unwrap();
+    /// let distribution = bag.make_exact_distribution().unwrap();
+    /// assert!((distribution[&18] - 2100.0 / 1296.0).abs() < 1e-9);
+    ///
+    /// let bag: DiceBag = libazdice::parse("4d6dl1!".to_owned()).unwrap();
+    /// assert!(bag.make_exact_distribution().is_err());
+    ///
+    /// // All 1s or all 44s on 210 dice are too unlikely to represent.
+    /// let bag: DiceBag = libazdice::parse("-210d44".to_owned()).unwrap();
+    /// let distribution = bag.make_exact_distribution().unwrap();
+    /// assert!(*distribution.keys().next().unwrap() > *bag.range().start());
+    /// assert!(*distribution.keys().last().unwrap() < *bag.range().end());
+    /// ```
+    pub fn make_exact_distribution(&self) -> Result<BTreeMap<i64, f64>, String> {
+        Ok(self.exact_pmf(&mut Budget::new())?.to_percentages())
+    }
+}
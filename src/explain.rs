@@ -0,0 +1,200 @@
+//! This module contains `DiceBag::explain`, which describes what each group of a `DiceBag` does
+//! in words.
+use super::distribution::*;
+
+/// "1 die" or "3 dice".
+fn dice_count(n: usize) -> String {
+    if n == 1 {
+        "1 die".to_owned()
+    } else {
+        format!("{} dice", n)
+    }
+}
+
+/// "the lowest die" or "the lowest 2 dice".
+fn end_dice(end: &str, n: usize) -> String {
+    if n == 1 {
+        format!("the {} die", end)
+    } else {
+        format!("the {} {} dice", end, n)
+    }
+}
+
+/// A range of totals, eg "3 to 18", "2 or more" or "-8 or less".
+fn range_text([low, high]: [Option<i64>; 2]) -> String {
+    match (low, high) {
+        (Some(low), Some(high)) if low == high => format!("always {}", low),
+        (Some(low), Some(high)) => format!("{} to {}", low, high),
+        (Some(low), None) => format!("{} or more", low),
+        (None, Some(high)) => format!("{} or less", high),
+        (None, None) => "any total".to_owned(),
+    }
+}
+
+impl Dice {
+    /// Describe, in order, the steps of rolling the dice.
+    fn explain(&self, explosion_depth: usize) -> String {
+        let sides = if self.size == 1 { "side" } else { "sides" };
+        let nested = self.nested.as_deref();
+        let rolled_count = nested.and_then(|n| n.count.as_ref());
+        let rolled_size = nested.and_then(|n| n.size.as_ref());
+        let mut steps = vec![match (self.pick.as_deref(), rolled_count, rolled_size) {
+            (Some(pick), _, _) => format!(
+                "roll {} and keep the {} total",
+                pick.bags
+                    .iter()
+                    .map(DiceBag::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" and "),
+                if pick.highest { "highest" } else { "lowest" }
+            ),
+            (None, None, None) => format!(
+                "roll {} with {} {}",
+                dice_count(self.count),
+                self.size,
+                sides
+            ),
+            (None, Some(count), None) => format!(
+                "roll {} for the number of dice, then roll that many dice with {} {}",
+                count, self.size, sides
+            ),
+            (None, None, Some(size)) => format!(
+                "roll {} for the number of sides, then roll {} with that many sides",
+                size,
+                dice_count(self.count)
+            ),
+            (None, Some(count), Some(size)) => format!(
+                "roll {} for the number of dice and {} for the number of sides, then roll them",
+                count, size
+            ),
+        }];
+
+        if self.explosive {
+            let highest = match rolled_size {
+                Some(_) => "its highest side".to_owned(),
+                None => self.size.to_string(),
+            };
+            steps.push(format!(
+                "roll again and add each die showing {} (at most {} times per die)",
+                highest, explosion_depth
+            ));
+        }
+
+        let reroll = match self.reroll {
+            ReRoll::IfAbove(rrt) => Some(("above", rrt)),
+            ReRoll::IfBelow(rrt) => Some(("below", rrt)),
+            ReRoll::Never => None,
+        };
+        if let Some((
+            side,
+            ReRollType {
+                count,
+                ex_threshold,
+            },
+        )) = reroll
+        {
+            steps.push(match count {
+                0 => "reroll nothing".to_owned(),
+                1 => format!("reroll the first die showing {} {}", side, ex_threshold),
+                n => format!(
+                    "reroll the first {} dice showing {} {}",
+                    n, side, ex_threshold
+                ),
+            });
+        }
+
+        match self.cutoff {
+            CutOff::Minimum(mn) => steps.push(format!("count any die below {} as {}", mn, mn)),
+            CutOff::Maximum(mx) => steps.push(format!("count any die above {} as {}", mx, mx)),
+            CutOff::Both(MinMax([mn, mx])) => steps.push(format!(
+                "count any die below {} as {} and above {} as {}",
+                mn, mn, mx, mx
+            )),
+            CutOff::Non => {}
+        }
+
+        match self.drop {
+            Drop::Lowest(n) if n > 0 => steps.push(format!("drop {}", end_dice("lowest", n))),
+            Drop::Highest(n) if n > 0 => steps.push(format!("drop {}", end_dice("highest", n))),
+            // A rolled count keeps a different number of dice each time.
+            Drop::Custom(ref keep) if rolled_count.is_some() => {
+                let low = keep.first().copied().unwrap_or(0);
+                let high = keep.last().map_or(0, |l| self.count.saturating_sub(l + 1));
+                steps.push(format!(
+                    "drop {} and {}",
+                    end_dice("lowest", low),
+                    end_dice("highest", high)
+                ))
+            }
+            Drop::Custom(ref keep) => {
+                steps.push(format!("keep {} from the middle", dice_count(keep.len())))
+            }
+            _ => {}
+        }
+
+        steps.push(
+            match self.op {
+                DiceOp::Add => "add the total",
+                DiceOp::Sub => "subtract the total",
+            }
+            .to_owned(),
+        );
+        steps.join(", ")
+    }
+}
+
+impl DiceBag {
+    /// Describe each group of the bag in words, with the totals it can add to the bag. There is a
+    /// line per group, in the order of the bag, and a last line with the range of the whole bag.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("4d6dl1rr1be2 - 1d4! + 3".to_owned()).unwrap();
+    /// assert_eq!(
+    ///     bag.explain(),
+    ///     vec![
+    ///         "4d6dl1rr1be2: roll 4 dice with 6 sides, reroll the first die showing below 2, \
+    ///          drop the lowest die, add the total (3 to 18)",
+    ///         "-1d4!: roll 1 die with 4 sides, roll again and add each die showing 4 \
+    ///          (at most 100 times per die), subtract the total (-1 or less)",
+    ///         "+3: add 3",
+    ///         "Total: 20 or less",
+    ///     ]
+    /// );
+    /// ```
+    pub fn explain(&self) -> Vec<String> {
+        let depth = self.limits().max_explosion_depth;
+        let mut lines = self
+            .dice
+            .iter()
+            .map(|group| {
+                let sign = match group.op() {
+                    DiceOp::Add => "",
+                    DiceOp::Sub => "-",
+                };
+                let label = group
+                    .label()
+                    .map_or_else(String::new, |l| format!(" [{}]", l));
+                match group {
+                    DiceGroup::Dice(d) => format!(
+                        "{}{}{}: {} ({})",
+                        sign,
+                        d,
+                        label,
+                        d.explain(depth),
+                        range_text(group.calculate_bounds(true))
+                    ),
+                    DiceGroup::Bonus(b) => match b.op {
+                        DiceOp::Add => format!("+{}{}: add {}", b.bonus, label, b.bonus),
+                        DiceOp::Sub => format!("-{}{}: subtract {}", b.bonus, label, b.bonus),
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        lines.push(format!(
+            "Total: {}",
+            range_text(self.calculate_bounds(true))
+        ));
+        lines
+    }
+}
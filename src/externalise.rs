@@ -1,10 +1,15 @@
 //! This submodule is for externing various parts of the module to C or C++
+use super::distribution::{seeded_rng, DiceBag, SeededRng};
 use super::parse::parse;
+use rand::{Rng, RngCore};
 
-use std::ffi::{CStr, CString};
+use std::ffi::{c_void, CStr, CString};
 use std::os::raw::c_char;
 use std::ptr;
 
+/// The error returned when a null `RngState` is passed in.
+const NULL_RNG: &str = "Random number generator is null.";
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 /// This structure returns a list of results in a mostly human readable format.
@@ -88,58 +93,120 @@ pub struct DistributionResult {
     pub err: *const CString,
 }
 
-#[no_mangle]
-/// A wrapper around `parse::parse` + `DiceBag::make_count_distribution` for C. As arguments it
-/// takes:
-///
-/// `input`: the equivalent of C `char *`, (a string of bytes).
-///
-/// `l`; the byte length of `input`.
-///
-/// `n`: The number of rolls required. It is recommended to use at least 1,000,000 for this value.
-///
-/// This function returns a `DistributionResult` containing a pointer to a `Distribution` if
-/// if succesful, or a pointer to a `CString` error if it fails.
-pub unsafe extern "C" fn parse_and_generate_distribution(
-    input: &*const c_char,
+/// An opaque random number generator which can be held by the calling environment. It is either
+/// seeded (and therefore reproducible across machines) or drives a C callback.
+/// It must be created by `rng_from_seed` or `rng_from_callback` and released with `free_rng`.
+pub struct RngState {
+    source: RngSource,
+}
+
+/// The possible sources of randomness behind a `RngState`.
+enum RngSource {
+    Seeded(Box<SeededRng>),
+    Callback(CallbackRng),
+}
+
+/// A random number generator which asks the calling environment for each random number.
+struct CallbackRng {
+    callback: extern "C" fn(*mut c_void) -> u64,
+    user_data: *mut c_void,
+}
+
+impl RngCore for CallbackRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.callback)(self.user_data)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl RngCore for RngState {
+    fn next_u32(&mut self) -> u32 {
+        match self.source {
+            RngSource::Seeded(ref mut r) => r.next_u32(),
+            RngSource::Callback(ref mut r) => r.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self.source {
+            RngSource::Seeded(ref mut r) => r.next_u64(),
+            RngSource::Callback(ref mut r) => r.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self.source {
+            RngSource::Seeded(ref mut r) => r.fill_bytes(dest),
+            RngSource::Callback(ref mut r) => r.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// Box an error message as a `CString` for the calling environment.
+fn error_to_c(e: &str) -> *const CString {
+    let e: Vec<u8> = e.as_bytes().to_vec();
+    // If future me messes up the error message, this should catch.
+    let e = if e.contains(&0) {
+        b"Error parsing initial roll".to_vec()
+    } else {
+        e
+    };
+    let err = Box::new(unsafe { CString::from_vec_unchecked(e) });
+    Box::into_raw(err)
+}
+
+/// Read a dice string from C and parse it into a `DiceBag`.
+unsafe fn parse_from_c(input: *const c_char) -> Result<DiceBag, *const CString> {
+    // A little dangerous. But what can one expect from C-chan?
+    let input_string = match CStr::from_ptr(input).to_str() {
+        Ok(s) => s.to_owned(),
+        Err(_) => return Err(error_to_c("Invalid dice string in calling environment.")),
+    };
+    parse(input_string).map_err(|e| error_to_c(&e))
+}
+
+/// The shared body of `parse_and_generate_distribution` and its seeded variant.
+unsafe fn generate_distribution<R: Rng + ?Sized>(
+    input: *const c_char,
     l: u64,
     n: u64,
+    rng: &mut R,
 ) -> DistributionResult {
-    let input = input.to_owned();
-
     let mut final_result = DistributionResult {
         succ: ptr::null_mut(),
         err: ptr::null(),
     };
-    // A little dangerous. But what can one expect from C-chan?
-    // let input_string = unsafe { CString::from_raw(input) };
-    let input_string = if let Ok(s) = CStr::from_ptr(input).to_str() {
-        s.to_owned()
-    } else {
-        let err = b"Invalid dice string in calling environment.".to_vec();
-        let err = Box::new(CString::from_vec_unchecked(err));
-        final_result.err = Box::into_raw(err);
-        return final_result;
-    };
 
-    let dice = match parse(input_string) {
-        // Error is fully dealt with. If future me messes up the error message, this should catch.
+    let dice = match parse_from_c(input) {
         Err(e) => {
-            let e: Vec<u8> = e.as_bytes().to_vec();
-            let e = if e.contains(&0) {
-                b"Error parsing initial roll".to_vec()
-            } else {
-                e
-            };
-            let err = Box::new(CString::from_vec_unchecked(e));
-            final_result.err = Box::into_raw(err);
+            final_result.err = e;
             return final_result;
         }
         Ok(r) => r,
     };
 
-    let mut roll_and_frequencies = dice
-        .make_count_distribution(n as usize)
+    let roll_and_frequencies = dice
+        .make_count_distribution_with_rng(n as usize, rng)
         .into_iter()
         .map(|(x, y)| XY {
             value: x,
@@ -152,7 +219,7 @@ pub unsafe extern "C" fn parse_and_generate_distribution(
     let count = roll_and_frequencies.len() as u64; // Cannot be odd!
 
     let distribution = Distribution {
-        rolls_and_frequency: roll_and_frequencies.as_mut_ptr(),
+        rolls_and_frequency: Box::into_raw(roll_and_frequencies.into_boxed_slice()) as *const XY,
         count,
         len_input: l,
         input,
@@ -162,54 +229,21 @@ pub unsafe extern "C" fn parse_and_generate_distribution(
     final_result
 }
 
-#[no_mangle]
-/// A wrapper for `parse::parse` and `DiceBag::roll`, allowing parsing a string from C and generating
-/// N rolls. As arguments it takes:
-///
-/// `input`: the equivalent of C `char *`, (a string of bytes).
-///
-/// `l`; the byte length of `input`.
-///
-/// `n`: The number of rolls required.
-///
-/// This function returns a `ResultListRolls`, which either gives a complex report of subrolls
-/// if succesful (pointer to `ListRolls`), or a pointer to an error string otherwise.
-///
-/// NB: This function is fairly dangerous as it can fail if the input from C/C++ cannot be
-/// expressed as a rust String, but what's a dice roller without a little risk?
-pub unsafe extern "C" fn parse_and_roll_n_times(
-    input: &*const c_char,
+/// The shared body of `parse_and_roll_n_times` and its seeded variant.
+unsafe fn roll_n_times<R: Rng + ?Sized>(
+    input: *const c_char,
     l: u64,
     n: u64,
+    rng: &mut R,
 ) -> ResultListRolls {
-    let input = input.to_owned();
-
     let mut final_result = ResultListRolls {
         succ: ptr::null(),
         err: ptr::null(),
     };
-    // A little dangerous. But what can one expect from C-chan?
-    // let input_string = unsafe { CString::from_raw(input) };
-    let input_string = if let Ok(s) = CStr::from_ptr(input).to_str() {
-        s.to_owned()
-    } else {
-        let err = b"Invalid dice string in calling environment.".to_vec();
-        let err = Box::new(CString::from_vec_unchecked(err));
-        final_result.err = Box::into_raw(err);
-        return final_result;
-    };
 
-    let dice = match parse(input_string) {
-        // Error is fully dealt with. If future me messes up the error message, this should catch.
+    let dice = match parse_from_c(input) {
         Err(e) => {
-            let e: Vec<u8> = e.as_bytes().to_vec();
-            let e = if e.contains(&0) {
-                b"Error parsing initial roll".to_vec()
-            } else {
-                e
-            };
-            let err = Box::new(CString::from_vec_unchecked(e));
-            final_result.err = Box::into_raw(err);
+            final_result.err = e;
             return final_result;
         }
         Ok(r) => r,
@@ -217,7 +251,7 @@ pub unsafe extern "C" fn parse_and_roll_n_times(
 
     let mut results = Vec::with_capacity(n as usize);
     for _ in 0..n {
-        results.push(dice.roll());
+        results.push(dice.roll_with_rng(rng));
     }
 
     let results: Vec<_> = results
@@ -229,21 +263,114 @@ pub unsafe extern "C" fn parse_and_roll_n_times(
                 len_input: l,
                 input,
                 len_dice_groups: results.len() as u64,
-                groups: results.as_ptr(),
-                bonus: res.get_bonus().total() as i64,
-                total: res.total() as i64,
+                groups: Box::into_raw(results.into_boxed_slice()) as *const i64,
+                bonus: res.get_bonus().total(),
+                total: res.total(),
             }
         })
         .collect();
-    println!("Results construced!");
     let len = results.len() as u64;
-    let results = results.as_ptr();
+    let results = Box::into_raw(results.into_boxed_slice()) as *const Rolls;
     let results = Box::new(ListRolls { len, results });
 
     final_result.succ = Box::into_raw(results);
     final_result
 }
 
+/// The shared body of `parse_and_roll` and its seeded variants.
+unsafe fn roll_once<R: Rng + ?Sized>(input: *const c_char, rng: &mut R) -> SingleRollResult {
+    let mut final_result = SingleRollResult {
+        roll: 0,
+        err: ptr::null(),
+    };
+
+    match parse_from_c(input) {
+        // An error at the parsing stage is good here.
+        Err(e) => final_result.err = e,
+        Ok(dice) => final_result.roll = dice.roll_with_rng(rng).total(),
+    }
+    final_result
+}
+
+#[no_mangle]
+/// A wrapper around `parse::parse` + `DiceBag::make_count_distribution` for C. As arguments it
+/// takes:
+///
+/// `input`: the equivalent of C `char *`, (a string of bytes).
+///
+/// `l`; the byte length of `input`.
+///
+/// `n`: The number of rolls required. It is recommended to use at least 1,000,000 for this value.
+///
+/// This function returns a `DistributionResult` containing a pointer to a `Distribution` if
+/// if succesful, or a pointer to a `CString` error if it fails.
+pub unsafe extern "C" fn parse_and_generate_distribution(
+    input: &*const c_char,
+    l: u64,
+    n: u64,
+) -> DistributionResult {
+    generate_distribution(*input, l, n, &mut rand::thread_rng())
+}
+
+#[no_mangle]
+/// The same as `parse_and_generate_distribution`, but the rolls are drawn from `rng`, which must
+/// have been created by `rng_from_seed` or `rng_from_callback`. A null `rng` gives an error.
+pub unsafe extern "C" fn parse_and_generate_distribution_with_rng(
+    input: &*const c_char,
+    l: u64,
+    n: u64,
+    rng: *mut RngState,
+) -> DistributionResult {
+    match rng.as_mut() {
+        Some(rng) => generate_distribution(*input, l, n, rng),
+        None => DistributionResult {
+            succ: ptr::null_mut(),
+            err: error_to_c(NULL_RNG),
+        },
+    }
+}
+
+#[no_mangle]
+/// A wrapper for `parse::parse` and `DiceBag::roll`, allowing parsing a string from C and generating
+/// N rolls. As arguments it takes:
+///
+/// `input`: the equivalent of C `char *`, (a string of bytes).
+///
+/// `l`; the byte length of `input`.
+///
+/// `n`: The number of rolls required.
+///
+/// This function returns a `ResultListRolls`, which either gives a complex report of subrolls
+/// if succesful (pointer to `ListRolls`), or a pointer to an error string otherwise.
+///
+/// NB: This function is fairly dangerous as it can fail if the input from C/C++ cannot be
+/// expressed as a rust String, but what's a dice roller without a little risk?
+pub unsafe extern "C" fn parse_and_roll_n_times(
+    input: &*const c_char,
+    l: u64,
+    n: u64,
+) -> ResultListRolls {
+    roll_n_times(*input, l, n, &mut rand::thread_rng())
+}
+
+#[no_mangle]
+/// The same as `parse_and_roll_n_times`, but the rolls are drawn from `rng`, which must have been
+/// created by `rng_from_seed` or `rng_from_callback`. A null `rng` gives an error.
+pub unsafe extern "C" fn parse_and_roll_n_times_with_rng(
+    input: &*const c_char,
+    l: u64,
+    n: u64,
+    rng: *mut RngState,
+) -> ResultListRolls {
+    match rng.as_mut() {
+        Some(rng) => roll_n_times(*input, l, n, rng),
+        None => ResultListRolls {
+            succ: ptr::null(),
+            err: error_to_c(NULL_RNG),
+        },
+    }
+}
+
 #[no_mangle]
 /// A wrapper for `parse::parse` and `DiceBag::roll`, allowing parsing a string from C and generating
 /// a single roll. As arguments it takes:
@@ -257,40 +384,77 @@ pub unsafe extern "C" fn parse_and_roll_n_times(
 /// NB: This function is fairly dangerous as it can fail if the input from C/C++ cannot be
 /// expressed as a rust String, but what's a dice roller without a little risk?
 pub unsafe extern "C" fn parse_and_roll(input: &*const c_char) -> SingleRollResult {
-    let input = input.to_owned();
-    let mut final_result = SingleRollResult {
-        roll: 0,
-        err: ptr::null(),
-    };
+    roll_once(*input, &mut rand::thread_rng())
+}
 
-    let input_string = if let Ok(s) = CStr::from_ptr(input).to_str() {
-        s.to_owned()
-    } else {
-        let err = b"Invalid dice string in calling environment.".to_vec();
-        let err = Box::new(CString::from_vec_unchecked(err));
-        final_result.err = Box::into_raw(err);
-        return final_result;
+#[no_mangle]
+/// The same as `parse_and_roll`, but the roll is drawn from `rng`, which must have been created by
+/// `rng_from_seed` or `rng_from_callback`. A null `rng` gives an error.
+///
+/// Successive calls with the same `rng` continue its sequence, so two peers which create their
+/// `rng` from the same seed and make the same calls get the same rolls.
+pub unsafe extern "C" fn parse_and_roll_with_rng(
+    input: &*const c_char,
+    rng: *mut RngState,
+) -> SingleRollResult {
+    match rng.as_mut() {
+        Some(rng) => roll_once(*input, rng),
+        None => SingleRollResult {
+            roll: 0,
+            err: error_to_c(NULL_RNG),
+        },
+    }
+}
+
+#[no_mangle]
+/// The same as `parse_and_roll`, but the roll is made with a fresh generator seeded with `seed`.
+/// The same string and seed always give the same result.
+pub unsafe extern "C" fn parse_and_roll_seeded(
+    input: &*const c_char,
+    seed: u64,
+) -> SingleRollResult {
+    roll_once(*input, &mut seeded_rng(seed))
+}
+
+#[no_mangle]
+/// Create a seeded random number generator. The same seed gives the same sequence of rolls on
+/// every machine. The result must be released with `free_rng`.
+pub extern "C" fn rng_from_seed(seed: u64) -> *mut RngState {
+    let state = RngState {
+        source: RngSource::Seeded(Box::new(seeded_rng(seed))),
     };
+    Box::into_raw(Box::new(state))
+}
 
-    let dice = match parse(input_string) {
-        Err(e) => {
-            // An error at the parsing stage is good here.
-            // Of course the error string must make sense.
-            let e: Vec<u8> = e.as_bytes().to_vec();
-            let e = if e.contains(&0) {
-                b"Error parsing initial roll".to_vec()
-            } else {
-                e
-            };
-            let err = Box::new(CString::from_vec_unchecked(e));
-            final_result.err = Box::into_raw(err);
-            return final_result;
-        }
-        Ok(r) => r,
+#[no_mangle]
+/// Create a random number generator which calls `callback(user_data)` every time it needs 64
+/// random bits. The callback should return uniformly distributed `uint64_t` values.
+/// `user_data` is passed through untouched and may be null. The result must be released with
+/// `free_rng`. A null `callback` gives a null pointer.
+pub extern "C" fn rng_from_callback(
+    callback: Option<extern "C" fn(*mut c_void) -> u64>,
+    user_data: *mut c_void,
+) -> *mut RngState {
+    let callback = match callback {
+        Some(callback) => callback,
+        None => return ptr::null_mut(),
     };
+    let state = RngState {
+        source: RngSource::Callback(CallbackRng {
+            callback,
+            user_data,
+        }),
+    };
+    Box::into_raw(Box::new(state))
+}
 
-    final_result.roll = dice.roll().total() as i64;
-    final_result
+#[no_mangle]
+/// Release a random number generator created by `rng_from_seed` or `rng_from_callback`.
+/// Passing a null pointer does nothing.
+pub unsafe extern "C" fn free_rng(rng: *mut RngState) {
+    if !rng.is_null() {
+        drop(Box::from_raw(rng));
+    }
 }
 
 #[no_mangle]
@@ -0,0 +1,302 @@
+//! This module contains the totals and distributions of the labelled groups of a bag (eg the
+//! "fire" of "1d8+3 [slashing] + 2d6 [fire]"), and the `RuleSet`s which change those totals
+//! afterwards, such as resistances to types of damage.
+//! ```
+//! use libazdice::distribution::*;
+//! use libazdice::labels::RuleSet;
+//!
+//! let bag: DiceBag = libazdice::parse("1d1+3 [slashing] + 2d1 [fire] + 1 [poison]".to_owned())
+//!     .unwrap();
+//! let roll = bag.roll();
+//! assert_eq!(
+//!     roll.totals_by_label(),
+//!     [(Some("slashing"), 4), (Some("fire"), 2), (Some("poison"), 1)]
+//! );
+//!
+//! let rules: RuleSet = "halve fire, ignore poison".parse().unwrap();
+//! assert_eq!(
+//!     rules.apply(&roll),
+//!     [(Some("slashing"), 4), (Some("fire"), 1), (Some("poison"), 0)]
+//! );
+//! assert_eq!(rules.total(&roll), 5);
+//! ```
+use super::distribution::*;
+use super::exact::{Budget, Pmf};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// A label as `RuleSet`s match it, ignoring case and the spaces around it.
+fn normalize_label(label: &str) -> String {
+    label.trim().to_lowercase()
+}
+
+/// Whether two labels (or the lack of one) are the same label, as `RuleSet`s match them.
+fn same_label(a: Option<&str>, b: Option<&str>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => normalize_label(a) == normalize_label(b),
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+impl RollResults {
+    /// The total of each label, in the order the labels first appear among the dice and then
+    /// the boni. Groups without a label are totalled under `None`. Labels which differ only in
+    /// case (eg "Fire" and "fire") are one label, written as it first appears.
+    pub fn totals_by_label(&self) -> Vec<(Option<&str>, i64)> {
+        fn add<'a>(totals: &mut Vec<(Option<&'a str>, i64)>, label: Option<&'a str>, value: i64) {
+            match totals.iter_mut().find(|(l, _)| same_label(*l, label)) {
+                Some((_, total)) => *total += value,
+                None => totals.push((label, value)),
+            }
+        }
+        let mut totals = Vec::new();
+        for group in self.dice_groups.iter() {
+            add(&mut totals, group.label(), group.total);
+        }
+        let labelled = &self.bonus.labelled;
+        if self.bonus.boni.len() > labelled.len() {
+            let unlabelled = self.bonus.total - labelled.iter().map(|(_, b)| b).sum::<i64>();
+            add(&mut totals, None, unlabelled);
+        }
+        for (label, bonus) in labelled.iter() {
+            add(&mut totals, Some(label), *bonus);
+        }
+        totals
+    }
+}
+
+impl DiceBag {
+    /// Split the bag into a bag for each label, in the order the labels first appear, with the
+    /// groups without a label under `None`. Labels are matched as in `totals_by_label`. Each bag
+    /// keeps the `Limits` of this one, so their distributions can be made in the usual ways.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("2d6+3 [fire] - 1d4 [cold] + 1d20".to_owned()).unwrap();
+    /// let split = bag.split_by_label();
+    /// let shown: Vec<_> = split.iter().map(|(l, b)| (*l, b.to_string())).collect();
+    /// assert_eq!(
+    ///     shown,
+    ///     [
+    ///         (Some("fire"), "2d6 + 3 [fire]".to_owned()),
+    ///         (Some("cold"), "-1d4 [cold]".to_owned()),
+    ///         (None, "1d20".to_owned()),
+    ///     ]
+    /// );
+    /// let fire = split[0].1.make_exact_distribution().unwrap();
+    /// assert!((fire[&10] - 100.0 / 6.0).abs() < 1e-9);
+    /// ```
+    pub fn split_by_label(&self) -> Vec<(Option<&str>, DiceBag)> {
+        let mut split: Vec<(Option<&str>, Vec<DiceGroup>)> = Vec::new();
+        for group in self.dice.iter() {
+            match split
+                .iter_mut()
+                .find(|(l, _)| same_label(*l, group.label()))
+            {
+                Some((_, groups)) => groups.push(group.clone()),
+                None => split.push((group.label(), vec![group.clone()])),
+            }
+        }
+        split
+            .into_iter()
+            .map(|(label, groups)| {
+                let mut bag = DiceBag::from_dice(groups);
+                bag.limits = self.limits;
+                (label, bag)
+            })
+            .collect()
+    }
+
+    /// Work out the distribution of the total exactly (as `make_exact_distribution`) once the
+    /// total of each label has been changed by `rules`.
+    /// ```
+    /// use libazdice::distribution::*;
+    /// use libazdice::labels::RuleSet;
+    ///
+    /// let bag: DiceBag = libazdice::parse("1d4 [fire] + 1d4 [poison]".to_owned()).unwrap();
+    /// let rules = RuleSet::new().halve("fire").ignore("poison");
+    /// let distribution = bag.make_exact_distribution_with_rules(&rules).unwrap();
+    /// assert_eq!(distribution.keys().cloned().collect::<Vec<_>>(), [0, 1, 2]);
+    /// assert!((distribution[&1] - 50.0).abs() < 1e-9);
+    /// ```
+    pub fn make_exact_distribution_with_rules(
+        &self,
+        rules: &RuleSet,
+    ) -> Result<BTreeMap<i64, f64>, String> {
+        let mut budget = Budget::new();
+        let mut total = Pmf::constant(0);
+        for (label, bag) in self.split_by_label() {
+            let mut pmf = bag.exact_pmf(&mut budget)?;
+            if let Some(rule) = label.and_then(|l| rules.rule_for(l)) {
+                pmf = pmf.map(|x| rule.apply(x), &mut budget)?;
+            }
+            total = total.convolve(&pmf, &mut budget)?;
+        }
+        Ok(total.to_percentages())
+    }
+}
+
+/// What a `RuleSet` does to the total of a label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// Halve the total, rounding down (eg resistance).
+    Halve,
+    /// Make the total 0 (eg immunity).
+    Ignore,
+    /// Double the total (eg vulnerability).
+    Double,
+    /// Take this much off a positive total, but not below 0 (eg damage reduction).
+    Reduce(u64),
+}
+
+impl Rule {
+    /// The total of a label once the rule is applied.
+    pub fn apply(self, total: i64) -> i64 {
+        match self {
+            Rule::Halve => total.div_euclid(2),
+            Rule::Ignore => 0,
+            Rule::Double => total.saturating_mul(2),
+            Rule::Reduce(n) if total > 0 => (total as u64).saturating_sub(n) as i64,
+            Rule::Reduce(_) => total,
+        }
+    }
+}
+
+/// Rules which change the totals of labels after a roll, eg "halve fire, ignore poison". Labels
+/// are matched without regard to case, and a later rule for a label replaces an earlier one.
+/// Groups without a label, or with a label that has no rule, are left as they are.
+///
+/// As text, the rules are separated by commas, and each is "halve", "ignore" or "double" and a
+/// label, or "reduce", a label, "by" and an amount of 0 or more.
+/// ```
+/// use libazdice::labels::{Rule, RuleSet};
+///
+/// let rules: RuleSet = "Halve Fire, ignore poison, reduce cold iron by 3".parse().unwrap();
+/// assert_eq!(rules, RuleSet::new().halve("fire").ignore("poison").reduce("cold iron", 3));
+/// assert_eq!(rules.to_string(), "halve fire, ignore poison, reduce cold iron by 3");
+/// assert_eq!(rules.rule_for("FIRE"), Some(Rule::Halve));
+/// assert_eq!(rules.rule_for("acid"), None);
+///
+/// assert!("halve".parse::<RuleSet>().is_err());
+/// assert!("sing fire".parse::<RuleSet>().is_err());
+/// assert!("reduce fire by -3".parse::<RuleSet>().is_err());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleSet {
+    rules: Vec<(String, Rule)>,
+}
+
+impl RuleSet {
+    /// A rule set with no rules, which changes nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `rule` for `label`.
+    pub fn rule(mut self, label: &str, rule: Rule) -> Self {
+        let label = normalize_label(label);
+        self.rules.retain(|(l, _)| *l != label);
+        self.rules.push((label, rule));
+        self
+    }
+
+    /// Halve the total of `label`, rounding down.
+    pub fn halve(self, label: &str) -> Self {
+        self.rule(label, Rule::Halve)
+    }
+
+    /// Make the total of `label` 0.
+    pub fn ignore(self, label: &str) -> Self {
+        self.rule(label, Rule::Ignore)
+    }
+
+    /// Double the total of `label`.
+    pub fn double(self, label: &str) -> Self {
+        self.rule(label, Rule::Double)
+    }
+
+    /// Take `n` off the total of `label`, but not below 0.
+    pub fn reduce(self, label: &str, n: u64) -> Self {
+        self.rule(label, Rule::Reduce(n))
+    }
+
+    /// The rule for `label`, if there is one.
+    pub fn rule_for(&self, label: &str) -> Option<Rule> {
+        let label = normalize_label(label);
+        self.rules
+            .iter()
+            .find(|(l, _)| *l == label)
+            .map(|(_, r)| *r)
+    }
+
+    /// The total of each label of a roll (see `RollResults::totals_by_label`) once the rules are
+    /// applied.
+    pub fn apply<'a>(&self, roll: &'a RollResults) -> Vec<(Option<&'a str>, i64)> {
+        roll.totals_by_label()
+            .into_iter()
+            .map(
+                |(label, total)| match label.and_then(|l| self.rule_for(l)) {
+                    Some(rule) => (label, rule.apply(total)),
+                    None => (label, total),
+                },
+            )
+            .collect()
+    }
+
+    /// The total of a roll once the rules are applied.
+    pub fn total(&self, roll: &RollResults) -> i64 {
+        self.apply(roll).iter().map(|(_, total)| total).sum()
+    }
+}
+
+impl Display for RuleSet {
+    /// The rules as text, which parses back into the same rules.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, (label, rule)) in self.rules.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match rule {
+                Rule::Halve => write!(f, "halve {}", label)?,
+                Rule::Ignore => write!(f, "ignore {}", label)?,
+                Rule::Double => write!(f, "double {}", label)?,
+                Rule::Reduce(n) => write!(f, "reduce {} by {}", label, n)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RuleSet {
+    type Err = String;
+
+    /// Parse rules such as "halve fire, ignore poison, reduce slashing by 3".
+    fn from_str(s: &str) -> Result<RuleSet, String> {
+        let mut rules = RuleSet::new();
+        for text in s.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            let (verb, label) = text
+                .split_once(char::is_whitespace)
+                .map(|(v, l)| (v.to_lowercase(), l.trim()))
+                .ok_or_else(|| format!("Rule \"{}\" needs a label.", text))?;
+            let rule = match verb.as_str() {
+                "halve" => Rule::Halve,
+                "ignore" => Rule::Ignore,
+                "double" => Rule::Double,
+                "reduce" => {
+                    let (label, n) = label
+                        .rsplit_once(" by ")
+                        .and_then(|(l, n)| Some((l, n.trim().parse::<u64>().ok()?)))
+                        .ok_or_else(|| {
+                            format!("Rule \"{}\" needs \"by\" and an amount of 0 or more.", text)
+                        })?;
+                    rules = rules.reduce(label, n);
+                    continue;
+                }
+                _ => return Err(format!("Unknown rule \"{}\".", verb)),
+            };
+            rules = rules.rule(label, rule);
+        }
+        Ok(rules)
+    }
+}
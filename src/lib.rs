@@ -16,9 +16,27 @@
 #![allow(clippy::needless_range_loop)]
 #![allow(clippy::comparison_chain)]
 
+pub mod builder;
+pub mod cache;
+pub mod chart;
 pub mod distribution;
+mod exact;
+mod explain;
 pub mod externalise;
+pub mod labels;
+mod nested;
+mod normalize;
 pub mod parse;
+mod pick;
+pub mod repl;
+#[cfg(feature = "serde")]
+pub mod schema;
+pub mod svg;
+pub mod symbols;
+pub mod systems;
+pub mod tables;
 mod tests;
+pub mod validation;
+pub mod variables;
 
 pub use parse::parse;
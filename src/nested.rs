@@ -0,0 +1,198 @@
+//! This module contains dice whose count or sides are rolled, such as "(1d4)d6" (1d4 dice with
+//! 6 sides) or "2d(1d4 + 2)". The inner dice are rolled first, every time the group is rolled.
+//!
+//! The `count` and `size` of such `Dice` hold the smallest values the inner dice can roll, so
+//! that the clauses (eg "dl1") are checked against the fewest dice and sides, where they are most
+//! likely to be invalid. `Dice::resolved` makes the plain dice for a rolled count and size.
+use super::distribution::*;
+use super::exact::{Budget, Pmf};
+use super::validation::{DiceError, Limits};
+use rand::Rng;
+use std::convert::TryFrom;
+
+/// The dice rolled for the count or the sides of a group, eg the "(1d4)" of "(1d4)d6".
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Nested {
+    pub(crate) count: Option<DiceBag>,
+    pub(crate) size: Option<DiceBag>,
+}
+
+/// The lowest and highest total of `bag`, with `None` for an unbounded end.
+fn bounds(bag: &DiceBag) -> [Option<i64>; 2] {
+    bag.calculate_bounds(true)
+}
+
+impl Nested {
+    /// Roll the inner dice, returning the plain dice to roll and the rolls of the count and the
+    /// sides.
+    pub(crate) fn roll<R: Rng + ?Sized>(
+        &self,
+        dice: &Dice,
+        rng: &mut R,
+    ) -> (Dice, Option<RollResults>, Option<RollResults>) {
+        let count_roll = self.count.as_ref().map(|bag| bag.roll_with_rng(rng));
+        let size_roll = self.size.as_ref().map(|bag| bag.roll_with_rng(rng));
+        let resolved = dice.resolved(
+            count_roll
+                .as_ref()
+                .map_or(dice.count as i64, RollResults::total),
+            size_roll.as_ref().map_or(dice.size, RollResults::total),
+        );
+        (resolved, count_roll, size_roll)
+    }
+
+    /// As `roll`, but only rolling the totals of the inner dice. For the same random number
+    /// generator, this gives the same dice as `roll`.
+    pub(crate) fn roll_total<R: Rng + ?Sized>(
+        &self,
+        dice: &Dice,
+        rng: &mut R,
+        buffer: &mut RollBuffer,
+    ) -> Dice {
+        let count = self.count.as_ref().map_or(dice.count as i64, |bag| {
+            bag.roll_total_with_rng(rng, buffer)
+        });
+        let size = self
+            .size
+            .as_ref()
+            .map_or(dice.size, |bag| bag.roll_total_with_rng(rng, buffer));
+        dice.resolved(count, size)
+    }
+}
+
+impl Dice {
+    /// The plain dice (with nothing rolled for the count or sides) with `count` dice of `size`
+    /// sides. A kept run of dice keeps the same number dropped from each end.
+    pub(crate) fn resolved(&self, count: i64, size: i64) -> Dice {
+        let count = usize::try_from(count).unwrap_or(0);
+        let mut dice = self.clone();
+        dice.nested = None;
+        dice.size = size;
+        if let Drop::Custom(ref keep) = self.drop {
+            if let (Some(&low), Some(&last)) = (keep.first(), keep.last()) {
+                let high = self.count.saturating_sub(last + 1);
+                dice.drop = Drop::custom((low..count.saturating_sub(high).max(low)).collect());
+            }
+        }
+        dice.count = count;
+        dice
+    }
+
+    /// The plain dice with the fewest dice and sides, and those with the most (`None` if the
+    /// count or sides are unbounded).
+    pub(crate) fn corners(&self) -> (Dice, Option<Dice>) {
+        let (count, size) = match self.nested {
+            Some(ref nested) => (
+                nested.count.as_ref().map(bounds),
+                nested.size.as_ref().map(bounds),
+            ),
+            None => (None, None),
+        };
+        let low_count = count.and_then(|[low, _]| low).unwrap_or(self.count as i64);
+        let low_size = size.and_then(|[low, _]| low).unwrap_or(self.size);
+        let high_count = count.map_or(Some(self.count as i64), |[_, high]| high);
+        let high_size = size.map_or(Some(self.size), |[_, high]| high);
+        let high = match (high_count, high_size) {
+            (Some(c), Some(s)) => Some(self.resolved(c, s)),
+            _ => None,
+        };
+        (self.resolved(low_count, low_size), high)
+    }
+
+    /// The lowest and highest totals of the dice, before their operation is applied.
+    pub(crate) fn nested_bounds(&self, explode: bool) -> (Option<i64>, Option<i64>) {
+        // Every die shows at least 1, so more dice or more sides never lower the total.
+        let bounds = |mut dice: Dice| {
+            dice.op = DiceOp::Add;
+            DiceGroup::Dice(dice).calculate_bounds(explode)
+        };
+        let (low, high) = self.corners();
+        (bounds(low)[0], high.and_then(|high| bounds(high)[1]))
+    }
+
+    /// Check the inner dice, and the dice with the fewest and the most dice and sides.
+    pub(crate) fn validate_nested(&self, nested: &Nested) -> Result<(), DiceError> {
+        for bag in nested.count.iter().chain(nested.size.iter()) {
+            bag.validate()?;
+        }
+        if let Some([Some(low), _]) = nested.count.as_ref().map(bounds) {
+            if low < 0 {
+                return Err(DiceError::NegativeCount(low));
+            }
+        }
+        if let Some([Some(low), _]) = nested.size.as_ref().map(bounds) {
+            if low < 1 {
+                return Err(DiceError::InvalidSize(low));
+            }
+        }
+        let (low, high) = self.corners();
+        low.validate()?;
+        match high {
+            Some(high) => high.validate(),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the dice with the most dice and sides against `limits`, returning the most dice
+    /// rolled by one roll, counting the inner dice.
+    pub(crate) fn check_nested_limits(
+        &self,
+        nested: &Nested,
+        limits: &Limits,
+    ) -> Result<usize, DiceError> {
+        let mut rolls = 0_usize;
+        for bag in nested.count.iter().chain(nested.size.iter()) {
+            for group in bag.dice.iter() {
+                if let DiceGroup::Dice(d) = group {
+                    rolls = rolls.saturating_add(d.check_limits(limits)?);
+                }
+            }
+        }
+        match self.corners().1 {
+            Some(high) => Ok(rolls.saturating_add(high.check_limits(limits)?)),
+            // Exploding dice can roll any count or sides.
+            None if nested.count.as_ref().is_some_and(|bag| !bag.is_bounded()) => {
+                Err(DiceError::TooManyDice {
+                    count: usize::MAX,
+                    max: limits.max_dice_per_group,
+                })
+            }
+            None => Err(DiceError::TooManySides {
+                size: i64::MAX,
+                max: limits.max_sides,
+            }),
+        }
+    }
+
+    /// The largest magnitude the dice can total. See `Dice::checked_magnitude`.
+    pub(crate) fn nested_magnitude(&self, explosion_depth: usize) -> Option<i64> {
+        self.corners().1?.checked_magnitude(explosion_depth)
+    }
+
+    /// The exact distribution of the dice, before their operation is applied: the distribution
+    /// of each count and size, weighted by the chance of rolling them.
+    pub(crate) fn compound_pmf(
+        &self,
+        nested: &Nested,
+        explosion_depth: usize,
+        budget: &mut Budget,
+    ) -> Result<Pmf, String> {
+        let inner = |bag: &Option<DiceBag>, value: i64, budget: &mut Budget| match bag {
+            Some(bag) => bag.exact_pmf(budget),
+            None => Ok(Pmf::constant(value)),
+        };
+        let counts = inner(&nested.count, self.count as i64, budget)?;
+        let sizes = inner(&nested.size, self.size, budget)?;
+
+        let mut total = Pmf::empty();
+        for (count, p) in counts.iter().filter(|(_, p)| *p > 0.0) {
+            for (size, q) in sizes.iter().filter(|(_, q)| *q > 0.0) {
+                let pmf = self
+                    .resolved(count, size)
+                    .exact_pmf(explosion_depth, budget)?;
+                total.add_scaled(&pmf, p * q, 0, budget)?;
+            }
+        }
+        Ok(total)
+    }
+}
@@ -0,0 +1,168 @@
+//! This module contains `DiceBag::normalize`, which puts a `DiceBag` into a canonical form so that
+//! bags which roll the same way compare as equal.
+use super::distribution::*;
+use std::cmp::{Ordering, Reverse};
+
+impl Dice {
+    /// Remove clauses which do nothing (eg "dl0" or "rr0be2"), and write a kept run of dice
+    /// which reaches either end as a single drop clause (eg "dl1dh0" as "dl1").
+    fn simplify(&mut self) {
+        if let Some(ref mut pick) = self.pick {
+            for bag in pick.bags.iter_mut() {
+                *bag = bag.normalize();
+            }
+        }
+        if let Some(ref mut nested) = self.nested {
+            for bag in nested.count.iter_mut().chain(nested.size.iter_mut()) {
+                *bag = bag.normalize();
+            }
+            // Dice rolled for a count or sides which can only be one value are not dice at all.
+            let fixed = |bag: &DiceBag| bag.dice.iter().all(|g| matches!(g, DiceGroup::Bonus(_)));
+            if nested.count.iter().chain(nested.size.iter()).all(fixed) {
+                *self = self.corners().0;
+            }
+        }
+        let drop = match self.drop {
+            Drop::Lowest(0) | Drop::Highest(0) => Drop::Non,
+            // Exploding dice keep a fixed number of dice with a kept run, so it is left alone.
+            Drop::Custom(ref keep) if !keep.is_empty() && !self.explosive => {
+                let low = keep[0];
+                let high = self.count.saturating_sub(keep[keep.len() - 1] + 1);
+                match (low, high) {
+                    (0, 0) => Drop::Non,
+                    (low, 0) => Drop::Lowest(low),
+                    (0, high) => Drop::Highest(high),
+                    _ => Drop::Custom(keep.clone()),
+                }
+            }
+            ref drop => drop.clone(),
+        };
+        self.drop = drop;
+
+        match self.reroll {
+            ReRoll::IfAbove(ReRollType { count: 0, .. })
+            | ReRoll::IfBelow(ReRollType { count: 0, .. }) => self.reroll = ReRoll::Never,
+            _ => {}
+        }
+    }
+
+    /// Whether the dice have no drop, reroll or cutoff clause.
+    fn is_unmodified(&self) -> bool {
+        self.drop == Drop::Non
+            && self.reroll == ReRoll::Never
+            && self.cutoff == CutOff::Non
+            && self.nested.is_none()
+            && self.pick.is_none()
+    }
+
+    /// Whether `other` can be added to these dice as more of the same dice.
+    fn can_merge(&self, other: &Dice) -> bool {
+        self.is_unmodified()
+            && other.is_unmodified()
+            && self.size == other.size
+            && self.op == other.op
+            && self.explosive == other.explosive
+    }
+
+    /// The canonical order of dice groups: added before subtracted, then the largest dice first.
+    fn canonical_cmp(&self, other: &Dice) -> Ordering {
+        let key = |d: &Dice| {
+            (
+                d.op == DiceOp::Sub,
+                Reverse(d.size),
+                d.explosive,
+                d.to_string(),
+            )
+        };
+        key(self).cmp(&key(other))
+    }
+}
+
+impl DiceBag {
+    /// Make the canonical form of the `DiceBag`, which rolls the same totals with the same
+    /// probabilities. In the canonical form:
+    /// - Clauses which do nothing (eg "dl0") are removed.
+    /// - Unmodified groups of the same dice are merged (eg "2d6+3d6" becomes "5d6"), as long as
+    ///   the merged group is within the bag's `Limits`.
+    /// - Groups of no dice are removed, and unmodified one-sided dice are treated as boni.
+    /// - Dice rolled for a count or sides (eg "(1d4)d6") are normalized, and replaced by their
+    ///   total if they are only boni (eg "(2+1)d6" becomes "3d6").
+    /// - The bags of a "max(..)" or "min(..)" group are normalized, but kept in their order.
+    /// - The boni are folded into one bonus at the end, which is left out if it is zero.
+    /// - The dice groups are sorted, added before subtracted and largest dice first.
+    /// - Labels and the comment are left out, as they do not change the totals.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag: DiceBag = libazdice::parse("2d6+1+3d6-4".to_owned()).unwrap();
+    /// let normal: DiceBag = libazdice::parse("5d6-3".to_owned()).unwrap();
+    /// assert_ne!(bag, normal);
+    /// assert_eq!(bag.normalize(), normal);
+    /// assert_eq!(bag.normalize().to_string(), "5d6 - 3");
+    ///
+    /// let bag: DiceBag = libazdice::parse("-1d4 + 2 + 2d6 + 1d20dl0 - 2".to_owned()).unwrap();
+    /// assert_eq!(bag.normalize().to_string(), "1d20 + 2d6 - 1d4");
+    /// ```
+    pub fn normalize(&self) -> DiceBag {
+        let mut bonus = 0_i64;
+        let mut dice: Vec<Dice> = Vec::with_capacity(self.dice.len());
+        for group in self.dice.iter() {
+            let mut d = match group {
+                DiceGroup::Bonus(b) => {
+                    bonus = b.op.saturating_operate(bonus, b.bonus);
+                    continue;
+                }
+                DiceGroup::Dice(d) => d.clone(),
+            };
+            d.label = None;
+            d.simplify();
+
+            if d.count == 0 && d.nested.is_none() {
+                continue;
+            } else if d.size == 1 && !d.explosive && d.is_unmodified() {
+                bonus = d.op.saturating_operate(bonus, d.count as i64);
+                continue;
+            }
+
+            let max_count = self.limits.max_dice_per_group;
+            match dice
+                .iter_mut()
+                .find(|m| m.can_merge(&d) && m.count + d.count <= max_count)
+            {
+                Some(merged) => merged.count += d.count,
+                None => dice.push(d),
+            }
+        }
+        dice.sort_by(Dice::canonical_cmp);
+
+        let mut groups: Vec<DiceGroup> = dice.into_iter().map(DiceGroup::from).collect();
+        if bonus < 0 {
+            let mut minus = Bonus::of(bonus.saturating_neg());
+            minus.op = DiceOp::Sub;
+            groups.push(minus.into());
+        } else if bonus > 0 || groups.is_empty() {
+            groups.push(Bonus::of(bonus).into());
+        }
+
+        let mut bag = DiceBag::from_dice(groups);
+        bag.limits = self.limits;
+        bag
+    }
+
+    /// Whether two `DiceBag`s roll the same way, which is when their canonical forms (see
+    /// `DiceBag::normalize`) have the same groups. The `Limits` of the bags are not compared.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag = |s: &str| -> DiceBag { libazdice::parse(s.to_owned()).unwrap() };
+    /// assert!(bag("2d6+3d6+1-4").is_equivalent(&bag("5d6-3")));
+    /// assert!(bag("1d20+5+2d6").is_equivalent(&bag("2d6+1d20+5")));
+    /// assert!(bag("4d6dl1dh0").is_equivalent(&bag("4d6kh3")));
+    ///
+    /// // Not every difference of dice is a dice.
+    /// assert!(!bag("3d6-2d6").is_equivalent(&bag("1d6")));
+    /// ```
+    pub fn is_equivalent(&self, other: &DiceBag) -> bool {
+        self.normalize().dice == other.normalize().dice
+    }
+}
@@ -1,13 +1,19 @@
-//! This module contains the parser for dice strings. The only public function here is `parse`.
+//! This module contains the parser for dice strings. The public functions here are `parse`,
+//! `parse_with_limits` and `parse_with_context`.
 use super::distribution::*;
+use super::nested::Nested;
+use super::variables::{Context, DiceTemplate};
 use std::result::Result;
 
 /// An important piece of shorthand.
 const CANT: &str = "Can't parse, won't parse!";
 
+/// The deepest brackets may be nested (eg "((1d2)d4)d6" is 2 deep).
+const MAX_BRACKET_DEPTH: usize = 3;
+
 /// an enum to store various parsing groups dynamically.
 #[derive(Debug, Clone, PartialEq)]
-enum ModifierGroup {
+pub(crate) enum ModifierGroup {
     // The number is just there as demo.
     DropLowest(usize),  // dl2
     KeepHighest(usize), // kh2
@@ -77,53 +83,300 @@ const MN: &str = "mn"; // MinimumOf
 /// assert!(count_of_rolls_between_8_and_16 > 0);
 /// ```
 pub fn parse(input: String) -> Result<DiceBag, String> {
-    // Lowercase the string for simplicity.
-    let input = input.to_lowercase();
-
-    // Remove spaces and other crud.
-    let input = input
-        .chars()
-        .filter(|c| !c.is_whitespace() && !c.is_control())
-        .collect::<String>();
-
-    //Initial check.
-    let chars = input.chars();
-    for c in chars {
-        if !valid_chars(c) {
-            return Result::Err(format!("Input contained invalid character ({}).", c));
+    parse_with_limits(input, &Limits::default())
+}
+
+/// Parse as `parse`, but check the dice against `limits` rather than the default `Limits`.
+/// ```
+/// use libazdice::parse::parse_with_limits;
+/// use libazdice::validation::Limits;
+///
+/// let limits = Limits {
+///     max_sides: 100,
+///     ..Limits::default()
+/// };
+/// assert!(parse_with_limits("1d100".to_owned(), &limits).is_ok());
+/// assert!(parse_with_limits("1d1000".to_owned(), &limits).is_err());
+/// ```
+pub fn parse_with_limits(input: String, limits: &Limits) -> Result<DiceBag, String> {
+    // Labels and the comment keep their case and spaces, so they come out first.
+    let (runs, comment) = split_labels(&input)?;
+
+    let mut parsed_groups = Vec::new();
+    for (i, (text, label)) in runs.into_iter().enumerate() {
+        // Lowercase the string for simplicity.
+        let text = text.to_lowercase();
+
+        // Remove spaces and other crud.
+        let text = text
+            .chars()
+            .filter(|c| !c.is_whitespace() && !c.is_control())
+            .collect::<String>();
+
+        //Initial check.
+        let chars = text.chars();
+        for c in chars {
+            if !valid_chars(c) {
+                return Result::Err(format!("Input contained invalid character ({}).", c));
+            }
         }
-    }
 
-    // Parse to dice.
-    let parsed_groups = map_ops_and_parse(input)?;
+        // After a label, there is either nothing or another sign and groups.
+        if i > 0 {
+            if text.is_empty() && label.is_none() {
+                break;
+            } else if !text.starts_with(['+', '-']) {
+                return Err(format!("A label must be followed by + or -. {}", CANT));
+            }
+        } else if text.is_empty() && label.is_some() {
+            return Err(format!("A label must follow a dice group. {}", CANT));
+        }
 
-    // Convert to dicebag.
-    let mut dice_bag = DiceBag::from_dice(parsed_groups);
-    dice_bag.calculate_range();
+        // Parse to dice.
+        let mut groups = map_ops_and_parse(text, limits)?;
+        for group in groups.iter_mut() {
+            group.set_label(label.clone());
+        }
+        parsed_groups.append(&mut groups);
+    }
 
-    Ok(dice_bag)
+    // Convert to dicebag. This also catches what the group parser cannot (eg "1d0" or "1d1!").
+    let mut bag = DiceBag::try_from_dice_with_limits(parsed_groups, *limits)
+        .map_err(|e| format!("{} {}", e, CANT))?;
+    bag.comment = comment;
+    Ok(bag)
 }
 
-/// Splits a whitespaceless String into ops.
-pub(crate) fn map_ops_and_parse(input: String) -> Result<Vec<DiceGroup>, String> {
-    let ops = std::iter::repeat(DiceOp::Add)
-        .take(1)
-        .chain(input.chars().filter_map(char_to_op));
-
-    let groups = input.split(|c| (c == '+') || (c == '-'));
-    if groups.clone().count() == 0 {
-        // A little parsing on the side.
-        return Result::Err("Input contains no valid dice groups.".to_owned());
+/// A run of groups in a dice string and the label which follows it.
+pub(crate) type LabelledRun<'a> = (&'a str, Option<String>);
+
+/// Split a dice string into runs of groups, each with the label which follows it (eg "2d6+3" and
+/// "slashing" for "2d6+3 [slashing]"), and the comment after a "#" at the end, if there is one.
+/// The last run has no label, and is empty if the string ends with a label.
+pub(crate) fn split_labels(input: &str) -> Result<(Vec<LabelledRun<'_>>, Option<String>), String> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut label_start = None;
+    for (i, c) in input.char_indices() {
+        match (c, label_start) {
+            ('[', None) => label_start = Some(i),
+            (']', Some(open)) => {
+                let written = &input[open + 1..i];
+                let label = written.trim();
+                if label.is_empty() || label.contains('[') || label.contains(char::is_control) {
+                    return Err(format!(
+                        "{} {}",
+                        DiceError::InvalidLabel(written.to_owned()),
+                        CANT
+                    ));
+                }
+                runs.push((&input[start..open], Some(label.to_owned())));
+                start = i + 1;
+                label_start = None;
+            }
+            (']', None) => return Err(format!("A label has no opening \"[\". {}", CANT)),
+            ('#', None) => {
+                runs.push((&input[start..i], None));
+                let comment = input[i + 1..].trim();
+                if comment.contains(char::is_control) {
+                    return Err(format!(
+                        "{} {}",
+                        DiceError::InvalidComment(comment.to_owned()),
+                        CANT
+                    ));
+                }
+                return Ok((runs, Some(comment.to_owned()).filter(|c| !c.is_empty())));
+            }
+            _ => {}
+        }
+    }
+    if label_start.is_some() {
+        return Err(format!("A label has no closing \"]\". {}", CANT));
     }
-    let mut output = Vec::with_capacity(groups.clone().count());
-    for (op, group) in ops.zip(groups) {
-        let mut dice_group = parse_string_to_dicegroup2(group)?;
+    runs.push((&input[start..], None));
+    Ok((runs, None))
+}
+
+/// Parse a dice string with variables (eg "1d20 + @str_mod" or "@level d6"), taking their values
+/// from `context` straight away. To keep the variables, and take their values each time the dice
+/// are rolled, use `variables::DiceTemplate`.
+/// ```
+/// use libazdice::parse::parse_with_context;
+/// use std::collections::HashMap;
+///
+/// let mut sheet: HashMap<&str, i64> = HashMap::new();
+/// sheet.insert("level", 3);
+/// sheet.insert("con_mod", 2);
+/// let hit_points = parse_with_context("@level d10 + @con_mod".to_owned(), &sheet).unwrap();
+/// assert_eq!(hit_points.to_string(), "3d10 + 2");
+///
+/// assert!(parse_with_context("@level d10 + @dex_mod".to_owned(), &sheet).is_err());
+/// ```
+pub fn parse_with_context<C: Context + ?Sized>(
+    input: String,
+    context: &C,
+) -> Result<DiceBag, String> {
+    DiceTemplate::parse(&input)?.resolve(context)
+}
+
+/// Splits a whitespaceless String into ops. Dice in brackets are parsed with the same `limits`.
+pub(crate) fn map_ops_and_parse(input: String, limits: &Limits) -> Result<Vec<DiceGroup>, String> {
+    let groups = split_groups(&input)?;
+    let mut output = Vec::with_capacity(groups.len());
+    for (op, group) in groups {
+        let mut dice_group = if group.starts_with("max(") || group.starts_with("min(") {
+            parse_pick_group(group, limits)?
+        } else if group.contains('(') {
+            parse_nested_group(group, limits)?
+        } else {
+            parse_string_to_dicegroup2(group)?
+        };
         dice_group.add_op(op);
         output.push(dice_group);
     }
     Ok(output)
 }
 
+/// Split a whitespaceless string into groups with their operations. A "+" or "-" in brackets
+/// (eg "(1d4+1)d6") belongs to the bracketed dice rather than splitting the string.
+pub(crate) fn split_groups(input: &str) -> Result<Vec<(DiceOp, &str)>, String> {
+    let mut groups = Vec::new();
+    let mut op = DiceOp::Add;
+    let mut start = 0;
+    let mut depth = 0_usize;
+    for (i, c) in input.char_indices() {
+        match c {
+            '(' => {
+                depth += 1;
+                if depth > MAX_BRACKET_DEPTH {
+                    return Err(format!("Brackets are nested too deeply. {}", CANT));
+                }
+            }
+            ')' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| format!("Brackets in ({}) do not match. {}", input, CANT))?;
+            }
+            '+' | '-' if depth == 0 => {
+                // The first group may have a sign of its own (eg "-2+1d6").
+                if i > 0 {
+                    groups.push((op, &input[start..i]));
+                }
+                op = char_to_op(c).expect("Checked.");
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(format!("Brackets in ({}) do not match. {}", input, CANT));
+    }
+    groups.push((op, &input[start..]));
+    Ok(groups)
+}
+
+/// Parse a group whose count or sides are dice in brackets, eg "(1d4)d6dl1" or "2d(1d4+2)!".
+/// The group is checked with the smallest count and sides the brackets can roll, as that is
+/// where its clauses are most likely to be invalid. The largest are checked with the bag.
+fn parse_nested_group(group: &str, limits: &Limits) -> Result<DiceGroup, String> {
+    let (count, rest) = match group.strip_prefix('(') {
+        Some(inner) => {
+            let (bag, rest) = parse_brackets(inner, limits)?;
+            (Some(bag), rest)
+        }
+        None => (None, group),
+    };
+    let d = rest
+        .find('d')
+        .ok_or_else(|| format!("Dice group ({}) has brackets but no dice. {}", group, CANT))?;
+    let (count_text, rest) = (&rest[..d], &rest[d + 1..]);
+    let (size, tail) = match rest.strip_prefix('(') {
+        Some(inner) => {
+            let (bag, tail) = parse_brackets(inner, limits)?;
+            (Some(bag), tail)
+        }
+        None => (None, rest),
+    };
+    let digits_after = size.is_some() && tail.starts_with(|c: char| c.is_ascii_digit());
+    if (count.is_some() && !count_text.is_empty()) || digits_after || tail.contains(['(', ')']) {
+        return Err(format!(
+            "Brackets may only hold the count or sides of dice ({}). {}",
+            group, CANT
+        ));
+    }
+
+    let count_text = match count {
+        Some(ref bag) => match bag.get_range()[0] {
+            n if n < 0 => return Err(format!("{} {}", DiceError::NegativeCount(n), CANT)),
+            n => n.to_string(),
+        },
+        None => count_text.to_owned(),
+    };
+    let size_text = match size {
+        Some(ref bag) => match bag.get_range()[0] {
+            n if n < 1 => return Err(format!("{} {}", DiceError::InvalidSize(n), CANT)),
+            n => format!("{}{}", n, tail),
+        },
+        None => tail.to_owned(),
+    };
+    let mut dice_group = parse_string_to_dicegroup2(&format!("{}d{}", count_text, size_text))?;
+    if let DiceGroup::Dice(ref mut dice) = dice_group {
+        dice.nested = Some(Box::new(Nested { count, size }));
+    }
+    Ok(dice_group)
+}
+
+/// Parse a group which keeps the highest or lowest total of the bags in its brackets, separated
+/// by commas (eg "max(1d8!,1d6!)" or "min(1d20,1d12+4)").
+fn parse_pick_group(group: &str, limits: &Limits) -> Result<DiceGroup, String> {
+    let inner = &group[4..];
+    let mut bags = Vec::new();
+    let mut start = 0;
+    let mut depth = 0_usize;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ',' | ')' if depth == 0 => {
+                bags.push(parse_with_limits(inner[start..i].to_owned(), limits)?);
+                start = i + 1;
+                if c == ',' {
+                    continue;
+                }
+                if i + 1 < inner.len() {
+                    return Err(format!("{} {}", DiceError::ClauseOnPick, CANT));
+                }
+                let dice = if group.starts_with("max") {
+                    Dice::max_of(bags)
+                } else {
+                    Dice::min_of(bags)
+                };
+                return Ok(dice.into());
+            }
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    Err(format!("Brackets in ({}) do not match. {}", group, CANT))
+}
+
+/// Parse the dice in brackets at the start of `input` (which follows the opening bracket),
+/// returning them and what follows the closing bracket.
+fn parse_brackets<'a>(input: &'a str, limits: &Limits) -> Result<(DiceBag, &'a str), String> {
+    let mut depth = 0_usize;
+    for (i, c) in input.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                let bag = parse_with_limits(input[..i].to_owned(), limits)?;
+                return Ok((bag, &input[i + 1..]));
+            }
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    Err(format!("Brackets in ({}) do not match. {}", input, CANT))
+}
+
 // REWORKING OF PARSING STRATEGY:
 // 1) Check that numeric only: Then it becomes a bonus.
 // 2) Check for d followed by numeric. If failed, then we don't want it.
@@ -214,11 +467,11 @@ fn parse_conditional_clauses(input: String, base_dice: &mut Dice) -> Result<(),
 
     // Now we try to parse.
     let mut groups = Vec::with_capacity(list_cond.len());
-    for (num, cond) in list_num.zip(list_cond.into_iter()) {
+    for (num, cond) in list_num.zip(list_cond) {
         groups.push(make_group(num, cond)?);
     }
     // Finalise the dice
-    fill_dice(groups, base_dice)
+    fill_dice(groups, base_dice).map_err(|e| format!("{} {}", e, CANT))
 }
 
 /// Turn splits into groups.
@@ -242,214 +495,40 @@ fn make_group(num: String, cond: String) -> Result<ModifierGroup, String> {
     Ok(group)
 }
 
-/// Finalises a dice group based on the `ModifierGroups`. Groups to add.
-/// 1) `Reroll` clause,
-/// 2) `CutOff` clause,
-/// 3) `Drop` clause.
-fn fill_dice(mods: Vec<ModifierGroup>, die: &mut Dice) -> Result<(), String> {
+/// Finalises a dice group based on the `ModifierGroups`, in the order they were given. The checks
+/// are those of the `Dice::with_*` functions, so the parser and the API agree on what is valid.
+/// The reroll count and condition are separate modifiers, so they are gathered up first.
+pub(crate) fn fill_dice(mods: Vec<ModifierGroup>, die: &mut Dice) -> Result<(), DiceError> {
     use self::ModifierGroup::*;
-    // Make Drop.
-    {
-        let mut drop_highest = Drop::Non;
-        let mut drop_lowest = Drop::Non;
-        let mut had_lowest = false;
-        let mut had_highest = false;
-        'drop_loop: for mod_group in mods.iter() {
-            match mod_group {
-                DropLowest(n) => {
-                    if had_lowest {
-                        return Err(format!("Multiple (dl/kh) clauses found! {}!", CANT));
-                    }
-                    had_lowest = true;
-                    if die.count <= *n {
-                        return Err(format!(
-                            "Keeping more dice than you have({} vs {})! {}!",
-                            n, die.count, CANT
-                        ));
-                    }
-                    drop_lowest = Drop::Lowest(*n);
-                }
-                KeepHighest(n) => {
-                    if had_lowest {
-                        return Err(format!("Multiple (dl/kh) clauses found! {}!", CANT));
-                    }
-                    had_lowest = true;
-                    if die.count <= *n {
-                        return Err(format!(
-                            "Keeping more dice than you have({} vs {})! {}!",
-                            n, die.count, CANT
-                        ));
-                    }
-                    drop_lowest = Drop::Lowest(die.count - *n);
-                }
-                DropHighest(n) => {
-                    if had_highest {
-                        return Err(format!("Multiple (dh/kl) clauses found! {}!", CANT));
-                    }
-                    had_highest = true;
-                    if die.count <= *n {
-                        return Err(format!(
-                            "Keeping more dice than you have({} vs {})! {}!",
-                            n, die.count, CANT
-                        ));
-                    }
-                    drop_highest = Drop::Highest(*n);
-                }
-                KeepLowest(n) => {
-                    if had_highest {
-                        return Err(format!("Multiple (dh/kl) clauses found! {}!", CANT));
-                    }
-                    had_highest = true;
-                    if die.count <= *n {
-                        return Err(format!(
-                            "Keeping more dice than you have({} vs {})! {}!",
-                            n, die.count, CANT
-                        ));
-                    }
-                    drop_highest = Drop::Highest(die.count - *n);
-                }
-                _ => continue 'drop_loop,
-            }
-            // We must keep cycling to the end to make sure that we do not have contradictory clauses.
-        }
-
-        match (drop_highest, drop_lowest) {
-            (Drop::Non, Drop::Non) => die.add_drop(Drop::non()),
-            (Drop::Non, Drop::Lowest(n)) => die.add_drop(Drop::lowest(n)),
-            (Drop::Highest(n), Drop::Non) => die.add_drop(Drop::highest(n)),
-            (Drop::Highest(n), Drop::Lowest(m)) => {
-                if m + n >= die.count {
-                    return Err(format!(
-                        "Dropping more dice than you have({} vs {})! {}!",
-                        n + m,
-                        die.count,
-                        CANT
-                    ));
+    let mut count = None;
+    let mut condition = None;
+    for mod_group in mods {
+        match mod_group {
+            DropLowest(n) => die.with_drop_lowest(n)?,
+            KeepHighest(n) => die.with_keep_highest(n)?,
+            DropHighest(n) => die.with_drop_highest(n)?,
+            KeepLowest(n) => die.with_keep_lowest(n)?,
+            CutOffMinimum(x) => die.with_minimum_roll(x)?,
+            CutOffMaximum(x) => die.with_maximum_roll(x)?,
+            ReRollCount(n) => {
+                if count.replace(n).is_some() {
+                    return Err(DiceError::MultipleClauses("rr"));
                 }
-                let drop_vector = ((m)..(die.count - n)).collect::<Vec<_>>();
-                die.add_drop(Drop::custom(drop_vector));
             }
-            _ => return Err("Impossible drop combo. How'd you do it?".to_owned()),
-        }
-    }
-
-    // Make ReRoll
-    {
-        let mut count = None;
-        let mut ex_threshold = None;
-        let mut above = false;
-        'reroll_loop: for mod_group in mods.iter() {
-            match mod_group {
-                ModifierGroup::ReRollCount(n) => {
-                    if count.is_none() {
-                        if die.count < *n {
-                            return Err(format!(
-                                "Re-rolling more dice than you have({} vs {})! {}!",
-                                n, die.count, CANT
-                            ));
-                        }
-                        count = Some(*n);
-                    } else {
-                        return Err(format!("Multiple reroll counts found! {}", CANT));
-                    }
-                }
-                ModifierGroup::ReRollAbove(x) => {
-                    above = true;
-                    if ex_threshold.is_none() {
-                        if die.size <= *x {
-                            return Err(format!(
-                                "Re-rolling more dice than you have({} vs {})! {}!",
-                                x, die.size, CANT
-                            ));
-                        }
-                        ex_threshold = Some(*x);
-                    } else {
-                        return Err(format!("Multiple reroll conditions found! {}", CANT));
-                    }
-                }
-                ModifierGroup::ReRollBelow(x) => {
-                    if ex_threshold.is_none() {
-                        if 1 >= *x {
-                            return Err(format!(
-                                "Re-rolling more dice than you have({} vs {})! {}!",
-                                x, die.count, CANT
-                            ));
-                        }
-                        ex_threshold = Some(*x);
-                    } else {
-                        return Err(format!("Multiple reroll conditions found! {}", CANT));
-                    }
+            ReRollAbove(_) | ReRollBelow(_) => {
+                if condition.replace(mod_group).is_some() {
+                    return Err(DiceError::MultipleClauses("ab/be"));
                 }
-                _ => continue 'reroll_loop,
             }
-            // We must keep cycling to the end to make sure there is only one of each.
-        }
-
-        match (count, ex_threshold) {
-            (None, Some(_)) | (Some(_), None) => {
-                return Err(format!("Incomplete reroll clause: {}", CANT));
-            }
-            (Some(n), Some(x)) => {
-                if above {
-                    die.add_reroll_if_above(x, n);
-                } else {
-                    die.add_reroll_if_below(x, n);
-                }
-            }
-            _ => {}
         }
     }
 
-    // Make CutOff
-    {
-        let mut cutoff_min = CutOff::Non;
-        let mut cutoff_max = CutOff::Non;
-        'cutoff_loop: for mod_group in mods.iter() {
-            match mod_group {
-                ModifierGroup::CutOffMaximum(x) => {
-                    if cutoff_max != CutOff::Non {
-                        return Err(format!("Multiple cutoff clauses found! {}!", CANT));
-                    }
-                    if (die.size <= *x) || (*x < 1) {
-                        return Err(format!(
-                            "Cut-off Maximum is ridiculous ({} for a d{}). {}.",
-                            x, die.size, CANT
-                        ));
-                    }
-                    cutoff_max = CutOff::Maximum(*x);
-                }
-                ModifierGroup::CutOffMinimum(x) => {
-                    if cutoff_min != CutOff::Non {
-                        return Err(format!("Multiple cutoff clauses found! {}!", CANT));
-                    }
-                    if (die.size < *x) || (*x <= 1) {
-                        return Err(format!(
-                            "Cut-off Minimum is ridiculous ({} for a d{}). {}.",
-                            x, die.size, CANT
-                        ));
-                    }
-                    cutoff_min = CutOff::Minimum(*x);
-                }
-                _ => continue 'cutoff_loop,
-            }
-        }
-        let cutoff = match (cutoff_min, cutoff_max) {
-            (CutOff::Non, CutOff::Non) => CutOff::Non,
-            (CutOff::Minimum(x), CutOff::Non) => CutOff::Minimum(x),
-            (CutOff::Non, CutOff::Maximum(x)) => CutOff::Maximum(x),
-            (CutOff::Minimum(x), CutOff::Maximum(y)) => {
-                if x > y {
-                    return Err(format!("Maximum is bigger than minimum. {}!", CANT));
-                } else {
-                    CutOff::Both(MinMax([x, y]))
-                }
-            }
-            _ => return Err(format!("MinMax Error! {}!", CANT)),
-        };
-
-        die.add_checked_cutoff(cutoff);
+    match (count, condition) {
+        (None, None) => Ok(()),
+        (Some(n), Some(ReRollAbove(x))) => die.add_reroll_if_above(x, n),
+        (Some(n), Some(ReRollBelow(x))) => die.add_reroll_if_below(x, n),
+        _ => Err(DiceError::IncompleteReRoll),
     }
-    Ok(())
 }
 
 /// Needs to be done because rust is typed and I am not clever.
@@ -579,9 +658,10 @@ fn char_to_op(char: char) -> Option<DiceOp> {
     }
 }
 
-fn valid_chars(c: char) -> bool {
+pub(crate) fn valid_chars(c: char) -> bool {
     match c {
-        '+' | '-' | 'd' | 'l' | 'k' | 'x' | 'h' | 'r' | 'b' | 'e' | 'a' | 'm' | '!' | 'n' => true,
+        '+' | '-' | 'd' | 'l' | 'k' | 'x' | 'h' | 'r' | 'b' | 'e' | 'a' | 'm' | '!' | 'n' | 'i'
+        | '(' | ')' | ',' => true,
         c => c.is_numeric(),
     }
 }
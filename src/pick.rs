@@ -0,0 +1,222 @@
+//! This module contains groups which roll several bags and keep the highest or lowest total, such
+//! as "max(1d8!, 1d6!)" (a Savage Worlds trait die with its wild die) or "min(1d20, 1d12 + 4)".
+//! The bags may hold any dice, each is rolled in turn, and the group adds the total it keeps.
+//!
+//! Such a group is a `Dice` with one "die" for each bag, and it cannot take clauses.
+use super::distribution::*;
+use super::exact::{Budget, Pmf};
+use super::validation::{DiceError, Limits};
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// The bags of a "max(..)" or "min(..)" group.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Pick {
+    /// Whether the highest total is kept, rather than the lowest.
+    pub(crate) highest: bool,
+    pub(crate) bags: Vec<DiceBag>,
+}
+
+impl Pick {
+    /// The total kept from the totals of the bags.
+    fn choose<I: Iterator<Item = i64>>(&self, totals: I) -> i64 {
+        let kept = if self.highest {
+            totals.max()
+        } else {
+            totals.min()
+        };
+        kept.unwrap_or(0)
+    }
+
+    /// Roll every bag, returning the kept total and the rolls.
+    pub(crate) fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> (i64, Vec<RollResults>) {
+        let rolls: Vec<RollResults> = self.bags.iter().map(|b| b.roll_with_rng(rng)).collect();
+        (self.choose(rolls.iter().map(RollResults::total)), rolls)
+    }
+
+    /// As `roll`, but only rolling the totals. For the same random number generator, this keeps
+    /// the same total as `roll`.
+    pub(crate) fn roll_total<R: Rng + ?Sized>(&self, rng: &mut R, buffer: &mut RollBuffer) -> i64 {
+        let mut totals = Vec::with_capacity(self.bags.len());
+        for bag in self.bags.iter() {
+            totals.push(bag.roll_total_with_rng(rng, buffer));
+        }
+        self.choose(totals.into_iter())
+    }
+
+    /// The word written before the bags.
+    pub(crate) fn name(&self) -> &'static str {
+        if self.highest {
+            "max"
+        } else {
+            "min"
+        }
+    }
+}
+
+impl Dice {
+    /// A group which rolls each of `bags` and adds the highest total, eg "max(1d8!, 1d6!)".
+    /// Its count is the number of bags, and it cannot take clauses (such as drops).
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let trait_die: DiceBag = "1d8!".parse().unwrap();
+    /// let wild_die: DiceBag = "1d6!".parse().unwrap();
+    /// let bag = DiceBag::try_from_dice(vec![Dice::max_of(vec![trait_die, wild_die]).into()])
+    ///     .unwrap();
+    /// assert_eq!(bag.to_string(), "max(1d8!, 1d6!)");
+    /// assert!(bag.roll().total() >= 1);
+    /// ```
+    pub fn max_of(bags: Vec<DiceBag>) -> Dice {
+        Dice::pick_of(true, bags)
+    }
+
+    /// A group which rolls each of `bags` and adds the lowest total, eg "min(1d20, 1d20)".
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bags = vec!["1d20".parse().unwrap(), "1d12 + 4".parse().unwrap()];
+    /// let bag = DiceBag::try_from_dice(vec![Dice::min_of(bags).into()]).unwrap();
+    /// assert_eq!(bag.to_string(), "min(1d20, 1d12 + 4)");
+    /// assert_eq!(bag.range(), 1..=16);
+    /// ```
+    pub fn min_of(bags: Vec<DiceBag>) -> Dice {
+        Dice::pick_of(false, bags)
+    }
+
+    fn pick_of(highest: bool, bags: Vec<DiceBag>) -> Dice {
+        let mut dice = Dice::with_size_and_count(1, bags.len());
+        dice.pick = Some(Box::new(Pick { highest, bags }));
+        dice
+    }
+
+    /// The lowest and highest totals the group can keep, before its operation is applied.
+    pub(crate) fn pick_bounds(&self, explode: bool) -> (Option<i64>, Option<i64>) {
+        let pick = match self.pick {
+            Some(ref pick) => pick,
+            None => return (None, None),
+        };
+        let bounds: Vec<[Option<i64>; 2]> = pick
+            .bags
+            .iter()
+            .map(|b| b.calculate_bounds(explode))
+            .collect();
+        let lows = bounds.iter().map(|[low, _]| *low);
+        let highs = bounds.iter().map(|[_, high]| *high);
+        // An unbounded bag can keep any total beyond the others in its direction.
+        if pick.highest {
+            let high = highs.collect::<Option<Vec<_>>>();
+            (lows.flatten().max(), high.and_then(|h| h.into_iter().max()))
+        } else {
+            let low = lows.collect::<Option<Vec<_>>>();
+            (low.and_then(|l| l.into_iter().min()), highs.flatten().min())
+        }
+    }
+
+    /// Check the bags, and that no clauses were added to the group.
+    pub(crate) fn validate_pick(&self, pick: &Pick) -> Result<(), DiceError> {
+        if pick.bags.is_empty() {
+            return Err(DiceError::NoGroups);
+        }
+        if self.count != pick.bags.len()
+            || self.size != 1
+            || self.drop != Drop::Non
+            || self.reroll != ReRoll::Never
+            || self.cutoff != CutOff::Non
+            || self.explosive
+            || self.nested.is_some()
+        {
+            return Err(DiceError::ClauseOnPick);
+        }
+        pick.bags.iter().try_for_each(DiceBag::validate)
+    }
+
+    /// Check the dice of the bags against `limits`, returning the most dice rolled by one roll.
+    pub(crate) fn check_pick_limits(
+        &self,
+        pick: &Pick,
+        limits: &Limits,
+    ) -> Result<usize, DiceError> {
+        let mut rolls = 0_usize;
+        for bag in pick.bags.iter() {
+            for group in bag.dice.iter() {
+                if let DiceGroup::Dice(d) = group {
+                    rolls = rolls.saturating_add(d.check_limits(limits)?);
+                }
+            }
+        }
+        Ok(rolls)
+    }
+
+    /// The largest magnitude the group can total. See `Dice::checked_magnitude`.
+    pub(crate) fn pick_magnitude(&self, pick: &Pick, explosion_depth: usize) -> Option<i64> {
+        let mut largest = 0_i64;
+        for bag in pick.bags.iter() {
+            let mut magnitude = 0_i64;
+            for group in bag.dice.iter() {
+                let group_magnitude = match group {
+                    DiceGroup::Bonus(b) => b.bonus,
+                    DiceGroup::Dice(d) => d.checked_magnitude(explosion_depth)?,
+                };
+                magnitude = magnitude.checked_add(group_magnitude)?;
+            }
+            largest = largest.max(magnitude);
+        }
+        Some(largest)
+    }
+
+    /// The exact distribution of the kept total, before the operation is applied. The chance of
+    /// keeping `x` or less is the product of the chances of each bag rolling `x` or less (or,
+    /// for the lowest, of rolling more than `x`).
+    pub(crate) fn pick_pmf(&self, pick: &Pick, budget: &mut Budget) -> Result<Pmf, String> {
+        let mut cdfs = Vec::with_capacity(pick.bags.len());
+        for bag in pick.bags.iter() {
+            let pmf = bag.exact_pmf(budget)?;
+            let mut cdf = BTreeMap::new();
+            let mut sum = 0.0;
+            for (value, p) in pmf.iter() {
+                sum += p;
+                cdf.insert(value, sum);
+            }
+            cdfs.push((pmf.min(), pmf.max(), cdf));
+        }
+        let cdf_at =
+            |cdf: &BTreeMap<i64, f64>, x: i64| cdf.range(..=x).next_back().map_or(0.0, |(_, c)| *c);
+        // The chance that the kept total is `x` or less.
+        let kept_at_most = |x: i64| {
+            if pick.highest {
+                cdfs.iter()
+                    .map(|(_, _, cdf)| cdf_at(cdf, x))
+                    .product::<f64>()
+            } else {
+                1.0 - cdfs
+                    .iter()
+                    .map(|(_, _, cdf)| 1.0 - cdf_at(cdf, x))
+                    .product::<f64>()
+            }
+        };
+
+        let lows = cdfs.iter().map(|(low, _, _)| *low);
+        let highs = cdfs.iter().map(|(_, high, _)| *high);
+        let (low, high) = if pick.highest {
+            (lows.max(), highs.max())
+        } else {
+            (lows.min(), highs.min())
+        };
+        let (low, high) = match (low, high) {
+            (Some(low), Some(high)) => (low, high),
+            _ => return Ok(Pmf::empty()),
+        };
+        let span = u64::try_from(high.saturating_sub(low)).unwrap_or(u64::MAX);
+        budget.spend(span.saturating_mul(cdfs.len() as u64))?;
+        let mut below = kept_at_most(low.saturating_sub(1));
+        let mut pairs = Vec::new();
+        for x in low..=high {
+            let at_most = kept_at_most(x);
+            pairs.push((x, at_most - below));
+            below = at_most;
+        }
+        Pmf::from_pairs(pairs, budget)
+    }
+}
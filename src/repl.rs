@@ -0,0 +1,533 @@
+//! This module contains `Session`, the state of an interactive dice roller: named expressions,
+//! the results rolled so far and the random number generator they were rolled with. A front end
+//! (such as `az-dice-rolls-demo`) reads lines, passes them to `Session::eval` and prints what
+//! comes back.
+//!
+//! A line may be:
+//! - A dice string to roll, which may use names and earlier results, eg "atk + 2" or "$1 + 1d6".
+//! - An assignment of a name, eg "atk = 1d20 + 7". Names start with a letter or "_" and hold
+//!   the dice (not a roll), so each use of the name is rolled afresh.
+//! - A reference to an earlier result, to show it again: "!!" for the last and "$1" for the
+//!   first. Used in a dice string, these stand for the total of that result.
+//! - A command: ":dist <expr>", ":stats <expr>", ":seed [<n>]", ":save <file>", ":load <file>",
+//!   ":vars", ":history" or ":help".
+//!
+//! Dice strings and assignments may have labels and a comment, as parsed dice strings may (eg
+//! "atk = 1d20 + 7 [attack] # longsword").
+//! ```
+//! use libazdice::repl::Session;
+//!
+//! let mut session = Session::with_seed(7);
+//! session.eval("atk = 1d20 + 7").unwrap();
+//! let shown = session.eval("atk + 2").unwrap();
+//! assert!(shown.starts_with("$1 = "));
+//!
+//! let total = session.history()[0].total();
+//! assert!((10..=29).contains(&total));
+//! assert_eq!(session.eval("!!").unwrap(), format!("$1 = {}: 1d20 + 7 + 2", total));
+//!
+//! // Earlier results are numbers in later expressions.
+//! session.eval("$1 - $1").unwrap();
+//! assert_eq!(session.history()[1].total(), 0);
+//! assert!(session.eval("bogus + 1").is_err());
+//! ```
+use super::distribution::*;
+use super::validation::Limits;
+use crate::chart::Chart;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// The first line of a saved session.
+const HEADER: &str = "libazdice-session 1";
+
+/// The number of rolls used for a distribution which cannot be worked out exactly.
+const SAMPLES: usize = 100_000;
+
+const HELP: &str = "\
+<expr>               Roll a dice string, eg \"1d20 + atk - $1\".
+<name> = <expr>      Name a dice string, eg \"atk = 1d20 + 7\".
+!!, $<n>             Show the last or the nth result; in a dice string, its total.
+:dist [<expr>]       The probability of each total (of the last roll if no dice string).
+:stats [<expr>]      Mean, standard deviation, median and range.
+:seed [<n>]          Seed the rolls with n, or at random.
+:save <file>         Save the names and results to a file.
+:load <file>         Replace the names and results with those in a file.
+:vars                Show the names.
+:history             Show the results.
+:help                Show this message.";
+
+/// A result rolled in a `Session`: the dice string rolled (with names written out) and the total.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    expression: String,
+    total: i64,
+}
+
+impl Record {
+    /// The dice string that was rolled, with the names in it written out.
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// The total that was rolled.
+    pub fn total(&self) -> i64 {
+        self.total
+    }
+}
+
+/// The state of an interactive dice roller. See the module documentation for what it accepts.
+#[derive(Debug, Clone)]
+pub struct Session {
+    variables: BTreeMap<String, DiceBag>,
+    history: Vec<Record>,
+    rng: SeededRng,
+    limits: Limits,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}
+
+/// Whether `s` could be a name (which is only used as one if it is not a dice string).
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A group which adds `n`.
+fn constant(n: i64) -> Result<DiceGroup, String> {
+    let mut group = DiceGroup::bonus(
+        n.checked_abs()
+            .ok_or_else(|| format!("{} is too large to add.", n))?,
+    );
+    if n < 0 {
+        group.add_op(DiceOp::Sub);
+    }
+    Ok(group)
+}
+
+/// Split a dice string into its terms, with the operation joining each to the rest.
+fn terms(expression: &str) -> Result<Vec<(DiceOp, String)>, String> {
+    let expression = expression
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+    if expression.is_empty() {
+        return Err("Nothing to roll.".to_owned());
+    }
+    let terms = crate::parse::split_groups(&expression)?;
+    if terms.iter().any(|(_, term)| term.is_empty()) {
+        return Err(format!("\"{}\" has a sign without a term.", expression));
+    }
+    Ok(terms
+        .into_iter()
+        .map(|(op, term)| (op, term.to_owned()))
+        .collect())
+}
+
+impl Session {
+    /// A session with no names or results, seeded at random.
+    pub fn new() -> Self {
+        Session::with_seed(rand::random())
+    }
+
+    /// A session with no names or results, whose rolls are the same for the same `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        Session {
+            variables: BTreeMap::new(),
+            history: Vec::new(),
+            rng: seeded_rng(seed),
+            limits: Limits::default(),
+        }
+    }
+
+    /// Check the dice of the session against `limits` rather than the default `Limits`.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Seed the rolls from now on.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = seeded_rng(seed);
+    }
+
+    /// The named dice strings.
+    pub fn variables(&self) -> &BTreeMap<String, DiceBag> {
+        &self.variables
+    }
+
+    /// The results so far, the first being "$1".
+    pub fn history(&self) -> &[Record] {
+        &self.history
+    }
+
+    /// Read a line, returning what to show, or why the line is wrong. A wrong line changes
+    /// nothing. Empty lines show nothing.
+    pub fn eval(&mut self, line: &str) -> Result<String, String> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(String::new());
+        }
+        if let Some(command) = line.strip_prefix(':') {
+            let (command, argument) = match command.split_once(char::is_whitespace) {
+                Some((command, argument)) => (command, argument.trim()),
+                None => (command, ""),
+            };
+            return self.command(command, argument);
+        }
+        // An "=" in a label or the comment does not make an assignment.
+        let code = &line[..line.find(['[', '#']).unwrap_or(line.len())];
+        if let Some(i) = code.find('=') {
+            return self.assign(line[..i].trim(), &line[i + 1..]);
+        }
+        if let Some(n) = self.reference(line) {
+            let n = n?;
+            let record = &self.history[n];
+            return Ok(format!(
+                "${} = {}: {}",
+                n + 1,
+                record.total,
+                record.expression
+            ));
+        }
+
+        let bag = self.resolve(line)?;
+        let roll = bag.roll_with_rng(&mut self.rng);
+        self.history.push(Record {
+            expression: bag.to_string(),
+            total: roll.total(),
+        });
+        Ok(format!(
+            "${} = {}: {}",
+            self.history.len(),
+            roll.total(),
+            roll
+        ))
+    }
+
+    /// The index of the result referred to by `term` ("!!" or "$n"), `None` if it is not a
+    /// reference and an error if the result does not exist.
+    fn reference(&self, term: &str) -> Option<Result<usize, String>> {
+        let n = if term == "!!" {
+            Ok(self.history.len())
+        } else {
+            let digits = term.strip_prefix('$')?;
+            // Part of a longer dice string, eg "$1 + 2".
+            if digits.contains(|c: char| c.is_whitespace() || c == '+' || c == '-') {
+                return None;
+            }
+            digits
+                .parse::<usize>()
+                .map_err(|_| format!("\"{}\" is not a result (use eg $1).", term))
+        };
+        Some(n.and_then(|n| match n {
+            0 if term == "!!" => Err("Nothing has been rolled yet.".to_owned()),
+            n if n == 0 || n > self.history.len() => Err(format!(
+                "There is no result {} (there are {}).",
+                term,
+                self.history.len()
+            )),
+            n => Ok(n - 1),
+        }))
+    }
+
+    /// Turn a dice string which may use names and results into a bag.
+    fn resolve(&self, expression: &str) -> Result<DiceBag, String> {
+        // Labels and the comment keep their case and spaces, so they come out first.
+        let (runs, comment) = crate::parse::split_labels(expression)?;
+        let mut groups = Vec::new();
+        for (i, (text, label)) in runs.into_iter().enumerate() {
+            let text = text.trim();
+            if i > 0 {
+                if text.is_empty() && label.is_none() {
+                    break;
+                } else if !text.starts_with(['+', '-']) {
+                    return Err("A label must be followed by + or -.".to_owned());
+                }
+            } else if text.is_empty() && label.is_some() {
+                return Err("A label must follow a dice group.".to_owned());
+            }
+            let mut run_groups = self.resolve_run(text)?;
+            if label.is_some() {
+                for group in run_groups.iter_mut() {
+                    group.set_label(label.clone());
+                }
+            }
+            groups.append(&mut run_groups);
+        }
+        let mut bag =
+            DiceBag::try_from_dice_with_limits(groups, self.limits).map_err(|e| e.to_string())?;
+        bag.comment = comment;
+        Ok(bag)
+    }
+
+    /// The groups of a run of terms without labels.
+    fn resolve_run(&self, expression: &str) -> Result<Vec<DiceGroup>, String> {
+        let mut groups = Vec::new();
+        for (op, term) in terms(expression)? {
+            let mut term_groups = if let Some(n) = self.reference(&term) {
+                vec![constant(self.history[n?].total)?]
+            } else if let Some(bag) = self.variables.get(&term) {
+                bag.dice.clone()
+            } else {
+                match crate::parse::parse_with_limits(term.clone(), &self.limits) {
+                    Ok(bag) => bag.dice,
+                    Err(_) if is_identifier(&term) => {
+                        return Err(format!("Unknown name \"{}\".", term))
+                    }
+                    Err(e) => return Err(format!("\"{}\": {}", term, e)),
+                }
+            };
+            if op == DiceOp::Sub {
+                for group in term_groups.iter_mut() {
+                    let flipped = match group.op() {
+                        DiceOp::Add => DiceOp::Sub,
+                        DiceOp::Sub => DiceOp::Add,
+                    };
+                    group.add_op(flipped);
+                }
+            }
+            groups.append(&mut term_groups);
+        }
+        Ok(groups)
+    }
+
+    fn assign(&mut self, name: &str, expression: &str) -> Result<String, String> {
+        if !is_identifier(name) {
+            return Err(format!(
+                "\"{}\" is not a name (use letters, digits and \"_\").",
+                name
+            ));
+        }
+        if crate::parse::parse(name.to_owned()).is_ok() {
+            return Err(format!(
+                "\"{}\" is a dice string, so cannot be a name.",
+                name
+            ));
+        }
+        let bag = self.resolve(expression)?;
+        let shown = format!("{} = {}", name, bag);
+        self.variables.insert(name.to_owned(), bag);
+        Ok(shown)
+    }
+
+    /// The bag of a command's argument, or of the last result if there is none.
+    fn argument_bag(&self, argument: &str) -> Result<DiceBag, String> {
+        if !argument.is_empty() {
+            return self.resolve(argument);
+        }
+        match self.history.last() {
+            Some(record) => self.resolve(&record.expression),
+            None => Err("Nothing has been rolled yet, so give a dice string.".to_owned()),
+        }
+    }
+
+    /// The distribution of `bag` on the base of 0-100%, exact if possible, and whether it was
+    /// sampled.
+    fn distribution(&mut self, bag: &DiceBag) -> (BTreeMap<i64, f64>, bool) {
+        match bag.make_exact_distribution() {
+            Ok(distribution) => (distribution, false),
+            Err(_) => {
+                let counts = bag.make_count_distribution_with_rng(SAMPLES, &mut self.rng);
+                let frequencies = counts
+                    .into_iter()
+                    .map(|(total, count)| (total, count as f64 / SAMPLES as f64 * 100.0))
+                    .collect();
+                (frequencies, true)
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str, argument: &str) -> Result<String, String> {
+        let method = |sampled: bool| {
+            if sampled {
+                format!("{} samples", SAMPLES)
+            } else {
+                "exact".to_owned()
+            }
+        };
+        match command {
+            "dist" => {
+                let bag = self.argument_bag(argument)?;
+                let (distribution, sampled) = self.distribution(&bag);
+                let chart = Chart::new().frequencies("", &distribution).render();
+                Ok(format!(
+                    "{} ({})\n{}",
+                    bag,
+                    method(sampled),
+                    chart.trim_end()
+                ))
+            }
+            "stats" => {
+                let bag = self.argument_bag(argument)?;
+                let (distribution, sampled) = self.distribution(&bag);
+                let possible = distribution
+                    .iter()
+                    .filter(|(_, p)| **p > 0.0)
+                    .map(|(total, p)| (*total as f64, p / 100.0))
+                    .collect::<Vec<_>>();
+                let mean = possible.iter().map(|(t, p)| t * p).sum::<f64>();
+                let variance = possible
+                    .iter()
+                    .map(|(t, p)| (t - mean).powi(2) * p)
+                    .sum::<f64>();
+                let mut cumulative = 0.0;
+                let median = possible
+                    .iter()
+                    .find(|(_, p)| {
+                        cumulative += p;
+                        cumulative >= 0.5
+                    })
+                    .or_else(|| possible.last())
+                    .map_or(0.0, |(t, _)| *t);
+                let (min, max) = (
+                    possible.first().map_or(0.0, |(t, _)| *t),
+                    possible.last().map_or(0.0, |(t, _)| *t),
+                );
+                Ok(format!(
+                    "{} ({})\nmean     {:.4}\nstd dev  {:.4}\nmedian   {}\nrange    {} to {}",
+                    bag,
+                    method(sampled),
+                    mean,
+                    variance.sqrt(),
+                    median,
+                    min,
+                    max
+                ))
+            }
+            "seed" => {
+                let seed = if argument.is_empty() {
+                    rand::random()
+                } else {
+                    argument.parse::<u64>().map_err(|_| {
+                        format!("The seed must be a whole number, not \"{}\".", argument)
+                    })?
+                };
+                self.seed(seed);
+                Ok(format!("Seeded with {}.", seed))
+            }
+            "save" if !argument.is_empty() => self
+                .save(argument)
+                .map(|()| format!("Saved to {}.", argument))
+                .map_err(|e| format!("Could not save to {}: {}", argument, e)),
+            "load" if !argument.is_empty() => {
+                let mut loaded = self.clone();
+                loaded
+                    .load(argument)
+                    .map_err(|e| format!("Could not load {}: {}", argument, e))?;
+                *self = loaded;
+                Ok(format!(
+                    "Loaded {} names and {} results from {}.",
+                    self.variables.len(),
+                    self.history.len(),
+                    argument
+                ))
+            }
+            "save" | "load" => Err(format!(":{} needs a file.", command)),
+            "vars" => Ok(self
+                .variables
+                .iter()
+                .map(|(name, bag)| format!("{} = {}", name, bag))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            "history" => Ok(self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(i, r)| format!("${} = {}: {}", i + 1, r.total, r.expression))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            "help" => Ok(HELP.to_owned()),
+            _ => Err(format!("Unknown command \":{}\" (see :help).", command)),
+        }
+    }
+
+    /// Write the names and results to a file, which `load` can read back. See `write_to`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Replace the names and results with those in a file written by `save`. See `read_from`.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.read_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Write the names and results as text: a header line, then a line per name of "name",
+    /// the name and its dice string, and a line per result of "result", its dice string and its
+    /// total, separated by tabs (eg "name\tatk\t1d20 + 7" and "result\t1d20 + 9\t21").
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", HEADER)?;
+        for (name, bag) in self.variables.iter() {
+            writeln!(writer, "name\t{}\t{}", name, bag)?;
+        }
+        for record in self.history.iter() {
+            writeln!(writer, "result\t{}\t{}", record.expression, record.total)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the names and results with those written by `write_to`. The dice strings are
+    /// parsed again, against the limits of this session. The random number generator is kept.
+    pub fn read_from<R: BufRead>(&mut self, reader: R) -> io::Result<()> {
+        let invalid = |line: usize, message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Session line {}: {}", line + 1, message),
+            )
+        };
+
+        let mut lines = reader.lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(invalid(0, "not a saved session."));
+        }
+
+        let mut variables = BTreeMap::new();
+        let mut history = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let parse = |expression: &str| {
+                crate::parse::parse_with_limits(expression.to_owned(), &self.limits)
+                    .map_err(|e| invalid(i + 1, &e))
+            };
+            match line.split('\t').collect::<Vec<_>>().as_slice() {
+                ["name", name, expression] => {
+                    if !is_identifier(name) {
+                        return Err(invalid(i + 1, "not a name."));
+                    }
+                    variables.insert(name.to_string(), parse(expression)?);
+                }
+                ["result", expression, total] => {
+                    let bag = parse(expression)?;
+                    let total = total
+                        .parse::<i64>()
+                        .map_err(|e| invalid(i + 1, &e.to_string()))?;
+                    let [low, high] = bag.calculate_bounds(true);
+                    if low.is_some_and(|low| total < low) || high.is_some_and(|high| total > high) {
+                        return Err(invalid(i + 1, "the total cannot be rolled."));
+                    }
+                    history.push(Record {
+                        expression: bag.to_string(),
+                        total,
+                    });
+                }
+                _ => return Err(invalid(i + 1, "not a name or a result.")),
+            }
+        }
+        self.variables = variables;
+        self.history = history;
+        Ok(())
+    }
+}
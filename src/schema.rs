@@ -0,0 +1,491 @@
+//! This module contains the `serde` representation of dice bags, roll results and distributions,
+//! enabled by the "serde" feature.
+//!
+//! The top level documents (`DiceBag`, `RollResults`, `Histogram` and `AdaptiveDistribution`)
+//! carry a `version` field, which is `SCHEMA_VERSION` when written. Documents of earlier versions
+//! are still read, as long as they use nothing added since, and fields which are not in the schema
+//! are refused rather than ignored.
+//! The clauses of a dice group are written by name rather than as the internal representation,
+//! and a `DiceBag` is checked as it is read, so a bag read in is exactly as valid as a parsed one.
+//! ```
+//! use libazdice::distribution::*;
+//!
+//! let bag: DiceBag = libazdice::parse("4d6dl1rr1be2 - 3".to_owned()).unwrap();
+//! let json = serde_json::to_string(&bag).unwrap();
+//! assert_eq!(
+//!     json,
+//!     concat!(
+//!         r#"{"version":2,"dice":["#,
+//!         r#"{"dice":{"count":4,"sides":6,"op":"add","drop_lowest":1,"#,
+//!         r#""reroll":{"below":{"threshold":2,"count":1}},"explode":false}},"#,
+//!         r#"{"bonus":{"bonus":3,"op":"sub"}}],"#,
+//!         r#""limits":{"max_dice_per_group":1000,"max_sides":1000000000,"max_groups":100,"#,
+//!         r#""max_explosion_depth":100,"max_total_rolls":10000}}"#,
+//!     )
+//! );
+//! assert_eq!(serde_json::from_str::<DiceBag>(&json).unwrap(), bag);
+//!
+//! // Dice which could not be parsed cannot be read either.
+//! let json = json.replace(r#""drop_lowest":1"#, r#""drop_lowest":4"#);
+//! assert!(serde_json::from_str::<DiceBag>(&json).is_err());
+//! ```
+use super::distribution::*;
+use super::nested::Nested;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// The version of the schema written by this version of the library. Version 2 added dice rolled
+/// for the count or sides of dice, "max(..)" and "min(..)" groups, labels and comments.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Refuse documents written with a schema this version cannot read.
+fn check_version<E: Error>(version: u32) -> Result<(), E> {
+    if (1..=SCHEMA_VERSION).contains(&version) {
+        Ok(())
+    } else {
+        Err(E::custom(format!(
+            "Unsupported schema version ({} vs {}).",
+            version, SCHEMA_VERSION
+        )))
+    }
+}
+
+/// Refuse a version 1 document which uses something added in version 2.
+fn check_version_1<E: Error>(version: u32, uses_version_2: bool) -> Result<(), E> {
+    if version == 1 && uses_version_2 {
+        Err(E::custom(
+            "Nested dice, \"max(..)\", \"min(..)\", labels and comments need schema version 2.",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether the dice use anything added in version 2 of the schema.
+fn dice_use_version_2(dice: &Dice) -> bool {
+    dice.nested.is_some() || dice.pick.is_some() || dice.label.is_some()
+}
+
+/// Whether the group uses anything added in version 2 of the schema.
+fn group_uses_version_2(group: &DiceGroup) -> bool {
+    match group {
+        DiceGroup::Bonus(bonus) => bonus.label.is_some(),
+        DiceGroup::Dice(dice) => dice_use_version_2(dice),
+    }
+}
+
+/// A reroll clause, eg `{"above": {"threshold": 4, "count": 1}}` for "rr1ab4".
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum ReRollRepr {
+    Above { threshold: i64, count: usize },
+    Below { threshold: i64, count: usize },
+}
+
+/// A group of dice, with each clause as it would be written in a dice string.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiceRepr {
+    count: usize,
+    sides: i64,
+    op: DiceOp,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    drop_lowest: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    drop_highest: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reroll: Option<ReRollRepr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    minimum: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    maximum: Option<i64>,
+    #[serde(default)]
+    explode: bool,
+    /// Dice rolled for the count (eg "(1d4)d6"), in which case `count` is the fewest they roll.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    count_dice: Option<DiceBag>,
+    /// Dice rolled for the sides, in which case `sides` is the fewest they roll.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sides_dice: Option<DiceBag>,
+    /// The bags of which the highest total is kept (eg "max(1d8!, 1d6!)"), in which case `count`
+    /// is the number of bags and `sides` is 1.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    max_of: Vec<DiceBag>,
+    /// The bags of which the lowest total is kept (eg "min(1d20, 1d20)").
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    min_of: Vec<DiceBag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+impl From<&Dice> for DiceRepr {
+    fn from(dice: &Dice) -> DiceRepr {
+        let (drop_lowest, drop_highest) = match dice.drop {
+            Drop::Non => (None, None),
+            Drop::Lowest(n) => (Some(n), None),
+            Drop::Highest(n) => (None, Some(n)),
+            // A kept run of the sorted dice is the same as dropping from both ends.
+            Drop::Custom(ref keep) => (
+                Some(keep.first().copied().unwrap_or(dice.count)),
+                Some(keep.last().map_or(0, |l| dice.count.saturating_sub(l + 1))),
+            ),
+        };
+        let reroll = match dice.reroll {
+            ReRoll::Never => None,
+            ReRoll::IfAbove(rrt) => Some(ReRollRepr::Above {
+                threshold: rrt.ex_threshold,
+                count: rrt.count,
+            }),
+            ReRoll::IfBelow(rrt) => Some(ReRollRepr::Below {
+                threshold: rrt.ex_threshold,
+                count: rrt.count,
+            }),
+        };
+        let (minimum, maximum) = match dice.cutoff {
+            CutOff::Non => (None, None),
+            CutOff::Minimum(min) => (Some(min), None),
+            CutOff::Maximum(max) => (None, Some(max)),
+            CutOff::Both(MinMax([min, max])) => (Some(min), Some(max)),
+        };
+        DiceRepr {
+            count: dice.count,
+            sides: dice.size,
+            op: dice.op,
+            drop_lowest,
+            drop_highest,
+            reroll,
+            minimum,
+            maximum,
+            explode: dice.explosive,
+            count_dice: dice.nested.as_ref().and_then(|n| n.count.clone()),
+            sides_dice: dice.nested.as_ref().and_then(|n| n.size.clone()),
+            max_of: match dice.pick {
+                Some(ref pick) if pick.highest => pick.bags.clone(),
+                _ => Vec::new(),
+            },
+            min_of: match dice.pick {
+                Some(ref pick) if !pick.highest => pick.bags.clone(),
+                _ => Vec::new(),
+            },
+            label: dice.label.clone(),
+        }
+    }
+}
+
+impl TryFrom<DiceRepr> for Dice {
+    type Error = DiceError;
+
+    /// Rebuild the dice with the same checks as the parser.
+    fn try_from(repr: DiceRepr) -> Result<Dice, DiceError> {
+        if !repr.max_of.is_empty() || !repr.min_of.is_empty() {
+            return pick_from_repr(repr);
+        }
+        // The clauses are checked against the fewest dice and sides, as when parsing.
+        let count = match repr.count_dice {
+            Some(ref bag) => {
+                let low = bag.get_range()[0];
+                usize::try_from(low).map_err(|_| DiceError::NegativeCount(low))?
+            }
+            None => repr.count,
+        };
+        let sides = repr
+            .sides_dice
+            .as_ref()
+            .map_or(repr.sides, |bag| bag.get_range()[0]);
+        let mut dice = Dice::with_size_and_count(sides, count);
+        if let Some(n) = repr.drop_lowest {
+            dice.with_drop_lowest(n)?;
+        }
+        if let Some(n) = repr.drop_highest {
+            dice.with_drop_highest(n)?;
+        }
+        match repr.reroll {
+            Some(ReRollRepr::Above { threshold, count }) => {
+                dice.add_reroll_if_above(threshold, count)?
+            }
+            Some(ReRollRepr::Below { threshold, count }) => {
+                dice.add_reroll_if_below(threshold, count)?
+            }
+            None => {}
+        }
+        if let Some(min) = repr.minimum {
+            dice.with_minimum_roll(min)?;
+        }
+        if let Some(max) = repr.maximum {
+            dice.with_maximum_roll(max)?;
+        }
+        dice.add_op(repr.op);
+        dice.explosive = repr.explode;
+        dice.label = repr.label;
+        if repr.count_dice.is_some() || repr.sides_dice.is_some() {
+            dice.nested = Some(Box::new(Nested {
+                count: repr.count_dice,
+                size: repr.sides_dice,
+            }));
+        }
+        dice.validate()?;
+        Ok(dice)
+    }
+}
+
+/// Rebuild a "max(..)" or "min(..)" group, which has no clauses of its own.
+fn pick_from_repr(repr: DiceRepr) -> Result<Dice, DiceError> {
+    if repr.drop_lowest.is_some()
+        || repr.drop_highest.is_some()
+        || repr.reroll.is_some()
+        || repr.minimum.is_some()
+        || repr.maximum.is_some()
+        || repr.count_dice.is_some()
+        || repr.sides_dice.is_some()
+        || !(repr.max_of.is_empty() || repr.min_of.is_empty())
+    {
+        return Err(DiceError::ClauseOnPick);
+    }
+    let mut dice = if repr.min_of.is_empty() {
+        Dice::max_of(repr.max_of)
+    } else {
+        Dice::min_of(repr.min_of)
+    };
+    // The count, sides and explosion must be as a pick writes them, which `validate` checks.
+    dice.count = repr.count;
+    dice.size = repr.sides;
+    dice.explosive = repr.explode;
+    dice.add_op(repr.op);
+    dice.label = repr.label;
+    dice.validate()?;
+    Ok(dice)
+}
+
+impl Serialize for Dice {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        DiceRepr::from(self).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Dice {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Dice::try_from(DiceRepr::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Serialize)]
+struct DiceBagOut<'a> {
+    version: u32,
+    dice: &'a [DiceGroup],
+    limits: &'a Limits,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DiceBagIn {
+    version: u32,
+    dice: Vec<DiceGroup>,
+    #[serde(default)]
+    limits: Limits,
+    #[serde(default)]
+    comment: Option<String>,
+}
+
+impl Serialize for DiceBag {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        DiceBagOut {
+            version: SCHEMA_VERSION,
+            dice: &self.dice,
+            limits: self.limits(),
+            comment: self.comment(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// The limits read with a bag, which may tighten the default `Limits` but never loosen them.
+fn no_looser_than_default(limits: Limits) -> Limits {
+    let default = Limits::default();
+    Limits {
+        max_dice_per_group: limits.max_dice_per_group.min(default.max_dice_per_group),
+        max_sides: limits.max_sides.min(default.max_sides),
+        max_groups: limits.max_groups.min(default.max_groups),
+        max_explosion_depth: limits.max_explosion_depth.min(default.max_explosion_depth),
+        max_total_rolls: limits.max_total_rolls.min(default.max_total_rolls),
+    }
+}
+
+impl<'de> Deserialize<'de> for DiceBag {
+    /// The bag must be valid within its limits (see `DiceBag::validate`). The limits written in
+    /// the document are not trusted: any above the defaults are read as the defaults.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let DiceBagIn {
+            version,
+            dice,
+            limits,
+            comment,
+        } = DiceBagIn::deserialize(deserializer)?;
+        check_version(version)?;
+        check_version_1(
+            version,
+            comment.is_some() || dice.iter().any(group_uses_version_2),
+        )?;
+        let limits = no_looser_than_default(limits);
+        let mut bag = DiceBag::from_dice(dice);
+        bag.limits = limits;
+        bag.comment = comment;
+        bag.validate().map_err(D::Error::custom)?;
+        Ok(bag)
+    }
+}
+
+#[derive(Serialize)]
+struct RollResultsOut<'a> {
+    version: u32,
+    dice_groups: &'a [DiceResult],
+    bonus: &'a BonusResult,
+    total: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RollResultsIn {
+    version: u32,
+    dice_groups: Vec<DiceResult>,
+    bonus: BonusResult,
+    total: i64,
+}
+
+impl Serialize for RollResults {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RollResultsOut {
+            version: SCHEMA_VERSION,
+            dice_groups: &self.dice_groups,
+            bonus: &self.bonus,
+            total: self.total,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RollResults {
+    /// The total must be the sum of the totals of the groups and boni.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let RollResultsIn {
+            version,
+            dice_groups,
+            bonus,
+            total,
+        } = RollResultsIn::deserialize(deserializer)?;
+        check_version(version)?;
+        let uses_version_2 = !bonus.labelled.is_empty()
+            || dice_groups.iter().any(|g| {
+                dice_use_version_2(&g.dice)
+                    || g.count_roll.is_some()
+                    || g.size_roll.is_some()
+                    || !g.pick_rolls.is_empty()
+            });
+        check_version_1(version, uses_version_2)?;
+
+        let sum = dice_groups
+            .iter()
+            .try_fold(bonus.total, |acc, g| acc.checked_add(g.total));
+        if sum != Some(total) {
+            return Err(D::Error::custom(format!(
+                "Roll total ({}) does not match its groups.",
+                total
+            )));
+        }
+        Ok(RollResults {
+            dice_groups,
+            bonus,
+            total,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct HistogramOut<'a> {
+    version: u32,
+    counts: &'a BTreeMap<i64, usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HistogramIn {
+    version: u32,
+    counts: BTreeMap<i64, usize>,
+}
+
+impl Serialize for Histogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        HistogramOut {
+            version: SCHEMA_VERSION,
+            counts: &self.counts,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Histogram {
+    /// Only the counts are stored.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let HistogramIn { version, counts } = HistogramIn::deserialize(deserializer)?;
+        check_version(version)?;
+
+        Ok(histogram_from_counts(counts))
+    }
+}
+
+/// Rebuild a histogram from its counts, working out the number of rolls and their sum again.
+fn histogram_from_counts(counts: BTreeMap<i64, usize>) -> Histogram {
+    let rolls = counts.values().sum();
+    let sum = counts.iter().map(|(k, v)| *k as i128 * *v as i128).sum();
+    Histogram { counts, rolls, sum }
+}
+
+#[derive(Serialize)]
+struct AdaptiveDistributionOut<'a> {
+    version: u32,
+    counts: &'a BTreeMap<i64, usize>,
+    precision: f64,
+    converged: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AdaptiveDistributionIn {
+    version: u32,
+    counts: BTreeMap<i64, usize>,
+    precision: f64,
+    converged: bool,
+}
+
+impl Serialize for AdaptiveDistribution {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        AdaptiveDistributionOut {
+            version: SCHEMA_VERSION,
+            counts: self.counts(),
+            precision: self.precision,
+            converged: self.converged,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AdaptiveDistribution {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let AdaptiveDistributionIn {
+            version,
+            counts,
+            precision,
+            converged,
+        } = AdaptiveDistributionIn::deserialize(deserializer)?;
+        check_version(version)?;
+
+        Ok(AdaptiveDistribution {
+            histogram: histogram_from_counts(counts),
+            precision,
+            converged,
+        })
+    }
+}
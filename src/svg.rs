@@ -0,0 +1,393 @@
+//! This module contains `SvgChart`, which draws distributions (as made by eg
+//! `DiceBag::make_exact_distribution`) as an SVG image, with no dependencies beyond the standard
+//! library.
+//!
+//! The chance of each total (the PMF) is drawn as bars on the left axis, and the chance of each
+//! total or lower (the CDF) as lines on the right axis. Several distributions share the axes,
+//! with a colour each and a legend.
+//! ```
+//! use libazdice::distribution::*;
+//! use libazdice::svg::SvgChart;
+//!
+//! let attack: DiceBag = "1d20+5".parse().unwrap();
+//! let advantage: DiceBag = "2d20dl1+5".parse().unwrap();
+//! let svg = SvgChart::new()
+//!     .title("Attack rolls")
+//!     .frequencies("1d20+5", &attack.make_exact_distribution().unwrap())
+//!     .frequencies("2d20dl1+5", &advantage.make_exact_distribution().unwrap())
+//!     .render();
+//!
+//! assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+//! assert!(svg.contains("<title>Attack rolls</title>"));
+//! assert!(svg.contains(">2d20dl1+5</text>"));
+//! assert!(svg.trim_end().ends_with("</svg>"));
+//! ```
+use crate::chart::{all_totals, Series};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Write as _};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// The colours of the distributions, in order (repeating after the last).
+const PALETTE: [&str; 8] = [
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7",
+];
+
+/// The space around the plot, for the axes and their labels.
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_RIGHT: f64 = 70.0;
+const MARGIN_TOP: f64 = 50.0;
+const MARGIN_BOTTOM: f64 = 60.0;
+
+/// The most labelled ticks on the axis of totals.
+const MAX_TOTAL_TICKS: f64 = 20.0;
+
+/// Escape text for use in SVG.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A round step (1, 2 or 5 times a power of ten) of at least `rough`.
+fn nice_step(rough: f64) -> f64 {
+    if rough <= 0.0 || !rough.is_finite() {
+        return 1.0;
+    }
+    let magnitude = 10_f64.powf(rough.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|step| *step >= rough * (1.0 - 1e-9))
+        .unwrap_or(10.0 * magnitude)
+}
+
+/// A percentage tick label, with as many decimals as the step needs.
+fn tick_label(value: f64, step: f64) -> String {
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    format!("{:.*}%", decimals, value)
+}
+
+/// An SVG chart of one or more distributions. The settings are chained, as with `chart::Chart`,
+/// and the image is made with `render` (or `to_string`) or written with `write_to` or `save`.
+/// ```
+/// use libazdice::distribution::*;
+/// use libazdice::svg::SvgChart;
+///
+/// let bag: DiceBag = "3d6".parse().unwrap();
+/// let counts = bag.make_count_distribution(10_000);
+///
+/// // Only the bars, in a smaller image.
+/// let svg = SvgChart::new().counts("3d6", &counts).cdf(false).size(400, 300).render();
+/// assert!(svg.contains("width=\"400\" height=\"300\""));
+/// assert_eq!(svg.matches("<rect class=\"pmf\"").count(), 16);
+/// assert!(!svg.contains("<polyline"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvgChart {
+    series: Vec<Series>,
+    width: u32,
+    height: u32,
+    title: String,
+    pmf: bool,
+    cdf: bool,
+}
+
+impl Default for SvgChart {
+    fn default() -> Self {
+        SvgChart::new()
+    }
+}
+
+impl SvgChart {
+    /// An empty 800 by 450 chart with no title, drawing both the PMF and the CDF.
+    pub fn new() -> Self {
+        SvgChart {
+            series: Vec::new(),
+            width: 800,
+            height: 450,
+            title: String::new(),
+            pmf: true,
+            cdf: true,
+        }
+    }
+
+    /// Add a distribution on the base of 0-100% (eg from `DiceBag::make_frequency_distribution`
+    /// or `DiceBag::make_exact_distribution`), called `name` in the legend.
+    pub fn frequencies(mut self, name: &str, distribution: &BTreeMap<i64, f64>) -> Self {
+        self.series.push(Series::frequencies(name, distribution));
+        self
+    }
+
+    /// Add a distribution by count (eg from `DiceBag::make_count_distribution` or
+    /// `Histogram::counts`), called `name` in the legend.
+    pub fn counts(mut self, name: &str, distribution: &BTreeMap<i64, usize>) -> Self {
+        self.series.push(Series::counts(name, distribution));
+        self
+    }
+
+    /// The size of the image in pixels.
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// The title written above the chart.
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.to_owned();
+        self
+    }
+
+    /// Whether to draw the chance of each total as bars.
+    pub fn pmf(mut self, pmf: bool) -> Self {
+        self.pmf = pmf;
+        self
+    }
+
+    /// Whether to draw the chance of each total or lower as lines.
+    pub fn cdf(mut self, cdf: bool) -> Self {
+        self.cdf = cdf;
+        self
+    }
+
+    /// Make the SVG image. A chart with no distributions has axes but nothing on them.
+    pub fn render(&self) -> String {
+        let mut svg = String::new();
+        self.write_svg(&mut svg)
+            .expect("Writing to a string cannot fail.");
+        svg
+    }
+
+    /// Write the SVG image to `writer`.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(self.render().as_bytes())
+    }
+
+    /// Write the SVG image to a file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    fn write_svg(&self, svg: &mut String) -> std::fmt::Result {
+        let (width, height) = (f64::from(self.width), f64::from(self.height));
+        let left = MARGIN_LEFT;
+        let right = (width - MARGIN_RIGHT).max(left + 1.0);
+        let top = MARGIN_TOP;
+        let bottom = (height - MARGIN_BOTTOM).max(top + 1.0);
+
+        let totals = all_totals(&self.series);
+        let pmfs = self
+            .series
+            .iter()
+            .map(|s| s.values_at(&totals, true, false))
+            .collect::<Vec<_>>();
+        let cdfs = self
+            .series
+            .iter()
+            .map(|s| s.values_at(&totals, true, true))
+            .collect::<Vec<_>>();
+
+        // Each total gets a band of the plot, which its bars share.
+        let band = (right - left) / totals.len().max(1) as f64;
+        let band_centre = |i: usize| left + band * (i as f64 + 0.5);
+        let highest = pmfs.iter().flatten().cloned().fold(0.0_f64, f64::max);
+        let step = nice_step(highest / 5.0);
+        let pmf_top = (highest / step).ceil().max(1.0) * step;
+        let pmf_y = |p: f64| bottom - p / pmf_top * (bottom - top);
+        let cdf_y = |p: f64| bottom - p / 100.0 * (bottom - top);
+
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             viewBox=\"0 0 {} {}\" font-family=\"sans-serif\" font-size=\"12\">",
+            self.width, self.height, self.width, self.height
+        )?;
+        if !self.title.is_empty() {
+            writeln!(svg, "<title>{}</title>", escape(&self.title))?;
+        }
+        writeln!(
+            svg,
+            "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>",
+            self.width, self.height
+        )?;
+        if !self.title.is_empty() {
+            writeln!(
+                svg,
+                "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"middle\" font-size=\"16\">{}</text>",
+                width / 2.0,
+                top / 2.0,
+                escape(&self.title)
+            )?;
+        }
+
+        // The grid and the left axis, of the chance of each total.
+        if self.pmf {
+            let mut tick = 0.0;
+            while tick <= pmf_top * (1.0 + 1e-9) {
+                let y = pmf_y(tick);
+                writeln!(
+                    svg,
+                    "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"#e0e0e0\"/>",
+                    left, y, right, y
+                )?;
+                writeln!(
+                    svg,
+                    "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"end\" dominant-baseline=\"middle\">{}</text>",
+                    left - 6.0,
+                    y,
+                    tick_label(tick, step)
+                )?;
+                tick += step;
+            }
+            writeln!(
+                svg,
+                "<text transform=\"translate({:.2},{:.2}) rotate(-90)\" text-anchor=\"middle\">Probability</text>",
+                left - 52.0,
+                (top + bottom) / 2.0
+            )?;
+        }
+
+        // The right axis, of the chance of each total or lower.
+        if self.cdf {
+            for tick in (0..=100).step_by(20) {
+                writeln!(
+                    svg,
+                    "<text x=\"{:.2}\" y=\"{:.2}\" dominant-baseline=\"middle\">{}%</text>",
+                    right + 6.0,
+                    cdf_y(f64::from(tick)),
+                    tick
+                )?;
+            }
+            writeln!(
+                svg,
+                "<text transform=\"translate({:.2},{:.2}) rotate(90)\" text-anchor=\"middle\">Cumulative probability</text>",
+                right + 52.0,
+                (top + bottom) / 2.0
+            )?;
+        }
+
+        // The axis of totals.
+        writeln!(
+            svg,
+            "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"black\"/>",
+            left, bottom, right, bottom
+        )?;
+        writeln!(
+            svg,
+            "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"black\"/>",
+            left, top, left, bottom
+        )?;
+        let every = nice_step(totals.len() as f64 / MAX_TOTAL_TICKS) as i64;
+        for (i, total) in totals.iter().enumerate() {
+            if total.rem_euclid(every.max(1)) != 0 {
+                continue;
+            }
+            let x = band_centre(i);
+            writeln!(
+                svg,
+                "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"black\"/>",
+                x,
+                bottom,
+                x,
+                bottom + 4.0
+            )?;
+            writeln!(
+                svg,
+                "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"middle\">{}</text>",
+                x,
+                bottom + 18.0,
+                total
+            )?;
+        }
+        writeln!(
+            svg,
+            "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"middle\">Total</text>",
+            (left + right) / 2.0,
+            bottom + 42.0
+        )?;
+
+        // The bars, side by side within each band.
+        if self.pmf {
+            let bar = band * 0.9 / self.series.len().max(1) as f64;
+            for (j, pmf) in pmfs.iter().enumerate() {
+                let colour = PALETTE[j % PALETTE.len()];
+                for (i, p) in pmf.iter().enumerate() {
+                    if *p <= 0.0 {
+                        continue;
+                    }
+                    let x = left + band * (i as f64 + 0.05) + bar * j as f64;
+                    writeln!(
+                        svg,
+                        "<rect class=\"pmf\" x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" \
+                         fill=\"{}\" fill-opacity=\"0.75\"><title>{}: {} ({:.4}%)</title></rect>",
+                        x,
+                        pmf_y(*p),
+                        bar,
+                        bottom - pmf_y(*p),
+                        colour,
+                        escape(&self.series[j].name),
+                        totals[i],
+                        p
+                    )?;
+                }
+            }
+        }
+
+        // The lines, through the middle of each band.
+        if self.cdf {
+            for (j, cdf) in cdfs.iter().enumerate() {
+                let points = cdf
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| format!("{:.2},{:.2}", band_centre(i), cdf_y(*p)))
+                    .collect::<Vec<_>>();
+                writeln!(
+                    svg,
+                    "<polyline class=\"cdf\" points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>",
+                    points.join(" "),
+                    PALETTE[j % PALETTE.len()]
+                )?;
+            }
+        }
+
+        // The legend, in the top left of the plot.
+        for (j, s) in self.series.iter().enumerate() {
+            let y = top + 8.0 + 18.0 * j as f64;
+            writeln!(
+                svg,
+                "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"12\" height=\"12\" fill=\"{}\"/>",
+                left + 10.0,
+                y,
+                PALETTE[j % PALETTE.len()]
+            )?;
+            writeln!(
+                svg,
+                "<text x=\"{:.2}\" y=\"{:.2}\" dominant-baseline=\"middle\">{}</text>",
+                left + 28.0,
+                y + 6.0,
+                escape(&s.name)
+            )?;
+        }
+        writeln!(svg, "</svg>")
+    }
+}
+
+impl Display for SvgChart {
+    /// The same as `SvgChart::render`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render())
+    }
+}
@@ -0,0 +1,523 @@
+//! This module contains dice whose faces show symbols rather than numbers, such as the narrative
+//! dice of Genesys, where successes cancel failures and advantages cancel threats. The dice and
+//! the rules for cancelling are given in a `SymbolSet` (`SymbolSet::genesys` has the Genesys
+//! dice), a `SymbolPool` of its dice is rolled, and the result is the `Tally` of the symbols left
+//! once the rules are applied.
+//! ```
+//! use libazdice::symbols::SymbolSet;
+//!
+//! let set: SymbolSet = "
+//!     fight: -, -, hit, hit, miss, miss # two blank faces, two hits and two misses
+//!     edge: hit hit, -
+//!     hit cancels miss
+//! "
+//! .parse()
+//! .unwrap();
+//! let pool = set.pool("2 fight + edge").unwrap();
+//!
+//! let roll = pool.roll();
+//! assert_eq!(roll.faces().len(), 3);
+//! assert!(roll.net().get("hit") == 0 || roll.net().get("miss") == 0);
+//!
+//! let distribution = pool.distribution().unwrap();
+//! assert!((distribution.values().sum::<f64>() - 100.0).abs() < 1e-9);
+//! let hits = pool.chance(|net| net.get("hit") >= 1).unwrap();
+//! assert!(hits > 50.0);
+//! ```
+use super::exact::Budget;
+use super::validation::Limits;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// A die of a `SymbolSet`: a name and the symbols on each face. A face may show no symbols, or
+/// the same symbol more than once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolDie {
+    name: String,
+    faces: Vec<Vec<String>>,
+}
+
+impl SymbolDie {
+    /// The name of the die.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The symbols on each face.
+    pub fn faces(&self) -> &[Vec<String>] {
+        &self.faces
+    }
+}
+
+/// The dice of a game with symbolic dice, and the rules for adding up their symbols:
+/// - "a cancels b": each `a` removes one `b`, and each `b` one `a`, until only one is left.
+/// - "a counts as b": each `a` also adds a `b` (before cancelling), and is kept itself. Only one
+///   step is taken, so if `b` counts as `c` the `a` does not add a `c`.
+///
+/// Names of dice and symbols are matched without regard to case, and symbols are single words.
+///
+/// As text, each line is a die (its name, a colon, and its faces separated by commas, with the
+/// symbols of a face separated by spaces and "-" for a blank face) or a rule. Blank lines and
+/// anything after a '#' are ignored. The text of `SymbolSet::genesys` is an example.
+/// ```
+/// use libazdice::symbols::SymbolSet;
+///
+/// let set = SymbolSet::new()
+///     .die("coin", &[&["heads"], &["tails"]])
+///     .die("token", &[&[], &["heads", "heads"]])
+///     .cancels("heads", "tails");
+/// assert_eq!(
+///     set.to_string(),
+///     "coin: heads, tails\ntoken: -, heads heads\nheads cancels tails\n"
+/// );
+/// assert_eq!(set.to_string().parse::<SymbolSet>(), Ok(set));
+///
+/// assert!("coin: heads, tails\nheads beats tails".parse::<SymbolSet>().is_err());
+/// assert!("coin:".parse::<SymbolSet>().is_err());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolSet {
+    dice: Vec<SymbolDie>,
+    cancels: Vec<(String, String)>,
+    counts_as: Vec<(String, String)>,
+}
+
+/// A symbol's place in the net tally: which slot it adds to, and whether it adds or takes away.
+type Place = (usize, i64);
+
+/// What each slot of a net tally holds: one symbol, or a pair which cancel (the first counted up
+/// and the second counted down).
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Slot {
+    Single(String),
+    Pair(String, String),
+}
+
+fn normal(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// The symbols of a face as text, with "-" for a blank face.
+fn face_text(face: &[String]) -> String {
+    if face.is_empty() {
+        "-".to_owned()
+    } else {
+        face.join(" ")
+    }
+}
+
+impl SymbolSet {
+    /// A set with no dice and no rules.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The dice of Genesys (and the Star Wars games from the same designers): boost, setback,
+    /// ability, difficulty, proficiency and challenge dice. Successes cancel failures and
+    /// advantages cancel threats, and a triumph (or despair) also counts as a success (or a
+    /// failure) without being cancelled itself.
+    /// ```
+    /// use libazdice::symbols::SymbolSet;
+    ///
+    /// let pool = SymbolSet::genesys().pool("2 ability + 1 difficulty").unwrap();
+    /// let distribution = pool.distribution().unwrap();
+    /// let succeeds = pool.chance(|net| net.get("success") > 0).unwrap();
+    /// assert!(succeeds > 50.0 && succeeds < 70.0);
+    /// assert!(distribution.keys().all(|net| net.get("success") == 0 || net.get("failure") == 0));
+    /// ```
+    pub fn genesys() -> Self {
+        GENESYS.parse().expect("The Genesys dice are always valid.")
+    }
+
+    /// Add a die called `name` with `faces`, replacing any die of the same name.
+    pub fn die(mut self, name: &str, faces: &[&[&str]]) -> Self {
+        let faces = faces
+            .iter()
+            .map(|face| face.iter().map(|s| normal(s)).collect())
+            .collect();
+        self.add_die(normal(name), faces);
+        self
+    }
+
+    fn add_die(&mut self, name: String, faces: Vec<Vec<String>>) {
+        match self.dice.iter_mut().find(|d| d.name == name) {
+            Some(die) => die.faces = faces,
+            None => self.dice.push(SymbolDie { name, faces }),
+        }
+    }
+
+    /// Add the rule that `a` and `b` cancel each other.
+    pub fn cancels(mut self, a: &str, b: &str) -> Self {
+        self.cancels.push((normal(a), normal(b)));
+        self
+    }
+
+    /// Add the rule that each `a` also counts as a `b`.
+    pub fn counts_as(mut self, a: &str, b: &str) -> Self {
+        self.counts_as.push((normal(a), normal(b)));
+        self
+    }
+
+    /// The dice of the set.
+    pub fn dice(&self) -> &[SymbolDie] {
+        &self.dice
+    }
+
+    /// The die called `name`, if there is one.
+    pub fn get(&self, name: &str) -> Option<&SymbolDie> {
+        let name = normal(name);
+        self.dice.iter().find(|d| d.name == name)
+    }
+
+    /// A pool of the dice of the set, written as counts and names joined by '+', such as
+    /// "2 ability + proficiency + 3 difficulty" (a die without a count is rolled once).
+    pub fn pool(&self, dice: &str) -> Result<SymbolPool, String> {
+        let mut counts = Vec::new();
+        for part in dice.split('+').map(str::trim) {
+            let (count, name) = part
+                .split_once(char::is_whitespace)
+                .and_then(|(count, name)| Some((count.parse::<usize>().ok()?, name.trim())))
+                .unwrap_or((1, part));
+            if name.is_empty() {
+                return Err(format!("\"{}\" needs the name of a die.", dice.trim()));
+            }
+            counts.push((name, count));
+        }
+        self.pool_of(&counts)
+    }
+
+    /// A pool of `count` dice of each name. As with numeric dice, no more than
+    /// `Limits::max_dice_per_group` of one die may be rolled.
+    pub fn pool_of(&self, dice: &[(&str, usize)]) -> Result<SymbolPool, String> {
+        let (slots, places) = self.slots()?;
+        let mut pool = SymbolPool {
+            dice: Vec::new(),
+            slots,
+        };
+        for (name, count) in dice.iter() {
+            let die = self
+                .get(name)
+                .ok_or_else(|| format!("There is no die called \"{}\".", name.trim()))?;
+            if *count > Limits::default().max_dice_per_group {
+                return Err(format!("Too many \"{}\" dice.", die.name));
+            }
+            if die.faces.is_empty() {
+                return Err(format!("The die \"{}\" has no faces.", die.name));
+            }
+            let faces = die
+                .faces
+                .iter()
+                .map(|face| self.net_of(face, &places, pool.slots.len()))
+                .collect();
+            pool.dice.push((*count, die.clone(), faces));
+        }
+        Ok(pool)
+    }
+
+    /// The slots of a net tally, and the place of each symbol in them. Fails if the rules do not
+    /// make sense, such as a symbol cancelling two others.
+    fn slots(&self) -> Result<(Vec<Slot>, BTreeMap<String, Place>), String> {
+        let mut slots = Vec::new();
+        let mut places = BTreeMap::new();
+        for (a, b) in self.cancels.iter() {
+            if a == b {
+                return Err(format!("\"{}\" cannot cancel itself.", a));
+            }
+            if let Some(s) = [a, b].iter().find(|s| places.contains_key(s.as_str())) {
+                return Err(format!("\"{}\" cancels more than one symbol.", s));
+            }
+            places.insert(a.clone(), (slots.len(), 1));
+            places.insert(b.clone(), (slots.len(), -1));
+            slots.push(Slot::Pair(a.clone(), b.clone()));
+        }
+        let shown = self.dice.iter().flat_map(|d| d.faces.iter().flatten());
+        let rules = self.counts_as.iter().flat_map(|(a, b)| vec![a, b]);
+        for symbol in shown.chain(rules) {
+            if !places.contains_key(symbol) {
+                places.insert(symbol.clone(), (slots.len(), 1));
+                slots.push(Slot::Single(symbol.clone()));
+            }
+        }
+        Ok((slots, places))
+    }
+
+    /// The net tally of one face, by slot.
+    fn net_of(&self, face: &[String], places: &BTreeMap<String, Place>, len: usize) -> Vec<i64> {
+        let mut net = vec![0; len];
+        for symbol in face.iter() {
+            let also = self
+                .counts_as
+                .iter()
+                .filter(|(a, _)| a == symbol)
+                .map(|(_, b)| b);
+            for counted in std::iter::once(symbol).chain(also) {
+                let (slot, sign) = places[counted];
+                net[slot] += sign;
+            }
+        }
+        net
+    }
+}
+
+/// The text of `SymbolSet::genesys`.
+const GENESYS: &str = "\
+boost: -, -, success, success advantage, advantage advantage, advantage
+setback: -, -, failure, failure, threat, threat
+ability: -, success, success, success success, advantage, advantage, success advantage, \
+advantage advantage
+difficulty: -, failure, failure failure, threat, threat, threat, threat threat, failure threat
+proficiency: -, success, success, success success, success success, advantage, \
+success advantage, success advantage, success advantage, advantage advantage, \
+advantage advantage, triumph
+challenge: -, failure, failure, failure failure, failure failure, threat, threat, \
+failure threat, failure threat, threat threat, threat threat, despair
+success cancels failure
+advantage cancels threat
+triumph counts as success
+despair counts as failure
+";
+
+impl Display for SymbolSet {
+    /// The set as text, which parses back into the same set.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for die in self.dice.iter() {
+            let faces: Vec<String> = die.faces.iter().map(|face| face_text(face)).collect();
+            writeln!(f, "{}: {}", die.name, faces.join(", "))?;
+        }
+        for (a, b) in self.cancels.iter() {
+            writeln!(f, "{} cancels {}", a, b)?;
+        }
+        for (a, b) in self.counts_as.iter() {
+            writeln!(f, "{} counts as {}", a, b)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SymbolSet {
+    type Err = String;
+
+    /// Parse dice and rules, one to a line. See `SymbolSet`.
+    fn from_str(s: &str) -> Result<SymbolSet, String> {
+        let mut set = SymbolSet::new();
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some((name, faces)) = line.split_once(':') {
+                let faces: Vec<Vec<String>> = faces
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|face| !face.is_empty())
+                    .map(|face| match face {
+                        "-" => Vec::new(),
+                        _ => face.split_whitespace().map(normal).collect(),
+                    })
+                    .collect();
+                if faces.is_empty() || name.trim().is_empty() {
+                    return Err(format!("The die \"{}\" needs a name and faces.", line));
+                }
+                set.add_die(normal(name), faces);
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            set = match words.as_slice() {
+                [a, "cancels", b] => set.cancels(a, b),
+                [a, "counts", "as", b] => set.counts_as(a, b),
+                _ => return Err(format!("Unknown rule \"{}\".", line)),
+            };
+        }
+        set.slots()?;
+        Ok(set)
+    }
+}
+
+/// The symbols left after a roll once the rules of a `SymbolSet` are applied, and how many of
+/// each. Symbols with none left are not kept, so two tallies with the same symbols are equal.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tally {
+    symbols: BTreeMap<String, u32>,
+}
+
+impl Tally {
+    /// How many of `symbol` there are.
+    pub fn get(&self, symbol: &str) -> u32 {
+        self.symbols.get(&normal(symbol)).cloned().unwrap_or(0)
+    }
+
+    /// Each symbol and how many of it there are, in alphabetical order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.symbols.iter().map(|(s, n)| (s.as_str(), *n))
+    }
+
+    /// Whether there are no symbols at all.
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    fn from_net(net: &[i64], slots: &[Slot]) -> Tally {
+        let mut symbols = BTreeMap::new();
+        for (n, slot) in net.iter().zip(slots.iter()) {
+            let symbol = match slot {
+                Slot::Single(s) | Slot::Pair(s, _) if *n > 0 => s,
+                Slot::Pair(_, s) if *n < 0 => s,
+                _ => continue,
+            };
+            let count = u32::try_from(n.unsigned_abs()).unwrap_or(u32::MAX);
+            symbols.insert(symbol.clone(), count);
+        }
+        Tally { symbols }
+    }
+}
+
+impl Display for Tally {
+    /// The symbols and their counts, eg "2 success, 1 threat", or "nothing".
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "nothing");
+        }
+        for (i, (symbol, n)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} {}", n, symbol)?;
+        }
+        Ok(())
+    }
+}
+
+/// A die of a pool, how many of it there are, and the net tally of each face.
+type PoolDie = (usize, SymbolDie, Vec<Vec<i64>>);
+
+/// Dice of a `SymbolSet` to roll together. Made by `SymbolSet::pool`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolPool {
+    dice: Vec<PoolDie>,
+    slots: Vec<Slot>,
+}
+
+impl SymbolPool {
+    /// Roll the pool.
+    pub fn roll(&self) -> SymbolRoll {
+        self.roll_with_rng(&mut rand::thread_rng())
+    }
+
+    /// As `SymbolPool::roll`, but with the given random number generator.
+    /// ```
+    /// use libazdice::distribution::seeded_rng;
+    /// use libazdice::symbols::SymbolSet;
+    ///
+    /// let pool = SymbolSet::genesys().pool("proficiency + challenge").unwrap();
+    /// let first = pool.roll_with_rng(&mut seeded_rng(7));
+    /// assert_eq!(first, pool.roll_with_rng(&mut seeded_rng(7)));
+    /// ```
+    pub fn roll_with_rng<R: Rng + ?Sized>(&self, rng: &mut R) -> SymbolRoll {
+        let mut net = vec![0; self.slots.len()];
+        let mut faces = Vec::new();
+        for (count, die, nets) in self.dice.iter() {
+            for _ in 0..*count {
+                let face = rng.gen_range(0, nets.len());
+                add_into(&mut net, &nets[face]);
+                faces.push((die.name.clone(), die.faces[face].clone()));
+            }
+        }
+        SymbolRoll {
+            faces,
+            net: Tally::from_net(&net, &self.slots),
+        }
+    }
+
+    /// The exact chance of each net tally, as percentages (as
+    /// `DiceBag::make_exact_distribution`). Fails if the pool has too many outcomes to work
+    /// through.
+    pub fn distribution(&self) -> Result<BTreeMap<Tally, f64>, String> {
+        let mut budget = Budget::new();
+        let mut nets: BTreeMap<Vec<i64>, f64> = BTreeMap::new();
+        nets.insert(vec![0; self.slots.len()], 1.0);
+        for (count, _, faces) in self.dice.iter() {
+            let chance = 1.0 / faces.len() as f64;
+            for _ in 0..*count {
+                budget.spend((nets.len() * faces.len()) as u64)?;
+                let mut next = BTreeMap::new();
+                for (net, p) in nets.iter() {
+                    for face in faces.iter() {
+                        let mut sum = net.clone();
+                        add_into(&mut sum, face);
+                        *next.entry(sum).or_insert(0.0) += p * chance;
+                    }
+                }
+                nets = next;
+            }
+        }
+        let mut tallies = BTreeMap::new();
+        for (net, p) in nets {
+            *tallies
+                .entry(Tally::from_net(&net, &self.slots))
+                .or_insert(0.0) += p * 100.0;
+        }
+        Ok(tallies)
+    }
+
+    /// The exact chance, as a percentage, of a net tally for which `test` is true.
+    pub fn chance(&self, test: impl Fn(&Tally) -> bool) -> Result<f64, String> {
+        let distribution = self.distribution()?;
+        Ok(distribution
+            .iter()
+            .filter(|(tally, _)| test(tally))
+            .map(|(_, p)| p)
+            .sum())
+    }
+}
+
+fn add_into(net: &mut [i64], face: &[i64]) {
+    for (n, f) in net.iter_mut().zip(face.iter()) {
+        *n += f;
+    }
+}
+
+impl Display for SymbolPool {
+    /// The pool as text, which `SymbolSet::pool` reads back.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, (count, die, _)) in self.dice.iter().enumerate() {
+            if i > 0 {
+                write!(f, " + ")?;
+            }
+            write!(f, "{} {}", count, die.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of rolling a `SymbolPool`: the face rolled on each die, and the net tally.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolRoll {
+    faces: Vec<(String, Vec<String>)>,
+    net: Tally,
+}
+
+impl SymbolRoll {
+    /// The name of each die rolled and the symbols on the face it fell on.
+    pub fn faces(&self) -> &[(String, Vec<String>)] {
+        &self.faces
+    }
+
+    /// The symbols left once the rules are applied.
+    pub fn net(&self) -> &Tally {
+        &self.net
+    }
+}
+
+impl Display for SymbolRoll {
+    /// Each die and its face, then the net tally, eg "ability( success ) difficulty( - )
+    /// (Net = 1 success )".
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (name, face) in self.faces.iter() {
+            write!(f, "{}( {} ) ", name, face_text(face))?;
+        }
+        write!(f, "(Net = {} )", self.net)
+    }
+}
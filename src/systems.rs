@@ -0,0 +1,598 @@
+//! This module contains the dice of some common game systems, ready made, with what their rolls
+//! mean. Each implements `System`, which gives the `DiceBag` to roll, the outcome of a roll, and
+//! the exact chance of each outcome.
+//! ```
+//! use libazdice::systems::{Pbta, PbtaOutcome, System};
+//!
+//! let move_roll = Pbta::new(1);
+//! assert_eq!(move_roll.bag().to_string(), "2d6 + 1");
+//!
+//! let (roll, outcome) = move_roll.roll();
+//! assert_eq!(outcome, move_roll.outcome(&roll));
+//!
+//! let table = move_roll.outcome_table().unwrap();
+//! // 9 or more on 2d6: 10 of the 36 ways they can fall.
+//! assert!((table[&PbtaOutcome::StrongHit] - 1000.0 / 36.0).abs() < 1e-9);
+//! assert!((table.values().sum::<f64>() - 100.0).abs() < 1e-9);
+//! ```
+use super::builder::DiceBagBuilder;
+use super::distribution::*;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+
+/// A game system's roll: the dice and what they mean.
+pub trait System {
+    /// What a roll means, ordered from worst to best.
+    type Outcome: Clone + Debug + Ord + Display;
+
+    /// The dice to roll.
+    fn bag(&self) -> DiceBag;
+
+    /// What a roll of `System::bag` means.
+    fn outcome(&self, roll: &RollResults) -> Self::Outcome;
+
+    /// The exact chance of each possible outcome, as percentages (as
+    /// `DiceBag::make_exact_distribution`).
+    fn outcome_table(&self) -> Result<BTreeMap<Self::Outcome, f64>, String>;
+
+    /// Roll the dice, returning the roll and what it means.
+    fn roll(&self) -> (RollResults, Self::Outcome) {
+        self.roll_with_rng(&mut rand::thread_rng())
+    }
+
+    /// As `System::roll`, but with the given random number generator.
+    fn roll_with_rng<R: Rng + ?Sized>(&self, rng: &mut R) -> (RollResults, Self::Outcome) {
+        let roll = self.bag().roll_with_rng(rng);
+        let outcome = self.outcome(&roll);
+        (roll, outcome)
+    }
+}
+
+/// Add `modifier` to the bag being built, as a bonus or a penalty (nothing for 0).
+fn with_modifier(builder: DiceBagBuilder, modifier: i32) -> DiceBagBuilder {
+    if modifier > 0 {
+        builder.plus(modifier.unsigned_abs())
+    } else if modifier < 0 {
+        builder.minus(modifier.unsigned_abs())
+    } else {
+        builder
+    }
+}
+
+/// Build a bag which is valid for any arguments the systems take.
+fn build(builder: DiceBagBuilder) -> DiceBag {
+    builder
+        .build()
+        .expect("The dice of a system are always valid.")
+}
+
+/// The chance of each outcome, for outcomes which depend only on the total of `bag`.
+fn table_by_total<O: Ord>(
+    bag: &DiceBag,
+    outcome: impl Fn(i64) -> O,
+) -> Result<BTreeMap<O, f64>, String> {
+    let mut table = BTreeMap::new();
+    for (total, p) in bag.make_exact_distribution()? {
+        *table.entry(outcome(total)).or_insert(0.0) += p;
+    }
+    Ok(table)
+}
+
+/// A Dungeons & Dragons (5th edition) d20 roll against a target: a check or save against a DC,
+/// or an attack against an AC, with or without advantage.
+///
+/// Attacks miss on a natural 1 and hit critically on a natural 20 (or from `crit_on`), whatever
+/// the total. Checks only compare the total with the target.
+/// ```
+/// use libazdice::systems::{Dnd5e, Dnd5eOutcome, System};
+///
+/// let attack = Dnd5e::attack(5, 15).advantage();
+/// assert_eq!(attack.bag().to_string(), "2d20dl1 + 5");
+///
+/// let table = attack.outcome_table().unwrap();
+/// // A natural 20 on either d20 is a critical hit.
+/// assert!((table[&Dnd5eOutcome::CriticalHit] - 9.75).abs() < 1e-9);
+/// assert!((table[&Dnd5eOutcome::CriticalMiss] - 0.25).abs() < 1e-9);
+///
+/// // Advantage and disadvantage cancel out.
+/// assert_eq!(Dnd5e::check(2, 12).advantage().disadvantage().bag().to_string(), "1d20 + 2");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dnd5e {
+    modifier: i32,
+    target: i32,
+    advantage: bool,
+    disadvantage: bool,
+    crit_on: Option<i64>,
+}
+
+/// The outcome of a `Dnd5e` roll. Checks are only ever a `Failure` or a `Success`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Dnd5eOutcome {
+    /// A natural 1 on an attack.
+    CriticalMiss,
+    /// A total below the target.
+    Failure,
+    /// A total of the target or more.
+    Success,
+    /// A natural 20 (or from `Dnd5e::crit_on`) on an attack.
+    CriticalHit,
+}
+
+impl Dnd5e {
+    /// A check or save with `modifier` against a DC of `dc`.
+    pub fn check(modifier: i32, dc: i32) -> Self {
+        Dnd5e {
+            modifier,
+            target: dc,
+            advantage: false,
+            disadvantage: false,
+            crit_on: None,
+        }
+    }
+
+    /// An attack with `modifier` against an AC of `ac`, hitting critically on a natural 20.
+    pub fn attack(modifier: i32, ac: i32) -> Self {
+        Dnd5e {
+            crit_on: Some(20),
+            ..Dnd5e::check(modifier, ac)
+        }
+    }
+
+    /// Roll with advantage (the higher of two d20s), unless there is also disadvantage.
+    pub fn advantage(mut self) -> Self {
+        self.advantage = true;
+        self
+    }
+
+    /// Roll with disadvantage (the lower of two d20s), unless there is also advantage.
+    pub fn disadvantage(mut self) -> Self {
+        self.disadvantage = true;
+        self
+    }
+
+    /// Hit critically on a natural `natural` or more (eg 19 for an improved critical). This
+    /// makes the roll an attack.
+    pub fn crit_on(mut self, natural: i64) -> Self {
+        self.crit_on = Some(natural.clamp(2, 20));
+        self
+    }
+
+    /// The outcome of rolling `natural` on the d20.
+    fn outcome_of(&self, natural: i64) -> Dnd5eOutcome {
+        match self.crit_on {
+            Some(_) if natural == 1 => Dnd5eOutcome::CriticalMiss,
+            Some(crit) if natural >= crit => Dnd5eOutcome::CriticalHit,
+            _ if natural + i64::from(self.modifier) >= i64::from(self.target) => {
+                Dnd5eOutcome::Success
+            }
+            _ => Dnd5eOutcome::Failure,
+        }
+    }
+}
+
+impl System for Dnd5e {
+    type Outcome = Dnd5eOutcome;
+
+    fn bag(&self) -> DiceBag {
+        let builder = match (self.advantage, self.disadvantage) {
+            (true, false) => DiceBag::builder().dice(2, 20).keep_highest(1),
+            (false, true) => DiceBag::builder().dice(2, 20).keep_lowest(1),
+            _ => DiceBag::builder().dice(1, 20),
+        };
+        build(with_modifier(builder, self.modifier))
+    }
+
+    fn outcome(&self, roll: &RollResults) -> Dnd5eOutcome {
+        self.outcome_of(roll.get_dice_groups()[0].total())
+    }
+
+    fn outcome_table(&self) -> Result<BTreeMap<Dnd5eOutcome, f64>, String> {
+        let modifier = i64::from(self.modifier);
+        table_by_total(&self.bag(), |total| self.outcome_of(total - modifier))
+    }
+}
+
+impl Display for Dnd5eOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dnd5eOutcome::CriticalMiss => write!(f, "critical miss"),
+            Dnd5eOutcome::Failure => write!(f, "failure"),
+            Dnd5eOutcome::Success => write!(f, "success"),
+            Dnd5eOutcome::CriticalHit => write!(f, "critical hit"),
+        }
+    }
+}
+
+/// A Powered by the Apocalypse move: 2d6 plus a stat, a miss on 6 or less, a weak hit on 7 to 9
+/// and a strong hit on 10 or more.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pbta {
+    modifier: i32,
+}
+
+/// The outcome of a `Pbta` move.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PbtaOutcome {
+    /// 6 or less.
+    Miss,
+    /// 7 to 9.
+    WeakHit,
+    /// 10 or more.
+    StrongHit,
+}
+
+impl Pbta {
+    /// A move rolled with `modifier` (eg +1 for a stat of +1).
+    pub fn new(modifier: i32) -> Self {
+        Pbta { modifier }
+    }
+
+    fn outcome_of(total: i64) -> PbtaOutcome {
+        match total {
+            t if t >= 10 => PbtaOutcome::StrongHit,
+            t if t >= 7 => PbtaOutcome::WeakHit,
+            _ => PbtaOutcome::Miss,
+        }
+    }
+}
+
+impl System for Pbta {
+    type Outcome = PbtaOutcome;
+
+    fn bag(&self) -> DiceBag {
+        build(with_modifier(DiceBag::builder().dice(2, 6), self.modifier))
+    }
+
+    fn outcome(&self, roll: &RollResults) -> PbtaOutcome {
+        Pbta::outcome_of(roll.total())
+    }
+
+    fn outcome_table(&self) -> Result<BTreeMap<PbtaOutcome, f64>, String> {
+        table_by_total(&self.bag(), Pbta::outcome_of)
+    }
+}
+
+impl Display for PbtaOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PbtaOutcome::Miss => write!(f, "miss (6-)"),
+            PbtaOutcome::WeakHit => write!(f, "weak hit (7-9)"),
+            PbtaOutcome::StrongHit => write!(f, "strong hit (10+)"),
+        }
+    }
+}
+
+/// A Blades in the Dark action roll: a pool of d6s, of which the highest counts. Two or more 6s
+/// are a critical. A pool of no dice rolls 2d6 and takes the lowest, and cannot be a critical.
+///
+/// The bag rolls every die of the pool (two for an empty pool), as the outcome depends on more
+/// than their total.
+/// ```
+/// use libazdice::systems::{Blades, BladesOutcome, System};
+///
+/// let desperate = Blades::new(0);
+/// assert_eq!(desperate.bag().to_string(), "2d6");
+/// let table = desperate.outcome_table().unwrap();
+/// assert!((table[&BladesOutcome::Success] - 100.0 / 36.0).abs() < 1e-9);
+/// assert!(!table.contains_key(&BladesOutcome::Critical));
+///
+/// let table = Blades::new(2).outcome_table().unwrap();
+/// assert!((table[&BladesOutcome::Critical] - 100.0 / 36.0).abs() < 1e-9);
+/// assert!((table[&BladesOutcome::Failure] - 25.0).abs() < 1e-9);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Blades {
+    dice: u8,
+}
+
+/// The outcome of a `Blades` roll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BladesOutcome {
+    /// The highest die is 1 to 3.
+    Failure,
+    /// The highest die is 4 or 5.
+    Partial,
+    /// The highest die is 6.
+    Success,
+    /// Two or more 6s.
+    Critical,
+}
+
+impl Blades {
+    /// A pool of `dice` d6s.
+    pub fn new(dice: u8) -> Self {
+        Blades { dice }
+    }
+
+    fn outcome_of(top: i64) -> BladesOutcome {
+        match top {
+            6 => BladesOutcome::Success,
+            4 | 5 => BladesOutcome::Partial,
+            _ => BladesOutcome::Failure,
+        }
+    }
+}
+
+impl System for Blades {
+    type Outcome = BladesOutcome;
+
+    fn bag(&self) -> DiceBag {
+        let dice = if self.dice == 0 { 2 } else { self.dice };
+        build(DiceBag::builder().dice(usize::from(dice), 6))
+    }
+
+    fn outcome(&self, roll: &RollResults) -> BladesOutcome {
+        let results = roll.get_dice_groups()[0].results();
+        if self.dice == 0 {
+            return Blades::outcome_of(results.iter().copied().min().unwrap_or(1));
+        }
+        match results.iter().filter(|r| **r == 6).count() {
+            0 | 1 => Blades::outcome_of(results.iter().copied().max().unwrap_or(1)),
+            _ => BladesOutcome::Critical,
+        }
+    }
+
+    fn outcome_table(&self) -> Result<BTreeMap<BladesOutcome, f64>, String> {
+        // The chance that the die which counts is `k` or less.
+        let n = i32::from(self.dice);
+        let at_most = |k: i64| match self.dice {
+            0 => 1.0 - ((6 - k) as f64 / 6.0).powi(2),
+            _ => (k as f64 / 6.0).powi(n),
+        };
+        let mut table = BTreeMap::new();
+        for k in 1..=6 {
+            let p = 100.0 * (at_most(k) - at_most(k - 1));
+            *table.entry(Blades::outcome_of(k)).or_insert(0.0) += p;
+        }
+        if n >= 2 {
+            // Less the chance of exactly one 6.
+            let one_six = f64::from(n) / 6.0 * (5.0_f64 / 6.0).powi(n - 1);
+            let critical = 100.0 * (1.0 - (5.0_f64 / 6.0).powi(n) - one_six);
+            table.insert(BladesOutcome::Critical, critical);
+            *table.entry(BladesOutcome::Success).or_insert(0.0) -= critical;
+        }
+        Ok(table)
+    }
+}
+
+impl Display for BladesOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BladesOutcome::Failure => write!(f, "failure (1-3)"),
+            BladesOutcome::Partial => write!(f, "partial success (4-5)"),
+            BladesOutcome::Success => write!(f, "full success (6)"),
+            BladesOutcome::Critical => write!(f, "critical (6, 6)"),
+        }
+    }
+}
+
+/// A Fate roll: four Fate dice (each -1, 0 or +1, rolled here as "d3 - 2") plus a skill, against
+/// an opposition. Beating it by 3 or more shifts succeeds with style. The adjectives of the Fate
+/// ladder are given by `Fate::ladder`.
+/// ```
+/// use libazdice::systems::{Fate, FateOutcome, System};
+///
+/// let roll = Fate::new(3).against(2);
+/// assert_eq!(roll.bag().to_string(), "4d3 - 8 + 3");
+/// assert_eq!(roll.bag().range(), -1..=7);
+/// assert_eq!(Fate::ladder(3), "Good");
+///
+/// let table = roll.outcome_table().unwrap();
+/// // A tie needs a roll of -1 on the dice: 16 of the 81 ways they can fall.
+/// assert!((table[&FateOutcome::Tie] - 1600.0 / 81.0).abs() < 1e-9);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fate {
+    skill: i32,
+    opposition: i32,
+}
+
+/// The outcome of a `Fate` roll, by its shifts (the total less the opposition).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FateOutcome {
+    /// Fewer than 0 shifts.
+    Fail,
+    /// 0 shifts.
+    Tie,
+    /// 1 or 2 shifts.
+    Success,
+    /// 3 or more shifts.
+    SuccessWithStyle,
+}
+
+impl Fate {
+    /// A roll with a skill of `skill`, against a Mediocre (0) opposition.
+    pub fn new(skill: i32) -> Self {
+        Fate {
+            skill,
+            opposition: 0,
+        }
+    }
+
+    /// Roll against `opposition` instead.
+    pub fn against(mut self, opposition: i32) -> Self {
+        self.opposition = opposition;
+        self
+    }
+
+    /// The adjective of the Fate ladder for `value`, from Terrible (-2 or less) to Legendary
+    /// (+8 or more).
+    pub fn ladder(value: i64) -> &'static str {
+        match value {
+            v if v >= 8 => "Legendary",
+            7 => "Epic",
+            6 => "Fantastic",
+            5 => "Superb",
+            4 => "Great",
+            3 => "Good",
+            2 => "Fair",
+            1 => "Average",
+            0 => "Mediocre",
+            -1 => "Poor",
+            _ => "Terrible",
+        }
+    }
+
+    fn outcome_of(&self, total: i64) -> FateOutcome {
+        match total - i64::from(self.opposition) {
+            s if s >= 3 => FateOutcome::SuccessWithStyle,
+            s if s > 0 => FateOutcome::Success,
+            0 => FateOutcome::Tie,
+            _ => FateOutcome::Fail,
+        }
+    }
+}
+
+impl System for Fate {
+    type Outcome = FateOutcome;
+
+    fn bag(&self) -> DiceBag {
+        let dice = DiceBag::builder().dice(4, 3).minus(8);
+        build(with_modifier(dice, self.skill))
+    }
+
+    fn outcome(&self, roll: &RollResults) -> FateOutcome {
+        self.outcome_of(roll.total())
+    }
+
+    fn outcome_table(&self) -> Result<BTreeMap<FateOutcome, f64>, String> {
+        table_by_total(&self.bag(), |total| self.outcome_of(total))
+    }
+}
+
+impl Display for FateOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FateOutcome::Fail => write!(f, "fail"),
+            FateOutcome::Tie => write!(f, "tie"),
+            FateOutcome::Success => write!(f, "success"),
+            FateOutcome::SuccessWithStyle => write!(f, "success with style"),
+        }
+    }
+}
+
+/// A Savage Worlds trait roll: the trait die and a d6 wild die, both exploding ("acing"), of
+/// which the higher counts, plus a modifier, against a target number (4 unless set). Every 4 over
+/// the target is a raise. A Wild Card who rolls a 1 on both dice fails critically. An Extra rolls
+/// only the trait die.
+/// ```
+/// use libazdice::systems::{SavageOutcome, SavageWorlds, System};
+///
+/// let fighting = SavageWorlds::trait_roll(8, 1);
+/// assert_eq!(fighting.bag().to_string(), "max(1d8!, 1d6!) + 1");
+/// assert_eq!(SavageWorlds::trait_roll(8, -2).extra().bag().to_string(), "1d8! - 2");
+///
+/// assert_eq!(SavageWorlds::raises(11, 4), Some(1));
+/// assert_eq!(SavageWorlds::raises(3, 4), None);
+///
+/// let table = fighting.outcome_table().unwrap();
+/// assert!((table[&SavageOutcome::CriticalFailure] - 100.0 / 48.0).abs() < 1e-9);
+/// // Only 1s and 2s on both dice fail, but 1s on both are a critical failure.
+/// assert!((table[&SavageOutcome::Failure] - 300.0 / 48.0).abs() < 1e-9);
+/// assert_eq!(SavageOutcome::Success(2).to_string(), "success with 2 raises");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SavageWorlds {
+    die: i64,
+    modifier: i32,
+    target: i32,
+    wild: bool,
+}
+
+/// The outcome of a `SavageWorlds` roll.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SavageOutcome {
+    /// A 1 on both the trait die and the wild die.
+    CriticalFailure,
+    /// A total below the target.
+    Failure,
+    /// A total of the target or more, with the number of raises.
+    Success(i64),
+}
+
+impl SavageWorlds {
+    /// A Wild Card's roll of a trait with a die of `die` sides (eg 8 for a d8) and `modifier`.
+    /// Dice with fewer than 2 sides are rolled as d2s, as they could not explode.
+    pub fn trait_roll(die: i64, modifier: i32) -> Self {
+        SavageWorlds {
+            die: die.clamp(2, Limits::default().max_sides),
+            modifier,
+            target: 4,
+            wild: true,
+        }
+    }
+
+    /// Roll as an Extra, without the wild die.
+    pub fn extra(mut self) -> Self {
+        self.wild = false;
+        self
+    }
+
+    /// Roll against `target` rather than 4 (eg a Parry).
+    pub fn target(mut self, target: i32) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// The number of raises of `total` against `target` (one for each full 4 over it), or
+    /// `None` if the total misses the target.
+    pub fn raises(total: i64, target: i64) -> Option<i64> {
+        if total >= target {
+            Some((total - target) / 4)
+        } else {
+            None
+        }
+    }
+
+    fn outcome_of(&self, total: i64) -> SavageOutcome {
+        if self.wild && total - i64::from(self.modifier) == 1 {
+            return SavageOutcome::CriticalFailure;
+        }
+        match SavageWorlds::raises(total, i64::from(self.target)) {
+            Some(raises) => SavageOutcome::Success(raises),
+            None => SavageOutcome::Failure,
+        }
+    }
+}
+
+impl System for SavageWorlds {
+    type Outcome = SavageOutcome;
+
+    fn bag(&self) -> DiceBag {
+        let trait_die = build(DiceBag::builder().dice(1, self.die).explode());
+        let mut groups: Vec<DiceGroup> = if self.wild {
+            let wild_die = build(DiceBag::builder().dice(1, 6).explode());
+            vec![Dice::max_of(vec![trait_die, wild_die]).into()]
+        } else {
+            trait_die.groups().to_vec()
+        };
+        if self.modifier > 0 {
+            groups.push(Bonus::plus(self.modifier.unsigned_abs()).into());
+        } else if self.modifier < 0 {
+            groups.push(Bonus::minus(self.modifier.unsigned_abs()).into());
+        }
+        DiceBag::try_from_dice(groups).expect("The dice of a system are always valid.")
+    }
+
+    fn outcome(&self, roll: &RollResults) -> SavageOutcome {
+        self.outcome_of(roll.total())
+    }
+
+    fn outcome_table(&self) -> Result<BTreeMap<SavageOutcome, f64>, String> {
+        table_by_total(&self.bag(), |total| self.outcome_of(total))
+    }
+}
+
+impl Display for SavageOutcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SavageOutcome::CriticalFailure => write!(f, "critical failure"),
+            SavageOutcome::Failure => write!(f, "failure"),
+            SavageOutcome::Success(0) => write!(f, "success"),
+            SavageOutcome::Success(1) => write!(f, "success with a raise"),
+            SavageOutcome::Success(n) => write!(f, "success with {} raises", n),
+        }
+    }
+}
@@ -0,0 +1,546 @@
+//! This module contains random tables, such as for loot or encounters: a `Table` rolls its
+//! `DiceBag` and gives the entry whose range holds the total. The text of an entry may hold dice
+//! in braces, such as "{2d6} gold pieces", which are rolled and replaced by their total, and the
+//! names of other tables in square brackets, such as "a [gem]", which are rolled in turn.
+//!
+//! Tables are kept together in `Tables`, which can be read from text such as the below.
+//! ```
+//! use libazdice::distribution::seeded_rng;
+//! use libazdice::tables::Tables;
+//!
+//! let tables: Tables = "
+//!     table loot: 1d100
+//!     1-40: {2d6} gold pieces
+//!     41-60: a [gem]
+//!     61-100: {1d4} potions and a [gem]
+//!
+//!     table gem: 2d4
+//!     2-5: garnet
+//!     6-8: ruby
+//! "
+//! .parse()
+//! .unwrap();
+//!
+//! let roll = tables.roll_with_rng("loot", &mut seeded_rng(50)).unwrap();
+//! assert_eq!(roll.rolls()[0].0, "loot");
+//!
+//! // A garnet is 10 of the 16 ways 2d4 can fall, and a ruby the other 6.
+//! let chances = tables.probabilities("loot").unwrap();
+//! assert!((chances["a ruby"] - 20.0 * 6.0 / 16.0).abs() < 1e-9);
+//! assert!((chances["{1d4} potions and a garnet"] - 40.0 * 10.0 / 16.0).abs() < 1e-9);
+//! assert!((chances.values().sum::<f64>() - 100.0).abs() < 1e-9);
+//! ```
+use super::distribution::*;
+use super::exact::Budget;
+use super::parse::parse;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// How many tables deep a roll may go by default, counting the first table.
+pub const DEFAULT_MAX_DEPTH: usize = 10;
+
+/// One entry of a `Table`: the totals which give it and its text, with the dice and tables in
+/// the text read once, when the entry is made.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    low: i64,
+    high: i64,
+    text: String,
+    pieces: Vec<Piece>,
+}
+
+impl Entry {
+    /// The lowest total which gives the entry.
+    pub fn low(&self) -> i64 {
+        self.low
+    }
+
+    /// The highest total which gives the entry.
+    pub fn high(&self) -> i64 {
+        self.high
+    }
+
+    /// The text of the entry, before its dice and tables are rolled.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// A random table: a name, the dice to roll on it, and its entries. The entries must run, with
+/// no gaps or overlaps, from lowest to highest. They must cover every total the dice can roll,
+/// unless the dice explode, in which case totals past either end give the first or last entry.
+/// ```
+/// use libazdice::tables::{Table, Tables};
+///
+/// let weather = Table::new("weather", "1d6".parse().unwrap())
+///     .entry(1, 4, "clear")
+///     .and_then(|t| t.entry(5, 6, "rain for {1d4} hours"))
+///     .unwrap();
+/// let tables = Tables::new().table(weather).unwrap();
+/// assert!(tables.validate().is_ok());
+/// assert_eq!(
+///     tables.to_string(),
+///     "table weather: 1d6\n1-4: clear\n5-6: rain for {1d4} hours\n"
+/// );
+///
+/// // Entries must read, and tables must have their entries in order, as they are made.
+/// let table = Table::new("weather", "1d6".parse().unwrap());
+/// assert!(table.clone().entry(1, 6, "{1d} showers").is_err());
+/// let gap = table.entry(1, 3, "clear").and_then(|t| t.entry(5, 6, "rain")).unwrap();
+/// assert!(Tables::new().table(gap).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    name: String,
+    bag: DiceBag,
+    entries: Vec<Entry>,
+}
+
+impl Table {
+    /// A table called `name` with no entries, rolled with `bag`.
+    pub fn new(name: &str, bag: DiceBag) -> Self {
+        Table {
+            name: normal(name),
+            bag,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Add an entry for the totals from `low` to `high`. Fails if the dice in braces in `text`
+    /// cannot be parsed, or a brace or square bracket is not closed.
+    pub fn entry(mut self, low: i64, high: i64, text: &str) -> Result<Self, String> {
+        let text = text.trim().to_owned();
+        let pieces = pieces(&text)?;
+        self.entries.push(Entry {
+            low,
+            high,
+            text,
+            pieces,
+        });
+        Ok(self)
+    }
+
+    /// The name of the table.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The dice rolled on the table.
+    pub fn bag(&self) -> &DiceBag {
+        &self.bag
+    }
+
+    /// The entries of the table, from lowest to highest.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The entry given by `total`. Totals past either end give the first or last entry.
+    fn entry_for(&self, total: i64) -> Option<(usize, &Entry)> {
+        let last = self.entries.len().checked_sub(1)?;
+        let index = self
+            .entries
+            .iter()
+            .position(|e| total <= e.high)
+            .unwrap_or(last);
+        Some((index, &self.entries[index]))
+    }
+
+    /// Check the entries run without gaps or overlaps over the totals of the dice.
+    fn validate(&self) -> Result<(), String> {
+        let (first, last) = match (self.entries.first(), self.entries.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Err(format!("The table \"{}\" has no entries.", self.name)),
+        };
+        for pair in self.entries.windows(2) {
+            if pair[0].high.checked_add(1) != Some(pair[1].low) {
+                return Err(format!(
+                    "The entries of the table \"{}\" must run on from {} without gaps or \
+                     overlaps.",
+                    self.name, pair[0].high
+                ));
+            }
+        }
+        if let Some(entry) = self.entries.iter().find(|e| e.low > e.high) {
+            return Err(format!(
+                "The entry {}-{} of the table \"{}\" runs backwards.",
+                entry.low, entry.high, self.name
+            ));
+        }
+        let range = self.bag.range();
+        if self.bag.is_bounded() && (*range.start() < first.low || *range.end() > last.high) {
+            return Err(format!(
+                "The entries of the table \"{}\" must cover {} to {}.",
+                self.name,
+                range.start(),
+                range.end()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A part of the text of an entry.
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(String),
+    /// Dice in braces, with the text between them.
+    Dice(String, DiceBag),
+    /// The name of a table in square brackets.
+    Table(String),
+}
+
+fn normal(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Split the text of an entry into plain text, dice and tables.
+fn pieces(text: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(['{', '[']) {
+        if start > 0 {
+            pieces.push(Piece::Text(rest[..start].to_owned()));
+        }
+        let close = if rest[start..].starts_with('{') {
+            '}'
+        } else {
+            ']'
+        };
+        let end = rest[start..]
+            .find(close)
+            .map(|end| start + end)
+            .ok_or_else(|| format!("\"{}\" is missing a '{}'.", text, close))?;
+        let inner = &rest[start + 1..end];
+        if close == '}' {
+            let bag = parse(inner.to_owned())?;
+            pieces.push(Piece::Dice(inner.trim().to_owned(), bag));
+        } else {
+            pieces.push(Piece::Table(normal(inner)));
+        }
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest.to_owned()));
+    }
+    Ok(pieces)
+}
+
+/// A set of tables which may refer to each other.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tables {
+    tables: Vec<Table>,
+    max_depth: usize,
+}
+
+impl Default for Tables {
+    fn default() -> Self {
+        Tables {
+            tables: Vec::new(),
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+impl Tables {
+    /// No tables, with the default `max_depth`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `table`, replacing any table of the same name. Fails if its entries do not run
+    /// without gaps or overlaps over the totals of its dice (see `Table`).
+    pub fn table(mut self, table: Table) -> Result<Self, String> {
+        table.validate()?;
+        match self.tables.iter_mut().find(|t| t.name == table.name) {
+            Some(old) => *old = table,
+            None => self.tables.push(table),
+        }
+        Ok(self)
+    }
+
+    /// Set how many tables deep a roll may go, counting the first table. A roll which would go
+    /// deeper (such as one on a table which keeps referring to itself) fails.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// The table called `name`, if there is one.
+    pub fn get(&self, name: &str) -> Option<&Table> {
+        let name = normal(name);
+        self.tables.iter().find(|t| t.name == name)
+    }
+
+    /// The tables, in the order they were added.
+    pub fn tables(&self) -> &[Table] {
+        &self.tables
+    }
+
+    fn find(&self, name: &str) -> Result<&Table, String> {
+        self.get(name)
+            .ok_or_else(|| format!("There is no table called \"{}\".", name.trim()))
+    }
+
+    /// Check that the tables named by the entries exist. The rest of each table is checked as
+    /// it is made, and a roll which reaches a missing table fails then, so this is only needed
+    /// to find them up front. Parsing tables checks this.
+    pub fn validate(&self) -> Result<(), String> {
+        for table in self.tables.iter() {
+            for entry in table.entries.iter() {
+                for piece in entry.pieces.iter() {
+                    if let Piece::Table(name) = piece {
+                        self.find(name)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Roll on the table called `name`, and on any tables its entry names.
+    pub fn roll(&self, name: &str) -> Result<TableRoll, String> {
+        self.roll_with_rng(name, &mut rand::thread_rng())
+    }
+
+    /// As `Tables::roll`, but with the given random number generator.
+    pub fn roll_with_rng<R: Rng + ?Sized>(
+        &self,
+        name: &str,
+        rng: &mut R,
+    ) -> Result<TableRoll, String> {
+        let mut roll = TableRoll {
+            text: String::new(),
+            rolls: Vec::new(),
+        };
+        self.roll_into(name, rng, 1, &mut roll)?;
+        Ok(roll)
+    }
+
+    fn roll_into<R: Rng + ?Sized>(
+        &self,
+        name: &str,
+        rng: &mut R,
+        depth: usize,
+        roll: &mut TableRoll,
+    ) -> Result<(), String> {
+        if depth > self.max_depth {
+            return Err(self.too_deep(name));
+        }
+        let table = self.find(name)?;
+        let total = table.bag.roll_with_rng(rng).total();
+        roll.rolls.push((table.name.clone(), total));
+        let (_, entry) = table
+            .entry_for(total)
+            .ok_or_else(|| format!("The table \"{}\" has no entries.", table.name))?;
+        for piece in entry.pieces.iter() {
+            match piece {
+                Piece::Text(text) => roll.text.push_str(text),
+                Piece::Dice(_, bag) => roll
+                    .text
+                    .push_str(&bag.roll_with_rng(rng).total().to_string()),
+                Piece::Table(name) => self.roll_into(name, rng, depth + 1, roll)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn too_deep(&self, name: &str) -> String {
+        format!(
+            "Rolling on \"{}\" would go more than {} tables deep.",
+            name.trim(),
+            self.max_depth
+        )
+    }
+
+    /// The exact chance, as a percentage, of each final entry of the table called `name`: the
+    /// text of the entry with the tables it names replaced by their own final entries. Dice in
+    /// braces are left as they are. Fails if a table could go deeper than `max_depth`, or its
+    /// distribution cannot be worked out exactly (see `DiceBag::make_exact_distribution`).
+    pub fn probabilities(&self, name: &str) -> Result<BTreeMap<String, f64>, String> {
+        let mut budget = Budget::new();
+        let outcomes = self.outcomes(name, 1, &mut budget)?;
+        Ok(outcomes
+            .into_iter()
+            .map(|(text, p)| (text, p * 100.0))
+            .collect())
+    }
+
+    /// The chance of each final entry of a table, as a fraction.
+    fn outcomes(
+        &self,
+        name: &str,
+        depth: usize,
+        budget: &mut Budget,
+    ) -> Result<BTreeMap<String, f64>, String> {
+        if depth > self.max_depth {
+            return Err(self.too_deep(name));
+        }
+        let table = self.find(name)?;
+        let mut chances = vec![0.0; table.entries.len()];
+        for (total, p) in table.bag.make_exact_distribution()? {
+            if let Some((index, _)) = table.entry_for(total) {
+                chances[index] += p / 100.0;
+            }
+        }
+        let mut outcomes = BTreeMap::new();
+        for (entry, chance) in table.entries.iter().zip(chances) {
+            if chance == 0.0 {
+                continue;
+            }
+            for (text, p) in self.expand(entry, depth, budget)? {
+                *outcomes.entry(text).or_insert(0.0) += chance * p;
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// The chance of each final text of an entry, as a fraction.
+    fn expand(
+        &self,
+        entry: &Entry,
+        depth: usize,
+        budget: &mut Budget,
+    ) -> Result<BTreeMap<String, f64>, String> {
+        let mut texts = BTreeMap::new();
+        texts.insert(String::new(), 1.0);
+        for piece in entry.pieces.iter() {
+            let next = match piece {
+                Piece::Text(text) => vec![(text.clone(), 1.0)],
+                Piece::Dice(dice, _) => vec![(format!("{{{}}}", dice), 1.0)],
+                Piece::Table(name) => self
+                    .outcomes(name, depth + 1, budget)?
+                    .into_iter()
+                    .collect(),
+            };
+            budget.spend((texts.len() * next.len()) as u64)?;
+            let mut joined = BTreeMap::new();
+            for (text, p) in texts.iter() {
+                for (more, q) in next.iter() {
+                    *joined.entry(format!("{}{}", text, more)).or_insert(0.0) += p * q;
+                }
+            }
+            texts = joined;
+        }
+        Ok(texts)
+    }
+}
+
+impl Display for Tables {
+    /// The tables as text, which parses back into the same tables (with the default
+    /// `max_depth`).
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, table) in self.tables.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "table {}: {}", table.name, table.bag)?;
+            for entry in table.entries.iter() {
+                if entry.low == entry.high {
+                    writeln!(f, "{}: {}", entry.low, entry.text)?;
+                } else {
+                    writeln!(f, "{}-{}: {}", entry.low, entry.high, entry.text)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Tables {
+    type Err = String;
+
+    /// Parse tables from text. Each table starts with a line "table", its name, a colon and its
+    /// dice, and is followed by its entries, one to a line: a total or a range of totals (such
+    /// as "41-60"), a colon, and the text. Blank lines and lines starting with '#' are ignored.
+    fn from_str(s: &str) -> Result<Tables, String> {
+        let mut tables = Tables::new();
+        let mut current: Option<Table> = None;
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (head, text) = line
+                .split_once(':')
+                .ok_or_else(|| format!("\"{}\" is missing a ':'.", line))?;
+            let head = head.trim();
+            let name = head
+                .get(..6)
+                .filter(|word| word.eq_ignore_ascii_case("table "))
+                .map(|_| &head[6..]);
+            if let Some(name) = name {
+                if let Some(table) = current.take() {
+                    tables = tables.table(table)?;
+                }
+                let bag = parse(text.trim().to_owned())?;
+                current = Some(Table::new(name, bag));
+                continue;
+            }
+            let table = current
+                .take()
+                .ok_or_else(|| format!("\"{}\" comes before any table.", line))?;
+            let (low, high) = parse_range(head)?;
+            current = Some(table.entry(low, high, text)?);
+        }
+        if let Some(table) = current {
+            tables = tables.table(table)?;
+        }
+        tables.validate()?;
+        Ok(tables)
+    }
+}
+
+/// Read a total or a range of totals, such as "7", "41-60", "41–60" or "-3--1".
+fn parse_range(text: &str) -> Result<(i64, i64), String> {
+    let read = |n: &str| {
+        n.trim()
+            .parse::<i64>()
+            .map_err(|_| format!("\"{}\" is not a total or a range of totals.", text))
+    };
+    match text
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| *c == '-' || *c == '–')
+    {
+        Some((i, c)) => Ok((read(&text[..i])?, read(&text[i + c.len_utf8()..])?)),
+        None => read(text).map(|n| (n, n)),
+    }
+}
+
+/// The result of rolling on a table: the final text, and the total rolled on each table in
+/// turn.
+/// ```
+/// use libazdice::tables::Tables;
+///
+/// let tables: Tables = "table a: 1d1\n1: found [b]\n\ntable b: 1d1 + 2\n3: {2d1} coins"
+///     .parse()
+///     .unwrap();
+/// let roll = tables.roll("a").unwrap();
+/// assert_eq!(roll.text(), "found 2 coins");
+/// assert_eq!(roll.rolls(), [("a".to_owned(), 1), ("b".to_owned(), 3)]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TableRoll {
+    text: String,
+    rolls: Vec<(String, i64)>,
+}
+
+impl TableRoll {
+    /// The final text, with the dice and tables rolled.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The name of each table rolled on, with the total rolled, in the order they were rolled.
+    pub fn rolls(&self) -> &[(String, i64)] {
+        &self.rolls
+    }
+}
+
+impl Display for TableRoll {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
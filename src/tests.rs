@@ -3,6 +3,7 @@ use super::distribution::*;
 use super::parse;
 
 #[test]
+#[allow(clippy::assertions_on_constants)]
 fn zero_test() {
     assert!(true)
 }
@@ -17,13 +18,16 @@ fn single_parse_inner(size: i64) {
     assert_eq!(
         dice_bag.dice,
         vec![DiceGroup::Dice(Dice {
-            size: size,
+            size,
             count: 1,
             drop: Drop::Non,
             cutoff: CutOff::Non,
             reroll: ReRoll::Never,
             op: DiceOp::Add,
             explosive: false,
+            nested: None,
+            pick: None,
+            label: None,
         })]
     );
 }
@@ -34,20 +38,20 @@ fn simple_parse_inner(count: usize, size: i64) {
 
     let dice_bag = parse::parse(input).expect("should parse");
 
-    assert_eq!(
-        dice_bag.range,
-        MinMax([1 * count as i64, count as i64 * size])
-    );
+    assert_eq!(dice_bag.range, MinMax([count as i64, count as i64 * size]));
     assert_eq!(
         dice_bag.dice,
         vec![DiceGroup::Dice(Dice {
-            size: size,
-            count: count,
+            size,
+            count,
             drop: Drop::Non,
             cutoff: CutOff::Non,
             reroll: ReRoll::Never,
             op: DiceOp::Add,
             explosive: false,
+            nested: None,
+            pick: None,
+            label: None,
         })]
     );
 }
@@ -72,6 +76,9 @@ fn parse_3d20dl1dh1() {
             reroll: ReRoll::Never,
             op: DiceOp::Add,
             explosive: false,
+            nested: None,
+            pick: None,
+            label: None,
         })]
     );
 }
@@ -96,6 +103,9 @@ fn parse_5d20dl3() {
             reroll: ReRoll::Never,
             op: DiceOp::Add,
             explosive: false,
+            nested: None,
+            pick: None,
+            label: None,
         })]
     );
 }
@@ -120,6 +130,9 @@ fn parse_5d20dh3() {
             reroll: ReRoll::Never,
             op: DiceOp::Add,
             explosive: false,
+            nested: None,
+            pick: None,
+            label: None,
         })]
     );
 }
@@ -144,6 +157,9 @@ fn parse_12d20dl4dh3() {
             reroll: ReRoll::Never,
             op: DiceOp::Add,
             explosive: false,
+            nested: None,
+            pick: None,
+            label: None,
         })]
     );
 }
@@ -171,6 +187,9 @@ fn parse_15d20dl4dh3rr3be4() {
             }),
             op: DiceOp::Add,
             explosive: false,
+            nested: None,
+            pick: None,
+            label: None,
         })]
     );
 }
@@ -198,6 +217,9 @@ fn parse_15d20dl4dh3rr3ab4mn2() {
             }),
             op: DiceOp::Add,
             explosive: false,
+            nested: None,
+            pick: None,
+            label: None,
         })]
     );
 }
@@ -225,6 +247,9 @@ fn parse_15d20dl4dh3rr3ab4mn2mx18() {
             }),
             op: DiceOp::Add,
             explosive: false,
+            nested: None,
+            pick: None,
+            label: None,
         })]
     );
 }
@@ -264,6 +289,9 @@ fn parse_15d20dl4dh3rr3ab4mn2_explosive() {
             }),
             op: DiceOp::Add,
             explosive: true,
+            nested: None,
+            pick: None,
+            label: None,
         })]
     );
 }
@@ -298,10 +326,14 @@ fn parse_7d23_plus_11() {
                 reroll: ReRoll::Never,
                 op: DiceOp::Add,
                 explosive: false,
+                nested: None,
+                pick: None,
+                label: None,
             }),
             DiceGroup::Bonus(Bonus {
                 bonus: 11,
                 op: DiceOp::Add,
+                label: None,
             }),
         ]
     );
@@ -313,7 +345,7 @@ fn parse_7d23_explosive_plus_11() {
 
     let dice_bag = parse::parse(input).expect("should parse");
 
-    assert_eq!(dice_bag.range, MinMax([18, 172]));
+    assert_eq!(dice_bag.range, MinMax([18, i64::MAX]));
     assert_eq!(
         dice_bag.dice,
         vec![
@@ -325,10 +357,14 @@ fn parse_7d23_explosive_plus_11() {
                 reroll: ReRoll::Never,
                 op: DiceOp::Add,
                 explosive: true,
+                nested: None,
+                pick: None,
+                label: None,
             }),
             DiceGroup::Bonus(Bonus {
                 bonus: 11,
                 op: DiceOp::Add,
+                label: None,
             }),
         ]
     );
@@ -361,10 +397,14 @@ fn parse_7d23_minus_11() {
                 reroll: ReRoll::Never,
                 op: DiceOp::Add,
                 explosive: false,
+                nested: None,
+                pick: None,
+                label: None,
             }),
             DiceGroup::Bonus(Bonus {
                 bonus: 11,
                 op: DiceOp::Sub,
+                label: None,
             }),
         ]
     );
@@ -394,7 +434,7 @@ fn parse_5d6_minus_10d10() {
 
     let dice_bag = parse::parse(input).expect("should parse");
 
-    assert_eq!(dice_bag.range, MinMax([-70, -5]));
+    assert_eq!(dice_bag.range, MinMax([-95, 20]));
     assert_eq!(
         dice_bag.dice,
         vec![
@@ -406,6 +446,9 @@ fn parse_5d6_minus_10d10() {
                 reroll: ReRoll::Never,
                 op: DiceOp::Add,
                 explosive: false,
+                nested: None,
+                pick: None,
+                label: None,
             }),
             DiceGroup::Dice(Dice {
                 size: 10,
@@ -415,6 +458,9 @@ fn parse_5d6_minus_10d10() {
                 reroll: ReRoll::Never,
                 op: DiceOp::Sub,
                 explosive: false,
+                nested: None,
+                pick: None,
+                label: None,
             }),
         ]
     );
@@ -426,7 +472,7 @@ fn parse_5d6_minus_10d10_explosive() {
 
     let dice_bag = parse::parse(input).expect("should parse");
 
-    assert_eq!(dice_bag.range, MinMax([-70, -5]));
+    assert_eq!(dice_bag.range, MinMax([i64::MIN, 20]));
     assert_eq!(
         dice_bag.dice,
         vec![
@@ -438,6 +484,9 @@ fn parse_5d6_minus_10d10_explosive() {
                 reroll: ReRoll::Never,
                 op: DiceOp::Add,
                 explosive: false,
+                nested: None,
+                pick: None,
+                label: None,
             }),
             DiceGroup::Dice(Dice {
                 size: 10,
@@ -447,6 +496,9 @@ fn parse_5d6_minus_10d10_explosive() {
                 reroll: ReRoll::Never,
                 op: DiceOp::Sub,
                 explosive: true,
+                nested: None,
+                pick: None,
+                label: None,
             }),
         ]
     );
@@ -601,3 +653,2337 @@ fn test_random_clustering_d_ten() {
     let three = (three > 0.096) && (three < 0.104);
     assert!(zero && one && two && three);
 }
+
+#[test]
+fn test_seeded_rolls_are_reproducible() {
+    let bag = super::parse("10d6dl2rr2be3+4d8!-3".to_owned()).unwrap();
+
+    let mut rng_1 = seeded_rng(0xD1CE);
+    let mut rng_2 = seeded_rng(0xD1CE);
+    let mut rng_3 = seeded_rng(0xD1CF);
+
+    let rolls_1 = (0..1_000)
+        .map(|_| bag.roll_with_rng(&mut rng_1))
+        .collect::<Vec<_>>();
+    let rolls_2 = (0..1_000)
+        .map(|_| bag.roll_with_rng(&mut rng_2))
+        .collect::<Vec<_>>();
+    let rolls_3 = (0..1_000)
+        .map(|_| bag.roll_with_rng(&mut rng_3))
+        .collect::<Vec<_>>();
+
+    assert_eq!(rolls_1, rolls_2);
+    assert_ne!(rolls_1, rolls_3);
+}
+
+#[cfg(test)]
+/// A xorshift generator for testing C-style callbacks. `state` points to a `u64`.
+extern "C" fn xorshift_callback(state: *mut std::ffi::c_void) -> u64 {
+    let state = unsafe { &mut *(state as *mut u64) };
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+#[test]
+fn test_ffi_rng_reproducible() {
+    use super::externalise::*;
+    use std::ffi::CString;
+
+    let input = CString::new("3d20+5").unwrap();
+    let input = input.as_ptr();
+
+    let seeded_1 = rng_from_seed(99);
+    let seeded_2 = rng_from_seed(99);
+    let mut state_1 = 12345_u64;
+    let mut state_2 = 12345_u64;
+    let callback_1 = rng_from_callback(Some(xorshift_callback), &mut state_1 as *mut u64 as *mut _);
+    let callback_2 = rng_from_callback(Some(xorshift_callback), &mut state_2 as *mut u64 as *mut _);
+
+    for _ in 0..1_000 {
+        unsafe {
+            let r1 = parse_and_roll_with_rng(&input, seeded_1);
+            let r2 = parse_and_roll_with_rng(&input, seeded_2);
+            assert!(r1.err.is_null() && r2.err.is_null());
+            assert_eq!(r1.roll, r2.roll);
+            assert!((r1.roll >= 8) && (r1.roll <= 65));
+
+            let r1 = parse_and_roll_with_rng(&input, callback_1);
+            let r2 = parse_and_roll_with_rng(&input, callback_2);
+            assert_eq!(r1.roll, r2.roll);
+            assert!((r1.roll >= 8) && (r1.roll <= 65));
+        }
+    }
+    assert_eq!(state_1, state_2);
+    assert_ne!(state_1, 12345);
+
+    unsafe {
+        let r1 = parse_and_roll_seeded(&input, 7);
+        let r2 = parse_and_roll_seeded(&input, 7);
+        assert_eq!(r1.roll, r2.roll);
+
+        let null_rng = parse_and_roll_with_rng(&input, std::ptr::null_mut());
+        assert!(!null_rng.err.is_null());
+        assert!(rng_from_callback(None, std::ptr::null_mut()).is_null());
+
+        free_rng(seeded_1);
+        free_rng(seeded_2);
+        free_rng(callback_1);
+        free_rng(callback_2);
+    }
+}
+
+#[test]
+fn test_parallel_distribution_deterministic() {
+    let bag = super::parse("3d6!+1d4".to_owned()).unwrap();
+
+    for threads in 1..6 {
+        let dist_1 = bag.make_count_distribution_parallel(100_003, threads, Some(11));
+        let dist_2 = bag.make_count_distribution_parallel(100_003, threads, Some(11));
+        assert_eq!(dist_1, dist_2);
+        assert_eq!(dist_1.values().sum::<usize>(), 100_003);
+    }
+
+    // A single seeded thread is the same as a serial seeded run on stream zero.
+    let serial = bag.make_count_distribution_with_rng(50_000, &mut seeded_rng(5));
+    assert_eq!(
+        serial,
+        bag.make_count_distribution_parallel(50_000, 1, Some(5))
+    );
+
+    // Zero threads still rolls.
+    let dist = bag.make_count_distribution_parallel(1_000, 0, None);
+    assert_eq!(dist.values().sum::<usize>(), 1_000);
+}
+
+#[test]
+fn test_multiple_boni_total() {
+    // A d1 always rolls 1, so the total is fixed.
+    let bag = super::parse("1d1+1+2-4+10".to_owned()).unwrap();
+    let roll = bag.roll();
+    assert_eq!(roll.get_bonus().total(), 9);
+    assert_eq!(roll.total(), 10);
+    assert_eq!(bag.roll_total(), 10);
+}
+
+#[test]
+fn test_roll_total_matches_roll() {
+    let inputs = [
+        "1d20",
+        "4d6dl1",
+        "5d20dh3",
+        "12d20dl4dh3",
+        "15d20dl4dh3rr3be4",
+        "15d20dl4dh3rr3ab4mn2mx18!",
+        "5d6!-10d10+7",
+        "3-1d4-1d4",
+    ];
+    let mut buffer = RollBuffer::new();
+    for input in inputs.iter() {
+        let bag = super::parse(input.to_string()).unwrap();
+        let mut rng_1 = seeded_rng(17);
+        let mut rng_2 = seeded_rng(17);
+        for _ in 0..10_000 {
+            assert_eq!(
+                bag.roll_with_rng(&mut rng_1).total(),
+                bag.roll_total_with_rng(&mut rng_2, &mut buffer)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_histogram_matches_count_distribution() {
+    let bag = super::parse("4d6dl1-1d4".to_owned()).unwrap();
+
+    let mut histogram = Histogram::for_bag(&bag);
+    histogram.extend(bag.totals_with_rng(seeded_rng(21)).take(50_000));
+    let distribution = bag.make_count_distribution_with_rng(50_000, &mut seeded_rng(21));
+    assert_eq!(histogram.counts(), &distribution);
+    assert_eq!(histogram.rolls(), 50_000);
+
+    // Feeding in two halves and merging gives the same thing.
+    let mut totals = bag.totals_with_rng(seeded_rng(21));
+    let mut first: Histogram = totals.by_ref().take(20_000).collect();
+    let second: Histogram = totals.take(30_000).collect();
+    first.merge(&second);
+    assert_eq!(first.rolls(), 50_000);
+    assert_eq!(first.mean(), histogram.mean());
+    for (value, count) in first.counts() {
+        assert_eq!(distribution.get(value), Some(count));
+    }
+
+    let frequencies = histogram.frequencies();
+    let percent = frequencies.values().sum::<f64>();
+    assert!((percent > 99.999) && (percent < 100.001));
+    assert!(Histogram::new().mean().is_none());
+    assert!(Histogram::new().frequencies().is_empty());
+}
+
+#[test]
+fn test_adaptive_distribution() {
+    let bag = super::parse("1d6".to_owned()).unwrap();
+    let mut rng = seeded_rng(30);
+
+    let result = bag.make_adaptive_distribution_with_rng(
+        PrecisionTarget::EveryBucket(0.5),
+        10_000_000,
+        &mut rng,
+    );
+    assert!(result.converged());
+    assert!(result.precision() <= 0.5);
+    // About 2 * 1.96 * sqrt(1/6 * 5/6 / n) * 100 = 0.5 gives n of roughly 85,000.
+    assert!((result.rolls() > 50_000) && (result.rolls() < 200_000));
+    for (_, f) in result.frequencies() {
+        assert!((f > 16.667 - 0.5) && (f < 16.667 + 0.5));
+    }
+
+    // A total which has turned up every time is not yet known exactly.
+    let bag = super::parse("5".to_owned()).unwrap();
+    let result = bag.make_adaptive_distribution(PrecisionTarget::EveryBucket(0.1), 1_000_000);
+    assert!(result.converged());
+    // About 1.96^2 / n * 100 = 0.1 gives n of roughly 3,800.
+    assert!(result.rolls() > 3_000);
+    let always: Histogram = (0..1_000).map(|_| 5).collect();
+    let expected = 1.96 * 1.96 / (1_000.0 + 1.96 * 1.96) * 100.0;
+    assert!((always.bucket_interval_width().unwrap() - expected).abs() < 1e-4);
+
+    // A constant never varies, so converges on the first check.
+    let result = bag.make_adaptive_distribution(PrecisionTarget::Mean(0.01), 1_000_000);
+    assert!(result.converged());
+    assert_eq!(result.rolls(), 1_000);
+
+    // A zero budget makes no rolls.
+    let result = bag.make_adaptive_distribution(PrecisionTarget::Mean(0.01), 0);
+    assert!(!result.converged());
+    assert_eq!(result.rolls(), 0);
+
+    // A range far wider than the rolls allowed is not filled in.
+    let wide = super::parse("1d1000000000".to_owned()).unwrap();
+    let result = wide.make_adaptive_distribution_with_rng(
+        PrecisionTarget::EveryBucket(0.5),
+        5_000,
+        &mut rng,
+    );
+    assert!(result.rolls() <= 5_000);
+    assert!(result.histogram().counts().len() <= result.rolls());
+}
+
+#[cfg(test)]
+/// Every total a group of non-exploding dice can add to a bag, found by going through every
+/// combination of faces. Rerolls only replace a face with another face, so they change the odds
+/// but not which totals can be rolled.
+fn enumerate_group(group: &DiceGroup) -> std::collections::BTreeSet<i64> {
+    let mut totals = std::collections::BTreeSet::new();
+    let d = match group {
+        DiceGroup::Bonus(b) => {
+            totals.insert(b.op.operate(0, b.bonus));
+            return totals;
+        }
+        DiceGroup::Dice(d) => d,
+    };
+
+    let mut faces = vec![1; d.count];
+    loop {
+        let mut rolled = faces.clone();
+        for val in rolled.iter_mut() {
+            d.cutoff.use_to_cut_off(val);
+        }
+        rolled.sort();
+        let kept: Vec<i64> = match d.drop {
+            Drop::Non => rolled,
+            Drop::Lowest(n) => rolled[n.min(rolled.len())..].to_vec(),
+            Drop::Highest(n) => rolled[..rolled.len().saturating_sub(n)].to_vec(),
+            Drop::Custom(ref v) => v.iter().map(|i| rolled[*i]).collect(),
+        };
+        totals.insert(d.op.operate(0, kept.iter().sum()));
+
+        // Next combination of faces.
+        let mut i = 0;
+        while i < faces.len() && faces[i] == d.size {
+            faces[i] = 1;
+            i += 1;
+        }
+        if i == faces.len() {
+            break;
+        }
+        faces[i] += 1;
+    }
+    totals
+}
+
+#[test]
+/// The range of small bags must be exactly the set of totals found by trying every roll.
+fn test_range_against_exhaustive_enumeration() {
+    use rand::Rng;
+    let mut rng = seeded_rng(31);
+
+    for _ in 0..500 {
+        let group_count = rng.gen_range(1, 4);
+        let mut groups = Vec::with_capacity(group_count);
+        for _ in 0..group_count {
+            if rng.gen_range(0, 4) == 0 {
+                let bonus = rng.gen_range(0, 10);
+                groups.push(if rng.gen() {
+                    Bonus::plus(bonus).into()
+                } else {
+                    Bonus::minus(bonus).into()
+                });
+                continue;
+            }
+
+            let count = rng.gen_range(0, 4);
+            let size = rng.gen_range(1, 6);
+            let mut dice = Dice::with_size_and_count(size, count);
+            // Invalid clauses are refused and leave the dice as they were, so may be ignored.
+            let _ = match rng.gen_range(0, 4) {
+                0 => dice.with_drop_lowest(rng.gen_range(0, count + 1)),
+                1 => dice.with_drop_highest(rng.gen_range(0, count + 1)),
+                2 => dice.with_drop_highest_and_lowest(1, 1),
+                _ => Ok(()),
+            };
+            let mn = rng.gen_range(1, size + 1);
+            let mx = rng.gen_range(mn, size + 1);
+            let _ = match rng.gen_range(0, 4) {
+                0 => dice.with_minimum_roll(mn),
+                1 => dice.with_maximum_roll(mx),
+                2 => dice.with_min_and_max_roll(mn, mx),
+                _ => Ok(()),
+            };
+            if rng.gen() {
+                let _ = dice.add_reroll_if_below(mx, 1);
+            }
+            if rng.gen() {
+                dice.to_minus_dice();
+            }
+            groups.push(dice.into());
+        }
+
+        let support = groups
+            .iter()
+            .fold(std::collections::BTreeSet::from([0]), |acc, group| {
+                let group_totals = enumerate_group(group);
+                acc.iter()
+                    .flat_map(|a| group_totals.iter().map(move |g| a + g))
+                    .collect()
+            });
+        let bag = DiceBag::from_dice(groups);
+        let min = *support.iter().next().unwrap();
+        let max = *support.iter().last().unwrap();
+
+        assert!(bag.is_bounded());
+        assert_eq!(bag.range(), min..=max, "Wrong range for {:?}", bag);
+        assert_eq!(bag.get_range(), [min, max]);
+        // Sums of dice have no gaps, so the support is the whole range.
+        assert_eq!(
+            bag.get_range_as_list(),
+            support.iter().cloned().collect::<Vec<_>>()
+        );
+        let distribution = bag.make_count_distribution_with_rng(200, &mut rng);
+        assert_eq!(
+            distribution.keys().cloned().collect::<Vec<_>>(),
+            bag.get_range_as_list()
+        );
+    }
+}
+
+#[test]
+fn test_exploding_range() {
+    let bag = super::parse("2d6!+1".to_owned()).unwrap();
+    assert!(!bag.is_bounded());
+    assert_eq!(bag.range(), 3..=i64::MAX);
+    assert_eq!(bag.get_range_as_list(), (3..=13).collect::<Vec<_>>());
+
+    // Keeping a fixed set of dice bounds even exploding dice.
+    let bag = super::parse("4d6dl1dh1!".to_owned()).unwrap();
+    assert!(bag.is_bounded());
+    assert_eq!(bag.range(), 2..=12);
+    let distribution = bag.make_count_distribution(10_000);
+    assert_eq!(
+        distribution.keys().cloned().collect::<Vec<_>>(),
+        bag.get_range_as_list()
+    );
+
+    // Dropping a number of dice from an exploding pool does not.
+    let bag = super::parse("3d6dl1!".to_owned()).unwrap();
+    assert!(!bag.is_bounded());
+    assert_eq!(bag.range(), 2..=i64::MAX);
+}
+
+#[cfg(test)]
+/// Make a random dice string which the parser accepts, using every clause the parser knows.
+fn random_dice_string<R: rand::Rng>(rng: &mut R) -> String {
+    let group_count = rng.gen_range(1, 5);
+    let mut input = String::new();
+    for i in 0..group_count {
+        if i != 0 || rng.gen_range(0, 4) == 0 {
+            input.push_str(if rng.gen() { " + " } else { " - " });
+        }
+        if rng.gen_range(0, 4) == 0 {
+            input.push_str(&rng.gen_range(0, 100).to_string());
+            continue;
+        }
+
+        let count: usize = rng.gen_range(1, 12);
+        let size: i64 = rng.gen_range(2, 30);
+        if count == 1 && rng.gen() {
+            input.push_str(&format!("d{}", size));
+        } else {
+            input.push_str(&format!("{}d{}", count, size));
+        }
+
+        // Drops. Both together must leave at least one die.
+        let low = rng.gen_range(0, count);
+        let high = rng.gen_range(0, count - low);
+        match rng.gen_range(0, 5) {
+            0 => input.push_str(&format!("dl{}", low)),
+            // Keeping every die is not allowed.
+            1 if high > 0 => input.push_str(&format!("kl{}", count - high)),
+            2 => input.push_str(&format!("dl{}dh{}", low, high)),
+            3 if high > 0 && low > 0 => {
+                input.push_str(&format!("kl{}kh{}", count - high, count - low))
+            }
+            _ => {}
+        }
+
+        // Rerolls.
+        match rng.gen_range(0, 3) {
+            0 => input.push_str(&format!(
+                "rr{}ab{}",
+                rng.gen_range(0, count + 1),
+                rng.gen_range(0, size)
+            )),
+            1 => input.push_str(&format!(
+                "be{}rr{}",
+                rng.gen_range(2, size + 1),
+                rng.gen_range(0, count + 1)
+            )),
+            _ => {}
+        }
+
+        // Cutoffs.
+        let mn = rng.gen_range(2, size + 1);
+        let mx = rng.gen_range(1, size);
+        match rng.gen_range(0, 4) {
+            0 => input.push_str(&format!("mn{}", mn)),
+            1 => input.push_str(&format!("mx{}", mx)),
+            2 if mn <= mx => input.push_str(&format!("mx{}mn{}", mx, mn)),
+            _ => {}
+        }
+
+        if rng.gen() {
+            input.push('!');
+        }
+    }
+    if rng.gen() {
+        input.to_uppercase()
+    } else {
+        input
+    }
+}
+
+#[test]
+fn test_display_parse_round_trip() {
+    let mut rng = seeded_rng(32);
+    for _ in 0..10_000 {
+        let input = random_dice_string(&mut rng);
+        let bag: DiceBag = input
+            .parse()
+            .unwrap_or_else(|e| panic!("({}) should parse: {}", input, e));
+        let output = bag.to_string();
+        let reparsed: DiceBag = output
+            .parse()
+            .unwrap_or_else(|e| panic!("({}) from ({}) should parse: {}", output, input, e));
+        assert_eq!(bag, reparsed, "({}) became ({})", input, output);
+        assert_eq!(output, reparsed.to_string());
+    }
+}
+
+#[cfg(test)]
+/// Build a random valid bag with the builder, using every clause it knows, and sometimes a
+/// "max(..)" or "min(..)" of smaller random bags.
+fn random_bag<R: rand::Rng>(rng: &mut R, depth: usize) -> DiceBag {
+    loop {
+        let mut builder = DiceBag::builder();
+        for _ in 0..rng.gen_range(1, 4) {
+            if rng.gen_range(0, 4) == 0 {
+                let n = rng.gen_range(0, 100);
+                builder = if rng.gen() {
+                    builder.plus(n)
+                } else {
+                    builder.minus(n)
+                };
+                continue;
+            }
+            let count: usize = rng.gen_range(1, 12);
+            let size: i64 = rng.gen_range(2, 30);
+            builder = if rng.gen() {
+                builder.dice(count, size)
+            } else {
+                builder.minus_dice(count, size)
+            };
+            builder = match rng.gen_range(0, 6) {
+                0 => builder.drop_lowest(rng.gen_range(0, count)),
+                1 => builder.drop_highest(rng.gen_range(0, count)),
+                2 => builder.keep_highest(rng.gen_range(1, count + 1)),
+                3 => builder.keep_lowest(rng.gen_range(1, count + 1)),
+                _ => builder,
+            };
+            builder = match rng.gen_range(0, 3) {
+                0 => builder.reroll_above(rng.gen_range(1, size), rng.gen_range(0, count + 1)),
+                1 => builder.reroll_below(rng.gen_range(2, size + 1), rng.gen_range(0, count + 1)),
+                _ => builder,
+            };
+            builder = match rng.gen_range(0, 4) {
+                0 => builder.minimum(rng.gen_range(2, size + 1)),
+                1 => builder.maximum(rng.gen_range(1, size)),
+                _ => builder,
+            };
+            if rng.gen() {
+                builder = builder.explode();
+            }
+        }
+        let bag = match builder.build() {
+            Ok(bag) => bag,
+            Err(_) => continue,
+        };
+        if depth == 0 || rng.gen_range(0, 4) != 0 {
+            return bag;
+        }
+        let bags = (0..rng.gen_range(1, 4))
+            .map(|_| random_bag(rng, depth - 1))
+            .collect::<Vec<_>>();
+        let pick = if rng.gen() {
+            Dice::max_of(bags)
+        } else {
+            Dice::min_of(bags)
+        };
+        let mut groups = bag.groups().to_vec();
+        groups.insert(rng.gen_range(0, groups.len() + 1), pick.into());
+        if let Ok(bag) = DiceBag::try_from_dice(groups) {
+            return bag;
+        }
+    }
+}
+
+#[test]
+fn test_display_round_trip_random_built_bags() {
+    let mut rng = seeded_rng(320);
+    for _ in 0..5_000 {
+        let bag = random_bag(&mut rng, 1);
+        let output = bag.to_string();
+        let reparsed = super::parse(output.clone())
+            .unwrap_or_else(|e| panic!("({}) should parse: {}", output, e));
+        assert_eq!(reparsed, bag, "({}) did not round-trip", output);
+    }
+}
+
+#[test]
+fn test_display_round_trip_built_bags() {
+    let mut four_d_six = Dice::with_size_and_count(6, 4);
+    four_d_six.with_drop_highest_and_lowest(1, 2).unwrap();
+    four_d_six.to_minus_dice();
+    let bag = DiceBag::from_dice(vec![
+        four_d_six.into(),
+        Bonus::minus(3).into(),
+        Dice::with_size_and_count(20, 1).into(),
+        Bonus::plus(2).into(),
+    ]);
+    assert_eq!(bag.to_string(), "-4d6dl1dh2 - 3 + 1d20 + 2");
+    assert_eq!(bag.to_string().parse::<DiceBag>(), Ok(bag));
+}
+
+#[test]
+fn test_builder_matches_parser() {
+    let built = DiceBag::builder()
+        .dice(15, 20)
+        .drop_lowest(4)
+        .drop_highest(3)
+        .reroll_above(4, 3)
+        .minimum(2)
+        .explode()
+        .minus(7)
+        .minus_dice(2, 4)
+        .maximum(3)
+        .plus(1)
+        .build()
+        .unwrap();
+    let parsed = super::parse("15d20dl4dh3rr3ab4mn2! - 7 - 2d4mx3 + 1".to_owned()).unwrap();
+    assert_eq!(built, parsed);
+
+    let built = DiceBag::builder()
+        .dice(5, 6)
+        .keep_highest(3)
+        .keep_lowest(4)
+        .reroll_below(3, 2)
+        .minimum(2)
+        .maximum(5)
+        .build()
+        .unwrap();
+    let parsed = super::parse("5d6kh3kl4rr2be3mn2mx5".to_owned()).unwrap();
+    assert_eq!(built, parsed);
+}
+
+#[test]
+fn test_builder_rejects_what_parser_rejects() {
+    use super::validation::DiceError;
+
+    let failures = [
+        // Overwriting clauses.
+        DiceBag::builder().dice(4, 6).minimum(3).minimum(2).build(),
+        DiceBag::builder()
+            .dice(4, 6)
+            .drop_lowest(1)
+            .keep_highest(2)
+            .build(),
+        DiceBag::builder()
+            .dice(4, 6)
+            .drop_highest(1)
+            .keep_lowest(2)
+            .build(),
+        DiceBag::builder()
+            .dice(4, 6)
+            .reroll_above(3, 1)
+            .reroll_below(3, 1)
+            .build(),
+        // Impossible clauses.
+        DiceBag::builder().dice(4, 6).drop_lowest(4).build(),
+        DiceBag::builder()
+            .dice(4, 6)
+            .drop_lowest(2)
+            .drop_highest(2)
+            .build(),
+        DiceBag::builder().dice(4, 6).minimum(1).build(),
+        DiceBag::builder().dice(4, 6).maximum(6).build(),
+        DiceBag::builder().dice(4, 6).minimum(5).maximum(4).build(),
+        DiceBag::builder().dice(4, 6).reroll_above(6, 1).build(),
+        DiceBag::builder().dice(4, 6).reroll_below(1, 1).build(),
+        DiceBag::builder().dice(4, 6).reroll_below(3, 5).build(),
+        DiceBag::builder().dice(0, 6).build(),
+        // Modifiers with nothing to modify.
+        DiceBag::builder().drop_lowest(1).dice(4, 6).build(),
+        DiceBag::builder().plus(3).explode().build(),
+        DiceBag::builder().build(),
+    ];
+    for (i, failure) in failures.iter().enumerate() {
+        assert!(failure.is_err(), "Case {} should fail.", i);
+    }
+
+    // A group must have dice, whether its count is written or rolled.
+    for input in ["0d6", "1d4 + 0d6", "(1d2-1)d6", "(1d1-1)d6"].iter() {
+        let error = super::parse(input.to_string()).unwrap_err();
+        assert!(error.contains(&DiceError::NoDice.to_string()), "{}", input);
+    }
+    assert!(super::parse("(1d2)d6".to_owned()).is_ok());
+}
+
+#[test]
+/// Whatever the `Dice` functions let through, a bag is valid if, and only if, it can be parsed.
+fn test_valid_if_and_only_if_parseable() {
+    use rand::Rng;
+    let mut rng = seeded_rng(34);
+    for _ in 0..10_000 {
+        let mut groups: Vec<DiceGroup> = Vec::new();
+        for _ in 0..rng.gen_range(0, 3) {
+            let size = rng.gen_range(-1, 8);
+            let count = rng.gen_range(0, 5);
+            let mut dice = Dice::with_size_and_count(size, count);
+            for _ in 0..rng.gen_range(0, 4) {
+                let n = rng.gen_range(0, 5);
+                let x = rng.gen_range(-1, 9);
+                let _ = match rng.gen_range(0, 10) {
+                    0 => dice.with_drop_lowest(n),
+                    1 => dice.with_drop_highest(n),
+                    2 => dice.with_keep_highest(n),
+                    3 => dice.with_keep_lowest(n),
+                    4 => dice.with_drop_highest_and_lowest(n, rng.gen_range(0, 3)),
+                    5 => dice.add_reroll_if_above(x, n),
+                    6 => dice.add_reroll_if_below(x, n),
+                    7 => dice.with_minimum_roll(x),
+                    8 => dice.with_maximum_roll(x),
+                    _ => dice.with_min_and_max_roll(x, rng.gen_range(-1, 9)),
+                };
+            }
+            let mut group: DiceGroup = dice.into();
+            group.is_explosive(rng.gen_range(0, 4) == 0);
+            groups.push(group);
+        }
+        let bag = DiceBag::from_dice(groups);
+        let parsed = super::parse(bag.to_string());
+        assert_eq!(
+            bag.validate().is_ok(),
+            parsed.is_ok(),
+            "({}) {:?} vs {:?}",
+            bag,
+            bag.validate(),
+            parsed
+        );
+        if let Ok(parsed) = parsed {
+            assert_eq!(bag, parsed);
+        }
+    }
+}
+
+#[test]
+fn test_dice_functions_combine_or_refuse() {
+    let mut dice = Dice::with_size_and_count(6, 5);
+    dice.with_maximum_roll(5).unwrap();
+    dice.with_minimum_roll(2).unwrap();
+    dice.with_drop_highest(1).unwrap();
+    dice.with_keep_highest(3).unwrap();
+    dice.add_reroll_if_below(2, 1).unwrap();
+    let parsed = super::parse("5d6mx5mn2dh1kh3rr1be2".to_owned()).unwrap();
+    assert_eq!(DiceBag::from_dice(vec![dice.clone().into()]), parsed);
+
+    // Refused clauses leave the dice as they were.
+    let before = dice.clone();
+    assert_eq!(
+        dice.with_minimum_roll(3),
+        Err(DiceError::MultipleClauses("mn"))
+    );
+    assert_eq!(
+        dice.with_drop_lowest(1),
+        Err(DiceError::MultipleClauses("dl/kh"))
+    );
+    assert_eq!(
+        dice.add_reroll_if_above(3, 1),
+        Err(DiceError::MultipleClauses("rr"))
+    );
+    assert_eq!(dice, before);
+
+    let mut dice = Dice::with_size_and_count(6, 4);
+    assert_eq!(
+        dice.with_drop_lowest(4),
+        Err(DiceError::DroppingTooMany {
+            dropping: 4,
+            count: 4
+        })
+    );
+    assert_eq!(
+        dice.add_reroll_if_above(6, 1),
+        Err(DiceError::ReRollThreshold {
+            threshold: 6,
+            size: 6
+        })
+    );
+    assert_eq!(
+        dice.with_min_and_max_roll(5, 4),
+        Err(DiceError::CutOffOrder { min: 5, max: 4 })
+    );
+    assert_eq!(dice, Dice::with_size_and_count(6, 4));
+}
+
+#[test]
+fn test_limits_refuse_huge_input() {
+    let refused = [
+        ("999999999d999999999", "Too many dice"),
+        ("1001d6", "Too many dice"),
+        ("1d1000000001", "too many sides"),
+        (
+            "999d6+999d6+999d6+999d6+999d6+999d6+999d6+999d6+999d6+999d6+999d6",
+            "Too many dice to roll",
+        ),
+        (
+            "1000d6rr1000be2+1000d6+1000d6+1000d6+1000d6+1000d6+1000d6+1000d6+1000d6+1d6",
+            "Too many dice to roll",
+        ),
+    ];
+    for (input, reason) in refused.iter() {
+        let error = super::parse(input.to_string()).unwrap_err();
+        assert!(error.contains(reason), "({}) gave ({})", input, error);
+    }
+
+    let too_many_groups = vec!["1"; 101].join("+");
+    assert!(super::parse(too_many_groups).is_err());
+    assert!(super::parse(vec!["1"; 100].join("+")).is_ok());
+
+    // Totals which could overflow are refused, even with the other limits lifted.
+    let unlimited = Limits {
+        max_dice_per_group: usize::MAX,
+        max_sides: i64::MAX,
+        max_groups: usize::MAX,
+        max_explosion_depth: usize::MAX,
+        max_total_rolls: usize::MAX,
+    };
+    let overflowing = [
+        "2d9223372036854775807",
+        "1d9223372036854775807+1",
+        "5000000000000000000+5000000000000000000",
+        "1d2!",
+    ];
+    for input in overflowing.iter() {
+        assert_eq!(
+            parse::parse_with_limits(input.to_string(), &unlimited)
+                .map_err(|e| e.contains("overflow")),
+            Err(true),
+            "({}) should overflow",
+            input
+        );
+    }
+    assert!(parse::parse_with_limits("1d9223372036854775807".to_owned(), &unlimited).is_ok());
+}
+
+#[test]
+fn test_explosion_depth_limit() {
+    let limits = Limits {
+        max_explosion_depth: 3,
+        ..Limits::default()
+    };
+    let bag = DiceBag::builder()
+        .dice(1, 2)
+        .explode()
+        .limits(limits)
+        .build()
+        .unwrap();
+    assert_eq!(bag.limits(), &limits);
+    assert_eq!(
+        bag,
+        parse::parse_with_limits("1d2!".to_owned(), &limits).unwrap()
+    );
+
+    let mut rng = seeded_rng(35);
+    let mut highest = 0;
+    for _ in 0..10_000 {
+        highest = highest.max(bag.roll_with_rng(&mut rng).total());
+    }
+    assert_eq!(highest, 8);
+
+    let bag = DiceBag::builder()
+        .dice(3, 6)
+        .explode()
+        .limits(Limits {
+            max_explosion_depth: 0,
+            ..Limits::default()
+        })
+        .build()
+        .unwrap();
+    assert!(bag
+        .totals_with_rng(seeded_rng(36))
+        .take(10_000)
+        .all(|t| t <= 18));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip_bags() {
+    let mut rng = seeded_rng(36);
+    for _ in 0..2_000 {
+        let input = random_dice_string(&mut rng);
+        let bag = super::parse(input.clone()).unwrap();
+        let json = serde_json::to_string(&bag).unwrap();
+        let read: DiceBag = serde_json::from_str(&json)
+            .unwrap_or_else(|e| panic!("({}) as ({}) should be read: {}", input, json, e));
+        assert_eq!(bag, read, "({}) became ({})", input, json);
+    }
+
+    let limits = Limits {
+        max_sides: 20,
+        ..Limits::default()
+    };
+    let bag = parse::parse_with_limits("2d20!".to_owned(), &limits).unwrap();
+    let read: DiceBag = serde_json::from_str(&serde_json::to_string(&bag).unwrap()).unwrap();
+    assert_eq!(read.limits(), &limits);
+
+    // But limits looser than the defaults are not taken from the document.
+    let json = concat!(
+        r#"{"version":1,"dice":[{"dice":{"count":5000,"sides":6,"op":"add"}}],"#,
+        r#""limits":{"max_dice_per_group":1000000,"max_sides":1000000000,"max_groups":100,"#,
+        r#""max_explosion_depth":1000000,"max_total_rolls":1000000}}"#,
+    );
+    assert!(serde_json::from_str::<DiceBag>(json).is_err());
+    let json = json.replace(r#""count":5000"#, r#""count":5"#);
+    let read: DiceBag = serde_json::from_str(&json).unwrap();
+    assert_eq!(read.limits(), &Limits::default());
+
+    // Dice rolled for a count or sides are written as bags of their own.
+    let bag =
+        parse::parse("(1d4+1)d6dl1 [cold] - 2d(1d3+1) - 2 [cold] # ice storm".to_owned()).unwrap();
+    let read: DiceBag = serde_json::from_str(&serde_json::to_string(&bag).unwrap()).unwrap();
+    assert_eq!(bag, read);
+    let roll = bag.roll_with_rng(&mut rng);
+    let json = serde_json::to_string(&roll).unwrap();
+    assert_eq!(roll, serde_json::from_str::<RollResults>(&json).unwrap());
+
+    // So are the bags of a "max(..)" or "min(..)" group, which cannot take clauses.
+    let bag = parse::parse("max(1d8!, 1d6!) - min(1d20, (1d4)d6) + 1".to_owned()).unwrap();
+    let json = serde_json::to_string(&bag).unwrap();
+    assert_eq!(bag, serde_json::from_str::<DiceBag>(&json).unwrap());
+    let roll = bag.roll_with_rng(&mut rng);
+    let roll_json = serde_json::to_string(&roll).unwrap();
+    assert_eq!(
+        roll,
+        serde_json::from_str::<RollResults>(&roll_json).unwrap()
+    );
+    let json = json.replacen(r#""op":"add","#, r#""op":"add","drop_lowest":1,"#, 1);
+    assert!(serde_json::from_str::<DiceBag>(&json).is_err(), "{}", json);
+
+    // Missing limits are the defaults, but other versions and invalid bags are refused.
+    let json = r#"{"version":1,"dice":[{"dice":{"count":2,"sides":6,"op":"add"}}]}"#;
+    let read: DiceBag = serde_json::from_str(json).unwrap();
+    assert_eq!(read, super::parse("2d6".to_owned()).unwrap());
+    let refused = [
+        r#"{"version":3,"dice":[{"dice":{"count":2,"sides":6,"op":"add"}}]}"#,
+        r#"{"version":2,"dice":[{"dice":{"count":2,"sides":6,"op":"add","keep":1}}]}"#,
+        r#"{"version":2,"dice":[{"dice":{"count":2,"sides":6,"op":"add"}}],"extra":1}"#,
+        r#"{"version":1,"dice":[]}"#,
+        r#"{"version":1,"dice":[{"dice":{"count":2,"sides":1,"op":"add","explode":true}}]}"#,
+        r#"{"version":1,"dice":[{"dice":{"count":2,"sides":6,"op":"add","minimum":1}}]}"#,
+        r#"{"version":1,"dice":[{"bonus":{"bonus":-2,"op":"add"}}]}"#,
+        r#"{"version":1,"dice":[{"dice":{"count":2000,"sides":6,"op":"add"}}]}"#,
+    ];
+    for json in refused.iter() {
+        assert!(serde_json::from_str::<DiceBag>(json).is_err(), "{}", json);
+    }
+
+    // Version 1 documents are read, but only without what version 2 added.
+    let bag = super::parse("2d6dl1 - 3".to_owned()).unwrap();
+    let json = serde_json::to_string(&bag).unwrap();
+    assert!(json.starts_with(r#"{"version":2,"#));
+    let old = json.replace(r#""version":2"#, r#""version":1"#);
+    assert_eq!(serde_json::from_str::<DiceBag>(&old).unwrap(), bag);
+    let mut rng = seeded_rng(48);
+    for input in [
+        "max(1d8!, 1d6!) + 1",
+        "(1d4)d6",
+        "2d(1d4)",
+        "1d6 [fire]",
+        "1d6 + 2 [fire]",
+        "1d6 # note",
+    ]
+    .iter()
+    {
+        let bag = super::parse(input.to_string()).unwrap();
+        let json = serde_json::to_string(&bag).unwrap();
+        assert_eq!(serde_json::from_str::<DiceBag>(&json).unwrap(), bag);
+        let old = json.replace(r#""version":2"#, r#""version":1"#);
+        assert!(serde_json::from_str::<DiceBag>(&old).is_err(), "{}", old);
+
+        let roll = bag.roll_with_rng(&mut rng);
+        let json = serde_json::to_string(&roll).unwrap();
+        assert_eq!(serde_json::from_str::<RollResults>(&json).unwrap(), roll);
+        let old = json.replace(r#""version":2"#, r#""version":1"#);
+        if *input != "1d6 # note" {
+            assert!(
+                serde_json::from_str::<RollResults>(&old).is_err(),
+                "{}",
+                old
+            );
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip_results() {
+    let bag = super::parse("4d6dl1dh1mn2 - 1d4! + 3".to_owned()).unwrap();
+    let mut rng = seeded_rng(37);
+    for _ in 0..100 {
+        let roll = bag.roll_with_rng(&mut rng);
+        let json = serde_json::to_string(&roll).unwrap();
+        assert_eq!(roll, serde_json::from_str::<RollResults>(&json).unwrap());
+    }
+    let roll = bag.roll_with_rng(&mut rng);
+    let json = serde_json::to_string(&roll).unwrap().replace(
+        &format!(r#""total":{}}}"#, roll.total()),
+        r#""total":1000}"#,
+    );
+    assert!(serde_json::from_str::<RollResults>(&json).is_err());
+
+    let histogram: Histogram = bag.totals_with_rng(seeded_rng(38)).take(1_000).collect();
+    let json = serde_json::to_string(&histogram).unwrap();
+    assert_eq!(histogram, serde_json::from_str::<Histogram>(&json).unwrap());
+
+    let adaptive =
+        bag.make_adaptive_distribution_with_rng(PrecisionTarget::Mean(0.5), 100_000, &mut rng);
+    let json = serde_json::to_string(&adaptive).unwrap();
+    assert_eq!(
+        adaptive,
+        serde_json::from_str::<AdaptiveDistribution>(&json).unwrap()
+    );
+
+    let json = serde_json::to_string(&PrecisionTarget::EveryBucket(0.5)).unwrap();
+    assert_eq!(json, r#"{"every_bucket":0.5}"#);
+}
+
+#[test]
+fn test_normalize_random_bags() {
+    let mut rng = seeded_rng(37);
+    for _ in 0..5_000 {
+        let input = random_dice_string(&mut rng);
+        let bag = super::parse(input.clone()).unwrap();
+        let normal = bag.normalize();
+        assert!(normal.validate().is_ok(), "({}) became ({})", input, normal);
+        assert_eq!(
+            normal.range(),
+            bag.range(),
+            "({}) became ({})",
+            input,
+            normal
+        );
+        assert_eq!(normal.normalize(), normal);
+        assert_eq!(super::parse(normal.to_string()).unwrap(), normal);
+        assert!(bag.is_equivalent(&normal));
+        assert!(normal.dice.len() <= bag.dice.len());
+    }
+}
+
+#[test]
+fn test_normalize_keeps_rr0_rolls() {
+    // "rr0" rerolls nothing, so it rolls just like the bag normalize drops it from.
+    let bag = super::parse("20d4rr0be4".to_owned()).unwrap();
+    let normal = bag.normalize();
+    assert_eq!(normal.to_string(), "20d4");
+    let mut rng_1 = seeded_rng(371);
+    let mut rng_2 = seeded_rng(371);
+    for _ in 0..1_000 {
+        assert_eq!(
+            bag.roll_with_rng(&mut rng_1).total(),
+            normal.roll_with_rng(&mut rng_2).total()
+        );
+    }
+    let mut buffer = RollBuffer::new();
+    let mut rng = seeded_rng(372);
+    let low_count = (0..1_000)
+        .filter(|_| bag.roll_total_with_rng(&mut rng, &mut buffer) <= 47)
+        .count();
+    // 20d4 totals 47 or less about 30% of the time, but rerolling every die below 4 would make
+    // it rare.
+    assert!(low_count > 200, "{}", low_count);
+}
+
+#[test]
+fn test_normalize_merges() {
+    let bag = |s: &str| super::parse(s.to_owned()).unwrap();
+    assert_eq!(bag("2d6+3d6+1-4").normalize(), bag("5d6-3"));
+    assert_eq!(
+        bag("2d6!+3d6-1d6-1d6+2d6!").normalize(),
+        bag("3d6+4d6!-2d6")
+    );
+    assert_eq!(bag("3d6+2d6dl1").normalize(), bag("2d6dl1+3d6"));
+    assert_eq!(bag("3d1-1").normalize(), bag("2"));
+    assert_eq!(bag("1-1").normalize(), bag("0"));
+    assert_eq!(bag("-2d4+1d4rr0be2").normalize(), bag("1d4-2d4"));
+    assert_eq!(bag("5d6dl0dh2").normalize(), bag("5d6dh2"));
+
+    // Merging never makes a group too big for the limits.
+    let big = bag("600d6+600d6+1d6").normalize();
+    assert_eq!(big.dice.len(), 2);
+    assert!(big.validate().is_ok());
+}
+
+#[test]
+fn test_distribution_cache_evicts_least_recently_used() {
+    use super::cache::DistributionCache;
+    let bag = |s: &str| super::parse(s.to_owned()).unwrap();
+    let mut cache = DistributionCache::new(2);
+    let d20 = cache.count_distribution(&bag("1d20"), 1_000);
+    cache.count_distribution(&bag("1d6"), 1_000);
+    // Using "1d20" again makes "1d6" the least recently used.
+    assert_eq!(cache.count_distribution(&bag("0+1d20"), 1_000), d20);
+    cache.count_distribution(&bag("1d8"), 1_000);
+
+    assert_eq!(cache.len(), 2);
+    assert!(cache.contains(&bag("1d20"), 1_000));
+    assert!(!cache.contains(&bag("1d6"), 1_000));
+    assert!(cache.contains(&bag("1d8"), 1_000));
+
+    // A different explosion depth is a different distribution.
+    let shallow = parse::parse_with_limits(
+        "1d20".to_owned(),
+        &Limits {
+            max_explosion_depth: 1,
+            ..Limits::default()
+        },
+    )
+    .unwrap();
+    assert!(!cache.contains(&shallow, 1_000));
+
+    let mut empty = DistributionCache::new(0);
+    assert_eq!(empty.count_distribution(&bag("1d4"), 100).len(), 4);
+    assert!(empty.is_empty());
+}
+
+#[test]
+fn test_distribution_cache_export_import() {
+    use super::cache::DistributionCache;
+    let bags = ["1d20", "4d6dl1", "8d6", "2d6! - 1d4 + 3"]
+        .iter()
+        .map(|s| super::parse(s.to_string()).unwrap())
+        .collect::<Vec<_>>();
+    let mut cache = DistributionCache::new(10);
+    cache.prewarm(&bags, 2_000);
+
+    let path = std::env::temp_dir().join(format!("libazdice-cache-{}.txt", std::process::id()));
+    cache.save(&path).unwrap();
+    let mut loaded = DistributionCache::new(10);
+    assert_eq!(loaded.load(&path).unwrap(), 4);
+    std::fs::remove_file(&path).unwrap();
+    for bag in bags.iter() {
+        assert!(loaded.contains(bag, 2_000));
+        assert_eq!(
+            loaded.count_distribution(bag, 2_000),
+            cache.count_distribution(bag, 2_000)
+        );
+    }
+
+    let mut written = Vec::new();
+    cache.write_to(&mut written).unwrap();
+    let mut small = DistributionCache::new(2);
+    assert_eq!(small.read_from(written.as_slice()).unwrap(), 4);
+    assert_eq!(small.len(), 2);
+
+    let invalid = [
+        "",
+        "not a cache\n",
+        "libazdice-distribution-cache 1\n10\t100\t1d4\t1:5,2:4\n",
+        "libazdice-distribution-cache 1\n10\t100\t1d0\t1:10\n",
+        "libazdice-distribution-cache 1\n10\t100\t1d4\n",
+        "libazdice-distribution-cache 1\n10\t100\t1d4\t1:5,x:5\n",
+        "libazdice-distribution-cache 1\n2\t100\t1d4\t1:18446744073709551615,2:3\n",
+    ];
+    for text in invalid.iter() {
+        assert!(
+            DistributionCache::new(2)
+                .read_from(text.as_bytes())
+                .is_err(),
+            "{:?}",
+            text
+        );
+    }
+
+    // A bad line anywhere leaves the cache as it was.
+    let mut text = String::from_utf8(written).unwrap();
+    text.push_str("10\t100\t1d4\t1:5,2:4\n");
+    let mut kept = DistributionCache::new(10);
+    kept.prewarm(&bags[..1], 1_000);
+    assert!(kept.read_from(text.as_bytes()).is_err());
+    assert_eq!(kept.len(), 1);
+    assert!(kept.contains(&bags[0], 1_000));
+    assert!(!kept.contains(&bags[0], 2_000));
+}
+
+#[test]
+/// The exact distributions of small groups must match those found by trying every roll, and
+/// those of whole bags must match rolling them.
+fn test_exact_distribution_against_enumeration() {
+    use super::exact::Budget;
+    use rand::Rng;
+    let mut rng = seeded_rng(37);
+
+    for _ in 0..300 {
+        let count = rng.gen_range(0, 5);
+        let size = rng.gen_range(1, 7);
+        let mut dice = Dice::with_size_and_count(size, count);
+        let _ = match rng.gen_range(0, 5) {
+            0 => dice.with_drop_lowest(rng.gen_range(0, count + 1)),
+            1 => dice.with_drop_highest(rng.gen_range(0, count + 1)),
+            2 => dice.with_drop_highest_and_lowest(1, 1),
+            _ => Ok(()),
+        };
+        let mn = rng.gen_range(1, size + 1);
+        let mx = rng.gen_range(mn, size + 1);
+        let _ = match rng.gen_range(0, 4) {
+            0 => dice.with_minimum_roll(mn),
+            1 => dice.with_maximum_roll(mx),
+            _ => Ok(()),
+        };
+        let _ = match rng.gen_range(0, 3) {
+            0 => dice.add_reroll_if_below(mx, rng.gen_range(0, count + 1)),
+            1 => dice.add_reroll_if_above(mn, rng.gen_range(0, count + 1)),
+            _ => Ok(()),
+        };
+
+        let exact = dice.exact_pmf(0, &mut Budget::new()).unwrap();
+        let enumerated = dice.enumerated_pmf(&mut Budget::new()).unwrap();
+        assert_eq!(exact.min(), enumerated.min(), "{}", dice);
+        assert_eq!(exact.max(), enumerated.max(), "{}", dice);
+        for ((_, p), (_, q)) in exact.iter().zip(enumerated.iter()) {
+            assert!((p - q).abs() < 1e-12, "{}: {} vs {}", dice, p, q);
+        }
+    }
+
+    for s in ["3d6!", "2d4! + 2d6dl1 - 3", "4d6dl1rr1be2", "2d8mn3 - 1d4!"].iter() {
+        let bag = super::parse(s.to_string()).unwrap();
+        let exact = bag.make_exact_distribution().unwrap();
+        assert!((exact.values().sum::<f64>() - 100.0).abs() < 1e-9);
+        let rolled = bag.make_count_distribution_with_rng(200_000, &mut rng);
+        for (total, count) in rolled {
+            let f = count as f64 / 2_000.0;
+            assert!((f - exact[&total]).abs() < 0.5, "{} at {}", s, total);
+        }
+    }
+
+    // Too big to work out, but not to roll.
+    let bag = super::parse("1000d1000000".to_owned()).unwrap();
+    assert!(bag.make_exact_distribution().is_err());
+}
+
+#[test]
+fn test_chart_rendering() {
+    use super::chart::*;
+    use std::collections::BTreeMap;
+    let bag = super::parse("2d6".to_owned()).unwrap();
+    let exact = bag.make_exact_distribution().unwrap();
+
+    // Every total gets a row, and the likeliest total the full width.
+    let chart = Chart::new().frequencies("", &exact).width(20).render();
+    let rows = chart.lines().collect::<Vec<_>>();
+    assert_eq!(rows.len(), 11);
+    assert!(rows[5].starts_with(" 7 │ ████████████████████ 16.67%"));
+    // 2 is a sixth as likely as 7, so gets 3 1/3 characters, drawn to the nearest eighth.
+    assert!(rows[0].starts_with(" 2 │ ███▍ "));
+    assert!(rows[0].ends_with("  2.78%"));
+
+    // The cumulative chart climbs to 100%.
+    let cumulative = Chart::new()
+        .frequencies("", &exact)
+        .cumulative(true)
+        .charset(Charset::Ascii)
+        .labels(Labels::None)
+        .width(4)
+        .render();
+    assert_eq!(cumulative.lines().last(), Some("12 | ####"));
+    assert!(!cumulative.contains('%'));
+
+    // Count labels show the counts, and bars are scaled to them.
+    let counts: BTreeMap<i64, usize> = vec![(-1, 3), (1, 1)].into_iter().collect();
+    let chart = Chart::new()
+        .counts("", &counts)
+        .labels(Labels::Count)
+        .charset(Charset::Ascii)
+        .width(3)
+        .render();
+    assert_eq!(chart, "-1 | ### 3\n 1 | #   1\n");
+
+    let vertical = Chart::new()
+        .frequencies("a", &exact)
+        .frequencies("b", &exact)
+        .orientation(Orientation::Vertical)
+        .height(4)
+        .charset(Charset::Ascii)
+        .render();
+    let rows = vertical.lines().collect::<Vec<_>>();
+    assert_eq!(rows[0], "# a   = b");
+    assert_eq!(rows.len(), 1 + 4 + 2);
+    assert!(rows[1].ends_with("#="));
+    assert!(rows[6].ends_with(" 2  3  4  5  6  7  8  9 10 11 12"));
+
+    assert_eq!(Chart::new().render(), "");
+}
+
+#[test]
+fn test_svg_rendering() {
+    use super::svg::SvgChart;
+
+    let first = "2d6".parse::<DiceBag>().unwrap();
+    let second = "1d12".parse::<DiceBag>().unwrap();
+    let svg = SvgChart::new()
+        .title("2d6 <vs> 1d12 & more")
+        .frequencies("2d6", &first.make_exact_distribution().unwrap())
+        .frequencies("1d12", &second.make_exact_distribution().unwrap())
+        .render();
+
+    assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(svg.ends_with("</svg>\n"));
+    // The title is escaped, and each opened element is closed.
+    assert!(svg.contains("<title>2d6 &lt;vs&gt; 1d12 &amp; more</title>"));
+    assert!(!svg.contains("<vs>"));
+    assert_eq!(svg.matches("<text").count(), svg.matches("</text>").count());
+    assert_eq!(svg.matches("<svg").count(), 1);
+    // A bar per possible total of each bag, a line per bag, and a legend entry per bag.
+    assert_eq!(svg.matches("<rect class=\"pmf\"").count(), 11 + 12);
+    assert_eq!(svg.matches("<polyline class=\"cdf\"").count(), 2);
+    assert!(svg.contains(">1d12</text>"));
+    // Each line ends at 100%, at the top of the plot.
+    for line in svg.lines().filter(|l| l.starts_with("<polyline")) {
+        let points = line.split('"').nth(3).unwrap();
+        assert!(points.ends_with(",50.00"), "{}", points);
+    }
+
+    let bars_only = SvgChart::new()
+        .counts(
+            "3d6",
+            &"3d6"
+                .parse::<DiceBag>()
+                .unwrap()
+                .make_count_distribution(1000),
+        )
+        .cdf(false)
+        .render();
+    assert!(!bars_only.contains("<polyline"));
+    assert!(!bars_only.contains("Cumulative"));
+    let lines_only = SvgChart::new()
+        .frequencies("2d6", &first.make_exact_distribution().unwrap())
+        .pmf(false)
+        .render();
+    assert!(!lines_only.contains("class=\"pmf\""));
+    assert_eq!(lines_only.matches("<polyline").count(), 1);
+
+    assert_eq!(SvgChart::new().to_string(), SvgChart::new().render());
+}
+
+#[test]
+fn test_repl_session() {
+    use super::repl::Session;
+
+    let mut session = Session::with_seed(11);
+    assert_eq!(session.eval("dmg = 2d6 + 3").unwrap(), "dmg = 2d6 + 3");
+    assert_eq!(
+        session.eval("crit = dmg + 2d6").unwrap(),
+        "crit = 2d6 + 3 + 2d6"
+    );
+    let shown = session.eval("crit - 1").unwrap();
+    let total = session.history()[0].total();
+    assert!(shown.starts_with(&format!("$1 = {}: ", total)));
+    assert!((6..=26).contains(&total));
+    assert_eq!(session.history()[0].expression(), "2d6 + 3 + 2d6 - 1");
+
+    // Subtracting a name subtracts all of it, and results are numbers.
+    session.eval("-dmg + $1").unwrap();
+    let second = session.history()[1].total();
+    assert!((total - 15..=total - 5).contains(&second));
+    assert_eq!(
+        session.eval("!!").unwrap(),
+        format!("$2 = {}: -2d6 - 3 + {}", second, total)
+    );
+    assert_eq!(
+        session.eval("$1 - $1").unwrap().split(':').next(),
+        Some("$3 = 0")
+    );
+
+    // The same seed rolls the same.
+    let mut again = Session::with_seed(11);
+    again.eval("dmg = 2d6 + 3").unwrap();
+    again.eval("crit = dmg + 2d6").unwrap();
+    again.eval("crit - 1").unwrap();
+    assert_eq!(again.history()[0], session.history()[0]);
+    session.eval(":seed 5").unwrap();
+    again.eval(":seed 5").unwrap();
+    session.eval("1d1000").unwrap();
+    again.eval("1d1000").unwrap();
+    assert_eq!(session.history().last(), again.history().last());
+
+    // Wrong lines change nothing.
+    let wrong = [
+        "bogus + 1",
+        "d6 = 3",
+        "2x = 1d4",
+        "1d6 ++ 2",
+        "$9",
+        "$x",
+        "1d0",
+        ":seed x",
+        ":save",
+        ":frob",
+    ];
+    for line in wrong.iter() {
+        assert!(session.eval(line).is_err(), "{}", line);
+    }
+    assert_eq!(session.history().len(), 4);
+    assert_eq!(session.variables().len(), 2);
+
+    let stats = session.eval(":stats dmg").unwrap();
+    assert!(stats.starts_with("2d6 + 3 (exact)\nmean     10.0000\n"));
+    assert!(stats.ends_with("median   10\nrange    5 to 15"));
+    let dist = session.eval(":dist").unwrap();
+    assert!(dist.starts_with("1d1000 (exact)\n"));
+    assert_eq!(dist.lines().count(), 1001);
+    let stats = session.eval(":stats 1d1000000000").unwrap();
+    assert!(stats.starts_with("1d1000000000 ("), "{}", stats);
+
+    // Saving and loading a session keeps the names and results.
+    let mut saved = Vec::new();
+    session.write_to(&mut saved).unwrap();
+    let mut loaded = Session::with_seed(1);
+    loaded.read_from(&saved[..]).unwrap();
+    assert_eq!(loaded.variables(), session.variables());
+    assert_eq!(loaded.history(), session.history());
+    assert!(loaded.read_from(&b"not a session\n"[..]).is_err());
+    assert!(loaded
+        .read_from(&b"libazdice-session 1\nname\tx\t1d0\n"[..])
+        .is_err());
+    assert_eq!(loaded.history(), session.history());
+
+    // Loaded totals must be ones the dice can roll.
+    for total in ["-9223372036854775808", "0", "7"].iter() {
+        let text = format!("libazdice-session 1\nresult\t1d6\t{}\n", total);
+        assert!(loaded.read_from(text.as_bytes()).is_err(), "{}", total);
+    }
+    let mut rolled = Session::with_seed(1);
+    rolled
+        .read_from(&b"libazdice-session 1\nresult\t1d6! + 2\t40\n"[..])
+        .unwrap();
+    assert_eq!(
+        rolled.eval("$1 + 1").unwrap().split(':').next(),
+        Some("$2 = 41")
+    );
+
+    // Terms are split as the parser splits groups, with brackets kept together.
+    let shown = rolled.eval("-2 + (1d4 + 1)d6 - $1").unwrap();
+    let total = rolled.history()[2].total();
+    assert!((2 - 42..=30 - 42).contains(&total), "{}", shown);
+    assert!(rolled.eval("(1d4 + 1d6").is_err());
+}
+
+#[test]
+fn test_repl_labels_and_comments() {
+    use super::repl::Session;
+
+    let mut session = Session::with_seed(3);
+    let cases = [
+        ("1d20 # vs half-orc", "1d20 # vs half-orc"),
+        ("1d20 # a=b", "1d20 # a=b"),
+        ("1d6 [a-b]", "1d6 [a-b]"),
+        ("1d20+5 [attack]", "1d20 + 5 [attack]"),
+        (
+            "2d6 [Fire] - 1 [cold] # burn",
+            "2d6 [Fire] - 1 [cold] # burn",
+        ),
+    ];
+    for (line, expression) in cases.iter() {
+        session.eval(line).unwrap();
+        assert_eq!(session.history().last().unwrap().expression(), *expression);
+    }
+    assert!(session.variables().is_empty());
+
+    // A label covers the whole run before it, names included.
+    assert_eq!(
+        session.eval("hit = 1d20 + 5 [attack] # sword").unwrap(),
+        "hit = 1d20 + 5 [attack] # sword"
+    );
+    session.eval("hit + 2d6 [fire]").unwrap();
+    assert_eq!(
+        session.history().last().unwrap().expression(),
+        "1d20 + 5 + 2d6 [fire]"
+    );
+    session.eval("1d8 [hit] + hit - $1 + 1d4 [x=y]").unwrap();
+    let last = session.history().last().unwrap().expression().to_owned();
+    assert!(last.starts_with("1d8 [hit] + 1d20 + 5 - "), "{}", last);
+    assert!(last.ends_with(" + 1d4 [x=y]"), "{}", last);
+    assert!(session.eval(":stats").is_ok());
+
+    for line in ["[attack]", "1d6 [a] 1d4", "1d6 [a", "x = 1d6 ]"].iter() {
+        assert!(session.eval(line).is_err(), "{}", line);
+    }
+    assert_eq!(session.variables().len(), 1);
+
+    // Labels and comments cannot break the lines of a saved session.
+    assert!(session.eval("atk = 1d20 + 7 [to\thit]").is_err());
+    let mut saved = Vec::new();
+    session.write_to(&mut saved).unwrap();
+    let mut loaded = Session::with_seed(3);
+    loaded.read_from(&saved[..]).unwrap();
+    assert_eq!(loaded.variables(), session.variables());
+    assert_eq!(loaded.history(), session.history());
+}
+
+#[test]
+fn test_dice_template_variables() {
+    use super::variables::*;
+    use std::collections::HashMap;
+
+    let mut sheet: HashMap<String, i64> = HashMap::new();
+    for (name, value) in [
+        ("level", 4),
+        ("die", 8),
+        ("drop", 1),
+        ("mod", -2),
+        ("zero", 0),
+    ]
+    .iter()
+    {
+        sheet.insert(name.to_string(), *value);
+    }
+
+    // Variables may be counts, sides, modifiers or groups of their own.
+    let cases = [
+        ("@level d6", "@level d6", "4d6"),
+        ("@LEVEL D6", "@LEVEL d6", ""),
+        ("1D@die + @mod", "1d@die + @mod", "1d8 - 2"),
+        ("-@mod - 1d4", "-@mod - 1d4", "2 - 1d4"),
+        ("4d6dl@drop!", "4d6dl@drop!", "4d6dl1!"),
+        (
+            "@{level}d@{die}rr1be@{level}",
+            "@level d@die rr1be@level",
+            "4d8rr1be4",
+        ),
+        (
+            "(1d@die+@drop)d6+@mod",
+            "(1d@die+@drop)d6 + @mod",
+            "(1d8 + 1)d6 - 2",
+        ),
+        // Labels and comments are kept as written.
+        (
+            "1d20+@mod [attack]",
+            "1d20 + @mod [attack]",
+            "1d20 - 2 [attack]",
+        ),
+        (
+            "1d6 [fire]+@level d6 [cold] # vs @mod",
+            "1d6 [fire] + @level d6 [cold] # vs @mod",
+            "1d6 [fire] + 4d6 [cold] # vs @mod",
+        ),
+    ];
+    for (input, shown, resolved) in cases.iter() {
+        let template = DiceTemplate::parse(input).unwrap();
+        assert_eq!(template.to_string(), *shown);
+        assert_eq!(
+            template.to_string().parse::<DiceTemplate>(),
+            Ok(template.clone())
+        );
+        match template.resolve(&sheet) {
+            Ok(bag) => assert_eq!(bag.to_string(), *resolved),
+            // Names are kept as written, so "LEVEL" is not "level".
+            Err(e) => {
+                assert!(resolved.is_empty(), "{}: {}", input, e);
+                assert_eq!(e, "Unknown variable \"@LEVEL\".");
+            }
+        }
+    }
+
+    // Rolling resolves the variables again each time.
+    let template = DiceTemplate::parse("@level d@die").unwrap();
+    let mut rng = seeded_rng(5);
+    let rolled = template.roll_with_rng(&sheet, &mut rng).unwrap();
+    let bag = template.resolve(&sheet).unwrap();
+    assert_eq!(rolled, bag.roll_with_rng(&mut seeded_rng(5)));
+    sheet.insert("level".to_owned(), 1);
+    assert_eq!(
+        template.roll(&sheet).unwrap().get_dice_groups()[0]
+            .results()
+            .len(),
+        1
+    );
+
+    // Values which make invalid dice are errors when resolving, not when parsing.
+    let wrong_values = ["1d@zero", "@mod d6", "1d@mod", "@level d6dl@die"];
+    for input in wrong_values.iter() {
+        let template = DiceTemplate::parse(input).unwrap();
+        assert!(template.resolve(&sheet).is_err(), "{}", input);
+    }
+    let wrong_strings = [
+        "",
+        "1d6 + @",
+        "1d6 ++ @level",
+        "1d6 + @level $",
+        "@{}d6",
+        "1d6 +",
+        "@{level",
+        // Values next to each other would run together, eg into "48d6" or "46".
+        "@level@die d6",
+        "@{level}6",
+        "@level 6",
+        "2@level",
+        "1d6 + 2 @mod",
+        "[attack] 1d20",
+        "1d20 [attack] @mod",
+        "1d20 [to\thit]",
+        "1d20 + @mod # vs\tgoblin",
+    ];
+    for input in wrong_strings.iter() {
+        assert!(DiceTemplate::parse(input).is_err(), "{}", input);
+    }
+    assert!(parse::parse("1d20 + @mod".to_owned()).is_err());
+    assert_eq!(
+        parse::parse_with_context("1d20 + @mod".to_owned(), &sheet),
+        parse::parse("1d20 - 2".to_owned())
+    );
+    assert_eq!(
+        parse::parse_with_context("1d20 + @mod [attack] # c".to_owned(), &sheet),
+        parse::parse("1d20 - 2 [attack] # c".to_owned())
+    );
+}
+
+#[test]
+fn test_nested_dice() {
+    // The count and sides may be dice of their own, rolled first.
+    let cases = [
+        ("(1d4)d6", "(1d4)d6", [1, 24]),
+        ("2d(1D4+2)!", "2d(1d4 + 2)!", [2, i64::MAX]),
+        ("-(1d2+1)d6dl1 + 3", "-(1d2 + 1)d6dl1 + 3", [-9, 2]),
+        ("(1d3)d(1d3) - (2)d4", "(1d3)d(1d3) - (2)d4", [-7, 7]),
+        ("((1d2)d2+2)d6dl1dh1", "((1d2)d2 + 2)d6dl1dh1", [1, 24]),
+    ];
+    for (input, shown, [low, high]) in cases.iter() {
+        let bag = parse::parse(input.to_string()).unwrap();
+        assert_eq!(bag.to_string(), *shown);
+        assert_eq!(bag.to_string().parse::<DiceBag>(), Ok(bag.clone()));
+        assert_eq!(bag.get_range(), [*low, *high], "{}", input);
+
+        // The totals-only roll agrees with the full roll.
+        let mut rng_1 = seeded_rng(11);
+        let mut rng_2 = seeded_rng(11);
+        let mut buffer = RollBuffer::new();
+        for _ in 0..1_000 {
+            let roll = bag.roll_with_rng(&mut rng_1);
+            assert_eq!(
+                roll.total(),
+                bag.roll_total_with_rng(&mut rng_2, &mut buffer)
+            );
+            assert!((*low..=*high).contains(&roll.total()));
+        }
+    }
+
+    // The rolls of the count and sides are kept with the results.
+    let bag = parse::parse("(1d4)d6 + 2d(1d4+4)".to_owned()).unwrap();
+    for _ in 0..1_000 {
+        let roll = bag.roll();
+        let groups = roll.get_dice_groups();
+        let count = groups[0].count_roll().unwrap().total();
+        assert_eq!(groups[0].results().len() as i64, count);
+        assert!(groups[0].size_roll().is_none());
+        let size = groups[1].size_roll().unwrap().total();
+        assert!((5..=8).contains(&size));
+        assert!(groups[1].results().iter().all(|r| (1..=size).contains(r)));
+    }
+    let roll = parse::parse("(1d1)d(1d1+1)mx1".to_owned()).unwrap().roll();
+    assert_eq!(
+        roll.to_string(),
+        "(1d1( 1 = 1 ) = 1)d(1d1( 1 = 1 ) + 1 = 2)mx1( 1 = 1 ) (Total = 1 )"
+    );
+
+    // The exact distribution weighs each count by its chance.
+    let bag = parse::parse("(1d2)d2".to_owned()).unwrap();
+    let exact = bag.make_exact_distribution().unwrap();
+    let expected = [(1, 25.0), (2, 37.5), (3, 25.0), (4, 12.5)];
+    assert_eq!(exact.len(), expected.len());
+    for (total, p) in expected.iter() {
+        assert!((exact[total] - p).abs() < 1e-9, "{}", total);
+    }
+    let bag = parse::parse("(1d3+1)d(1d4+2)dl1 - 2".to_owned()).unwrap();
+    let exact = bag.make_exact_distribution().unwrap();
+    let sampled = bag.make_frequency_distribution(200_000);
+    for (total, p) in exact.iter() {
+        let q = sampled.get(total).copied().unwrap_or(0.0);
+        assert!((p - q).abs() < 0.5, "{}: {} vs {}", total, p, q);
+    }
+
+    // Brackets which only hold boni are the same as no brackets.
+    let bag = |s: &str| -> DiceBag { parse::parse(s.to_owned()).unwrap() };
+    assert!(bag("(1d1+2)d6").is_equivalent(&bag("3d6")));
+    assert!(bag("2d(1d2)").is_equivalent(&bag("2d(1d2+0)")));
+    assert!(!bag("(1d2)d6").is_equivalent(&bag("1d6")));
+    assert_eq!(
+        bag("(1d4+1)d6dl1").explain()[0],
+        "(1d4 + 1)d6dl1: roll 1d4 + 1 for the number of dice, then roll that many dice with 6 \
+         sides, drop the lowest die, add the total (1 to 24)"
+    );
+
+    // Signs in brackets stay with the dice in sessions too.
+    let mut session = super::repl::Session::with_seed(3);
+    assert_eq!(session.eval("n = (1d4 + 1)d6").unwrap(), "n = (1d4 + 1)d6");
+    session.eval("n - 2").unwrap();
+    assert_eq!(session.history()[0].expression(), "(1d4 + 1)d6 - 2");
+
+    let wrong = [
+        "(1d4-2)d6",
+        "2d(1d4-1)",
+        "(1d4)d6dl2",
+        "(1d4!)d6",
+        "2d(1d4!)",
+        "(1d4)",
+        "(1d4)2d6",
+        "1d6(2)",
+        "(1d4d6",
+        "1d4)d6",
+        "(1d4)(1d6)",
+        "(1000d6)d6",
+        "((((1d2)d2)d2)d2)d2",
+        "2d(1d4)1",
+        "d(20)1",
+        "1d(1d4+1)5",
+    ];
+    for input in wrong.iter() {
+        assert!(parse::parse(input.to_string()).is_err(), "{}", input);
+    }
+}
+
+#[test]
+fn test_labels_and_comments() {
+    let input = "1d20+5 [attack] + 2d6+3 [slashing] # vs goblin";
+    let bag = parse::parse(input.to_owned()).unwrap();
+    assert_eq!(
+        bag.to_string(),
+        "1d20 + 5 [attack] + 2d6 + 3 [slashing] # vs goblin"
+    );
+    assert_eq!(bag.to_string().parse::<DiceBag>(), Ok(bag.clone()));
+    assert_eq!(bag.comment(), Some("vs goblin"));
+    let labels: Vec<_> = bag.groups().iter().map(DiceGroup::label).collect();
+    assert_eq!(
+        labels,
+        [
+            Some("attack"),
+            Some("attack"),
+            Some("slashing"),
+            Some("slashing")
+        ]
+    );
+    // Labels and comments do not change the totals.
+    assert!(bag.is_equivalent(&parse::parse("1d20 + 2d6 + 8".to_owned()).unwrap()));
+    assert_eq!(bag.normalize().to_string(), "1d20 + 2d6 + 8");
+
+    // A label belongs to every group since the last label, keeping its case and inner spaces.
+    let cases = [
+        ("1D6 [ Cold Iron ]", "1d6 [Cold Iron]"),
+        ("1d6 [fire] + 1d4", "1d6 [fire] + 1d4"),
+        ("1d6 [fire] + 1d4 [fire]", "1d6 + 1d4 [fire]"),
+        ("-(1d4)d6 [psychic] - 2 [#2]", "-(1d4)d6 [psychic] - 2 [#2]"),
+        ("1d6 #", "1d6"),
+        ("1d6 [a] #  [b] # c ", "1d6 [a] # [b] # c"),
+    ];
+    for (input, shown) in cases.iter() {
+        let bag = parse::parse(input.to_string()).unwrap();
+        assert_eq!(bag.to_string(), *shown);
+        assert_eq!(bag.to_string().parse::<DiceBag>(), Ok(bag));
+    }
+
+    // Rolls show the labels of dice with their results, and labelled boni one by one.
+    let bag = parse::parse("2d1+3 [slashing] + 1d1 [fire] - 2".to_owned()).unwrap();
+    let roll = bag.roll();
+    assert_eq!(
+        roll.to_string(),
+        "2d1( 1 + 1 = 2 ) [slashing] + 1d1( 1 = 1 ) [fire] - 2 + 3 [slashing] (Total = 4 )"
+    );
+    let labels: Vec<_> = roll.get_dice_groups().iter().map(|g| g.label()).collect();
+    assert_eq!(labels, [Some("slashing"), Some("fire")]);
+    assert_eq!(bag.explain()[1], "+3 [slashing]: add 3");
+
+    let built = DiceBag::builder()
+        .dice(2, 1)
+        .label("slashing")
+        .plus(3)
+        .label("slashing")
+        .dice(1, 1)
+        .label("fire")
+        .minus(2)
+        .build()
+        .unwrap();
+    assert_eq!(built, bag);
+
+    let wrong = [
+        "[fire] 1d6",
+        "1d6 [fire] 1d4",
+        "1d6 [fire] [cold]",
+        "1d6 []",
+        "1d6 [fire",
+        "1d6 fire]",
+        "1d6 [a[b]]",
+        "(1d4 [a])d6",
+        "# 1d6",
+        "1d20 + 7 [to\thit]",
+        "1d6 [fi\nre]",
+        "1d6 # vs\tgoblin",
+    ];
+    for input in wrong.iter() {
+        assert!(parse::parse(input.to_string()).is_err(), "{}", input);
+    }
+    // Labels are shown as written, and control characters so they can be seen.
+    assert!(parse::parse("1d6 [ ]".to_owned())
+        .unwrap_err()
+        .starts_with("Label [ ] "));
+    assert!(parse::parse("1d6 [a\tb]".to_owned())
+        .unwrap_err()
+        .starts_with("Label [a\\tb] "));
+    assert!(DiceBag::builder()
+        .dice(1, 6)
+        .label("to\thit")
+        .build()
+        .is_err());
+    assert!(DiceBag::builder().label("fire").dice(1, 6).build().is_err());
+    assert!(DiceBag::builder()
+        .dice(1, 6)
+        .label(" fire")
+        .build()
+        .is_err());
+}
+
+#[test]
+fn test_labelled_totals_and_rules() {
+    use super::labels::{Rule, RuleSet};
+
+    // Totals follow the first appearance of each label among the dice and then the boni, with
+    // unlabelled groups under `None`.
+    let bag =
+        parse::parse("2d1+3 [slashing] + 1d1 - 2 + 3d1 [fire] - 1 [slashing] + 1d1".to_owned())
+            .unwrap();
+    let roll = bag.roll();
+    assert_eq!(
+        roll.totals_by_label(),
+        [(Some("slashing"), 4), (Some("fire"), 2), (None, 1)]
+    );
+    let sum: i64 = roll.totals_by_label().iter().map(|(_, t)| t).sum();
+    assert_eq!(sum, roll.total());
+    let boni = parse::parse("1d1 [acid] - 1 + 2 [cold] - 5".to_owned()).unwrap();
+    assert_eq!(
+        boni.roll().totals_by_label(),
+        [(Some("acid"), 1), (None, -5), (Some("cold"), 1)]
+    );
+
+    let split = bag.split_by_label();
+    let labels: Vec<_> = split.iter().map(|(l, _)| *l).collect();
+    assert_eq!(labels, [Some("slashing"), Some("fire"), None]);
+    assert_eq!(split[0].1.to_string(), "2d1 + 3 - 1 [slashing]");
+    assert_eq!(split[1].1.to_string(), "1d1 - 2 + 3d1 [fire]");
+    assert_eq!(split[2].1.to_string(), "1d1");
+    assert!(split.iter().all(|(_, b)| b.limits == bag.limits));
+
+    // Rules round halves down, never reduce below 0 and leave what they do not name.
+    assert_eq!(Rule::Halve.apply(7), 3);
+    assert_eq!(Rule::Halve.apply(-3), -2);
+    assert_eq!(Rule::Reduce(5).apply(3), 0);
+    assert_eq!(Rule::Reduce(5).apply(-3), -3);
+    assert_eq!(Rule::Double.apply(4), 8);
+    let rules = RuleSet::new()
+        .halve("Slashing")
+        .double("fire")
+        .ignore("fire");
+    assert_eq!(rules.to_string(), "halve slashing, ignore fire");
+    assert_eq!(
+        rules.apply(&roll),
+        [(Some("slashing"), 2), (Some("fire"), 0), (None, 1)]
+    );
+    assert_eq!(rules.total(&roll), 3);
+    assert_eq!(RuleSet::new().total(&roll), roll.total());
+    assert_eq!(" , ".parse::<RuleSet>(), Ok(RuleSet::new()));
+    assert!("reduce fire".parse::<RuleSet>().is_err());
+    assert!("reduce fire by x".parse::<RuleSet>().is_err());
+    // A negative amount would add to the total rather than take from it.
+    assert!("reduce fire by -3".parse::<RuleSet>().is_err());
+    assert_eq!(Rule::Reduce(u64::MAX).apply(i64::MAX), 0);
+
+    // Labels are grouped as rules match them, whatever their case.
+    let mixed = parse::parse("1d1 [Fire] + 1d1 [fire] + 2 [FIRE]".to_owned()).unwrap();
+    let roll = mixed.roll();
+    assert_eq!(roll.totals_by_label(), [(Some("Fire"), 4)]);
+    let halved = "halve fire".parse::<RuleSet>().unwrap();
+    assert_eq!(halved.total(&roll), 2);
+    assert_eq!(mixed.split_by_label().len(), 1);
+    let ruled = mixed.make_exact_distribution_with_rules(&halved).unwrap();
+    assert_eq!(ruled.keys().collect::<Vec<_>>(), [&2]);
+    let two = parse::parse("1d1 [Fire] + 1d1 [fire]".to_owned()).unwrap();
+    assert_eq!(halved.total(&two.roll()), 1);
+
+    // The exact distribution applies the rules to each label before adding them up.
+    let bag = parse::parse("1d6 [fire] + 1d4 + 1 [cold]".to_owned()).unwrap();
+    let plain = bag
+        .make_exact_distribution_with_rules(&RuleSet::new())
+        .unwrap();
+    assert_eq!(plain, bag.make_exact_distribution().unwrap());
+    let rules = RuleSet::new().halve("fire").reduce("cold", 3);
+    let ruled = bag.make_exact_distribution_with_rules(&rules).unwrap();
+    assert_eq!(ruled.keys().next(), Some(&0));
+    assert_eq!(ruled.keys().last(), Some(&5));
+    // 1d4 + 1 - 3 is 0 on a 1 or a 2, and half of 1d6 is 0 on a 1.
+    assert!((ruled[&0] - 100.0 / 2.0 / 6.0).abs() < 1e-9);
+    let total: f64 = ruled.values().sum();
+    assert!((total - 100.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_system_presets() {
+    use super::systems::*;
+    use std::collections::BTreeMap;
+
+    // Rolled outcomes should turn up about as often as the exact tables say.
+    fn check<S: System>(system: &S, seed: u64) {
+        let table = system.outcome_table().unwrap();
+        let total: f64 = table.values().sum();
+        assert!((total - 100.0).abs() < 1e-9, "{}", total);
+
+        let rolls = 20_000;
+        let mut rng = seeded_rng(seed);
+        let mut counts: BTreeMap<S::Outcome, usize> = BTreeMap::new();
+        for _ in 0..rolls {
+            let (roll, outcome) = system.roll_with_rng(&mut rng);
+            assert_eq!(system.outcome(&roll), outcome);
+            *counts.entry(outcome).or_insert(0) += 1;
+        }
+        for (outcome, count) in counts.iter() {
+            let expected = table.get(outcome).copied().unwrap_or(0.0);
+            let found = 100.0 * *count as f64 / rolls as f64;
+            assert!((found - expected).abs() < 1.5, "{}: {}", outcome, found);
+        }
+    }
+
+    check(&Dnd5e::check(3, 15), 1);
+    check(&Dnd5e::attack(7, 18).disadvantage(), 2);
+    check(&Dnd5e::attack(-1, 30).advantage().crit_on(19), 3);
+    check(&Pbta::new(-1), 4);
+    for dice in 0..5 {
+        check(&Blades::new(dice), 5 + u64::from(dice));
+    }
+    check(&Fate::new(2).against(3), 10);
+
+    // An attack which cannot hit otherwise still hits critically, and checks never crit.
+    let table = Dnd5e::attack(0, 40).crit_on(19).outcome_table().unwrap();
+    assert_eq!(
+        table.keys().copied().collect::<Vec<_>>(),
+        [
+            Dnd5eOutcome::CriticalMiss,
+            Dnd5eOutcome::Failure,
+            Dnd5eOutcome::CriticalHit
+        ]
+    );
+    assert!((table[&Dnd5eOutcome::CriticalHit] - 10.0).abs() < 1e-9);
+    let table = Dnd5e::check(0, 1).disadvantage().outcome_table().unwrap();
+    assert_eq!(table.keys().collect::<Vec<_>>(), [&Dnd5eOutcome::Success]);
+    assert_eq!(
+        Dnd5e::check(-2, 10).disadvantage().bag().to_string(),
+        "2d20dh1 - 2"
+    );
+
+    assert_eq!(Pbta::new(0).bag().to_string(), "2d6");
+    assert_eq!(PbtaOutcome::WeakHit.to_string(), "weak hit (7-9)");
+    let table = Pbta::new(0).outcome_table().unwrap();
+    assert!((table[&PbtaOutcome::Miss] - 1500.0 / 36.0).abs() < 1e-9);
+
+    assert_eq!(Blades::new(1).bag().to_string(), "1d6");
+    assert_eq!(Blades::new(4).bag().to_string(), "4d6");
+    let table = Blades::new(1).outcome_table().unwrap();
+    assert!((table[&BladesOutcome::Partial] - 100.0 / 3.0).abs() < 1e-9);
+    assert!(!table.contains_key(&BladesOutcome::Critical));
+
+    assert_eq!(Fate::ladder(-5), "Terrible");
+    assert_eq!(Fate::ladder(0), "Mediocre");
+    assert_eq!(Fate::ladder(9), "Legendary");
+    let table = Fate::new(0).outcome_table().unwrap();
+    // Succeeding with style from 0 needs +3 or +4 on the dice: 4 + 1 of the 81 ways.
+    assert!((table[&FateOutcome::SuccessWithStyle] - 500.0 / 81.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_max_and_min_of_bags() {
+    use super::systems::{SavageOutcome, SavageWorlds, System};
+
+    let cases = [
+        ("max(1d8!,1d6!)+2", "max(1d8!, 1d6!) + 2", [Some(3), None]),
+        (
+            "MIN( 1d20 , 1d12+4 )",
+            "min(1d20, 1d12 + 4)",
+            [Some(1), Some(16)],
+        ),
+        (
+            "-max(1d4, 2d6dl1, 3)",
+            "-max(1d4, 2d6dl1, 3)",
+            [Some(-6), Some(-3)],
+        ),
+        ("min(1d6!, -1d4!)", "min(1d6!, -1d4!)", [None, Some(-1)]),
+        (
+            "(max(1d2, 1d3))d6",
+            "(max(1d2, 1d3))d6",
+            [Some(1), Some(18)],
+        ),
+        (
+            "max((1d2)d4, min(1d6, 1d8))",
+            "max((1d2)d4, min(1d6, 1d8))",
+            [Some(1), Some(8)],
+        ),
+        (
+            "max(1d6) [fire] # one bag",
+            "max(1d6) [fire] # one bag",
+            [Some(1), Some(6)],
+        ),
+    ];
+    for (input, shown, bounds) in cases.iter() {
+        let bag = parse::parse(input.to_string()).unwrap();
+        assert_eq!(bag.to_string(), *shown);
+        assert_eq!(bag.to_string().parse::<DiceBag>(), Ok(bag.clone()));
+        assert_eq!(bag.calculate_bounds(true), *bounds, "{}", input);
+    }
+
+    // The highest of two bags is distributed as keeping the highest of the same dice.
+    let pairs = [
+        ("max(1d6, 1d6)", "2d6kh1"),
+        ("min(1d6, 1d6)", "2d6kl1"),
+        ("max(1d8, 1d8, 1d8) - 1", "3d8kh1 - 1"),
+        ("-min(1d4, 1d4)", "-2d4kl1"),
+    ];
+    for (pick, kept) in pairs.iter() {
+        let pick = parse::parse(pick.to_string()).unwrap();
+        let kept = parse::parse(kept.to_string()).unwrap();
+        let pick = pick.make_exact_distribution().unwrap();
+        let kept = kept.make_exact_distribution().unwrap();
+        assert_eq!(
+            pick.keys().collect::<Vec<_>>(),
+            kept.keys().collect::<Vec<_>>()
+        );
+        for (p, k) in pick.values().zip(kept.values()) {
+            assert!((p - k).abs() < 1e-9);
+        }
+    }
+    let bag = parse::parse("max(1d4!, 1d6) + min(1d4, 1d3)".to_owned()).unwrap();
+    let exact = bag.make_exact_distribution().unwrap();
+    let sampled = bag.make_frequency_distribution(100_000);
+    for (total, p) in exact.iter().filter(|(_, p)| **p > 0.5) {
+        assert!(
+            (sampled[total] - p).abs() < 1.0,
+            "{}: {}",
+            total,
+            sampled[total]
+        );
+    }
+
+    // Rolls show each bag with its total, and keep one result.
+    let bag = parse::parse("max(2d1+1, 1d1+4) - min(1d1, 5)".to_owned()).unwrap();
+    assert_eq!(
+        bag.roll().to_string(),
+        "max(2d1( 1 + 1 = 2 ) + 1 = 3, 1d1( 1 = 1 ) + 4 = 5)( 5 = 5 ) \
+         - min(1d1( 1 = 1 ) = 1, 5)( 1 = -1 ) (Total = 4 )"
+    );
+    let bag = parse::parse("max(2d4+1, 1d6!) - min(1d8, 5)".to_owned()).unwrap();
+    let mut rng_1 = seeded_rng(48);
+    let mut rng_2 = seeded_rng(48);
+    let mut buffer = RollBuffer::new();
+    for _ in 0..1_000 {
+        let total = bag.roll_total_with_rng(&mut rng_1, &mut buffer);
+        assert_eq!(total, bag.roll_with_rng(&mut rng_2).total());
+    }
+
+    assert_eq!(
+        parse::parse("max(1d6, 2d4 + 2) - 1".to_owned()).unwrap().explain()[0],
+        "max(1d6, 2d4 + 2): roll 1d6 and 2d4 + 2 and keep the highest total, add the total (4 to 10)"
+    );
+    assert_eq!(
+        parse::parse("max(2d6 + 3d6, 1d4 - 1d4 + 2)".to_owned())
+            .unwrap()
+            .normalize()
+            .to_string(),
+        "max(5d6, 1d4 - 1d4 + 2)"
+    );
+
+    let wrong = [
+        "max()",
+        "max(1d6,)",
+        "max(1d6, 1d4",
+        "max(1d6, 1d4)dl1",
+        "max(1d6, 1d4)!",
+        "2max(1d6)",
+        "max(1d6 [fire], 1d4)",
+        "1d6, 1d4",
+        "mix(1d6, 1d4)",
+        "max(1d6, 1d1!)",
+        "max(2d1+1, 1d1!) - min(1d1, 2)",
+    ];
+    for input in wrong.iter() {
+        assert!(parse::parse(input.to_string()).is_err(), "{}", input);
+    }
+    let mut clause = Dice::max_of(vec!["1d6".parse().unwrap(), "1d4".parse().unwrap()]);
+    clause.with_drop_lowest(1).unwrap();
+    assert_eq!(clause.validate(), Err(DiceError::ClauseOnPick));
+    assert_eq!(
+        Dice::min_of(Vec::new()).validate(),
+        Err(DiceError::NoGroups)
+    );
+
+    // Savage Worlds rolls count raises on the higher of the trait and wild dice.
+    let parry = SavageWorlds::trait_roll(10, 0).target(6);
+    let table = parry.outcome_table().unwrap();
+    let total: f64 = table.values().sum();
+    assert!((total - 100.0).abs() < 1e-6);
+    assert!((table[&SavageOutcome::CriticalFailure] - 100.0 / 60.0).abs() < 1e-9);
+    // Missing needs at most 5 on both dice.
+    let missing = table[&SavageOutcome::CriticalFailure] + table[&SavageOutcome::Failure];
+    assert!((missing - 2500.0 / 60.0).abs() < 1e-9);
+    // A raise needs 10 or more: the trait die acing, or the wild die acing and then rolling 4+.
+    let raises: f64 = table
+        .iter()
+        .filter(|(o, _)| **o >= SavageOutcome::Success(1))
+        .map(|(_, p)| p)
+        .sum();
+    let miss_raise = (9.0 / 10.0) * (1.0 - 1.0 / 6.0 * (3.0 / 6.0));
+    assert!(
+        (raises - 100.0 * (1.0 - miss_raise)).abs() < 1e-6,
+        "{}",
+        raises
+    );
+    let mut rng = seeded_rng(49);
+    for _ in 0..1_000 {
+        let (roll, outcome) = parry.roll_with_rng(&mut rng);
+        let group = &roll.get_dice_groups()[0];
+        let both_ones = group.pick_rolls().iter().all(|r| r.total() == 1);
+        assert_eq!(both_ones, outcome == SavageOutcome::CriticalFailure);
+        assert_eq!(
+            SavageWorlds::raises(roll.total(), 6).map(SavageOutcome::Success),
+            Some(outcome).filter(|o| *o > SavageOutcome::Failure)
+        );
+    }
+    let extra = SavageWorlds::trait_roll(4, 2)
+        .extra()
+        .outcome_table()
+        .unwrap();
+    assert!(!extra.contains_key(&SavageOutcome::CriticalFailure));
+    assert!((extra[&SavageOutcome::Failure] - 25.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_symbolic_dice() {
+    use super::symbols::*;
+    use std::collections::BTreeMap;
+
+    // The exact distribution of a Genesys pool should match counting every way the dice fall,
+    // cancelling by hand.
+    let genesys = SymbolSet::genesys();
+    let names = ["ability", "difficulty", "proficiency", "challenge", "boost"];
+    let dice: Vec<&SymbolDie> = names.iter().map(|n| genesys.get(n).unwrap()).collect();
+    let mut counted: BTreeMap<String, f64> = BTreeMap::new();
+    let mut ways = 0_u64;
+    let mut faces = vec![0_usize; dice.len()];
+    'outer: loop {
+        let mut count = BTreeMap::new();
+        for (die, face) in dice.iter().zip(faces.iter()) {
+            for symbol in die.faces()[*face].iter() {
+                *count.entry(symbol.as_str()).or_insert(0_i64) += 1;
+            }
+        }
+        let get = |s: &str| count.get(s).cloned().unwrap_or(0);
+        let successes = get("success") + get("triumph") - get("failure") - get("despair");
+        let advantages = get("advantage") - get("threat");
+        let mut shown = Vec::new();
+        if advantages > 0 {
+            shown.push(format!("{} advantage", advantages));
+        }
+        if get("despair") > 0 {
+            shown.push(format!("{} despair", get("despair")));
+        }
+        if successes < 0 {
+            shown.push(format!("{} failure", -successes));
+        }
+        if successes > 0 {
+            shown.push(format!("{} success", successes));
+        }
+        if advantages < 0 {
+            shown.push(format!("{} threat", -advantages));
+        }
+        if get("triumph") > 0 {
+            shown.push(format!("{} triumph", get("triumph")));
+        }
+        let shown = if shown.is_empty() {
+            "nothing".to_owned()
+        } else {
+            shown.join(", ")
+        };
+        *counted.entry(shown).or_insert(0.0) += 1.0;
+        ways += 1;
+        for i in 0..faces.len() {
+            faces[i] += 1;
+            if faces[i] < dice[i].faces().len() {
+                continue 'outer;
+            }
+            faces[i] = 0;
+        }
+        break;
+    }
+    let pool = genesys
+        .pool("ability + difficulty + proficiency + challenge + boost")
+        .unwrap();
+    let exact = pool.distribution().unwrap();
+    assert_eq!(exact.len(), counted.len());
+    for (tally, p) in exact.iter() {
+        let expected = counted[&tally.to_string()] * 100.0 / ways as f64;
+        assert!((p - expected).abs() < 1e-9, "{}: {} {}", tally, p, expected);
+    }
+
+    // Rolls should net out the same way, and turn up about as often as the exact chances.
+    let mut rng = seeded_rng(49);
+    let mut rolled: BTreeMap<Tally, f64> = BTreeMap::new();
+    let rolls = 100_000;
+    for _ in 0..rolls {
+        let roll = pool.roll_with_rng(&mut rng);
+        assert_eq!(roll.faces().len(), 5);
+        assert!(exact.contains_key(roll.net()));
+        *rolled.entry(roll.net().clone()).or_insert(0.0) += 100.0 / rolls as f64;
+    }
+    for (tally, p) in exact.iter() {
+        let seen = rolled.get(tally).cloned().unwrap_or(0.0);
+        assert!((seen - p).abs() < 0.5, "{}: {} {}", tally, seen, p);
+    }
+
+    // A roll shows each face and the net symbols.
+    let set: SymbolSet = "
+        sure: success advantage
+        blank: -
+        foil: failure, failure
+        success cancels failure
+    "
+    .parse()
+    .unwrap();
+    let roll = set.pool("2 sure + blank + 3 foil").unwrap().roll();
+    assert_eq!(
+        roll.to_string(),
+        "sure( success advantage ) sure( success advantage ) blank( - ) foil( failure ) \
+         foil( failure ) foil( failure ) (Net = 2 advantage, 1 failure )"
+    );
+    assert_eq!(roll.net().get("Failure"), 1);
+    assert_eq!(roll.net().get("success"), 0);
+    let pool = set.pool("sure + foil").unwrap();
+    assert_eq!(pool.to_string(), "1 sure + 1 foil");
+    assert_eq!(pool.distribution().unwrap().len(), 1);
+    assert_eq!(pool.chance(|net| net.get("advantage") == 1), Ok(100.0));
+    assert!(set.pool("").unwrap_err().contains("needs the name"));
+
+    // The text of a set round-trips, and sets that make no sense are refused.
+    assert_eq!(
+        genesys.to_string().parse::<SymbolSet>(),
+        Ok(genesys.clone())
+    );
+    let wrong = [
+        "a: x\nx cancels x",
+        "a: x, y, z\nx cancels y\nz cancels x",
+        "a: x\nx cancels",
+        "a: x\nx counts y",
+        ": x",
+        "a: ,",
+    ];
+    for text in wrong.iter() {
+        assert!(text.parse::<SymbolSet>().is_err(), "{}", text);
+    }
+    assert!(genesys.pool("2 stunt").is_err());
+    assert!(genesys.pool("1001 boost").is_err());
+    assert!(SymbolSet::new().die("empty", &[]).pool("empty").is_err());
+}
+
+#[test]
+fn test_random_tables() {
+    use super::tables::*;
+    use std::collections::BTreeMap;
+
+    let tables: Tables = "
+        # Encounters in the woods.
+        table Woods: 1d6 + 1d4
+        2-4: [Beasts]
+        5: nothing
+        6–8: {1d4} bandits
+        9-10: [beasts] and [beasts]
+
+        table beasts: 1d3
+        1-2: wolves
+        3: a bear
+    "
+    .parse()
+    .unwrap();
+    assert_eq!(tables.tables().len(), 2);
+    assert_eq!(tables.get("WOODS").unwrap().entries()[2].low(), 6);
+    assert_eq!(tables.to_string().parse::<Tables>(), Ok(tables.clone()));
+
+    // 1d6 + 1d4 gives 2-4 in 6 of 24 ways, 5 in 4, 6-8 in 11 and 9-10 in 3.
+    let chances = tables.probabilities("woods").unwrap();
+    let expected = [
+        ("wolves", 6.0 / 24.0 * 2.0 / 3.0),
+        ("a bear", 6.0 / 24.0 / 3.0),
+        ("nothing", 4.0 / 24.0),
+        ("{1d4} bandits", 11.0 / 24.0),
+        ("wolves and wolves", 3.0 / 24.0 * 4.0 / 9.0),
+        ("wolves and a bear", 3.0 / 24.0 * 2.0 / 9.0),
+        ("a bear and wolves", 3.0 / 24.0 * 2.0 / 9.0),
+        ("a bear and a bear", 3.0 / 24.0 / 9.0),
+    ];
+    assert_eq!(chances.len(), expected.len());
+    for (text, p) in expected.iter() {
+        assert!((chances[*text] - p * 100.0).abs() < 1e-9, "{}", text);
+    }
+
+    // Rolls should give each final entry about as often as its chance.
+    let mut rng = seeded_rng(50);
+    let mut rolled: BTreeMap<String, f64> = BTreeMap::new();
+    let rolls = 50_000;
+    for _ in 0..rolls {
+        let roll = tables.roll_with_rng("woods", &mut rng).unwrap();
+        let (table, total) = &roll.rolls()[0];
+        assert_eq!(table, "woods");
+        assert!((2..=10).contains(total));
+        let text = match roll.text().strip_suffix(" bandits") {
+            Some(count) => {
+                assert!(("1"..="4").contains(&count), "{}", roll);
+                "{1d4} bandits".to_owned()
+            }
+            None => roll.to_string(),
+        };
+        assert_eq!(
+            roll.rolls().len(),
+            1 + text.matches("wolves").count() + text.matches("bear").count()
+        );
+        *rolled.entry(text).or_insert(0.0) += 100.0 / rolls as f64;
+    }
+    for (text, p) in chances.iter() {
+        assert!(
+            (rolled[text] - p).abs() < 0.6,
+            "{}: {} {}",
+            text,
+            rolled[text],
+            p
+        );
+    }
+
+    // Tables which go too deep fail, both to roll and to work out.
+    let chain: Tables = "table a: 1d1\n1: [b]\ntable b: 1d1\n1: [c]\ntable c: 1d1\n1: end"
+        .parse()
+        .unwrap();
+    assert_eq!(chain.probabilities("a").unwrap()["end"], 100.0);
+    let shallow = chain.clone().max_depth(2);
+    assert!(shallow.roll("a").is_err());
+    assert!(shallow.probabilities("a").is_err());
+    assert_eq!(shallow.roll("b").unwrap().text(), "end");
+    let forever: Tables = "table loop: 1d2\n1: end\n2: again [loop]".parse().unwrap();
+    assert!(forever
+        .probabilities("loop")
+        .unwrap_err()
+        .contains("10 tables deep"));
+    assert!(forever.roll("nowhere").is_err());
+
+    // Exploding dice past the last entry give the last entry.
+    let open: Tables = "table open: 1d2!\n1: low\n2: high".parse().unwrap();
+    let mut rng = seeded_rng(51);
+    for _ in 0..100 {
+        let roll = open.roll_with_rng("open", &mut rng).unwrap();
+        let expected = if roll.rolls()[0].1 == 1 {
+            "low"
+        } else {
+            "high"
+        };
+        assert_eq!(roll.text(), expected);
+    }
+    let chances = open.probabilities("open").unwrap();
+    assert!((chances["low"] - 50.0).abs() < 1e-6 && (chances["high"] - 50.0).abs() < 1e-6);
+
+    // Single totals and negative ranges can be read.
+    let signed: Tables = "table s: 1d3 - 2\n-1--1: below\n0: zero\n1: above"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        signed.to_string(),
+        "table s: 1d3 - 2\n-1: below\n0: zero\n1: above\n"
+    );
+
+    let wrong = [
+        "table a: 1d6\n1-3: low\n5-6: high",
+        "table a: 1d6\n1-3: low\n3-6: high",
+        "table a: 1d6\n1-5: low",
+        "table a: 1d6\n0-6: low\n7-6: never",
+        "table a: 1d6\n1-6: [b]",
+        "table a: 1d6\n1-6: {1d} coins",
+        "table a: 1d6\n1-6: {1d4 coins",
+        "table a: 1d6\n1-6: a [gem",
+        "1-6: low\ntable a: 1d6",
+        "table a: 1d6\n1-6 low",
+        "table a: 1d6\none-six: low",
+        "table a: 1d6",
+        "table a: 1e6\n1-6: low",
+    ];
+    for text in wrong.iter() {
+        assert!(text.parse::<Tables>().is_err(), "{}", text);
+    }
+
+    // Tables built by hand are checked as they are made, apart from the tables they name.
+    let one = Table::new("a", super::parse("1d1".to_owned()).unwrap());
+    assert!(one.clone().entry(1, 1, "{1d0} coins").is_err());
+    assert!(Tables::new().table(one.clone()).is_err());
+    let named = Tables::new()
+        .table(one.entry(1, 1, "a [b]").unwrap())
+        .unwrap();
+    assert!(named.validate().is_err());
+    assert_eq!(
+        named.roll("a").unwrap_err(),
+        "There is no table called \"b\"."
+    );
+}
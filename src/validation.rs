@@ -0,0 +1,479 @@
+//! This module contains the checks shared by the parser, `DiceBagBuilder` and the `Dice::with_*`
+//! functions, so that a `DiceBag` is valid if, and only if, it could have been parsed. It also
+//! contains the `Limits` which keep untrusted input from asking for absurd amounts of dice.
+use super::distribution::*;
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+/// Limits on the size of a `DiceBag`, checked whenever a bag is parsed or built. A bag within its
+/// limits can be rolled without huge allocations and without its total overflowing an `i64`.
+/// ```
+/// use libazdice::validation::{DiceError, Limits};
+///
+/// let limits = Limits {
+///     max_dice_per_group: 20,
+///     ..Limits::default()
+/// };
+/// assert!(libazdice::parse::parse_with_limits("20d6".to_owned(), &limits).is_ok());
+/// assert!(libazdice::parse::parse_with_limits("21d6".to_owned(), &limits).is_err());
+///
+/// // The defaults stop the worst of what can be typed into a chat.
+/// assert!(libazdice::parse("999999999d999999999".to_owned()).is_err());
+/// ```
+pub struct Limits {
+    /// The most dice a single group may have (the `8` in "8d6").
+    pub max_dice_per_group: usize,
+    /// The most sides a dice may have (the `6` in "8d6").
+    pub max_sides: i64,
+    /// The most groups (dice and boni) a bag may have.
+    pub max_groups: usize,
+    /// The most times a single exploding die may explode in one roll. Further explosions are
+    /// not rolled. NB: The range of a bag ignores this, so exploding bags are still unbounded.
+    pub max_explosion_depth: usize,
+    /// The most dice which one roll of the bag may roll before any explosions, counting rerolls.
+    pub max_total_rolls: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_dice_per_group: 1_000,
+            max_sides: 1_000_000_000,
+            max_groups: 100,
+            max_explosion_depth: 100,
+            max_total_rolls: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The reasons a dice group or `DiceBag` can be invalid.
+pub enum DiceError {
+    /// A `DiceBag` has no groups at all.
+    NoGroups,
+    /// A die has fewer than one side.
+    InvalidSize(i64),
+    /// Dice rolled for the count of a group (eg "(1d4-2)d6") can roll a negative count.
+    NegativeCount(i64),
+    /// A group has no dice (eg "0d6", or "(1d2-1)d6" which can roll a count of 0).
+    NoDice,
+    /// A one-sided die cannot explode, as it would explode forever.
+    ExplodingOneSide,
+    /// Boni are kept positive, with the sign in the operation.
+    NegativeBonus(i64),
+    /// A clause (eg "dl/kh") was given more than once for the same group.
+    MultipleClauses(&'static str),
+    /// A drop clause drops as many dice as the group has, or more.
+    DroppingTooMany { dropping: usize, count: usize },
+    /// A keep clause keeps as many dice as the group has, or more.
+    KeepingTooMany { keeping: usize, count: usize },
+    /// A drop clause which keeps something other than a run of the sorted dice.
+    InvalidDrop,
+    /// A reroll clause rerolls more dice than the group has.
+    ReRollingTooMany { rerolling: usize, count: usize },
+    /// A reroll threshold which can never (or must always) be met.
+    ReRollThreshold { threshold: i64, size: i64 },
+    /// A reroll count without a threshold, or a threshold without a count.
+    IncompleteReRoll,
+    /// A minimum cutoff outside of `2..=size`.
+    CutOffMinimum { min: i64, size: i64 },
+    /// A maximum cutoff outside of `1..size`.
+    CutOffMaximum { max: i64, size: i64 },
+    /// A minimum cutoff above the maximum cutoff.
+    CutOffOrder { min: i64, max: i64 },
+    /// A modifier was added to a bonus, or to nothing.
+    ModifierWithoutDice(&'static str),
+    /// A label which is empty, has spaces at either end, or holds a bracket or a control
+    /// character (eg a tab).
+    InvalidLabel(String),
+    /// A comment which holds a control character (eg a tab or a new line).
+    InvalidComment(String),
+    /// A clause was added to a group which keeps the highest or lowest of several bags (eg
+    /// "max(1d8!, 1d6!)dl1").
+    ClauseOnPick,
+    /// A group has more dice than `Limits::max_dice_per_group`.
+    TooManyDice { count: usize, max: usize },
+    /// A dice has more sides than `Limits::max_sides`.
+    TooManySides { size: i64, max: i64 },
+    /// A bag has more groups than `Limits::max_groups`.
+    TooManyGroups { groups: usize, max: usize },
+    /// A roll of the bag would roll more dice than `Limits::max_total_rolls`.
+    TooManyRolls { rolls: usize, max: usize },
+    /// The total of a roll of the bag might not fit in an `i64`.
+    Overflow,
+}
+
+impl Display for DiceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use self::DiceError::*;
+        match *self {
+            NoGroups => write!(f, "Input contains no valid dice groups."),
+            InvalidSize(size) => write!(f, "Dice must have at least one side (d{}).", size),
+            NegativeCount(count) => {
+                write!(f, "Dice count must not be negative ({}d).", count)
+            }
+            NoDice => write!(f, "A dice group must have at least one dice."),
+            ExplodingOneSide => write!(f, "A one-sided dice would explode forever!"),
+            NegativeBonus(n) => write!(f, "Bonus ({}) must not be negative!", n),
+            MultipleClauses(clause) => write!(f, "Multiple ({}) clauses found!", clause),
+            DroppingTooMany { dropping, count } => write!(
+                f,
+                "Dropping more dice than you have({} vs {})!",
+                dropping, count
+            ),
+            KeepingTooMany { keeping, count } => write!(
+                f,
+                "Keeping more dice than you have({} vs {})!",
+                keeping, count
+            ),
+            InvalidDrop => write!(f, "Drop clause does not keep a run of dice!"),
+            ReRollingTooMany { rerolling, count } => write!(
+                f,
+                "Re-rolling more dice than you have({} vs {})!",
+                rerolling, count
+            ),
+            ReRollThreshold { threshold, size } => write!(
+                f,
+                "Re-roll threshold is ridiculous ({} for a d{})!",
+                threshold, size
+            ),
+            IncompleteReRoll => write!(f, "Incomplete reroll clause!"),
+            CutOffMinimum { min, size } => write!(
+                f,
+                "Cut-off Minimum is ridiculous ({} for a d{}).",
+                min, size
+            ),
+            CutOffMaximum { max, size } => write!(
+                f,
+                "Cut-off Maximum is ridiculous ({} for a d{}).",
+                max, size
+            ),
+            CutOffOrder { min, max } => write!(
+                f,
+                "Cut-off Minimum is bigger than Maximum ({} vs {}).",
+                min, max
+            ),
+            ModifierWithoutDice(clause) => write!(
+                f,
+                "Modifier ({}) must follow a dice group, not a bonus or nothing.",
+                clause
+            ),
+            InvalidLabel(ref label) => write!(
+                f,
+                "Label [{}] must not be empty, start or end with spaces, or hold brackets or \
+                 control characters.",
+                label.escape_debug()
+            ),
+            InvalidComment(ref comment) => write!(
+                f,
+                "Comment ({}) must not hold control characters.",
+                comment.escape_debug()
+            ),
+            ClauseOnPick => write!(
+                f,
+                "A max(..) or min(..) group must not have modifiers. Put them on the dice inside."
+            ),
+            TooManyDice { count, max } => {
+                write!(f, "Too many dice in one group ({} vs {})!", count, max)
+            }
+            TooManySides { size, max } => {
+                write!(f, "Dice has too many sides ({} vs {})!", size, max)
+            }
+            TooManyGroups { groups, max } => {
+                write!(f, "Too many dice groups ({} vs {})!", groups, max)
+            }
+            TooManyRolls { rolls, max } => {
+                write!(f, "Too many dice to roll ({} vs {})!", rolls, max)
+            }
+            Overflow => write!(f, "The total of this roll might overflow!"),
+        }
+    }
+}
+
+impl std::error::Error for DiceError {}
+
+impl From<DiceError> for String {
+    fn from(e: DiceError) -> String {
+        e.to_string()
+    }
+}
+
+impl Dice {
+    /// Check that a drop clause fits this group.
+    pub(crate) fn check_drop(&self, drop: &Drop) -> Result<(), DiceError> {
+        match *drop {
+            Drop::Non => Ok(()),
+            Drop::Lowest(n) | Drop::Highest(n) => {
+                if n >= self.count {
+                    Err(DiceError::DroppingTooMany {
+                        dropping: n,
+                        count: self.count,
+                    })
+                } else {
+                    Ok(())
+                }
+            }
+            Drop::Custom(ref v) => {
+                // Must be a non-empty run of ascending positions, inside the group.
+                let contiguous = v.windows(2).all(|w| w[1] == w[0] + 1);
+                match v.last() {
+                    Some(last) if contiguous && *last < self.count => Ok(()),
+                    _ => Err(DiceError::InvalidDrop),
+                }
+            }
+        }
+    }
+
+    /// Check that a reroll clause fits this group.
+    pub(crate) fn check_reroll(&self, reroll: &ReRoll) -> Result<(), DiceError> {
+        let (rrt, threshold_ok) = match *reroll {
+            ReRoll::Never => return Ok(()),
+            ReRoll::IfAbove(ref rrt) => (rrt, (0..self.size).contains(&rrt.ex_threshold)),
+            ReRoll::IfBelow(ref rrt) => (rrt, rrt.ex_threshold > 1),
+        };
+        if rrt.count > self.count {
+            return Err(DiceError::ReRollingTooMany {
+                rerolling: rrt.count,
+                count: self.count,
+            });
+        }
+        if !threshold_ok {
+            return Err(DiceError::ReRollThreshold {
+                threshold: rrt.ex_threshold,
+                size: self.size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check that a cutoff clause fits this group.
+    pub(crate) fn check_cutoff(&self, cutoff: &CutOff) -> Result<(), DiceError> {
+        let (min, max) = match *cutoff {
+            CutOff::Non => (None, None),
+            CutOff::Minimum(min) => (Some(min), None),
+            CutOff::Maximum(max) => (None, Some(max)),
+            CutOff::Both(MinMax([min, max])) => (Some(min), Some(max)),
+        };
+        if let Some(min) = min {
+            if (min <= 1) || (min > self.size) {
+                return Err(DiceError::CutOffMinimum {
+                    min,
+                    size: self.size,
+                });
+            }
+        }
+        if let Some(max) = max {
+            if (max < 1) || (max >= self.size) {
+                return Err(DiceError::CutOffMaximum {
+                    max,
+                    size: self.size,
+                });
+            }
+        }
+        if let (Some(min), Some(max)) = (min, max) {
+            if min > max {
+                return Err(DiceError::CutOffOrder { min, max });
+            }
+        }
+        Ok(())
+    }
+
+    /// The drop clause made by dropping `low` of the lowest and `high` of the highest dice.
+    pub(crate) fn drop_lowest_and_highest(
+        &self,
+        low: usize,
+        high: usize,
+    ) -> Result<Drop, DiceError> {
+        if low + high >= self.count {
+            return Err(DiceError::DroppingTooMany {
+                dropping: low + high,
+                count: self.count,
+            });
+        }
+        Ok(Drop::custom((low..(self.count - high)).collect()))
+    }
+
+    /// Check that the dice group is one that the parser could have made.
+    /// ```
+    /// use libazdice::distribution::*;
+    /// use libazdice::validation::DiceError;
+    ///
+    /// assert!(Dice::with_size_and_count(6, 4).validate().is_ok());
+    /// assert_eq!(Dice::with_size_and_count(0, 4).validate(), Err(DiceError::InvalidSize(0)));
+    /// assert_eq!(Dice::with_size_and_count(6, 0).validate(), Err(DiceError::NoDice));
+    /// ```
+    pub fn validate(&self) -> Result<(), DiceError> {
+        if let Some(ref pick) = self.pick {
+            return self.validate_pick(pick);
+        }
+        if let Some(ref nested) = self.nested {
+            return self.validate_nested(nested);
+        }
+        if self.size < 1 {
+            return Err(DiceError::InvalidSize(self.size));
+        }
+        if self.count == 0 {
+            return Err(DiceError::NoDice);
+        }
+        if self.explosive && self.size == 1 {
+            return Err(DiceError::ExplodingOneSide);
+        }
+        self.check_drop(&self.drop)?;
+        self.check_reroll(&self.reroll)?;
+        self.check_cutoff(&self.cutoff)
+    }
+
+    /// Check the dice against `limits`, returning the number of dice rolled by one roll
+    /// (before explosions).
+    pub(crate) fn check_limits(&self, limits: &Limits) -> Result<usize, DiceError> {
+        if let Some(ref pick) = self.pick {
+            return self.check_pick_limits(pick, limits);
+        }
+        if let Some(ref nested) = self.nested {
+            return self.check_nested_limits(nested, limits);
+        }
+        if self.count > limits.max_dice_per_group {
+            return Err(DiceError::TooManyDice {
+                count: self.count,
+                max: limits.max_dice_per_group,
+            });
+        }
+        if self.size > limits.max_sides {
+            return Err(DiceError::TooManySides {
+                size: self.size,
+                max: limits.max_sides,
+            });
+        }
+        let rerolls = match self.reroll {
+            ReRoll::Never => 0,
+            ReRoll::IfAbove(ref rrt) | ReRoll::IfBelow(ref rrt) => rrt.count,
+        };
+        Ok(self.count + rerolls)
+    }
+
+    /// The largest magnitude the dice can total, with explosions limited to `explosion_depth`.
+    /// `None` if it does not fit in an `i64`.
+    pub(crate) fn checked_magnitude(&self, explosion_depth: usize) -> Option<i64> {
+        if let Some(ref pick) = self.pick {
+            return self.pick_magnitude(pick, explosion_depth);
+        }
+        if self.nested.is_some() {
+            return self.nested_magnitude(explosion_depth);
+        }
+        let per_die = if self.explosive {
+            let depth = i64::try_from(explosion_depth).ok()?.checked_add(1)?;
+            self.size.checked_mul(depth)?
+        } else {
+            self.size
+        };
+        per_die.checked_mul(i64::try_from(self.count).ok()?)
+    }
+}
+
+impl DiceGroup {
+    /// Check that the group is one that the parser could have made.
+    pub fn validate(&self) -> Result<(), DiceError> {
+        if let Some(label) = self.label() {
+            if label.is_empty()
+                || label.trim() != label
+                || label.contains(['[', ']'])
+                || label.contains(char::is_control)
+            {
+                return Err(DiceError::InvalidLabel(label.to_owned()));
+            }
+        }
+        match self {
+            DiceGroup::Dice(d) => d.validate(),
+            DiceGroup::Bonus(b) if b.bonus < 0 => Err(DiceError::NegativeBonus(b.bonus)),
+            DiceGroup::Bonus(_) => Ok(()),
+        }
+    }
+}
+
+impl DiceBag {
+    /// Check that the dicebag is one that the parser could have made, and that it is within its
+    /// `Limits` (see `DiceBag::limits`). Every bag made by `parse::parse`, `DiceBag::builder` or
+    /// `DiceBag::try_from_dice` is valid.
+    /// ```
+    /// use libazdice::distribution::*;
+    ///
+    /// let bag = DiceBag::from_dice(vec![Dice::with_size_and_count(6, 4).into()]);
+    /// assert!(bag.validate().is_ok());
+    ///
+    /// let bag = DiceBag::from_dice(vec![Dice::with_size_and_count(-6, 4).into()]);
+    /// assert!(bag.validate().is_err());
+    /// assert!(libazdice::parse(bag.to_string()).is_err());
+    /// ```
+    pub fn validate(&self) -> Result<(), DiceError> {
+        if self.dice.is_empty() {
+            return Err(DiceError::NoGroups);
+        }
+        self.dice.iter().try_for_each(DiceGroup::validate)?;
+        if let Some(comment) = self
+            .comment
+            .as_ref()
+            .filter(|c| c.contains(char::is_control))
+        {
+            return Err(DiceError::InvalidComment(comment.to_owned()));
+        }
+        self.check_limits()
+    }
+
+    /// Check the bag against its limits. The checks on the dice come first, so the arithmetic
+    /// here is only done on sensible dice, and is all checked.
+    fn check_limits(&self) -> Result<(), DiceError> {
+        let limits = self.limits();
+        if self.dice.len() > limits.max_groups {
+            return Err(DiceError::TooManyGroups {
+                groups: self.dice.len(),
+                max: limits.max_groups,
+            });
+        }
+
+        let mut rolls = 0_usize;
+        let mut magnitude = 0_i64;
+        for group in self.dice.iter() {
+            let group_magnitude = match group {
+                DiceGroup::Bonus(b) => Some(b.bonus),
+                DiceGroup::Dice(d) => {
+                    rolls = rolls.saturating_add(d.check_limits(limits)?);
+                    d.checked_magnitude(limits.max_explosion_depth)
+                }
+            };
+            magnitude = group_magnitude
+                .and_then(|m| magnitude.checked_add(m))
+                .ok_or(DiceError::Overflow)?;
+        }
+        if rolls > limits.max_total_rolls {
+            return Err(DiceError::TooManyRolls {
+                rolls,
+                max: limits.max_total_rolls,
+            });
+        }
+        Ok(())
+    }
+
+    /// Create a `DiceBag` from a vector of `DiceGroup`s, as `from_dice`, but only if it is valid
+    /// within the default `Limits`.
+    pub fn try_from_dice(dice: Vec<DiceGroup>) -> Result<DiceBag, DiceError> {
+        DiceBag::try_from_dice_with_limits(dice, Limits::default())
+    }
+
+    /// Create a `DiceBag` from a vector of `DiceGroup`s, as `from_dice`, but only if it is valid
+    /// within `limits`. The bag keeps the limits, which also cap explosions when it is rolled.
+    pub fn try_from_dice_with_limits(
+        dice: Vec<DiceGroup>,
+        limits: Limits,
+    ) -> Result<DiceBag, DiceError> {
+        let mut bag = DiceBag::from_dice(dice);
+        bag.limits = limits;
+        bag.validate()?;
+        Ok(bag)
+    }
+}
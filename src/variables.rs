@@ -0,0 +1,421 @@
+//! This module contains dice strings with variables, such as "1d20 + @str_mod + @prof", whose
+//! values come from a `Context` supplied by the caller (eg a character sheet).
+//!
+//! A variable is "@" and a name of letters, digits and "_" (or any name in braces, eg
+//! "@{spell level}"). It may stand for a whole group ("+ @prof"), where it adds its value, or for
+//! part of one, such as the count or sides of dice ("@level d6", "1d@die" or "4d6dl@drop"), where
+//! its value must not be negative. A space after a name ends it, so "@level d6" is "@level" dice
+//! with 6 sides. A variable cannot be next to another variable or a number (eg "@x@y" or
+//! "@{level}6"), as their values would run together.
+//!
+//! Variables can be resolved when parsing (`parse::parse_with_context`), giving a plain
+//! `DiceBag`, or kept in a `DiceTemplate`, which shows them by name and resolves them each time
+//! it is rolled. Labels ("[fire]") and a comment ("# vs @target") are kept as written and carried
+//! into the resolved `DiceBag`; variables in them are not replaced.
+//! ```
+//! use libazdice::variables::DiceTemplate;
+//! use std::collections::HashMap;
+//!
+//! let template: DiceTemplate = "1d20+@str_mod+@prof".parse().unwrap();
+//! assert_eq!(template.to_string(), "1d20 + @str_mod + @prof");
+//!
+//! let mut sheet = HashMap::new();
+//! sheet.insert("str_mod".to_owned(), 3);
+//! sheet.insert("prof".to_owned(), 2);
+//! assert_eq!(template.resolve(&sheet).unwrap().to_string(), "1d20 + 3 + 2");
+//!
+//! sheet.insert("str_mod".to_owned(), -1);
+//! assert_eq!(template.resolve(&sheet).unwrap().to_string(), "1d20 - 1 + 2");
+//! let total = template.roll(&sheet).unwrap().total();
+//! assert!((2..=21).contains(&total));
+//!
+//! sheet.remove("prof");
+//! assert_eq!(
+//!     template.resolve(&sheet).unwrap_err(),
+//!     "Unknown variable \"@prof\"."
+//! );
+//! ```
+use super::distribution::*;
+use super::validation::{DiceError, Limits};
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Where the values of variables come from. It is implemented for maps from names to values and
+/// for closures taking a name.
+/// ```
+/// use libazdice::parse::parse_with_context;
+///
+/// let level = |name: &str| if name == "level" { Some(5) } else { None };
+/// let bag = parse_with_context("@level d6".to_owned(), &level).unwrap();
+/// assert_eq!(bag.to_string(), "5d6");
+/// ```
+pub trait Context {
+    /// The value of the variable called `name` (without the "@"), or `None` if there is none.
+    fn value(&self, name: &str) -> Option<i64>;
+}
+
+impl Context for HashMap<String, i64> {
+    fn value(&self, name: &str) -> Option<i64> {
+        self.get(name).copied()
+    }
+}
+
+impl Context for HashMap<&str, i64> {
+    fn value(&self, name: &str) -> Option<i64> {
+        self.get(name).copied()
+    }
+}
+
+impl Context for BTreeMap<String, i64> {
+    fn value(&self, name: &str) -> Option<i64> {
+        self.get(name).copied()
+    }
+}
+
+impl Context for BTreeMap<&str, i64> {
+    fn value(&self, name: &str) -> Option<i64> {
+        self.get(name).copied()
+    }
+}
+
+impl<F: Fn(&str) -> Option<i64>> Context for F {
+    fn value(&self, name: &str) -> Option<i64> {
+        self(name)
+    }
+}
+
+/// A part of a group of a `DiceTemplate`.
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    /// Dice string, without spaces.
+    Text(String),
+    /// The name of a variable.
+    Variable(String),
+}
+
+/// Whether `name` can be written after "@" without braces.
+fn is_plain_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// The error for a variable written next to another variable or a number (eg "@x@y d6" or
+/// "@{level}6"), whose values would run together into one number.
+fn run_together(input: &str) -> String {
+    format!(
+        "\"{}\" has a variable next to another variable or a number.",
+        input
+    )
+}
+
+/// Split a run of a dice string with variables (without labels or a comment) into its groups.
+/// Errors show the whole `input`.
+fn parse_groups(text: &str, input: &str) -> Result<Vec<(DiceOp, Vec<Piece>)>, String> {
+    let mut groups = Vec::new();
+    let mut op = DiceOp::Add;
+    let mut pieces: Vec<Piece> = Vec::new();
+    let mut depth = 0_usize;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '@' => {
+                let name = if chars.peek() == Some(&'{') {
+                    chars.next();
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => {
+                                return Err(format!(
+                                    "\"{}\" has a \"@{{\" without a closing \"}}\".",
+                                    input
+                                ))
+                            }
+                        }
+                    }
+                    name.trim().to_owned()
+                } else {
+                    let mut name = String::new();
+                    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                        name.push(c);
+                    }
+                    name
+                };
+                if name.is_empty() {
+                    return Err(format!("\"{}\" has an \"@\" without a name.", input));
+                }
+                let runs_on = match pieces.last() {
+                    Some(Piece::Variable(_)) => true,
+                    Some(Piece::Text(text)) => text.ends_with(|c: char| c.is_ascii_digit()),
+                    None => false,
+                };
+                if runs_on {
+                    return Err(run_together(input));
+                }
+                pieces.push(Piece::Variable(name));
+            }
+            // Signs in brackets belong to the dice there, eg "(@level+1)d6".
+            '+' | '-' if depth > 0 => match pieces.last_mut() {
+                Some(Piece::Text(text)) => text.push(c),
+                _ => pieces.push(Piece::Text(c.to_string())),
+            },
+            '+' | '-' => {
+                // A sign at the very start belongs to the first group.
+                if !(pieces.is_empty() && groups.is_empty() && op == DiceOp::Add) {
+                    if pieces.is_empty() {
+                        return Err(format!("\"{}\" has a sign without a group.", input));
+                    }
+                    groups.push((op, std::mem::take(&mut pieces)));
+                }
+                op = if c == '+' { DiceOp::Add } else { DiceOp::Sub };
+            }
+            c if c.is_whitespace() || c.is_control() => {}
+            c => {
+                let c = c.to_ascii_lowercase();
+                if !crate::parse::valid_chars(c) {
+                    return Err(format!("Input contained invalid character ({}).", c));
+                }
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                match pieces.last_mut() {
+                    Some(Piece::Text(text)) => text.push(c),
+                    Some(Piece::Variable(_)) if c.is_ascii_digit() => {
+                        return Err(run_together(input))
+                    }
+                    _ => pieces.push(Piece::Text(c.to_string())),
+                }
+            }
+        }
+    }
+    if pieces.is_empty() {
+        return Err(DiceError::NoGroups.to_string());
+    }
+    groups.push((op, pieces));
+
+    Ok(groups)
+}
+
+/// A run of groups of a `DiceTemplate` and the label which follows it.
+type Run = (Vec<(DiceOp, Vec<Piece>)>, Option<String>);
+
+/// A dice string which may hold variables, kept in its symbolic form. It is made by parsing (as
+/// `DiceBag`s are) and becomes a `DiceBag` when its variables are resolved against a `Context`.
+/// ```
+/// use libazdice::variables::DiceTemplate;
+/// use std::collections::BTreeMap;
+///
+/// let fireball: DiceTemplate = "@{spell level}d6 - @save".parse().unwrap();
+/// assert_eq!(fireball.variables(), ["spell level", "save"]);
+///
+/// let context: BTreeMap<&str, i64> = vec![("spell level", 8), ("save", 0)].into_iter().collect();
+/// let bag = fireball.resolve(&context).unwrap();
+/// assert_eq!(bag.to_string(), "8d6 - 0");
+/// assert_eq!(bag.range(), 8..=48);
+///
+/// // Negative values cannot be counts or sides.
+/// let context: BTreeMap<&str, i64> = vec![("spell level", -1), ("save", 0)].into_iter().collect();
+/// assert!(fireball.resolve(&context).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiceTemplate {
+    runs: Vec<Run>,
+    comment: Option<String>,
+    limits: Limits,
+}
+
+impl DiceTemplate {
+    /// Parse a dice string with variables, checking the dice against the default `Limits` when
+    /// they are resolved.
+    pub fn parse(input: &str) -> Result<DiceTemplate, String> {
+        DiceTemplate::parse_with_limits(input, &Limits::default())
+    }
+
+    /// Parse as `DiceTemplate::parse`, but check the dice against `limits` when resolved.
+    /// Only the characters are checked here, as whether the dice are valid may depend on the
+    /// values of the variables.
+    pub fn parse_with_limits(input: &str, limits: &Limits) -> Result<DiceTemplate, String> {
+        // Labels and the comment keep their case and spaces, so they come out first.
+        let (labelled, comment) = crate::parse::split_labels(input)?;
+        let mut runs = Vec::new();
+        for (i, (text, label)) in labelled.into_iter().enumerate() {
+            let text = text.trim();
+            if i > 0 {
+                if text.is_empty() && label.is_none() {
+                    break;
+                } else if !text.starts_with(['+', '-']) {
+                    return Err("A label must be followed by + or -.".to_owned());
+                }
+            } else if text.is_empty() && label.is_some() {
+                return Err("A label must follow a dice group.".to_owned());
+            }
+            runs.push((parse_groups(text, input)?, label));
+        }
+
+        Ok(DiceTemplate {
+            runs,
+            comment,
+            limits: *limits,
+        })
+    }
+
+    /// The groups of every run, in order.
+    fn groups(&self) -> impl Iterator<Item = &(DiceOp, Vec<Piece>)> {
+        self.runs.iter().flat_map(|(groups, _)| groups)
+    }
+
+    /// The names of the variables, in the order they first appear.
+    pub fn variables(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for (_, pieces) in self.groups() {
+            for piece in pieces {
+                if let Piece::Variable(name) = piece {
+                    if !names.contains(&name.as_str()) {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+        names
+    }
+
+    /// Make the `DiceBag` with the values of the variables in `context`. It fails if a variable
+    /// is not in the context, or if a value is negative where it cannot be, or if the dice with
+    /// these values are not valid.
+    pub fn resolve<C: Context + ?Sized>(&self, context: &C) -> Result<DiceBag, String> {
+        let mut dice_string = String::new();
+        let mut i = 0;
+        for (groups, label) in self.runs.iter() {
+            for (op, pieces) in groups.iter() {
+                let mut op = *op;
+                let mut text = String::new();
+                for piece in pieces {
+                    match piece {
+                        Piece::Text(t) => text.push_str(t),
+                        Piece::Variable(name) => {
+                            let value = context
+                                .value(name)
+                                .ok_or_else(|| format!("Unknown variable \"@{}\".", name))?;
+                            if pieces.len() == 1 {
+                                // A group of its own, so its sign joins the operation.
+                                if value < 0 {
+                                    op = match op {
+                                        DiceOp::Add => DiceOp::Sub,
+                                        DiceOp::Sub => DiceOp::Add,
+                                    };
+                                }
+                                text.push_str(&value.unsigned_abs().to_string());
+                            } else if value < 0 {
+                                return Err(format!(
+                                    "\"@{}\" is {}, but cannot be negative in \"{}\".",
+                                    name,
+                                    value,
+                                    Group(pieces)
+                                ));
+                            } else {
+                                text.push_str(&value.to_string());
+                            }
+                        }
+                    }
+                }
+                match op {
+                    DiceOp::Add if i > 0 => dice_string.push('+'),
+                    DiceOp::Add => {}
+                    DiceOp::Sub => dice_string.push('-'),
+                }
+                dice_string.push_str(&text);
+                i += 1;
+            }
+            if let Some(label) = label {
+                dice_string.push_str(&format!(" [{}]", label));
+            }
+        }
+        if let Some(ref comment) = self.comment {
+            dice_string.push_str(&format!(" # {}", comment));
+        }
+        crate::parse::parse_with_limits(dice_string, &self.limits)
+    }
+
+    /// Resolve the variables against `context` and roll the dice.
+    pub fn roll<C: Context + ?Sized>(&self, context: &C) -> Result<RollResults, String> {
+        self.roll_with_rng(context, &mut rand::thread_rng())
+    }
+
+    /// As `DiceTemplate::roll`, but with the given random number generator.
+    pub fn roll_with_rng<C: Context + ?Sized, R: Rng + ?Sized>(
+        &self,
+        context: &C,
+        rng: &mut R,
+    ) -> Result<RollResults, String> {
+        self.resolve(context).map(|bag| bag.roll_with_rng(rng))
+    }
+}
+
+/// The pieces of a group, written as in a dice string.
+struct Group<'a>(&'a [Piece]);
+
+impl Display for Group<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, piece) in self.0.iter().enumerate() {
+            match piece {
+                Piece::Text(text) => write!(f, "{}", text)?,
+                Piece::Variable(name) if is_plain_name(name) => {
+                    write!(f, "@{}", name)?;
+                    // A space keeps the name from running into what follows.
+                    if let Some(Piece::Text(next)) = self.0.get(i + 1) {
+                        if next.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+                            write!(f, " ")?;
+                        }
+                    }
+                }
+                Piece::Variable(name) => write!(f, "@{{{}}}", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for DiceTemplate {
+    /// The dice string in the form `DiceBag`s are shown, with the variables by name. The result
+    /// parses back into the same template.
+    /// ```
+    /// use libazdice::variables::DiceTemplate;
+    ///
+    /// let template: DiceTemplate = "-@level d6! +1d@die-2+@{hit dice}d8".parse().unwrap();
+    /// assert_eq!(template.to_string(), "-@level d6! + 1d@die - 2 + @{hit dice}d8");
+    /// assert_eq!(template.to_string().parse::<DiceTemplate>().unwrap(), template);
+    ///
+    /// let template: DiceTemplate = "1d20+@bonus [to hit] +1d6 [fire] # vs @target".parse().unwrap();
+    /// assert_eq!(template.to_string(), "1d20 + @bonus [to hit] + 1d6 [fire] # vs @target");
+    /// ```
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut i = 0;
+        for (groups, label) in self.runs.iter() {
+            for (op, pieces) in groups.iter() {
+                op.write_prefix(f, i)?;
+                write!(f, "{}", Group(pieces))?;
+                i += 1;
+            }
+            if let Some(label) = label {
+                write!(f, " [{}]", label)?;
+            }
+        }
+        if let Some(ref comment) = self.comment {
+            write!(f, " # {}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DiceTemplate {
+    type Err = String;
+
+    /// The same as `DiceTemplate::parse`.
+    fn from_str(s: &str) -> Result<DiceTemplate, String> {
+        DiceTemplate::parse(s)
+    }
+}